@@ -0,0 +1,191 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::diagnostics::now_ms;
+
+/// Пребывание участника в комнате
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ParticipantSession {
+    pub id: String,
+    pub name: String,
+    pub joined_at_ms: u64,
+    pub left_at_ms: Option<u64>,
+    pub duration_secs: u64,
+}
+
+/// Итоговая сводка закрытой комнаты для аналитики
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RoomSummary {
+    pub room_id: String,
+    pub created_at_ms: u64,
+    pub closed_at_ms: u64,
+    pub participants: Vec<ParticipantSession>,
+    pub peak_participants: usize,
+    /// Сколько байт медиа получено от издателей
+    pub total_bytes: u64,
+    pub recording_files: Vec<String>,
+}
+
+/// История комнаты, из которой собирается сводка при закрытии
+#[derive(Debug)]
+pub struct RoomHistory {
+    created_at_ms: u64,
+    sessions: Vec<ParticipantSession>,
+    peak_participants: usize,
+}
+
+impl RoomHistory {
+    pub fn new() -> Self {
+        Self {
+            created_at_ms: now_ms(),
+            sessions: Vec::new(),
+            peak_participants: 0,
+        }
+    }
+
+    pub fn participant_joined(&mut self, id: &str, name: &str, participants_now: usize) {
+        self.sessions.push(ParticipantSession {
+            id: id.to_string(),
+            name: name.to_string(),
+            joined_at_ms: now_ms(),
+            left_at_ms: None,
+            duration_secs: 0,
+        });
+        self.peak_participants = self.peak_participants.max(participants_now);
+    }
+
+    pub fn participant_left(&mut self, id: &str) {
+        let now = now_ms();
+        if let Some(session) = self
+            .sessions
+            .iter_mut()
+            .rev()
+            .find(|s| s.id == id && s.left_at_ms.is_none())
+        {
+            session.left_at_ms = Some(now);
+            session.duration_secs = now.saturating_sub(session.joined_at_ms) / 1000;
+        }
+    }
+
+    pub fn summary(
+        &self,
+        room_id: &str,
+        total_bytes: u64,
+        recording_files: Vec<String>,
+    ) -> RoomSummary {
+        let now = now_ms();
+        let participants = self
+            .sessions
+            .iter()
+            .cloned()
+            .map(|mut session| {
+                let end = session.left_at_ms.unwrap_or(now);
+                session.duration_secs = end.saturating_sub(session.joined_at_ms) / 1000;
+                session
+            })
+            .collect();
+
+        RoomSummary {
+            room_id: room_id.to_string(),
+            created_at_ms: self.created_at_ms,
+            closed_at_ms: now,
+            participants,
+            peak_participants: self.peak_participants,
+            total_bytes,
+            recording_files,
+        }
+    }
+}
+
+impl Default for RoomHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Событие журнала комнаты
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomEventKind {
+    Joined,
+    Left,
+    ScreenShareStarted,
+    /// Удален ведущим (после этого в журнале будет и `Left`)
+    Kicked,
+}
+
+/// Запись журнала: что произошло с участником и когда
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RoomEvent {
+    pub kind: RoomEventKind,
+    pub participant_id: String,
+    /// Миллисекунды от создания комнаты по монотонным часам
+    pub at_ms: u64,
+}
+
+/// Журнал последних событий комнаты для разбора "кто был в комнате и когда".
+/// Хранит не больше `capacity` записей: старые вытесняются новыми
+#[derive(Debug)]
+pub struct RoomEventLog {
+    started: Instant,
+    capacity: usize,
+    events: VecDeque<RoomEvent>,
+}
+
+impl RoomEventLog {
+    pub fn new(capacity: usize, started: Instant) -> Self {
+        Self {
+            started,
+            capacity,
+            events: VecDeque::with_capacity(capacity.min(1024)),
+        }
+    }
+
+    pub fn record(&mut self, kind: RoomEventKind, participant_id: &str, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(RoomEvent {
+            kind,
+            participant_id: participant_id.to_string(),
+            at_ms: now.saturating_duration_since(self.started).as_millis() as u64,
+        });
+    }
+
+    /// События от старых к новым
+    pub fn events(&self) -> Vec<RoomEvent> {
+        self.events.iter().cloned().collect()
+    }
+}
+
+/// Имя файла из id, заданного клиентом: остаются только безопасные символы
+pub fn safe_file_name(id: &str) -> String {
+    id.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// Записывает сводку в `<dir>/<room>-<closed_at_ms>.json`
+pub async fn write_summary(dir: &Path, summary: &RoomSummary) -> Result<PathBuf> {
+    tokio::fs::create_dir_all(dir)
+        .await
+        .context("Failed to create room archive directory")?;
+
+    let path = dir.join(format!(
+        "{}-{}.json",
+        safe_file_name(&summary.room_id),
+        summary.closed_at_ms
+    ));
+
+    let content = serde_json::to_string_pretty(summary)?;
+    tokio::fs::write(&path, content)
+        .await
+        .context("Failed to write room summary")?;
+    Ok(path)
+}
@@ -0,0 +1,263 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use webrtc::rtp::packet::Packet;
+
+/// URI RTP расширения уровня звука (RFC 6464)
+pub const AUDIO_LEVEL_URI: &str = "urn:ietf:params:rtp-hdrext:ssrc-audio-level";
+
+/// Уровень звука из расширения: `|V| level |`, level - громкость в -dBov (0..=127, 127 - тишина)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioLevel {
+    pub voice_activity: bool,
+    pub level: u8,
+}
+
+impl AudioLevel {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let byte = *data.first()?;
+        Some(Self {
+            voice_activity: byte & 0x80 != 0,
+            level: byte & 0x7F,
+        })
+    }
+
+    pub fn from_packet(packet: &Packet, ext_id: u8) -> Option<Self> {
+        packet
+            .header
+            .get_extension(ext_id)
+            .and_then(|data| Self::parse(&data))
+    }
+}
+
+/// Наибольший размер полезной нагрузки Opus DTX кадра: TOC байт и, возможно, один
+/// байт числа кадров. Речь и обновления комфортного шума кодируются длиннее
+pub const OPUS_DTX_MAX_PAYLOAD: usize = 2;
+
+/// Пакет с DTX кадром Opus (тишина, которую декодер заполняет сам).
+/// Маркер (начало речи, RFC 3551) или флаг голосовой активности в `level` означают
+/// начало речи: такой пакет не считается DTX, даже если он короткий
+pub fn is_dtx_frame(packet: &Packet, level: Option<AudioLevel>) -> bool {
+    packet.payload.len() <= OPUS_DTX_MAX_PAYLOAD
+        && !packet.header.marker
+        && !level.is_some_and(|level| level.voice_activity)
+}
+
+/// Шумовой порог для одного входящего аудио потока.
+/// Пакеты тише порога не пересылаются; после громкого пакета порог остается
+/// открытым `hangover`, чтобы не обрезать затухание и тихую речь между слогами.
+#[derive(Debug, Clone)]
+pub struct NoiseGate {
+    /// Порог в -dBov: пакеты с level больше порога считаются шумом
+    threshold: u8,
+    hangover: Duration,
+    open_until: Option<Instant>,
+}
+
+impl NoiseGate {
+    pub fn new(threshold: u8, hangover: Duration) -> Self {
+        Self {
+            threshold,
+            hangover,
+            open_until: None,
+        }
+    }
+
+    /// Решает, пересылать ли пакет. Пакеты без уровня звука пропускаются всегда.
+    pub fn allows(&mut self, level: Option<AudioLevel>, now: Instant) -> bool {
+        let Some(level) = level else {
+            return true;
+        };
+
+        if level.level <= self.threshold {
+            self.open_until = Some(now + self.hangover);
+            return true;
+        }
+
+        self.open_until.is_some_and(|until| now < until)
+    }
+}
+
+/// Определяет активного говорящего по уровням звука за скользящее окно.
+/// Уровни в -dBov усредняются по энергии; говорящий - самый громкий участник,
+/// средний уровень которого не тише порога.
+#[derive(Debug, Clone)]
+pub struct SpeakerDetector {
+    threshold: u8,
+    window: Duration,
+    levels: HashMap<String, VecDeque<(Instant, u8)>>,
+    current: Option<String>,
+}
+
+impl SpeakerDetector {
+    pub fn new(threshold: u8, window: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            levels: HashMap::new(),
+            current: None,
+        }
+    }
+
+    pub fn record(&mut self, peer_id: &str, level: AudioLevel, now: Instant) {
+        // Вызывается на каждый аудио пакет: без выделения памяти для известных участников
+        if let Some(samples) = self.levels.get_mut(peer_id) {
+            samples.push_back((now, level.level));
+        } else {
+            self.levels
+                .insert(peer_id.to_string(), VecDeque::from([(now, level.level)]));
+        }
+    }
+
+    /// Забывает участника (вышел из комнаты)
+    pub fn remove(&mut self, peer_id: &str) {
+        self.levels.remove(peer_id);
+    }
+
+    /// Пересчитывает говорящего; `Some`, только если он сменился
+    /// (`Some(None)` - в комнате тишина)
+    pub fn update(&mut self, now: Instant) -> Option<Option<String>> {
+        let window = self.window;
+        self.levels.retain(|_, samples| {
+            while samples
+                .front()
+                .is_some_and(|(at, _)| now.duration_since(*at) > window)
+            {
+                samples.pop_front();
+            }
+            !samples.is_empty()
+        });
+
+        let speaker = self
+            .levels
+            .iter()
+            .map(|(peer_id, samples)| (peer_id, average_dbov(samples)))
+            .filter(|(_, level)| *level <= f64::from(self.threshold))
+            .min_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(b.0)))
+            .map(|(peer_id, _)| peer_id.clone());
+
+        if speaker == self.current {
+            return None;
+        }
+        self.current = speaker.clone();
+        Some(speaker)
+    }
+}
+
+/// Средний уровень в -dBov по энергии: громкие пакеты весят больше тихих
+fn average_dbov(samples: &VecDeque<(Instant, u8)>) -> f64 {
+    let energy = samples
+        .iter()
+        .map(|(_, level)| 10f64.powf(-f64::from(*level) / 10.0))
+        .sum::<f64>()
+        / samples.len() as f64;
+    -10.0 * energy.log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use webrtc::rtp::header::Header;
+
+    fn level(level: u8) -> Option<AudioLevel> {
+        Some(AudioLevel {
+            voice_activity: false,
+            level,
+        })
+    }
+
+    #[test]
+    fn test_noise_gate_forwards_only_above_threshold() {
+        assert_eq!(
+            AudioLevel::parse(&[0x80 | 30]),
+            Some(AudioLevel {
+                voice_activity: true,
+                level: 30
+            })
+        );
+
+        let start = Instant::now();
+        let mut gate = NoiseGate::new(50, Duration::ZERO);
+        let forwarded: Vec<bool> = [30, 90, 40, 127, 50]
+            .into_iter()
+            .enumerate()
+            .map(|(i, l)| gate.allows(level(l), start + Duration::from_millis(i as u64 * 20)))
+            .collect();
+        assert_eq!(forwarded, vec![true, false, true, false, true]);
+
+        // Тихий хвост сразу после речи не обрезается
+        let mut gate = NoiseGate::new(50, Duration::from_millis(300));
+        assert!(gate.allows(level(40), start));
+        assert!(gate.allows(level(90), start + Duration::from_millis(100)));
+        assert!(!gate.allows(level(90), start + Duration::from_millis(400)));
+        assert!(gate.allows(None, start + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_dtx_frames_never_include_onsets() {
+        let packet = |marker: bool, payload: &'static [u8]| Packet {
+            header: Header {
+                marker,
+                ..Default::default()
+            },
+            payload: Bytes::from_static(payload),
+        };
+        let speech: &[u8] = &[0x78, 0x0b, 0xe4, 0x41, 0x2c];
+        let dtx: &[u8] = &[0xf8];
+        let voice = Some(AudioLevel {
+            voice_activity: true,
+            level: 30,
+        });
+
+        // Речь, тишина с DTX, начало речи по маркеру, снова тишина и короткий
+        // пакет с флагом голосовой активности
+        let sequence = [
+            (packet(true, speech), voice, true),
+            (packet(false, speech), voice, false),
+            (packet(false, dtx), level(127), false),
+            (packet(false, &[]), None, false),
+            (packet(false, dtx), level(127), false),
+            (packet(true, speech), voice, true),
+            (packet(false, speech), level(40), false),
+            (packet(false, dtx), level(127), false),
+            (packet(true, dtx), None, true),
+            (packet(false, dtx), level(127), false),
+            (packet(false, &[0x78, 0x01]), voice, true),
+        ];
+        let forwarded: Vec<usize> = sequence
+            .iter()
+            .enumerate()
+            .filter(|(_, (packet, level, _))| !is_dtx_frame(packet, *level))
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(forwarded, vec![0, 1, 5, 6, 8, 10]);
+        for (i, (_, _, onset)) in sequence.iter().enumerate() {
+            assert!(!onset || forwarded.contains(&i), "onset packet {} was dropped", i);
+        }
+    }
+
+    #[test]
+    fn test_speaker_detector_reports_changes() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut detector = SpeakerDetector::new(50, Duration::from_millis(1000));
+
+        for ms in [0, 20, 40] {
+            detector.record("alice", level(30).unwrap(), at(ms));
+            detector.record("bob", level(45).unwrap(), at(ms));
+        }
+        assert_eq!(detector.update(at(100)), Some(Some("alice".to_string())));
+        // Говорящий не сменился - повторного события нет
+        assert_eq!(detector.update(at(200)), None);
+
+        // Алиса замолчала, Боб говорит громче порога
+        for ms in [1100, 1120] {
+            detector.record("alice", level(90).unwrap(), at(ms));
+            detector.record("bob", level(20).unwrap(), at(ms));
+        }
+        assert_eq!(detector.update(at(1200)), Some(Some("bob".to_string())));
+
+        // Все затихли: уровни вышли из окна
+        assert_eq!(detector.update(at(3000)), Some(None));
+    }
+}
@@ -0,0 +1,193 @@
+use std::time::{Duration, Instant};
+use webrtc::rtcp::transport_feedbacks::transport_layer_cc::{
+    PacketStatusChunk, SymbolTypeTcc, TransportLayerCc,
+};
+
+/// Как часто пересчитывается оценка по накопленной TWCC обратной связи
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Оценка до первой обратной связи: получатель не считается перегруженным заранее
+pub const INITIAL_ESTIMATE_BPS: u64 = 2_500_000;
+const MIN_ESTIMATE_BPS: u64 = 50_000;
+const MAX_ESTIMATE_BPS: u64 = 20_000_000;
+
+/// Сколько пакетов из TWCC отчета получено и сколько потеряно
+pub fn twcc_packet_counts(feedback: &TransportLayerCc) -> (u64, u64) {
+    let mut remaining = usize::from(feedback.packet_status_count);
+    let (mut received, mut lost) = (0u64, 0u64);
+    let mut count = |symbol: SymbolTypeTcc, run: usize| {
+        let run = run.min(remaining);
+        remaining -= run;
+        if symbol == SymbolTypeTcc::PacketNotReceived {
+            lost += run as u64;
+        } else {
+            received += run as u64;
+        }
+    };
+
+    for chunk in &feedback.packet_chunks {
+        match chunk {
+            PacketStatusChunk::RunLengthChunk(chunk) => {
+                count(chunk.packet_status_symbol, usize::from(chunk.run_length))
+            }
+            // В конце списка символов бывает выравнивание: его отсекает `packet_status_count`
+            PacketStatusChunk::StatusVectorChunk(chunk) => {
+                for symbol in &chunk.symbol_list {
+                    count(*symbol, 1);
+                }
+            }
+        }
+    }
+    (received, lost)
+}
+
+/// Оценка пропускной способности получателя по потерям из TWCC обратной связи
+/// (loss-based контроллер GCC): при потерях меньше 2% оценка растет на 8%,
+/// при потерях больше 10% снижается в `1 - потери / 2` раз, между ними не меняется.
+#[derive(Debug, Clone)]
+pub struct BandwidthEstimator {
+    estimate: u64,
+    /// Оценка пересчитана хотя бы раз по обратной связи
+    measured: bool,
+    received: u64,
+    lost: u64,
+    last_update: Instant,
+}
+
+impl BandwidthEstimator {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            estimate: INITIAL_ESTIMATE_BPS,
+            measured: false,
+            received: 0,
+            lost: 0,
+            last_update: now,
+        }
+    }
+
+    /// Текущая оценка (бит/с)
+    pub fn estimate(&self) -> u64 {
+        self.estimate
+    }
+
+    /// Оценка, если она уже получена по обратной связи, а не начальная
+    pub fn measured(&self) -> Option<u64> {
+        self.measured.then_some(self.estimate)
+    }
+
+    /// Учитывает TWCC отчет; `Some` с новой оценкой, если она пересчитана
+    pub fn on_feedback(&mut self, feedback: &TransportLayerCc, now: Instant) -> Option<u64> {
+        let (received, lost) = twcc_packet_counts(feedback);
+        self.received += received;
+        self.lost += lost;
+
+        let total = self.received + self.lost;
+        if now.duration_since(self.last_update) < UPDATE_INTERVAL || total == 0 {
+            return None;
+        }
+
+        let loss = self.lost as f64 / total as f64;
+        let estimate = if loss < 0.02 {
+            self.estimate as f64 * 1.08
+        } else if loss > 0.1 {
+            self.estimate as f64 * (1.0 - loss / 2.0)
+        } else {
+            self.estimate as f64
+        };
+        self.estimate = (estimate as u64).clamp(MIN_ESTIMATE_BPS, MAX_ESTIMATE_BPS);
+        self.measured = true;
+        self.received = 0;
+        self.lost = 0;
+        self.last_update = now;
+        Some(self.estimate)
+    }
+}
+
+/// Пороги переключения перегруженного получателя на нижний simulcast слой:
+/// переключение вниз при оценке ниже `down_bps`, обратно - только выше `up_bps`,
+/// чтобы колебания оценки около одного порога не переключали слои туда-обратно
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CongestionThresholds {
+    pub down_bps: u64,
+    pub up_bps: u64,
+}
+
+impl CongestionThresholds {
+    /// Перегружен ли получатель с оценкой `estimate`, если до этого был `congested`
+    pub fn is_congested(&self, congested: bool, estimate: u64) -> bool {
+        if congested {
+            estimate <= self.up_bps
+        } else {
+            estimate < self.down_bps
+        }
+    }
+}
+
+impl Default for CongestionThresholds {
+    fn default() -> Self {
+        Self {
+            down_bps: 500_000,
+            up_bps: 1_000_000,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc::rtcp::transport_feedbacks::transport_layer_cc::RunLengthChunk;
+
+    fn feedback(received: u16, lost: u16) -> TransportLayerCc {
+        let run = |symbol, run_length| {
+            PacketStatusChunk::RunLengthChunk(RunLengthChunk {
+                packet_status_symbol: symbol,
+                run_length,
+                ..Default::default()
+            })
+        };
+        TransportLayerCc {
+            packet_status_count: received + lost,
+            packet_chunks: vec![
+                run(SymbolTypeTcc::PacketReceivedSmallDelta, received),
+                run(SymbolTypeTcc::PacketNotReceived, lost),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_estimate_follows_loss_with_hysteresis() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut estimator = BandwidthEstimator::new(start);
+        let thresholds = CongestionThresholds::default();
+
+        // Оценка пересчитывается не чаще раза в секунду
+        assert_eq!(estimator.on_feedback(&feedback(100, 0), start), None);
+        assert_eq!(estimator.measured(), None);
+        assert_eq!(estimator.on_feedback(&feedback(100, 0), at(1)), Some(2_700_000));
+        assert_eq!(estimator.measured(), Some(2_700_000));
+
+        // Потери 50%: оценка падает на четверть за каждый интервал
+        let mut congested = false;
+        for secs in 2..10 {
+            estimator.on_feedback(&feedback(50, 50), at(secs));
+            congested = thresholds.is_congested(congested, estimator.estimate());
+        }
+        assert!(estimator.estimate() < thresholds.down_bps);
+        assert!(congested);
+
+        // Выше нижнего порога получатель остается перегруженным, пока не превысит верхний
+        let mut secs = 10;
+        while estimator.estimate() < thresholds.down_bps {
+            estimator.on_feedback(&feedback(100, 0), at(secs));
+            secs += 1;
+        }
+        assert!(thresholds.is_congested(true, estimator.estimate()));
+        while estimator.estimate() <= thresholds.up_bps {
+            estimator.on_feedback(&feedback(100, 0), at(secs));
+            secs += 1;
+        }
+        assert!(!thresholds.is_congested(true, estimator.estimate()));
+    }
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+/// Как сводить оценки пропускной способности получателей в одну оценку для издателя
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BitrateAggregation {
+    /// Обратная связь издателю не отправляется
+    #[default]
+    Off,
+    /// Минимальная оценка: издатель подстраивается под самого слабого получателя
+    Min,
+    /// Перцентиль оценок (`RoomConfig::bitrate_feedback_percentile`):
+    /// несколько самых слабых получателей не тянут качество вниз для всех
+    Percentile,
+}
+
+/// Сводит оценки получателей (бит/с); `None`, если оценок нет или обратная связь выключена
+pub fn aggregate_estimates(
+    estimates: &[u64],
+    policy: BitrateAggregation,
+    percentile: u8,
+) -> Option<u64> {
+    if estimates.is_empty() {
+        return None;
+    }
+
+    let mut sorted = estimates.to_vec();
+    sorted.sort_unstable();
+
+    match policy {
+        BitrateAggregation::Off => None,
+        BitrateAggregation::Min => sorted.first().copied(),
+        BitrateAggregation::Percentile => {
+            let rank = (sorted.len() - 1) * usize::from(percentile.min(100)) / 100;
+            sorted.get(rank).copied()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregate_estimates() {
+        let estimates = [2_000_000, 300_000, 1_000_000, 5_000_000, 800_000];
+        assert_eq!(
+            aggregate_estimates(&estimates, BitrateAggregation::Min, 0),
+            Some(300_000)
+        );
+        assert_eq!(
+            aggregate_estimates(&estimates, BitrateAggregation::Percentile, 50),
+            Some(1_000_000)
+        );
+        assert_eq!(aggregate_estimates(&estimates, BitrateAggregation::Off, 0), None);
+        assert_eq!(aggregate_estimates(&[], BitrateAggregation::Min, 0), None);
+    }
+}
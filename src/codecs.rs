@@ -0,0 +1,573 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use webrtc::api::media_engine::{
+    MediaEngine, MIME_TYPE_AV1, MIME_TYPE_G722, MIME_TYPE_H264, MIME_TYPE_OPUS, MIME_TYPE_PCMA,
+    MIME_TYPE_PCMU, MIME_TYPE_VP8, MIME_TYPE_VP9,
+};
+use webrtc::rtp_transceiver::rtp_codec::{
+    RTCRtpCodecCapability, RTCRtpCodecParameters, RTPCodecType,
+};
+use webrtc::rtp_transceiver::RTCPFeedback;
+
+/// Кодеки, которые сервер умеет согласовывать, в порядке по умолчанию
+fn supported_codecs() -> Vec<(RTCRtpCodecParameters, RTPCodecType)> {
+    let video_feedback = vec![
+        RTCPFeedback {
+            typ: "goog-remb".to_owned(),
+            parameter: "".to_owned(),
+        },
+        RTCPFeedback {
+            typ: "ccm".to_owned(),
+            parameter: "fir".to_owned(),
+        },
+        RTCPFeedback {
+            typ: "nack".to_owned(),
+            parameter: "".to_owned(),
+        },
+        RTCPFeedback {
+            typ: "nack".to_owned(),
+            parameter: "pli".to_owned(),
+        },
+    ];
+
+    let audio = |mime: &str, clock_rate, channels, fmtp: &str, pt| {
+        (
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: mime.to_owned(),
+                    clock_rate,
+                    channels,
+                    sdp_fmtp_line: fmtp.to_owned(),
+                    rtcp_feedback: vec![],
+                },
+                payload_type: pt,
+                ..Default::default()
+            },
+            RTPCodecType::Audio,
+        )
+    };
+    let video = |mime: &str, fmtp: &str, pt| {
+        (
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: mime.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: fmtp.to_owned(),
+                    rtcp_feedback: video_feedback.clone(),
+                },
+                payload_type: pt,
+                ..Default::default()
+            },
+            RTPCodecType::Video,
+        )
+    };
+
+    vec![
+        audio(MIME_TYPE_OPUS, 48000, 2, "minptime=10;useinbandfec=1", 111),
+        audio(MIME_TYPE_G722, 8000, 0, "", 9),
+        audio(MIME_TYPE_PCMU, 8000, 0, "", 0),
+        audio(MIME_TYPE_PCMA, 8000, 0, "", 8),
+        video(MIME_TYPE_VP8, "", 96),
+        video(MIME_TYPE_VP9, "profile-id=0", 98),
+        video(MIME_TYPE_VP9, "profile-id=1", 100),
+        video(
+            MIME_TYPE_H264,
+            "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42001f",
+            102,
+        ),
+        video(
+            MIME_TYPE_H264,
+            "level-asymmetry-allowed=1;packetization-mode=0;profile-level-id=42001f",
+            127,
+        ),
+        video(
+            MIME_TYPE_H264,
+            "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f",
+            125,
+        ),
+        video(
+            MIME_TYPE_H264,
+            "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=640032",
+            123,
+        ),
+        video(MIME_TYPE_AV1, "profile-id=0", 41),
+    ]
+}
+
+/// Имя кодека без типа медиа: "video/H264" -> "H264"
+fn codec_name(mime_type: &str) -> &str {
+    mime_type.split('/').nth(1).unwrap_or(mime_type)
+}
+
+/// Тип медиа для известного имени кодека
+pub fn codec_kind(name: &str) -> Option<RTPCodecType> {
+    supported_codecs()
+        .into_iter()
+        .find(|(codec, _)| codec_name(&codec.capability.mime_type).eq_ignore_ascii_case(name))
+        .map(|(_, kind)| kind)
+}
+
+/// Разрешенные имена кодеков данного типа (пусто - разрешены все)
+fn allowed_of_kind(allowed: &[String], kind: RTPCodecType) -> Vec<&str> {
+    allowed
+        .iter()
+        .filter(|name| codec_kind(name) == Some(kind))
+        .map(String::as_str)
+        .collect()
+}
+
+fn is_allowed(mime_type: &str, allowed: &[&str]) -> bool {
+    allowed.is_empty()
+        || allowed
+            .iter()
+            .any(|name| codec_name(mime_type).eq_ignore_ascii_case(name))
+}
+
+/// Кодеки сервера в порядке приоритета; пустой список - все поддерживаемые
+/// в порядке по умолчанию
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CodecPreferences {
+    #[serde(default)]
+    pub video: Vec<String>,
+    #[serde(default)]
+    pub audio: Vec<String>,
+}
+
+impl CodecPreferences {
+    pub fn validate(&self) -> Result<()> {
+        for (names, kind, media) in [
+            (&self.video, RTPCodecType::Video, "video"),
+            (&self.audio, RTPCodecType::Audio, "audio"),
+        ] {
+            if let Some(name) = names.iter().find(|name| codec_kind(name) != Some(kind)) {
+                anyhow::bail!("Unsupported {} codec in codec preferences: {}", media, name);
+            }
+        }
+        Ok(())
+    }
+
+    fn of_kind(&self, kind: RTPCodecType) -> &[String] {
+        match kind {
+            RTPCodecType::Audio => &self.audio,
+            _ => &self.video,
+        }
+    }
+}
+
+/// Поддерживаемые кодеки, разрешенные в `allowed`, в порядке предпочтений сервера.
+/// Если ни один предпочтительный кодек типа медиа не разрешен в комнате,
+/// для этого типа остается порядок по умолчанию
+fn ordered_codecs(
+    allowed: &[String],
+    preferences: &CodecPreferences,
+) -> Vec<(RTCRtpCodecParameters, RTPCodecType)> {
+    let mut ordered = Vec::new();
+    for kind in [RTPCodecType::Audio, RTPCodecType::Video] {
+        let allowed = allowed_of_kind(allowed, kind);
+        let mut codecs: Vec<_> = supported_codecs()
+            .into_iter()
+            .filter(|(codec, codec_kind)| {
+                *codec_kind == kind && is_allowed(&codec.capability.mime_type, &allowed)
+            })
+            .collect();
+
+        let order = preferences.of_kind(kind);
+        let rank = |codec: &RTCRtpCodecParameters| {
+            order
+                .iter()
+                .position(|name| codec_name(&codec.capability.mime_type).eq_ignore_ascii_case(name))
+        };
+        if codecs.iter().any(|(codec, _)| rank(codec).is_some()) {
+            codecs.retain(|(codec, _)| rank(codec).is_some());
+            // Сортировка устойчивая: варианты H264 сохраняют порядок по умолчанию
+            codecs.sort_by_key(|(codec, _)| rank(codec));
+        }
+        ordered.extend(codecs);
+    }
+    ordered
+}
+
+/// Регистрирует кодеки в MediaEngine в порядке предпочтений сервера. Если в `allowed`
+/// есть кодеки какого-то типа медиа, для этого типа регистрируются только они.
+pub fn register_codecs(
+    media_engine: &mut MediaEngine,
+    allowed: &[String],
+    preferences: &CodecPreferences,
+) -> Result<()> {
+    for (codec, kind) in ordered_codecs(allowed, preferences) {
+        media_engine.register_codec(codec, kind)?;
+    }
+
+    Ok(())
+}
+
+/// MIME тип, с которым сервер создает исходящие треки данного типа
+pub fn preferred_mime_type(
+    kind: RTPCodecType,
+    allowed: &[String],
+    preferences: &CodecPreferences,
+) -> String {
+    ordered_codecs(allowed, preferences)
+        .into_iter()
+        .find(|(_, codec_kind)| *codec_kind == kind)
+        .map(|(codec, _)| codec.capability.mime_type)
+        .unwrap_or_default()
+}
+
+/// Проверяет, что offer содержит хотя бы один разрешенный кодек
+/// для каждого ограниченного типа медиа
+pub fn check_offer(sdp: &str, allowed: &[String]) -> std::result::Result<(), String> {
+    for (kind, media) in [
+        (RTPCodecType::Audio, "audio"),
+        (RTPCodecType::Video, "video"),
+    ] {
+        let allowed_names = allowed_of_kind(allowed, kind);
+        if allowed_names.is_empty() {
+            continue;
+        }
+
+        let mut has_section = false;
+        let mut offered = Vec::new();
+        let mut in_section = false;
+        for line in sdp.lines() {
+            if let Some(m) = line.strip_prefix("m=") {
+                in_section = m.starts_with(media);
+                has_section |= in_section;
+            } else if in_section {
+                if let Some(rtpmap) = line.strip_prefix("a=rtpmap:") {
+                    if let Some(name) = rtpmap
+                        .split_whitespace()
+                        .nth(1)
+                        .and_then(|enc| enc.split('/').next())
+                    {
+                        offered.push(name.to_string());
+                    }
+                }
+            }
+        }
+
+        if has_section
+            && !offered
+                .iter()
+                .any(|name| allowed_names.iter().any(|a| a.eq_ignore_ascii_case(name)))
+        {
+            return Err(format!(
+                "Offer contains no allowed {} codec (allowed: {}, offered: {})",
+                media,
+                allowed_names.join(", "),
+                offered.join(", ")
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Максимальное разрешение (и частота кадров) видео, которое издатель может отправить в комнату
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VideoResolution {
+    pub width: u32,
+    pub height: u32,
+    /// Максимальная частота кадров; если не задана, ограничивается только размер кадра
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_framerate: Option<u32>,
+}
+
+/// Частота кадров для расчета уровня H264, если `max_framerate` не задан
+const DEFAULT_FRAMERATE: u32 = 30;
+
+/// Уровни H264: (level_idc, max-fs в макроблоках, max-mbps), ITU-T H.264 таблица A-1
+const H264_LEVELS: &[(u8, u32, u32)] = &[
+    (10, 99, 1_485),
+    (11, 396, 3_000),
+    (12, 396, 6_000),
+    (13, 396, 11_880),
+    (21, 792, 19_800),
+    (22, 1_620, 20_250),
+    (30, 1_620, 40_500),
+    (31, 3_600, 108_000),
+    (32, 5_120, 216_000),
+    (40, 8_192, 245_760),
+    (42, 8_704, 522_240),
+    (50, 22_080, 589_824),
+    (51, 36_864, 983_040),
+    (52, 36_864, 2_073_600),
+];
+
+impl VideoResolution {
+    pub fn validate(&self) -> Result<()> {
+        if !(16..=8192).contains(&self.width) || !(16..=8192).contains(&self.height) {
+            anyhow::bail!(
+                "Max video resolution must be between 16x16 and 8192x8192, got {}x{}",
+                self.width,
+                self.height
+            );
+        }
+        if self
+            .max_framerate
+            .is_some_and(|fps| !(1..=240).contains(&fps))
+        {
+            anyhow::bail!("Max video framerate must be between 1 and 240");
+        }
+        Ok(())
+    }
+
+    /// Размер кадра в макроблоках 16x16 (параметр `max-fs`)
+    pub fn frame_size(&self) -> u32 {
+        self.width.div_ceil(16) * self.height.div_ceil(16)
+    }
+
+    /// Наименьший уровень H264, вмещающий разрешение и частоту кадров
+    fn h264_level(&self) -> u8 {
+        let fs = self.frame_size();
+        let mbps = fs * self.max_framerate.unwrap_or(DEFAULT_FRAMERATE);
+        H264_LEVELS
+            .iter()
+            .find(|(_, max_fs, max_mbps)| *max_fs >= fs && *max_mbps >= mbps)
+            .map_or(52, |(level, _, _)| *level)
+    }
+}
+
+/// Ограничивает разрешение видео, которое клиент отправит по этому SDP (answer):
+/// для VP8/VP9 добавляет `max-fs`/`max-fr` в fmtp, для H264 понижает уровень
+/// в `profile-level-id` (точность ограничения - шаг уровня)
+pub fn limit_video_resolution(sdp: &str, limit: &VideoResolution) -> String {
+    // payload type -> имя кодека для видео кодеков, которые умеем ограничивать
+    let mut codecs = std::collections::HashMap::new();
+    let mut with_fmtp = std::collections::HashSet::new();
+    for line in sdp.lines() {
+        if let Some(rtpmap) = line.strip_prefix("a=rtpmap:") {
+            let mut parts = rtpmap.split_whitespace();
+            if let (Some(pt), Some(name)) = (parts.next(), parts.next()) {
+                let name = name
+                    .split('/')
+                    .next()
+                    .unwrap_or_default()
+                    .to_ascii_uppercase();
+                if matches!(name.as_str(), "VP8" | "VP9" | "H264") {
+                    codecs.insert(pt.to_string(), name);
+                }
+            }
+        } else if let Some(fmtp) = line.strip_prefix("a=fmtp:") {
+            if let Some(pt) = fmtp.split_whitespace().next() {
+                with_fmtp.insert(pt.to_string());
+            }
+        }
+    }
+
+    let mut vp_params = format!("max-fs={}", limit.frame_size());
+    if let Some(fps) = limit.max_framerate {
+        vp_params.push_str(&format!(";max-fr={}", fps));
+    }
+
+    let mut lines = Vec::new();
+    for line in sdp.lines() {
+        if let Some(fmtp) = line.strip_prefix("a=fmtp:") {
+            let (pt, params) = fmtp.split_once(' ').unwrap_or((fmtp, ""));
+            match codecs.get(pt).map(String::as_str) {
+                Some("H264") => {
+                    lines.push(format!("a=fmtp:{} {}", pt, limit_h264_level(params, limit)));
+                }
+                Some(_) => lines.push(format!("a=fmtp:{} {};{}", pt, params, vp_params)),
+                None => lines.push(line.to_string()),
+            }
+            continue;
+        }
+
+        lines.push(line.to_string());
+        if let Some(rtpmap) = line.strip_prefix("a=rtpmap:") {
+            let pt = rtpmap.split_whitespace().next().unwrap_or_default();
+            if !with_fmtp.contains(pt)
+                && matches!(codecs.get(pt).map(String::as_str), Some("VP8" | "VP9"))
+            {
+                lines.push(format!("a=fmtp:{} {}", pt, vp_params));
+            }
+        }
+    }
+
+    let mut munged = lines.join("\r\n");
+    if sdp.ends_with('\n') {
+        munged.push_str("\r\n");
+    }
+    munged
+}
+
+/// Понижает уровень в `profile-level-id` (последний байт) до уровня, вмещающего ограничение
+fn limit_h264_level(params: &str, limit: &VideoResolution) -> String {
+    let max_level = limit.h264_level();
+    params
+        .split(';')
+        .map(|param| match param.split_once('=') {
+            Some(("profile-level-id", id)) if id.len() == 6 => {
+                match u8::from_str_radix(&id[4..], 16) {
+                    Ok(level) if level > max_level => {
+                        format!("profile-level-id={}{:02x}", &id[..4], max_level)
+                    }
+                    _ => param.to_string(),
+                }
+            }
+            _ => param.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::{Peer, PeerMediaOptions};
+    use crate::signaling::{ws_channel, DEFAULT_WS_SEND_QUEUE_SIZE};
+
+    async fn offer_with(codecs: &[&str]) -> String {
+        let (tx, _rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let options = PeerMediaOptions {
+            allowed_codecs: codecs.iter().map(|c| c.to_string()).collect(),
+            ..Default::default()
+        };
+        let client = Peer::new(
+            "cli".to_string(),
+            "Client".to_string(),
+            tx,
+            Some(vec![]),
+            options,
+        )
+        .await
+        .unwrap();
+        client
+            .pc
+            .add_transceiver_from_kind(RTPCodecType::Video, None)
+            .await
+            .unwrap();
+        client.pc.create_offer(None).await.unwrap().sdp
+    }
+
+    #[tokio::test]
+    async fn test_room_codec_allowlist() {
+        let allowed = vec!["H264".to_string()];
+
+        let vp8_offer = offer_with(&["VP8"]).await;
+        assert!(check_offer(&vp8_offer, &allowed).is_err());
+
+        let h264_offer = offer_with(&["H264"]).await;
+        assert!(check_offer(&h264_offer, &allowed).is_ok());
+
+        let (tx, _rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let options = PeerMediaOptions {
+            allowed_codecs: allowed.clone(),
+            ..Default::default()
+        };
+        let server = Peer::new(
+            "srv".to_string(),
+            "Server".to_string(),
+            tx,
+            Some(vec![]),
+            options,
+        )
+        .await
+        .unwrap();
+        let answer = server.handle_offer(h264_offer).await.unwrap();
+        assert!(answer.contains("H264/90000"));
+        assert!(!answer.contains("VP8/90000"));
+    }
+
+    #[tokio::test]
+    async fn test_codec_preferences_order_offer() {
+        let (tx, _rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let options = PeerMediaOptions {
+            codec_preferences: CodecPreferences {
+                video: vec!["H264".to_string(), "VP8".to_string()],
+                audio: Vec::new(),
+            },
+            ..Default::default()
+        };
+        let peer = Peer::new(
+            "srv".to_string(),
+            "Server".to_string(),
+            tx,
+            Some(vec![]),
+            options,
+        )
+        .await
+        .unwrap();
+        peer.pc
+            .add_transceiver_from_kind(RTPCodecType::Video, None)
+            .await
+            .unwrap();
+        let offer = peer.pc.create_offer(None).await.unwrap().sdp;
+
+        // H264 первым, VP9 не предлагается
+        let m_video = offer.lines().find(|l| l.starts_with("m=video")).unwrap();
+        assert_eq!(m_video.split_whitespace().nth(3), Some("102"));
+        assert!(offer.contains("VP8/90000"));
+        assert!(!offer.contains("VP9/90000"));
+        assert_eq!(
+            preferred_mime_type(RTPCodecType::Video, &[], &peer.media_options.codec_preferences),
+            MIME_TYPE_H264
+        );
+
+        // Комната разрешает только VP9: предпочтения не оставляют кодеков и не применяются
+        let preferences = &peer.media_options.codec_preferences;
+        assert_eq!(
+            preferred_mime_type(RTPCodecType::Video, &["VP9".to_string()], preferences),
+            MIME_TYPE_VP9
+        );
+    }
+
+    #[tokio::test]
+    async fn test_answer_limits_video_resolution() {
+        let limit = VideoResolution {
+            width: 1280,
+            height: 720,
+            max_framerate: Some(30),
+        };
+        limit.validate().unwrap();
+
+        let (tx, _rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let options = PeerMediaOptions {
+            max_video_resolution: Some(limit),
+            ..Default::default()
+        };
+        let server = Peer::new(
+            "srv".to_string(),
+            "Server".to_string(),
+            tx,
+            Some(vec![]),
+            options,
+        )
+        .await
+        .unwrap();
+        let answer = server.handle_offer(offer_with(&[]).await).await.unwrap();
+
+        let fmtp = |codec: &str| {
+            let pt = answer
+                .lines()
+                .find_map(|l| {
+                    l.strip_prefix("a=rtpmap:")?
+                        .strip_suffix(&format!(" {}/90000", codec))
+                })
+                .unwrap()
+                .to_string();
+            answer
+                .lines()
+                .find_map(|l| {
+                    l.strip_prefix(&format!("a=fmtp:{} ", pt))
+                        .map(str::to_string)
+                })
+                .unwrap()
+        };
+        assert!(fmtp("VP8").contains("max-fs=3600;max-fr=30"));
+        assert!(fmtp("VP9").contains("max-fs=3600;max-fr=30"));
+        assert!(fmtp("H264").contains("profile-level-id=42001f"));
+        // High profile 5.0 понижается до уровня 3.1
+        assert!(answer.contains("profile-level-id=64001f"));
+        assert!(!answer.contains("profile-level-id=640032"));
+
+        let too_large = VideoResolution {
+            width: 10_000,
+            height: 720,
+            max_framerate: None,
+        };
+        assert!(too_large.validate().is_err());
+    }
+}
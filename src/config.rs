@@ -1,8 +1,25 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::env;
 use std::fs;
+use std::net::IpAddr;
 use std::path::Path;
+use std::time::{Duration, SystemTime};
+use crate::bitrate::BitrateAggregation;
+use crate::codecs::{CodecPreferences, VideoResolution};
+use crate::ice::{validate_candidate_preferences, HostCandidateFilter, IceCandidatePreference};
+use crate::logging::LogFormat;
+use crate::outbound::{ClassWeights, DropPolicy};
+use crate::messages;
+use crate::resources::ResourceLimits;
+use crate::signaling::DEFAULT_WS_SEND_QUEUE_SIZE;
+use crate::turn::{is_turn_url, TurnCredentials};
+use crate::peer::{
+    DEFAULT_MAX_AUDIO_TRANSCEIVERS, DEFAULT_MAX_VIDEO_TRANSCEIVERS, DEFAULT_OUTBOUND_QUEUE_SIZE,
+    MAX_TRANSCEIVERS_PER_KIND,
+};
+use webrtc::ice::url::Url;
 use webrtc::ice_transport::ice_credential_type::RTCIceCredentialType;
 use webrtc::ice_transport::ice_server::RTCIceServer;
 
@@ -32,6 +49,36 @@ impl IceServerConfig {
             },
         }
     }
+
+    /// Проверяет URL сервера тем же разбором, что и ICE агент. TURN без статических
+    /// учетных данных допустим только с `turn_secret` (временные выдаются при входе)
+    pub fn validate(&self, has_turn_secret: bool) -> Result<()> {
+        if self.urls.is_empty() {
+            anyhow::bail!("ICE server must have at least one URL");
+        }
+        let has_credentials = self.username.as_deref().is_some_and(|u| !u.is_empty())
+            && self.credential.as_deref().is_some_and(|c| !c.is_empty());
+        for url in &self.urls {
+            Url::parse_url(url)
+                .map_err(|e| anyhow::anyhow!("Invalid ICE server URL {}: {}", url, e))?;
+            if is_turn_url(url) && !has_credentials && !has_turn_secret {
+                anyhow::bail!(
+                    "Invalid ICE server URL {}: TURN requires username and credential",
+                    url
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Конфигурация для передачи клиенту в `Joined`
+    pub fn to_client(&self) -> messages::IceServerConfig {
+        messages::IceServerConfig {
+            urls: self.urls.clone(),
+            username: self.username.clone(),
+            credential: self.credential.clone(),
+        }
+    }
 }
 
 /// Основная конфигурация SFU сервера
@@ -45,15 +92,55 @@ pub struct ServerConfig {
     #[serde(default = "default_listen_address")]
     pub listen_address: String,
 
+    /// Порт HTTP сервера с `/metrics` для Prometheus; если не задан, метрики не отдаются
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_port: Option<u16>,
+
     /// ICE серверы (STUN/TURN)
     #[serde(default = "default_ice_servers")]
     pub ice_servers: Vec<IceServerConfig>,
 
+    /// Общий с TURN сервером секрет (`static-auth-secret` в coturn). Если задан, каждому
+    /// участнику выдаются временные учетные данные TURN вместо статических из `ice_servers`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub turn_secret: Option<String>,
+
+    /// Время жизни временных учетных данных TURN (секунды)
+    #[serde(default = "default_turn_credential_ttl")]
+    pub turn_credential_ttl_secs: u64,
+
+    /// Предпочтения для приоритетов локальных ICE кандидатов (по убыванию)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ice_candidate_preferences: Vec<IceCandidatePreference>,
+
+    /// Нижняя граница диапазона UDP портов для медиа (ICE)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ice_udp_port_min: Option<u16>,
+
+    /// Верхняя граница диапазона UDP портов для медиа (ICE), включительно
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ice_udp_port_max: Option<u16>,
+
+    /// Объявлять host кандидатами частные адреса интерфейсов; за балансировщиком
+    /// с публичным srflx адресом их можно выключить
+    #[serde(default = "default_true")]
+    pub ice_include_private_ips: bool,
+
+    /// Объявлять host кандидатами link-local адреса (169.254/16, fe80::/10)
+    #[serde(default)]
+    pub ice_include_link_local: bool,
+
+    /// Публичный IP при 1:1 NAT (облачная VM с частным адресом): объявляется
+    /// server reflexive кандидатом с портом локального сокета
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_ip: Option<IpAddr>,
+
     /// Максимальное количество участников в комнате
     #[serde(default = "default_max_participants")]
     pub max_participants_per_room: usize,
 
-    /// Таймаут для неактивных соединений (секунды)
+    /// Через сколько секунд без сообщений и медиа от участника соединение закрывается;
+    /// 0 - не ограничивать
     #[serde(default = "default_connection_timeout")]
     pub connection_timeout_secs: u64,
 
@@ -61,10 +148,156 @@ pub struct ServerConfig {
     #[serde(default = "default_verbose_logging")]
     pub verbose_logging: bool,
 
+    /// Формат логов: text или json (для систем сбора логов)
+    #[serde(default)]
+    pub log_format: LogFormat,
+
     /// Интервал очистки пустых комнат (секунды)
     #[serde(default = "default_cleanup_interval")]
     pub cleanup_interval_secs: u64,
 
+    /// Сколько при остановке сервера ждать отправки уже поставленных в очередь
+    /// пакетов и закрытия соединений (секунды)
+    #[serde(default = "default_shutdown_grace")]
+    pub shutdown_grace_secs: u64,
+
+    /// Уведомлять клиента об изменениях состояния его соединения (PC/ICE)
+    #[serde(default = "default_true")]
+    pub connection_state_notifications: bool,
+
+    /// Окно подавления дребезга уведомлений о состоянии соединения (миллисекунды)
+    #[serde(default = "default_connection_state_debounce")]
+    pub connection_state_debounce_ms: u64,
+
+    /// Сколько новых WebSocket соединений принимается с одного IP за
+    /// `connection_rate_window_secs`; 0 - не ограничивать
+    #[serde(default = "default_connection_rate_per_ip")]
+    pub connection_rate_per_ip: u64,
+
+    /// Окно лимита новых соединений с одного IP (секунды)
+    #[serde(default = "default_connection_rate_window")]
+    pub connection_rate_window_secs: u64,
+
+    /// Максимальное количество одновременных DTLS handshake; остальные ждут в очереди
+    #[serde(default = "default_max_concurrent_handshakes")]
+    pub max_concurrent_handshakes: usize,
+
+    /// Сколько неразбираемых сообщений допускается от клиента за соединение;
+    /// следующее закрывает соединение (0 - не ограничивать)
+    #[serde(default = "default_max_malformed_messages")]
+    pub max_malformed_messages: u32,
+
+    /// За сколько секунд после входа клиент должен прислать offer; 0 - не ограничивать
+    #[serde(default = "default_offer_timeout")]
+    pub offer_timeout_secs: u64,
+
+    /// Как часто сервер отправляет клиенту WebSocket ping (секунды); клиент, не ответивший
+    /// до следующего ping, считается отключенным. 0 - не отправлять
+    #[serde(default = "default_ws_ping_interval")]
+    pub ws_ping_interval_secs: u64,
+
+    /// Сколько хранить ICE кандидаты клиента, пришедшие раньше offer (миллисекунды)
+    #[serde(default = "default_candidate_grace")]
+    pub candidate_grace_ms: u64,
+
+    /// Согласовывать RTP расширение frame-marking для точного переключения слоев
+    #[serde(default = "default_true")]
+    pub frame_marking: bool,
+
+    /// Видео и аудио кодеки сервера в порядке приоритета (например, `["H264", "VP8"]`);
+    /// не перечисленные кодеки не согласуются, пустой список - все по умолчанию
+    #[serde(default)]
+    pub codecs: CodecPreferences,
+
+    /// Размер очереди исходящих пакетов участника
+    #[serde(default = "default_outbound_queue_size")]
+    pub outbound_queue_size: usize,
+
+    /// Что отбрасывать при переполнении очереди исходящих пакетов
+    #[serde(default)]
+    pub outbound_drop_policy: DropPolicy,
+
+    /// Размер очереди сигнальных сообщений клиенту: при заполнении отбрасываются
+    /// обновления состояния, а клиент, не принявший критичное сообщение, отключается
+    #[serde(default = "default_ws_send_queue_size")]
+    pub ws_send_queue_size: usize,
+
+    /// Веса классов исходящего трафика: за цикл отправки каждый класс отправляет
+    /// не больше своего веса пакетов, аудио обслуживается первым
+    #[serde(default)]
+    pub outbound_class_weights: ClassWeights,
+
+    /// Максимум видео треков, отправляемых участнику (несколько камер, картинка в картинке).
+    /// Фактическое число определяется offer клиента
+    #[serde(default = "default_max_video_transceivers")]
+    pub max_video_transceivers: usize,
+
+    /// Максимум аудио треков, отправляемых участнику
+    #[serde(default = "default_max_audio_transceivers")]
+    pub max_audio_transceivers: usize,
+
+    /// Ограничивать общее число участников по памяти и CPU хоста
+    #[serde(default)]
+    pub dynamic_participant_limit: bool,
+
+    /// Сколько памяти закладывается на одного участника (МиБ)
+    #[serde(default = "default_memory_per_participant")]
+    pub memory_per_participant_mb: u64,
+
+    /// Сколько участников приходится на одно ядро CPU
+    #[serde(default = "default_participants_per_cpu")]
+    pub participants_per_cpu: usize,
+
+    /// Порог занятой памяти хоста (%), выше которого новые участники не принимаются
+    #[serde(default = "default_memory_watermark")]
+    pub memory_watermark_percent: u8,
+
+    /// Время жизни одноразового токена переподключения (секунды)
+    #[serde(default = "default_reconnect_token_lifetime")]
+    pub reconnect_token_lifetime_secs: u64,
+
+    /// Как часто отправлять статистику комнаты подписчикам `watch_stats` (секунды)
+    #[serde(default = "default_stats_interval")]
+    pub stats_interval_secs: u64,
+
+    /// TWCC оценка канала к участнику (кбит/с), ниже которой ему пересылаются
+    /// нижние simulcast слои
+    #[serde(default = "default_bandwidth_down")]
+    pub bandwidth_down_kbps: u64,
+
+    /// TWCC оценка (кбит/с), выше которой перегруженный участник снова получает
+    /// выбранные слои; больше `bandwidth_down_kbps`, чтобы слои не переключались туда-обратно
+    #[serde(default = "default_bandwidth_up")]
+    pub bandwidth_up_kbps: u64,
+
+    /// Сколько ICE restart подряд сервер делает при обрыве соединения участника,
+    /// прежде чем сдаться (0 - не перезапускать)
+    #[serde(default = "default_max_ice_restarts")]
+    pub max_ice_restarts: u32,
+
+    /// Настройки по умолчанию для новых комнат
+    #[serde(default)]
+    pub room_defaults: RoomConfig,
+
+    /// Каталог с настройками отдельных комнат (`<id>.toml` или `<id>.json`),
+    /// которые накладываются на `room_defaults`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room_config_dir: Option<String>,
+
+    /// Каталог для JSON сводок закрытых комнат; если не задан, сводки не пишутся
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room_archive_dir: Option<String>,
+
+    /// Каталог записей комнат (`recording_enabled` и `set_recording`); без него
+    /// запись недоступна
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recording_dir: Option<String>,
+
+    /// Токен администратора для служебных запросов (диагностика).
+    /// Если не задан, служебные запросы отклоняются
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_token: Option<String>,
+
     /// Поддержка TLS (для будущего использования)
     #[serde(default)]
     pub tls_enabled: bool,
@@ -111,6 +344,10 @@ fn default_ice_servers() -> Vec<IceServerConfig> {
     ]
 }
 
+fn default_turn_credential_ttl() -> u64 {
+    86_400 // сутки
+}
+
 fn default_max_participants() -> usize {
     50
 }
@@ -127,16 +364,142 @@ fn default_cleanup_interval() -> u64 {
     60 // 1 минута
 }
 
+fn default_shutdown_grace() -> u64 {
+    10
+}
+
+fn default_connection_state_debounce() -> u64 {
+    500
+}
+
+fn default_connection_rate_per_ip() -> u64 {
+    10
+}
+
+fn default_connection_rate_window() -> u64 {
+    10
+}
+
+fn default_max_concurrent_handshakes() -> usize {
+    16
+}
+
+fn default_max_malformed_messages() -> u32 {
+    10
+}
+
+fn default_offer_timeout() -> u64 {
+    30
+}
+
+fn default_ws_ping_interval() -> u64 {
+    20
+}
+
+fn default_candidate_grace() -> u64 {
+    10_000
+}
+
+fn default_memory_per_participant() -> u64 {
+    64
+}
+
+fn default_participants_per_cpu() -> usize {
+    25
+}
+
+fn default_memory_watermark() -> u8 {
+    90
+}
+
+fn default_reconnect_token_lifetime() -> u64 {
+    30
+}
+
+fn default_stats_interval() -> u64 {
+    5
+}
+
+fn default_max_ice_restarts() -> u32 {
+    3
+}
+
+fn default_bandwidth_down() -> u64 {
+    500
+}
+
+fn default_bandwidth_up() -> u64 {
+    1000
+}
+
+fn default_outbound_queue_size() -> usize {
+    DEFAULT_OUTBOUND_QUEUE_SIZE
+}
+
+fn default_ws_send_queue_size() -> usize {
+    DEFAULT_WS_SEND_QUEUE_SIZE
+}
+
+fn default_max_video_transceivers() -> usize {
+    DEFAULT_MAX_VIDEO_TRANSCEIVERS
+}
+
+fn default_max_audio_transceivers() -> usize {
+    DEFAULT_MAX_AUDIO_TRANSCEIVERS
+}
+
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
             signaling_port: default_signaling_port(),
             listen_address: default_listen_address(),
+            metrics_port: None,
             ice_servers: default_ice_servers(),
+            turn_secret: None,
+            turn_credential_ttl_secs: default_turn_credential_ttl(),
+            ice_candidate_preferences: Vec::new(),
+            ice_udp_port_min: None,
+            ice_udp_port_max: None,
+            ice_include_private_ips: true,
+            ice_include_link_local: false,
+            public_ip: None,
             max_participants_per_room: default_max_participants(),
             connection_timeout_secs: default_connection_timeout(),
             verbose_logging: default_verbose_logging(),
+            log_format: LogFormat::default(),
             cleanup_interval_secs: default_cleanup_interval(),
+            shutdown_grace_secs: default_shutdown_grace(),
+            connection_state_notifications: true,
+            connection_state_debounce_ms: default_connection_state_debounce(),
+            connection_rate_per_ip: default_connection_rate_per_ip(),
+            connection_rate_window_secs: default_connection_rate_window(),
+            max_concurrent_handshakes: default_max_concurrent_handshakes(),
+            max_malformed_messages: default_max_malformed_messages(),
+            offer_timeout_secs: default_offer_timeout(),
+            ws_ping_interval_secs: default_ws_ping_interval(),
+            candidate_grace_ms: default_candidate_grace(),
+            frame_marking: true,
+            codecs: CodecPreferences::default(),
+            outbound_queue_size: default_outbound_queue_size(),
+            ws_send_queue_size: default_ws_send_queue_size(),
+            outbound_drop_policy: DropPolicy::default(),
+            outbound_class_weights: ClassWeights::default(),
+            max_video_transceivers: default_max_video_transceivers(),
+            max_audio_transceivers: default_max_audio_transceivers(),
+            dynamic_participant_limit: false,
+            memory_per_participant_mb: default_memory_per_participant(),
+            participants_per_cpu: default_participants_per_cpu(),
+            memory_watermark_percent: default_memory_watermark(),
+            reconnect_token_lifetime_secs: default_reconnect_token_lifetime(),
+            stats_interval_secs: default_stats_interval(),
+            bandwidth_down_kbps: default_bandwidth_down(),
+            bandwidth_up_kbps: default_bandwidth_up(),
+            max_ice_restarts: default_max_ice_restarts(),
+            room_defaults: RoomConfig::default(),
+            room_config_dir: None,
+            room_archive_dir: None,
+            recording_dir: None,
+            admin_token: None,
             tls_enabled: false,
             tls_cert_path: None,
             tls_key_path: None,
@@ -150,11 +513,24 @@ impl ServerConfig {
         let content = fs::read_to_string(path.as_ref())
             .context("Failed to read config file")?;
 
-        let config: ServerConfig = toml::from_str(&content)
-            .or_else(|_| serde_json::from_str(&content))
-            .context("Failed to parse config file (tried TOML and JSON)")?;
+        if let Some(format) = ConfigFormat::from_path(path.as_ref()) {
+            return format
+                .parse(&content)
+                .with_context(|| format!("Failed to parse config file as {}", format.name()));
+        }
 
-        Ok(config)
+        // Расширение неизвестно: пробуем все форматы по очереди
+        let mut errors = Vec::new();
+        for format in [ConfigFormat::Toml, ConfigFormat::Json, ConfigFormat::Yaml] {
+            match format.parse(&content) {
+                Ok(config) => return Ok(config),
+                Err(e) => errors.push(format!("{}: {}", format.name(), e)),
+            }
+        }
+        anyhow::bail!(
+            "Failed to parse config file (tried TOML, JSON and YAML): {}",
+            errors.join("; ")
+        )
     }
 
     /// Загружает конфигурацию из переменных окружения
@@ -165,6 +541,10 @@ impl ServerConfig {
             config.signaling_port = port.parse().context("Invalid SIGNALING_PORT")?;
         }
 
+        if let Ok(port) = env::var("METRICS_PORT") {
+            config.metrics_port = Some(port.parse().context("Invalid METRICS_PORT")?);
+        }
+
         if let Ok(addr) = env::var("LISTEN_ADDRESS") {
             config.listen_address = addr;
         }
@@ -179,6 +559,147 @@ impl ServerConfig {
             config.verbose_logging = verbose.parse().unwrap_or(false);
         }
 
+        if let Ok(format) = env::var("LOG_FORMAT") {
+            config.log_format = format
+                .parse()
+                .map_err(|e: String| anyhow::anyhow!(e))
+                .context("Invalid LOG_FORMAT")?;
+        }
+
+        if let Ok(notify) = env::var("CONNECTION_STATE_NOTIFICATIONS") {
+            config.connection_state_notifications = notify.parse().unwrap_or(true);
+        }
+
+        if let Ok(debounce) = env::var("CONNECTION_STATE_DEBOUNCE_MS") {
+            config.connection_state_debounce_ms = debounce
+                .parse()
+                .context("Invalid CONNECTION_STATE_DEBOUNCE_MS")?;
+        }
+
+        if let Ok(port) = env::var("ICE_UDP_PORT_MIN") {
+            config.ice_udp_port_min = Some(port.parse().context("Invalid ICE_UDP_PORT_MIN")?);
+        }
+
+        if let Ok(port) = env::var("ICE_UDP_PORT_MAX") {
+            config.ice_udp_port_max = Some(port.parse().context("Invalid ICE_UDP_PORT_MAX")?);
+        }
+
+        if let Ok(include) = env::var("ICE_INCLUDE_PRIVATE_IPS") {
+            config.ice_include_private_ips =
+                include.parse().context("Invalid ICE_INCLUDE_PRIVATE_IPS")?;
+        }
+
+        if let Ok(ip) = env::var("PUBLIC_IP") {
+            config.public_ip = Some(ip.parse().context("Invalid PUBLIC_IP")?);
+        }
+
+        if let Ok(include) = env::var("ICE_INCLUDE_LINK_LOCAL") {
+            config.ice_include_link_local =
+                include.parse().context("Invalid ICE_INCLUDE_LINK_LOCAL")?;
+        }
+
+        if let Ok(rate) = env::var("CONNECTION_RATE_PER_IP") {
+            config.connection_rate_per_ip = rate.parse().context("Invalid CONNECTION_RATE_PER_IP")?;
+        }
+
+        if let Ok(window) = env::var("CONNECTION_RATE_WINDOW_SECS") {
+            config.connection_rate_window_secs = window
+                .parse()
+                .context("Invalid CONNECTION_RATE_WINDOW_SECS")?;
+        }
+
+        if let Ok(max) = env::var("MAX_CONCURRENT_HANDSHAKES") {
+            config.max_concurrent_handshakes =
+                max.parse().context("Invalid MAX_CONCURRENT_HANDSHAKES")?;
+        }
+
+        if let Ok(max) = env::var("MAX_MALFORMED_MESSAGES") {
+            config.max_malformed_messages = max.parse().context("Invalid MAX_MALFORMED_MESSAGES")?;
+        }
+
+        if let Ok(timeout) = env::var("OFFER_TIMEOUT_SECS") {
+            config.offer_timeout_secs = timeout.parse().context("Invalid OFFER_TIMEOUT_SECS")?;
+        }
+
+        if let Ok(interval) = env::var("WS_PING_INTERVAL_SECS") {
+            config.ws_ping_interval_secs =
+                interval.parse().context("Invalid WS_PING_INTERVAL_SECS")?;
+        }
+
+        if let Ok(grace) = env::var("SHUTDOWN_GRACE_SECS") {
+            config.shutdown_grace_secs = grace.parse().context("Invalid SHUTDOWN_GRACE_SECS")?;
+        }
+
+        if let Ok(grace) = env::var("CANDIDATE_GRACE_MS") {
+            config.candidate_grace_ms = grace.parse().context("Invalid CANDIDATE_GRACE_MS")?;
+        }
+
+        let codec_list = |list: String| -> Vec<String> {
+            list.split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect()
+        };
+        if let Ok(codecs) = env::var("VIDEO_CODECS") {
+            config.codecs.video = codec_list(codecs);
+        }
+        if let Ok(codecs) = env::var("AUDIO_CODECS") {
+            config.codecs.audio = codec_list(codecs);
+        }
+
+        if let Ok(size) = env::var("OUTBOUND_QUEUE_SIZE") {
+            config.outbound_queue_size = size.parse().context("Invalid OUTBOUND_QUEUE_SIZE")?;
+        }
+
+        if let Ok(size) = env::var("WS_SEND_QUEUE_SIZE") {
+            config.ws_send_queue_size = size.parse().context("Invalid WS_SEND_QUEUE_SIZE")?;
+        }
+
+        if let Ok(max) = env::var("MAX_VIDEO_TRANSCEIVERS") {
+            config.max_video_transceivers =
+                max.parse().context("Invalid MAX_VIDEO_TRANSCEIVERS")?;
+        }
+
+        if let Ok(max) = env::var("MAX_AUDIO_TRANSCEIVERS") {
+            config.max_audio_transceivers =
+                max.parse().context("Invalid MAX_AUDIO_TRANSCEIVERS")?;
+        }
+
+        if let Ok(enabled) = env::var("DYNAMIC_PARTICIPANT_LIMIT") {
+            config.dynamic_participant_limit = enabled
+                .parse()
+                .context("Invalid DYNAMIC_PARTICIPANT_LIMIT")?;
+        }
+
+        if let Ok(lifetime) = env::var("RECONNECT_TOKEN_LIFETIME_SECS") {
+            config.reconnect_token_lifetime_secs = lifetime
+                .parse()
+                .context("Invalid RECONNECT_TOKEN_LIFETIME_SECS")?;
+        }
+
+        if let Ok(interval) = env::var("STATS_INTERVAL_SECS") {
+            config.stats_interval_secs = interval.parse().context("Invalid STATS_INTERVAL_SECS")?;
+        }
+
+        if let Ok(down) = env::var("BANDWIDTH_DOWN_KBPS") {
+            config.bandwidth_down_kbps = down.parse().context("Invalid BANDWIDTH_DOWN_KBPS")?;
+        }
+
+        if let Ok(up) = env::var("BANDWIDTH_UP_KBPS") {
+            config.bandwidth_up_kbps = up.parse().context("Invalid BANDWIDTH_UP_KBPS")?;
+        }
+
+        if let Ok(restarts) = env::var("MAX_ICE_RESTARTS") {
+            config.max_ice_restarts = restarts.parse().context("Invalid MAX_ICE_RESTARTS")?;
+        }
+
+        if let Ok(policy) = env::var("OUTBOUND_DROP_POLICY") {
+            config.outbound_drop_policy = policy
+                .parse()
+                .map_err(|e: String| anyhow::anyhow!(e))
+                .context("Invalid OUTBOUND_DROP_POLICY")?;
+        }
+
         // Загрузка TURN конфигурации из переменных окружения
         if let Ok(turn_url) = env::var("TURN_URL") {
             let username = env::var("TURN_USERNAME").ok();
@@ -211,6 +732,18 @@ impl ServerConfig {
             }
         }
 
+        config.turn_secret = env::var("TURN_SECRET").ok();
+
+        if let Ok(ttl) = env::var("TURN_CREDENTIAL_TTL_SECS") {
+            config.turn_credential_ttl_secs =
+                ttl.parse().context("Invalid TURN_CREDENTIAL_TTL_SECS")?;
+        }
+
+        config.admin_token = env::var("ADMIN_TOKEN").ok();
+        config.room_config_dir = env::var("ROOM_CONFIG_DIR").ok();
+        config.room_archive_dir = env::var("ROOM_ARCHIVE_DIR").ok();
+        config.recording_dir = env::var("RECORDING_DIR").ok();
+
         // TLS настройки
         if let Ok(tls_enabled) = env::var("TLS_ENABLED") {
             config.tls_enabled = tls_enabled.parse().unwrap_or(false);
@@ -244,6 +777,7 @@ impl ServerConfig {
     }
 
     /// Возвращает RTCIceServer конфигурацию для WebRTC
+    #[allow(dead_code)]
     pub fn get_rtc_ice_servers(&self) -> Vec<RTCIceServer> {
         self.ice_servers
             .iter()
@@ -251,12 +785,42 @@ impl ServerConfig {
             .collect()
     }
 
+    /// ICE серверы для участника `session_id`: при заданном `turn_secret` TURN серверы
+    /// получают временные учетные данные на `turn_credential_ttl_secs`
+    pub fn ice_servers_for(&self, session_id: &str, now: SystemTime) -> Vec<IceServerConfig> {
+        let Some(secret) = &self.turn_secret else {
+            return self.ice_servers.clone();
+        };
+
+        let credentials = TurnCredentials::generate(
+            secret,
+            session_id,
+            Duration::from_secs(self.turn_credential_ttl_secs),
+            now,
+        );
+        self.ice_servers
+            .iter()
+            .cloned()
+            .map(|mut server| {
+                if server.urls.iter().any(|url| is_turn_url(url)) {
+                    server.username = Some(credentials.username.clone());
+                    server.credential = Some(credentials.credential.clone());
+                }
+                server
+            })
+            .collect()
+    }
+
     /// Валидация конфигурации
     pub fn validate(&self) -> Result<()> {
         if self.signaling_port == 0 {
             anyhow::bail!("Signaling port cannot be 0");
         }
 
+        if self.metrics_port == Some(0) || self.metrics_port == Some(self.signaling_port) {
+            anyhow::bail!("Metrics port must be non-zero and differ from the signaling port");
+        }
+
         if self.max_participants_per_room == 0 {
             anyhow::bail!("Max participants per room must be greater than 0");
         }
@@ -265,21 +829,143 @@ impl ServerConfig {
             anyhow::bail!("At least one ICE server must be configured");
         }
 
-        if self.tls_enabled {
-            if self.tls_cert_path.is_none() || self.tls_key_path.is_none() {
-                anyhow::bail!("TLS enabled but cert/key paths not provided");
+        for server in &self.ice_servers {
+            server.validate(self.turn_secret.is_some())?;
+        }
+
+        if self.turn_secret.as_deref() == Some("") {
+            anyhow::bail!("TURN secret cannot be empty");
+        }
+
+        if self.turn_credential_ttl_secs == 0 {
+            anyhow::bail!("TURN credential TTL must be greater than 0");
+        }
+
+        if let Some(ip) = self.public_ip {
+            if ip.is_unspecified() || ip.is_loopback() || ip.is_multicast() {
+                anyhow::bail!("Public IP {} cannot be advertised to clients", ip);
+            }
+        }
+
+        match (self.ice_udp_port_min, self.ice_udp_port_max) {
+            (None, None) => {}
+            (Some(min), Some(max)) => {
+                if min == 0 || min > max {
+                    anyhow::bail!("Invalid ICE UDP port range {}-{}", min, max);
+                }
+                // Каждому участнику нужен хотя бы один порт
+                let size = usize::from(max - min) + 1;
+                if size < self.max_participants_per_room {
+                    anyhow::bail!(
+                        "ICE UDP port range {}-{} has {} ports, need at least {} (max participants per room)",
+                        min,
+                        max,
+                        size,
+                        self.max_participants_per_room
+                    );
+                }
+            }
+            _ => anyhow::bail!("Both ice_udp_port_min and ice_udp_port_max must be set"),
+        }
+
+        if self.cleanup_interval_secs == 0 {
+            anyhow::bail!("Cleanup interval must be greater than 0");
+        }
+
+        if self.connection_rate_per_ip > 0 && self.connection_rate_window_secs == 0 {
+            anyhow::bail!("Connection rate window must be greater than 0");
+        }
+
+        if self.max_concurrent_handshakes == 0 {
+            anyhow::bail!("Max concurrent handshakes must be greater than 0");
+        }
+
+        validate_candidate_preferences(&self.ice_candidate_preferences)?;
+        self.codecs.validate()?;
+
+        if self.outbound_queue_size == 0 {
+            anyhow::bail!("Outbound queue size must be greater than 0");
+        }
+
+        if self.ws_send_queue_size == 0 {
+            anyhow::bail!("WebSocket send queue size must be greater than 0");
+        }
+
+        self.outbound_class_weights.validate()?;
+
+        if self.memory_per_participant_mb == 0 || self.participants_per_cpu == 0 {
+            anyhow::bail!("Per-participant resource budget must be greater than 0");
+        }
+
+        if !(1..=100).contains(&self.memory_watermark_percent) {
+            anyhow::bail!("Memory watermark must be between 1 and 100 percent");
+        }
+
+        if self.reconnect_token_lifetime_secs == 0 {
+            anyhow::bail!("Reconnect token lifetime must be greater than 0");
+        }
+
+        if self.stats_interval_secs == 0 {
+            anyhow::bail!("Stats interval must be greater than 0");
+        }
+
+        if self.bandwidth_down_kbps == 0 || self.bandwidth_up_kbps <= self.bandwidth_down_kbps {
+            anyhow::bail!(
+                "Bandwidth thresholds must satisfy 0 < bandwidth_down_kbps < bandwidth_up_kbps"
+            );
+        }
+
+        for (name, max) in [
+            ("video", self.max_video_transceivers),
+            ("audio", self.max_audio_transceivers),
+        ] {
+            if max == 0 || max > MAX_TRANSCEIVERS_PER_KIND {
+                anyhow::bail!(
+                    "Max {} transceivers must be between 1 and {}",
+                    name,
+                    MAX_TRANSCEIVERS_PER_KIND
+                );
             }
         }
 
+        self.room_defaults.validate()?;
+
+        if self.tls_enabled && (self.tls_cert_path.is_none() || self.tls_key_path.is_none()) {
+            anyhow::bail!("TLS enabled but cert/key paths not provided");
+        }
+
         Ok(())
     }
 
+    /// Фильтр адресов для host кандидатов
+    pub fn host_candidate_filter(&self) -> HostCandidateFilter {
+        HostCandidateFilter {
+            include_private: self.ice_include_private_ips,
+            include_link_local: self.ice_include_link_local,
+        }
+    }
+
+    /// Диапазон UDP портов ICE, если задан
+    pub fn ice_udp_port_range(&self) -> Option<(u16, u16)> {
+        self.ice_udp_port_min.zip(self.ice_udp_port_max)
+    }
+
+    /// Параметры динамического лимита участников, если он включен
+    pub fn resource_limits(&self) -> Option<ResourceLimits> {
+        self.dynamic_participant_limit.then(|| ResourceLimits {
+            memory_per_participant_bytes: self.memory_per_participant_mb * 1024 * 1024,
+            participants_per_cpu: self.participants_per_cpu,
+            memory_watermark_percent: self.memory_watermark_percent,
+        })
+    }
+
     /// Сохраняет конфигурацию в файл
+    #[allow(dead_code)]
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let content = if path.as_ref().extension().and_then(|s| s.to_str()) == Some("json") {
-            serde_json::to_string_pretty(self)?
-        } else {
-            toml::to_string_pretty(self)?
+        let content = match ConfigFormat::from_path(path.as_ref()) {
+            Some(ConfigFormat::Json) => serde_json::to_string_pretty(self)?,
+            Some(ConfigFormat::Yaml) => serde_yaml::to_string(self)?,
+            Some(ConfigFormat::Toml) | None => toml::to_string_pretty(self)?,
         };
 
         fs::write(path, content)?;
@@ -287,9 +973,58 @@ impl ServerConfig {
     }
 }
 
+/// Формат файла конфигурации сервера
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfigFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Формат по расширению файла; `None`, если расширение неизвестно
+    fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "toml" => Some(Self::Toml),
+            "json" => Some(Self::Json),
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Toml => "TOML",
+            Self::Json => "JSON",
+            Self::Yaml => "YAML",
+        }
+    }
+
+    fn parse(self, content: &str) -> Result<ServerConfig> {
+        Ok(match self {
+            Self::Toml => toml::from_str(content)?,
+            Self::Json => serde_json::from_str(content)?,
+            Self::Yaml => serde_yaml::from_str(content)?,
+        })
+    }
+}
+
+/// Как новые участники попадают в комнату
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Admission {
+    /// Входит любой, кто прошел проверку пароля
+    #[default]
+    Open,
+    /// Ведущий допускает каждого нового участника (`join_request` / `admit`)
+    Manual,
+}
+
 /// Конфигурация для конкретной комнаты (расширенная)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RoomConfig {
+    #[serde(default)]
     pub id: String,
 
     /// Максимальное количество участников в этой комнате
@@ -307,12 +1042,223 @@ pub struct RoomConfig {
     /// Запись включена
     #[serde(default)]
     pub recording_enabled: bool,
+
+    /// Минимальное количество участников, после которого начинается пересылка медиа
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_participants_to_start: Option<usize>,
+
+    /// Приостанавливать пересылку, если участников снова стало меньше минимума
+    #[serde(default)]
+    pub pause_below_min_participants: bool,
+
+    /// Переключать simulcast слои только на ключевых кадрах
+    #[serde(default = "default_true")]
+    pub layer_switch_keyframe_only: bool,
+
+    /// Не пересылать участнику треки с его собственными SSRC/stream id
+    #[serde(default = "default_true")]
+    pub self_echo_suppression: bool,
+
+    /// Шумовой порог в -dBov (0..=127): аудио тише порога не пересылается.
+    /// Например, 60 пропускает только пакеты громче -60 dBov
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub noise_gate_threshold_dbov: Option<u8>,
+
+    /// Сколько порог остается открытым после громкого пакета (миллисекунды)
+    #[serde(default = "default_noise_gate_hangover")]
+    pub noise_gate_hangover_ms: u64,
+
+    /// Разрешить получателям отказываться от Opus DTX пакетов (`set_skip_silence`):
+    /// пакеты тишины им не пересылаются, начало речи пересылается всегда
+    #[serde(default)]
+    pub dtx_suppression: bool,
+
+    /// Глубина буфера переупорядочивания входящих RTP пакетов (номеров): пакеты
+    /// пересылаются по порядку sequence number ценой задержки; `None` - без буфера
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reorder_buffer_depth: Option<usize>,
+
+    /// Сколько буфер переупорядочивания ждет недостающий пакет (миллисекунды)
+    #[serde(default = "default_reorder_max_hold")]
+    pub reorder_max_hold_ms: u64,
+
+    /// Порог активного говорящего в -dBov (0..=127): самый громкий участник, чей
+    /// средний уровень громче порога, объявляется говорящим; `None` - не определять
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_speaker_threshold_dbov: Option<u8>,
+
+    /// Окно усреднения уровня звука для активного говорящего (миллисекунды)
+    #[serde(default = "default_active_speaker_window")]
+    pub active_speaker_window_ms: u64,
+
+    /// Группы участников для аудио подписок: имя группы -> id участников
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub audio_groups: HashMap<String, Vec<String>>,
+
+    /// Группа для участников, не перечисленных в `audio_groups`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_audio_group: Option<String>,
+
+    /// Чье аудио слышит группа: группа получателя -> группы источников.
+    /// Группа без записи слышит всех
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub audio_subscriptions: HashMap<String, Vec<String>>,
+
+    /// Порог частоты входящих пакетов трека (пакетов/с), выше которого трек
+    /// отключается сервером до решения ведущего; `None` - без ограничения
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flood_mute_max_pps: Option<u32>,
+
+    /// Сколько секунд частота должна держаться выше порога до отключения
+    #[serde(default = "default_flood_mute_sustain")]
+    pub flood_mute_sustain_secs: u64,
+
+    /// Обратная связь издателям о пропускной способности получателей (REMB):
+    /// off, min или percentile
+    #[serde(default)]
+    pub bitrate_feedback: BitrateAggregation,
+
+    /// Перцентиль оценок получателей для политики percentile (0..=100)
+    #[serde(default = "default_bitrate_feedback_percentile")]
+    pub bitrate_feedback_percentile: u8,
+
+    /// Разрешенные кодеки ("opus", "VP8", "H264", ...); пусто - все поддерживаемые
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_codecs: Vec<String>,
+
+    /// Максимальное разрешение видео издателей; ограничение передается в answer SDP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_video_resolution: Option<VideoResolution>,
+
+    /// Сколько последних событий (вход, выход, демонстрация экрана, удаление)
+    /// хранит журнал комнаты; 0 - журнал не ведется
+    #[serde(default = "default_event_log_size")]
+    pub event_log_size: usize,
+
+    /// Допуск новых участников: open или manual (через ведущего)
+    #[serde(default)]
+    pub admission: Admission,
+
+    /// Сколько секунд участник ждет решения ведущего при ручном допуске
+    #[serde(default = "default_admission_timeout")]
+    pub admission_timeout_secs: u64,
 }
 
 fn default_true() -> bool {
     true
 }
 
+fn default_flood_mute_sustain() -> u64 {
+    5
+}
+
+fn default_bitrate_feedback_percentile() -> u8 {
+    20
+}
+
+fn default_noise_gate_hangover() -> u64 {
+    300
+}
+
+fn default_reorder_max_hold() -> u64 {
+    50
+}
+
+fn default_active_speaker_window() -> u64 {
+    1000
+}
+
+fn default_event_log_size() -> usize {
+    200
+}
+
+fn default_admission_timeout() -> u64 {
+    120
+}
+
+impl RoomConfig {
+    /// Проверяет настройки комнаты
+    pub fn validate(&self) -> Result<()> {
+        if self.max_participants == Some(0) {
+            anyhow::bail!("Room max participants must be greater than 0");
+        }
+
+        if self.min_participants_to_start == Some(0) {
+            anyhow::bail!("Min participants to start must be greater than 0");
+        }
+
+        if self.noise_gate_threshold_dbov.is_some_and(|threshold| threshold > 127) {
+            anyhow::bail!("Noise gate threshold must be between 0 and 127 dBov");
+        }
+
+        if self.active_speaker_threshold_dbov.is_some_and(|threshold| threshold > 127) {
+            anyhow::bail!("Active speaker threshold must be between 0 and 127 dBov");
+        }
+
+        if self.active_speaker_window_ms == 0 {
+            anyhow::bail!("Active speaker window must be greater than 0");
+        }
+
+        if self.reorder_buffer_depth == Some(0) {
+            anyhow::bail!("Reorder buffer depth must be greater than 0");
+        }
+
+        if self.reorder_max_hold_ms == 0 {
+            anyhow::bail!("Reorder max hold must be greater than 0");
+        }
+
+        if self.flood_mute_max_pps == Some(0) {
+            anyhow::bail!("Flood mute packet rate threshold must be greater than 0");
+        }
+
+        if self.admission_timeout_secs == 0 {
+            anyhow::bail!("Admission timeout must be greater than 0");
+        }
+
+        if self.bitrate_feedback_percentile > 100 {
+            anyhow::bail!("Bitrate feedback percentile must be between 0 and 100");
+        }
+
+        if let Some(codec) = self
+            .allowed_codecs
+            .iter()
+            .find(|codec| crate::codecs::codec_kind(codec).is_none())
+        {
+            anyhow::bail!("Unsupported codec in allowed_codecs: {}", codec);
+        }
+
+        if let Some(limit) = &self.max_video_resolution {
+            limit.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Накладывает настройки из файла комнаты (TOML или JSON) на эти настройки:
+    /// поля, не указанные в файле, остаются прежними
+    pub fn with_overrides(&self, content: &str, json: bool) -> Result<Self> {
+        let overrides: serde_json::Value = if json {
+            serde_json::from_str(content).context("Failed to parse room config as JSON")?
+        } else {
+            serde_json::to_value(
+                toml::from_str::<toml::Value>(content)
+                    .context("Failed to parse room config as TOML")?,
+            )?
+        };
+        let serde_json::Value::Object(overrides) = overrides else {
+            anyhow::bail!("Room config must be a table of settings");
+        };
+
+        let mut merged = serde_json::to_value(self)?;
+        if let serde_json::Value::Object(fields) = &mut merged {
+            fields.extend(overrides);
+        }
+        let config: RoomConfig = serde_json::from_value(merged).context("Invalid room config")?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
 impl Default for RoomConfig {
     fn default() -> Self {
         Self {
@@ -321,6 +1267,29 @@ impl Default for RoomConfig {
             password: None,
             screen_sharing_enabled: true,
             recording_enabled: false,
+            min_participants_to_start: None,
+            pause_below_min_participants: false,
+            layer_switch_keyframe_only: true,
+            self_echo_suppression: true,
+            noise_gate_threshold_dbov: None,
+            noise_gate_hangover_ms: default_noise_gate_hangover(),
+            dtx_suppression: false,
+            reorder_buffer_depth: None,
+            reorder_max_hold_ms: default_reorder_max_hold(),
+            active_speaker_threshold_dbov: None,
+            active_speaker_window_ms: default_active_speaker_window(),
+            flood_mute_max_pps: None,
+            flood_mute_sustain_secs: default_flood_mute_sustain(),
+            bitrate_feedback: BitrateAggregation::default(),
+            bitrate_feedback_percentile: default_bitrate_feedback_percentile(),
+            audio_groups: HashMap::new(),
+            default_audio_group: None,
+            audio_subscriptions: HashMap::new(),
+            allowed_codecs: Vec::new(),
+            max_video_resolution: None,
+            event_log_size: default_event_log_size(),
+            admission: Admission::default(),
+            admission_timeout_secs: default_admission_timeout(),
         }
     }
 }
@@ -350,6 +1319,76 @@ mod tests {
         assert_eq!(rtc_server.username, "user");
     }
 
+    #[test]
+    fn test_ice_server_url_validation() {
+        let server = |url: &str, username: Option<&str>| IceServerConfig {
+            urls: vec![url.to_string()],
+            username: username.map(str::to_string),
+            credential: username.map(|_| "pass".to_string()),
+        };
+
+        assert!(server("stun:stun.l.google.com:19302", None).validate(false).is_ok());
+        assert!(server("turns:turn.example.com:5349", Some("user")).validate(false).is_ok());
+
+        // TURN без учетных данных допустим только с секретом для временных
+        let turn = server("turn:turn.example.com:3478?transport=udp", None);
+        let err = turn.validate(false).unwrap_err().to_string();
+        assert!(err.contains("turn:turn.example.com:3478?transport=udp"), "{}", err);
+        assert!(err.contains("username and credential"), "{}", err);
+        assert!(turn.validate(true).is_ok());
+
+        for garbage in ["stun//stun.l.google.com", "http://stun.example.com", "stun:", "garbage"] {
+            let err = server(garbage, None).validate(false).unwrap_err().to_string();
+            assert!(err.contains(garbage), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_turn_secret_replaces_turn_credentials() {
+        let config = ServerConfig {
+            turn_secret: Some("north".to_string()),
+            ..ServerConfig::default()
+        };
+        let now = std::time::UNIX_EPOCH + Duration::from_secs(1_433_809_518);
+
+        let servers = config.ice_servers_for("alice", now);
+        assert_eq!(servers.len(), config.ice_servers.len());
+        // STUN серверы не меняются, TURN получает временные учетные данные
+        assert_eq!(servers[0].username, None);
+        let turn = servers.iter().find(|s| s.urls[0].starts_with("turn:")).unwrap();
+        assert_eq!(turn.username.as_deref(), Some("1433895918:alice"));
+        assert_eq!(turn.credential.as_deref(), Some("RqMvcGPYTJMGThVLSi4amT4zFtI="));
+
+        // Без секрета остаются статические учетные данные
+        let config = ServerConfig::default();
+        assert_eq!(config.ice_servers_for("alice", now)[2].username.as_deref(), Some("webrtc"));
+    }
+
+    #[test]
+    fn test_joined_carries_client_ice_servers() {
+        let config = ServerConfig {
+            turn_secret: Some("north".to_string()),
+            ..ServerConfig::default()
+        };
+        let joined = messages::ServerMessage::Joined {
+            your_id: "alice".to_string(),
+            participants: vec![],
+            spotlight: None,
+            reconnect_token: None,
+            ice_servers: config
+                .ice_servers_for("alice", SystemTime::now())
+                .iter()
+                .map(IceServerConfig::to_client)
+                .collect(),
+        };
+
+        let json = serde_json::to_string(&joined).unwrap();
+        assert!(json.contains("stun:stun.l.google.com:19302"), "{}", json);
+        // Клиент получает временные учетные данные, но не сам секрет
+        assert!(!json.contains("north"), "{}", json);
+        assert!(!json.contains("\"webrtc\""), "{}", json);
+    }
+
     #[test]
     fn test_config_validation() {
         let config = ServerConfig::default();
@@ -358,5 +1397,57 @@ mod tests {
         let mut invalid_config = config.clone();
         invalid_config.signaling_port = 0;
         assert!(invalid_config.validate().is_err());
+
+        let mut nat = config.clone();
+        nat.public_ip = Some("203.0.113.7".parse().unwrap());
+        assert!(nat.validate().is_ok());
+        nat.public_ip = Some("0.0.0.0".parse().unwrap());
+        assert!(nat.validate().is_err());
+
+        let mut codecs = config.clone();
+        codecs.codecs.video = vec!["H264".to_string(), "vp8".to_string()];
+        codecs.codecs.audio = vec!["opus".to_string()];
+        assert!(codecs.validate().is_ok());
+        codecs.codecs.video.push("opus".to_string());
+        assert!(codecs.validate().is_err());
+        codecs.codecs.video = vec!["H265".to_string()];
+        assert!(codecs.validate().is_err());
+    }
+
+    #[test]
+    fn test_yaml_round_trip() {
+        let dir = std::env::temp_dir().join(format!("rrtc-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let config = ServerConfig {
+            signaling_port: 9443,
+            admin_token: Some("secret".to_string()),
+            room_defaults: RoomConfig {
+                max_participants: Some(12),
+                ..RoomConfig::default()
+            },
+            ..ServerConfig::default()
+        };
+
+        for name in ["config.yaml", "config.yml"] {
+            let path = dir.join(name);
+            config.save_to_file(&path).unwrap();
+            assert!(fs::read_to_string(&path).unwrap().contains("signaling_port: 9443"));
+
+            let loaded = ServerConfig::from_file(&path).unwrap();
+            assert_eq!(loaded.signaling_port, 9443);
+            assert_eq!(loaded.admin_token.as_deref(), Some("secret"));
+            assert_eq!(loaded.room_defaults.max_participants, Some(12));
+        }
+
+        // Без известного расширения пробуются все форматы
+        let path = dir.join("config.conf");
+        fs::write(&path, "signaling_port: 7000\n").unwrap();
+        assert_eq!(ServerConfig::from_file(&path).unwrap().signaling_port, 7000);
+        fs::write(&path, "signaling_port = [").unwrap();
+        let error = format!("{:#}", ServerConfig::from_file(&path).unwrap_err());
+        assert!(error.contains("tried TOML, JSON and YAML"), "{}", error);
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+/// Максимальное количество хранимых кандидатов каждого направления
+const MAX_CANDIDATES: usize = 64;
+/// Максимальное количество хранимых переходов состояния
+const MAX_STATE_CHANGES: usize = 64;
+/// Максимальное количество хранимых замеров качества
+const MAX_QUALITY_SAMPLES: usize = 32;
+
+/// Переход состояния соединения
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StateChange {
+    pub timestamp_ms: u64,
+    /// "peer_connection" или "ice"
+    pub kind: String,
+    pub state: String,
+}
+
+/// Замер качества соединения (сумма по всем RTP потокам)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct QualitySample {
+    pub timestamp_ms: u64,
+    pub packets_received: u64,
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+}
+
+/// Диагностический пакет сессии для службы поддержки
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DiagnosticsBundle {
+    pub room_id: String,
+    pub participant_id: String,
+    pub generated_at_ms: u64,
+    pub offer_sdp: Option<String>,
+    pub answer_sdp: Option<String>,
+    pub local_candidates: Vec<String>,
+    pub remote_candidates: Vec<String>,
+    pub selected_candidate_pair: Option<String>,
+    pub state_history: Vec<StateChange>,
+    pub quality_samples: Vec<QualitySample>,
+}
+
+#[derive(Default)]
+struct DiagnosticsData {
+    offer_sdp: Option<String>,
+    answer_sdp: Option<String>,
+    local_candidates: VecDeque<String>,
+    remote_candidates: VecDeque<String>,
+    state_history: VecDeque<StateChange>,
+    quality_samples: VecDeque<QualitySample>,
+}
+
+/// Ограниченное по размеру хранилище диагностики одного участника
+#[derive(Default)]
+pub struct SessionDiagnostics {
+    data: RwLock<DiagnosticsData>,
+}
+
+impl SessionDiagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn record_offer(&self, sdp: &str) {
+        self.data.write().await.offer_sdp = Some(redact_sdp(sdp));
+    }
+
+    pub async fn record_answer(&self, sdp: &str) {
+        self.data.write().await.answer_sdp = Some(redact_sdp(sdp));
+    }
+
+    pub async fn record_local_candidate(&self, candidate: String) {
+        push_bounded(&mut self.data.write().await.local_candidates, candidate, MAX_CANDIDATES);
+    }
+
+    pub async fn record_remote_candidate(&self, candidate: String) {
+        push_bounded(&mut self.data.write().await.remote_candidates, candidate, MAX_CANDIDATES);
+    }
+
+    pub async fn record_state(&self, kind: &str, state: String) {
+        let change = StateChange {
+            timestamp_ms: now_ms(),
+            kind: kind.to_string(),
+            state,
+        };
+        push_bounded(&mut self.data.write().await.state_history, change, MAX_STATE_CHANGES);
+    }
+
+    pub async fn record_quality(&self, mut sample: QualitySample) {
+        sample.timestamp_ms = now_ms();
+        push_bounded(&mut self.data.write().await.quality_samples, sample, MAX_QUALITY_SAMPLES);
+    }
+
+    /// Собирает пакет из накопленных данных
+    pub async fn bundle(
+        &self,
+        room_id: &str,
+        participant_id: &str,
+        selected_candidate_pair: Option<String>,
+    ) -> DiagnosticsBundle {
+        let data = self.data.read().await;
+        DiagnosticsBundle {
+            room_id: room_id.to_string(),
+            participant_id: participant_id.to_string(),
+            generated_at_ms: now_ms(),
+            offer_sdp: data.offer_sdp.clone(),
+            answer_sdp: data.answer_sdp.clone(),
+            local_candidates: data.local_candidates.iter().cloned().collect(),
+            remote_candidates: data.remote_candidates.iter().cloned().collect(),
+            selected_candidate_pair,
+            state_history: data.state_history.iter().cloned().collect(),
+            quality_samples: data.quality_samples.iter().cloned().collect(),
+        }
+    }
+}
+
+fn push_bounded<T>(queue: &mut VecDeque<T>, item: T, max: usize) {
+    if queue.len() >= max {
+        queue.pop_front();
+    }
+    queue.push_back(item);
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Удаляет из SDP секреты (ICE пароль), оставляя остальное для анализа
+pub fn redact_sdp(sdp: &str) -> String {
+    sdp.split("\r\n")
+        .map(|line| {
+            if line.starts_with("a=ice-pwd:") {
+                "a=ice-pwd:<redacted>"
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
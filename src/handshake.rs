@@ -0,0 +1,145 @@
+use log::info;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::utils::MetricsCounter;
+
+/// Ограничитель одновременных DTLS handshake.
+/// Handshake затратен по CPU, поэтому при массовом подключении лишние
+/// участники ждут в очереди, а не отнимают процессор у идущего медиа.
+pub struct HandshakeLimiter {
+    semaphore: Arc<Semaphore>,
+    max_concurrent: usize,
+    waiting: AtomicUsize,
+    /// Куда экспортируется глубина очереди
+    metrics: Option<MetricsCounter>,
+}
+
+/// Место в очереди ожидания: освобождается и при отмене ожидания
+/// (например, участник отключился, не дождавшись handshake)
+struct QueuedHandshake<'a> {
+    limiter: &'a HandshakeLimiter,
+}
+
+impl<'a> QueuedHandshake<'a> {
+    fn enter(limiter: &'a HandshakeLimiter) -> (Self, usize) {
+        let depth = limiter.waiting.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(metrics) = &limiter.metrics {
+            metrics.increment_handshakes_queued();
+        }
+        (Self { limiter }, depth)
+    }
+}
+
+impl Drop for QueuedHandshake<'_> {
+    fn drop(&mut self) {
+        self.limiter.waiting.fetch_sub(1, Ordering::Relaxed);
+        if let Some(metrics) = &self.limiter.metrics {
+            metrics.decrement_handshakes_queued();
+        }
+    }
+}
+
+impl HandshakeLimiter {
+    pub fn new(max_concurrent: usize) -> Self {
+        let max_concurrent = max_concurrent.max(1);
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent,
+            waiting: AtomicUsize::new(0),
+            metrics: None,
+        }
+    }
+
+    /// Экспортировать глубину очереди в счетчиках сервера
+    pub fn with_metrics(mut self, metrics: MetricsCounter) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Ждет свободного слота; handshake считается идущим, пока жив permit
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            return permit;
+        }
+
+        let (_queued, depth) = QueuedHandshake::enter(self);
+        info!("DTLS handshake limit reached, {} join(s) queued", depth);
+        self.semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("handshake semaphore is never closed")
+    }
+
+    /// Количество участников, ожидающих начала handshake
+    pub fn queue_depth(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    /// Количество идущих сейчас handshake
+    pub fn in_progress(&self) -> usize {
+        self.max_concurrent - self.semaphore.available_permits()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_handshakes_over_limit_are_queued() {
+        let limiter = Arc::new(HandshakeLimiter::new(2));
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..5)
+            .map(|_| {
+                let limiter = limiter.clone();
+                let active = active.clone();
+                let max_active = max_active.clone();
+                tokio::spawn(async move {
+                    let _permit = limiter.acquire().await;
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_active.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    active.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(limiter.queue_depth(), 3);
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(max_active.load(Ordering::SeqCst), 2);
+        assert_eq!(limiter.queue_depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_wait_leaves_queue() {
+        let metrics = MetricsCounter::new();
+        let limiter = Arc::new(HandshakeLimiter::new(1).with_metrics(metrics.clone()));
+        let _permit = limiter.acquire().await;
+
+        let waiter = {
+            let limiter = limiter.clone();
+            tokio::spawn(async move {
+                let _permit = limiter.acquire().await;
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(limiter.queue_depth(), 1);
+        assert!(metrics.to_prometheus().contains("rrtc_handshakes_queued 1"));
+
+        // Участник ушел, не дождавшись слота
+        waiter.abort();
+        let _ = waiter.await;
+        assert_eq!(limiter.queue_depth(), 0);
+        assert_eq!(metrics.get_handshakes_queued(), 0);
+    }
+}
@@ -0,0 +1,215 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use webrtc::util::ifaces::ifaces;
+
+/// Максимальное число предпочтений: type preference кандидата занимает 0..=126
+const MAX_CANDIDATE_PREFERENCES: usize = 64;
+
+/// Предпочтение для локальных ICE кандидатов. Кандидаты, подходящие под
+/// более раннее предпочтение, получают более высокий приоритет.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IceCandidatePreference {
+    /// IP адрес кандидата (например, публичный интерфейс)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Тип кандидата: host, srflx, prflx или relay
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub candidate_type: Option<String>,
+}
+
+impl IceCandidatePreference {
+    fn matches(&self, address: &str, candidate_type: &str) -> bool {
+        self.address.as_deref().is_none_or(|a| a == address)
+            && self
+                .candidate_type
+                .as_deref()
+                .is_none_or(|t| t.eq_ignore_ascii_case(candidate_type))
+    }
+}
+
+/// Проверяет список предпочтений: каждое задает адрес или тип,
+/// адреса и типы корректны, порядок однозначен (без повторов)
+pub fn validate_candidate_preferences(preferences: &[IceCandidatePreference]) -> Result<()> {
+    if preferences.len() > MAX_CANDIDATE_PREFERENCES {
+        anyhow::bail!(
+            "Too many ICE candidate preferences (max {})",
+            MAX_CANDIDATE_PREFERENCES
+        );
+    }
+
+    let mut seen = HashSet::new();
+    for preference in preferences {
+        if preference.address.is_none() && preference.candidate_type.is_none() {
+            anyhow::bail!("ICE candidate preference must set address or candidate_type");
+        }
+        if let Some(address) = &preference.address {
+            if address.parse::<IpAddr>().is_err() {
+                anyhow::bail!("Invalid address in ICE candidate preference: {}", address);
+            }
+        }
+        if let Some(candidate_type) = &preference.candidate_type {
+            if !matches!(
+                candidate_type.to_ascii_lowercase().as_str(),
+                "host" | "srflx" | "prflx" | "relay"
+            ) {
+                anyhow::bail!(
+                    "Invalid candidate_type in ICE candidate preference: {}",
+                    candidate_type
+                );
+            }
+        }
+        if !seen.insert((
+            preference.address.clone(),
+            preference.candidate_type.as_ref().map(|t| t.to_ascii_lowercase()),
+        )) {
+            anyhow::bail!("Duplicate ICE candidate preference: {:?}", preference);
+        }
+    }
+
+    Ok(())
+}
+
+/// Какие адреса интерфейсов сервера объявляются host кандидатами.
+/// Loopback не объявляется никогда (так работает ICE агент webrtc-rs)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostCandidateFilter {
+    /// Частные адреса (10/8, 172.16/12, 192.168/16, fc00::/7); за балансировщиком
+    /// с публичным srflx адресом их можно не объявлять
+    pub include_private: bool,
+    /// Link-local адреса (169.254/16, fe80::/10)
+    pub include_link_local: bool,
+}
+
+impl HostCandidateFilter {
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        let (private, link_local) = match ip {
+            IpAddr::V4(v4) => (v4.is_private(), v4.is_link_local()),
+            IpAddr::V6(v6) => (v6.is_unique_local(), v6.is_unicast_link_local()),
+        };
+        !ip.is_loopback()
+            && !ip.is_unspecified()
+            && (self.include_private || !private)
+            && (self.include_link_local || !link_local)
+    }
+}
+
+impl Default for HostCandidateFilter {
+    fn default() -> Self {
+        Self {
+            include_private: true,
+            include_link_local: false,
+        }
+    }
+}
+
+/// Адреса всех интерфейсов сервера, которые пройдут фильтр host кандидатов.
+/// ICE агент собирает кандидаты на тех же интерфейсах (порт выбирает он сам)
+pub fn gather_host_addresses(filter: &HostCandidateFilter) -> Vec<IpAddr> {
+    let mut addresses: Vec<IpAddr> = ifaces()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|iface| iface.addr.map(|addr| addr.ip()))
+        .filter(|ip| filter.allows(*ip))
+        .collect();
+    addresses.sort();
+    addresses.dedup();
+    addresses
+}
+
+/// Пересчитывает приоритет кандидата (строка вида
+/// `candidate:<foundation> <component> <protocol> <priority> <address> <port> typ <type> ...`)
+/// по списку предпочтений. Подходящие кандидаты получают type preference
+/// `126 - индекс`, остальные - не выше `126 - len`, поэтому клиент проверяет
+/// предпочтительные пути первыми. Строки другого формата не меняются.
+pub fn prioritize_candidate(candidate: &str, preferences: &[IceCandidatePreference]) -> String {
+    if preferences.is_empty() {
+        return candidate.to_string();
+    }
+
+    let mut fields: Vec<&str> = candidate.split(' ').collect();
+    if fields.len() < 8 || fields[6] != "typ" {
+        return candidate.to_string();
+    }
+    let (Ok(component), Ok(priority)) = (fields[1].parse::<u32>(), fields[3].parse::<u32>())
+    else {
+        return candidate.to_string();
+    };
+
+    let lowest_preferred = 126 - preferences.len() as u32;
+    let type_preference = match preferences
+        .iter()
+        .position(|p| p.matches(fields[4], fields[7]))
+    {
+        Some(index) => 126 - index as u32,
+        None => (priority >> 24).min(lowest_preferred),
+    };
+    let local_preference = (priority >> 8) & 0xFFFF;
+    let new_priority =
+        (type_preference << 24) + (local_preference << 8) + (256 - component.clamp(1, 256));
+
+    let new_priority = new_priority.to_string();
+    fields[3] = &new_priority;
+    fields.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn priority(candidate: &str) -> u32 {
+        candidate.split(' ').nth(3).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn test_host_candidate_filter() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let default = HostCandidateFilter::default();
+        assert!(default.allows(ip("203.0.113.10")));
+        assert!(default.allows(ip("10.0.0.5")));
+        assert!(!default.allows(ip("127.0.0.1")));
+        assert!(!default.allows(ip("169.254.10.1")));
+        assert!(!default.allows(ip("fe80::1")));
+
+        let public_only = HostCandidateFilter {
+            include_private: false,
+            include_link_local: false,
+        };
+        assert!(public_only.allows(ip("2001:db8::1")));
+        assert!(!public_only.allows(ip("192.168.1.2")));
+        assert!(!public_only.allows(ip("fd00::1")));
+
+        assert!(gather_host_addresses(&default).iter().all(|ip| default.allows(*ip)));
+    }
+
+    #[test]
+    fn test_candidate_priority_follows_preferences() {
+        let preferences = vec![
+            IceCandidatePreference {
+                address: Some("203.0.113.10".to_string()),
+                candidate_type: None,
+            },
+            IceCandidatePreference {
+                address: None,
+                candidate_type: Some("relay".to_string()),
+            },
+        ];
+        validate_candidate_preferences(&preferences).unwrap();
+
+        let private = "candidate:1 1 udp 2130706431 10.0.0.5 50000 typ host";
+        let public = "candidate:2 1 udp 2130706431 203.0.113.10 50000 typ host";
+        let relay = "candidate:3 1 udp 16777215 198.51.100.1 3478 typ relay raddr 0.0.0.0 rport 0";
+
+        let private = prioritize_candidate(private, &preferences);
+        let public = prioritize_candidate(public, &preferences);
+        let relay = prioritize_candidate(relay, &preferences);
+
+        assert!(priority(&public) > priority(&relay));
+        assert!(priority(&relay) > priority(&private));
+        assert!(relay.ends_with("typ relay raddr 0.0.0.0 rport 0"));
+
+        let duplicate = vec![preferences[1].clone(), preferences[1].clone()];
+        assert!(validate_candidate_preferences(&duplicate).is_err());
+    }
+}
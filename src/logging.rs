@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::EnvFilter;
+
+/// Формат строк лога
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Читаемый текст для консоли
+    #[default]
+    Text,
+    /// JSON объект на строку для систем сбора логов
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(format!("unknown log format: {}", other)),
+        }
+    }
+}
+
+/// Фильтр по умолчанию, если не задан RUST_LOG: подробный режим включает debug
+/// только для сервера, не для webrtc
+fn default_filter(verbose: bool) -> &'static str {
+    if verbose {
+        "info,rrtc=debug"
+    } else {
+        "info"
+    }
+}
+
+/// Настраивает вывод логов. Строки из span `room` и `peer` несут поля `room_id`
+/// и `participant_id`; записи `log` (webrtc и модули без span) тоже попадают в вывод
+pub fn init(verbose: bool, format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(default_filter(verbose)));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().with_current_span(false).init(),
+    }
+}
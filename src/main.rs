@@ -1,52 +1,161 @@
 use anyhow::Result;
 use futures_util::{SinkExt, StreamExt};
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
+use tracing::Instrument;
 use std::sync::Arc;
-use tokio::net::TcpListener;
-use tokio::sync::mpsc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
 use tokio::time::{interval, Duration};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
 use tokio_tungstenite::tungstenite::Message;
 
+mod archive;
+mod audio;
+mod bandwidth;
+mod bitrate;
+mod codecs;
 mod config;
+mod diagnostics;
+mod handshake;
+mod ice;
+mod logging;
 mod messages;
+mod metrics;
+mod outbound;
 mod peer;
+mod reconnect;
+mod reorder;
+mod recording;
+mod resources;
 mod room;
+mod signaling;
+mod simulcast;
+mod turn;
+mod utils;
 
-use config::ServerConfig;
-use messages::{ClientMessage, ParticipantInfo, ServerMessage};
+use config::{Admission, ServerConfig};
+use handshake::HandshakeLimiter;
+use ice::gather_host_addresses;
+use messages::{ClientMessage, ErrorCode, ServerMessage};
 use peer::{Peer, PeerBuilder};
+use reconnect::{ReconnectGrant, ReconnectTokens, TokenError, MAX_RECONNECT_TOKENS};
+use resources::ResourceMonitor;
 use room::RoomManager;
+use signaling::ws_channel;
+use utils::{IpRateLimiter, MetricsCounter};
+
+/// Максимальное время, на которое handshake занимает слот ограничителя
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Инициализация логирования
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
-
-    info!("Starting Rust WebRTC SFU Server");
-
     // Загрузка конфигурации
     let config = ServerConfig::load()?;
     config.validate()?;
 
+    // Инициализация логирования
+    logging::init(config.verbose_logging, config.log_format);
+
+    info!("Starting Rust WebRTC SFU Server");
+
     info!("Configuration loaded:");
     info!("  Listen address: {}", config.listen_address);
     info!("  Signaling port: {}", config.signaling_port);
     info!("  ICE servers: {}", config.ice_servers.len());
     info!("  Max participants per room: {}", config.max_participants_per_room);
+    let host_addresses = gather_host_addresses(&config.host_candidate_filter());
+    if host_addresses.is_empty() {
+        warn!("  No network interface passes the ICE host candidate filter");
+    } else {
+        info!("  ICE host addresses: {:?}", host_addresses);
+    }
+    if let Some(ip) = config.public_ip {
+        info!("  Public IP (1:1 NAT): {}, advertised as srflx candidate", ip);
+    }
 
     let config = Arc::new(config);
 
+    let metrics = MetricsCounter::new();
+    if let Some(port) = config.metrics_port {
+        let listener = TcpListener::bind((config.listen_address.as_str(), port)).await?;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(listener, metrics).await {
+                error!("Metrics server failed: {}", e);
+            }
+        });
+    }
+
     // Создание менеджера комнат
-    let room_manager = Arc::new(RoomManager::new());
+    let mut room_manager = RoomManager::with_room_defaults(config.room_defaults.clone())
+        .with_max_participants(config.max_participants_per_room)
+        .with_metrics(metrics.clone());
+    if config.connection_timeout_secs > 0 {
+        room_manager =
+            room_manager.with_idle_timeout(Duration::from_secs(config.connection_timeout_secs));
+    }
+    if let Some(dir) = &config.room_config_dir {
+        room_manager = room_manager.with_room_config_dir(dir);
+    }
+    if let Some(dir) = &config.room_archive_dir {
+        room_manager = room_manager.with_archive_dir(dir);
+    }
+    if let Some(dir) = &config.recording_dir {
+        room_manager = room_manager.with_recording_dir(dir);
+    }
+    let room_manager = Arc::new(room_manager);
 
-    // Запуск фоновой задачи для очистки пустых комнат
+    // Ограничение одновременных DTLS handshake
+    let handshakes = Arc::new(
+        HandshakeLimiter::new(config.max_concurrent_handshakes).with_metrics(metrics.clone()),
+    );
+    let reconnect_tokens = Arc::new(ReconnectTokens::new(
+        Duration::from_secs(config.reconnect_token_lifetime_secs),
+        MAX_RECONNECT_TOKENS,
+    ));
+    // Динамический лимит участников по ресурсам хоста
+    let resources = config.resource_limits().map(|limits| Arc::new(ResourceMonitor::new(limits)));
+
+    // Лимит новых соединений с одного IP
+    let connection_limiter = (config.connection_rate_per_ip > 0).then(|| {
+        Arc::new(IpRateLimiter::new(
+            config.connection_rate_per_ip,
+            Duration::from_secs(config.connection_rate_window_secs),
+        ))
+    });
+
+    // Запуск фоновой задачи для очистки пустых комнат; останавливается при завершении сервера
+    let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
     let rm_cleanup = room_manager.clone();
+    let limiter_cleanup = connection_limiter.clone();
     let cleanup_interval = config.cleanup_interval_secs;
-    tokio::spawn(async move {
+    let handshake_stats = handshakes.clone();
+    let cleanup_task = tokio::spawn(async move {
         let mut interval = interval(Duration::from_secs(cleanup_interval));
         loop {
-            interval.tick().await;
-            rm_cleanup.cleanup_all_empty_rooms().await;
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown_rx.changed() => break,
+            }
+            let removed = rm_cleanup.cleanup_all_empty_rooms().await;
+            if removed > 0 {
+                info!(
+                    "Cleanup reclaimed {} empty rooms, {} rooms active",
+                    removed,
+                    rm_cleanup.room_count().await
+                );
+            }
+            if let Some(limiter) = &limiter_cleanup {
+                limiter.prune();
+            }
+            if handshake_stats.queue_depth() > 0 {
+                info!(
+                    "DTLS handshakes: {} in progress, {} queued",
+                    handshake_stats.in_progress(),
+                    handshake_stats.queue_depth()
+                );
+            }
         }
     });
 
@@ -57,40 +166,116 @@ async fn main() -> Result<()> {
     info!("WebRTC SFU listening on {}", addr);
     info!("Server is ready to accept connections");
 
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
     loop {
-        let (stream, peer_addr) = listener.accept().await?;
+        let (stream, peer_addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = &mut shutdown => break,
+        };
         info!("New connection from {}", peer_addr);
 
+        if let Some(limiter) = &connection_limiter {
+            if !limiter.allow(peer_addr.ip()) {
+                warn!("Too many connections from {}, rejecting", peer_addr.ip());
+                tokio::spawn(reject_connection(stream));
+                continue;
+            }
+        }
+
         let room_manager = room_manager.clone();
         let config = config.clone();
+        let handshakes = handshakes.clone();
+        let reconnect_tokens = reconnect_tokens.clone();
+        let resources = resources.clone();
+        let metrics = metrics.clone();
 
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(stream, room_manager, config).await {
+            metrics.increment_connections();
+            let result = handle_connection(
+                stream,
+                metrics.clone(),
+                room_manager,
+                config,
+                handshakes,
+                reconnect_tokens,
+                resources,
+            )
+            .await;
+            metrics.decrement_connections();
+            if let Err(e) = result {
                 error!("Connection error from {}: {}", peer_addr, e);
             }
         });
     }
+
+    // Новые соединения больше не принимаются; участники получают 503 и отключаются
+    drop(listener);
+    info!("Shutting down");
+    let _ = shutdown_tx.send(true);
+    let grace = Duration::from_secs(config.shutdown_grace_secs);
+    let deadline = tokio::time::Instant::now() + grace;
+    let closed = room_manager.shutdown(grace).await;
+    info!("Closed {} peers", closed);
+
+    // Даем обработчикам соединений отправить Close и завершиться
+    while metrics.get_connections_active() > 0 && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let _ = cleanup_task.await;
+    Ok(())
+}
+
+/// Ждет SIGINT (Ctrl+C) или SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(e) => warn!("Failed to listen for SIGTERM: {}", e),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
 }
 
+#[tracing::instrument(name = "peer", skip_all, fields(room_id, participant_id))]
 async fn handle_connection(
     stream: tokio::net::TcpStream,
+    metrics: MetricsCounter,
     room_manager: Arc<RoomManager>,
     config: Arc<ServerConfig>,
+    handshakes: Arc<HandshakeLimiter>,
+    reconnect_tokens: Arc<ReconnectTokens>,
+    resources: Option<Arc<ResourceMonitor>>,
 ) -> Result<()> {
     // Принимаем WebSocket соединение
     let ws_stream = tokio_tungstenite::accept_async(stream).await?;
     let (mut ws_sink, mut ws_stream) = ws_stream.split();
 
-    // Создаем канал для отправки сообщений клиенту
-    let (tx, mut rx) = mpsc::unbounded_channel();
+    // Создаем ограниченную очередь для отправки сообщений клиенту
+    let (tx, mut rx) = ws_channel(config.ws_send_queue_size);
+    let tx = tx.with_metrics(metrics.clone());
 
     // Задача для отправки сообщений в WebSocket
-    let send_task = tokio::spawn(async move {
+    let mut send_task = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
+            let closing = matches!(msg, Message::Close(_));
             if let Err(e) = ws_sink.send(msg).await {
                 error!("Failed to send WebSocket message: {}", e);
                 break;
             }
+            // Сервер сам закрывает соединение: после Close отправлять нечего
+            if closing {
+                break;
+            }
         }
     });
 
@@ -112,25 +297,150 @@ async fn handle_connection(
     let join_msg: ClientMessage = match serde_json::from_str(&msg) {
         Ok(msg) => msg,
         Err(e) => {
-            error!("Failed to parse join message: {}", e);
-            send_task.abort();
+            warn!("Failed to parse join message, closing connection: {}", e);
+            metrics.increment_malformed_messages();
+            let _ = tx.send(Message::text(serde_json::to_string(&ServerMessage::malformed(&e))?));
+            let _ = tx.send(Message::Close(None));
+            drop(tx);
+            let _ = send_task.await;
             return Ok(());
         }
     };
 
-    let (room_id, participant_id, name) = match join_msg {
+    // Переподключение по токену не требует пароля: участник уже был допущен.
+    // Сессию возобновляет `rejoin` или `join` с `resume_sid` и токеном этой сессии;
+    // токен одноразовый, поэтому из двух соединений с одним токеном проходит первое
+    let claim = match join_msg {
         ClientMessage::Join {
             room,
             participant,
             name,
-        } => (room, participant, name),
+            password,
+            resume_sid: None,
+            ..
+        } => Ok((room, participant, name, password, false)),
+        ClientMessage::Join {
+            room,
+            name,
+            resume_sid: Some(sid),
+            reconnect_token,
+            ..
+        } => reconnect_tokens
+            .redeem_for(
+                reconnect_token.as_deref().unwrap_or_default(),
+                &room,
+                &sid,
+                std::time::Instant::now(),
+            )
+            .map(|_| (room, sid, name, None, true)),
+        ClientMessage::Rejoin { token } => reconnect_tokens
+            .redeem(&token, std::time::Instant::now())
+            .map(|grant| (grant.room_id, grant.participant_id, grant.name, None, true)),
+        ClientMessage::GetDiagnostics {
+            room,
+            participant,
+            token,
+        } => {
+            let reply =
+                diagnostics_reply(&room_manager, &config, &room, &participant, token.as_deref())
+                    .await;
+            let _ = tx.send(Message::text(serde_json::to_string(&reply)?));
+            // Дожидаемся отправки ответа перед закрытием соединения
+            drop(tx);
+            let _ = send_task.await;
+            return Ok(());
+        }
+        ClientMessage::ListRooms { token } => {
+            let reply = if is_admin(&config, token.as_deref()) {
+                ServerMessage::Rooms {
+                    rooms: room_manager.list_rooms().await,
+                }
+            } else {
+                warn!("Rejected room list request");
+                unauthorized()
+            };
+            let _ = tx.send(Message::text(serde_json::to_string(&reply)?));
+            drop(tx);
+            let _ = send_task.await;
+            return Ok(());
+        }
+        ClientMessage::WatchStats { room, token } => {
+            let every = Duration::from_secs(config.stats_interval_secs);
+            let stats = if is_admin(&config, token.as_deref()) {
+                room_manager.watch_stats(&room, every).await.ok_or_else(|| {
+                    ServerMessage::error(ErrorCode::NotFound, format!("Room {} not found", room))
+                })
+            } else {
+                warn!("Rejected stats subscription for room {}", room);
+                Err(unauthorized())
+            };
+
+            match stats {
+                Ok(mut stats) => {
+                    info!("Stats subscription for room {}", room);
+                    loop {
+                        tokio::select! {
+                            snapshot = stats.recv() => match snapshot {
+                                Some(stats) => {
+                                    let msg = ServerMessage::RoomStats { stats };
+                                    let _ = tx.send(Message::text(serde_json::to_string(&msg)?));
+                                }
+                                // Комната закрыта: завершаем подписку штатно
+                                None => {
+                                    let _ = tx.send(Message::Close(None));
+                                    break;
+                                }
+                            },
+                            msg = ws_stream.next() => {
+                                if !matches!(msg, Some(Ok(Message::Text(_) | Message::Ping(_) | Message::Pong(_)))) {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                Err(reply) => {
+                    let _ = tx.send(Message::text(serde_json::to_string(&reply)?));
+                }
+            }
+            drop(tx);
+            let _ = send_task.await;
+            return Ok(());
+        }
         _ => {
             error!("Expected join message");
-            send_task.abort();
+            let _ = tx.send(Message::text(serde_json::to_string(&ServerMessage::error(
+                ErrorCode::BadRequest,
+                "Expected join message",
+            ))?));
+            drop(tx);
+            let _ = send_task.await;
+            return Ok(());
+        }
+    };
+
+    let (room_id, participant_id, name, password, resume) = match claim {
+        Ok(claim) => claim,
+        Err(e) => {
+            warn!("Rejected session resume: {}", e);
+            let code = match e {
+                TokenError::Reused => ErrorCode::Conflict,
+                TokenError::Expired | TokenError::Unknown => ErrorCode::Unauthorized,
+            };
+            let _ = tx.send(Message::text(serde_json::to_string(&ServerMessage::error(
+                code,
+                e.code(),
+            ))?));
+            drop(tx);
+            let _ = send_task.await;
             return Ok(());
         }
     };
 
+    // Дальше строки лога соединения несут комнату и участника из span `peer`
+    tracing::Span::current()
+        .record("room_id", room_id.as_str())
+        .record("participant_id", participant_id.as_str());
     info!(
         "Participant {} ({}) joining room {}",
         participant_id, name, room_id
@@ -139,27 +449,185 @@ async fn handle_connection(
     // Получаем или создаем комнату
     let room = room_manager.get_or_create_room(room_id.clone()).await;
 
-    // Проверяем лимит участников
-    if room.peer_count().await >= config.max_participants_per_room {
+    // Пароль проверяется до замены прежнего соединения и создания Peer; пароль
+    // первого участника закрепляется только после допуска
+    if !resume && !room.check_password(password.as_deref()) {
+        warn!("Wrong password from participant {} for room {}", participant_id, room_id);
+        let _ = tx.send(Message::text(serde_json::to_string(&ServerMessage::error(
+            ErrorCode::Unauthorized,
+            "Invalid room password",
+        ))?));
+        room_manager.cleanup_empty_room(&room_id).await;
+        drop(tx);
+        let _ = send_task.await;
+        return Ok(());
+    }
+
+    // Возобновляемая сессия сохраняет имя участника; без возобновления прежнее
+    // соединение участника заменяется новым как выход и вход
+    let stale = room.get_peer(&participant_id).await;
+    let resuming = resume && stale.is_some();
+    let name = match &stale {
+        Some(stale) if resuming => stale.name(),
+        _ => name,
+    };
+
+    // Закрытая ведущим комната допускает только возобновление сессии по токену;
+    // без этой проверки вход с чужим id вытеснил бы участника из комнаты
+    if !room.accepts_join(resume) {
+        warn!("Room {} is locked, refusing participant {}", room_id, participant_id);
+        let _ = tx.send(Message::text(serde_json::to_string(&ServerMessage::error(
+            ErrorCode::Locked,
+            "Room is locked",
+        ))?));
+        room_manager.cleanup_empty_room(&room_id).await;
+        drop(tx);
+        let _ = send_task.await;
+        return Ok(());
+    }
+
+    // Проверяем лимит участников (возобновляемая сессия место уже занимает,
+    // заменяемое соединение освободит свое)
+    let occupied = room.peer_count().await.saturating_sub(usize::from(stale.is_some()));
+    if !resuming && occupied >= room.max_participants {
         error!("Room {} is full", room_id);
         let _ = tx.send(Message::text(
-            serde_json::to_string(&ServerMessage::Error {
-                message: "Room is full".to_string(),
-                code: Some(403),
-            })
-            .unwrap_or_default(),
+            serde_json::to_string(&ServerMessage::error(ErrorCode::RoomFull, "Room is full"))
+                .unwrap_or_default(),
         ));
-        send_task.abort();
+        drop(tx);
+        let _ = send_task.await;
         return Ok(());
     }
 
-    // Создаем Peer с ICE серверами из конфигурации
-    let ice_servers = config.get_rtc_ice_servers();
-    let peer = match PeerBuilder::new(participant_id.clone(), name.clone(), tx.clone())
-        .with_ice_servers(ice_servers)
-        .build()
-        .await
-    {
+    // Проверяем ресурсы хоста
+    if let Some(resources) = resources.as_ref().filter(|_| !resuming) {
+        if let Err(reason) = resources.admit(room_manager.participant_count().await) {
+            warn!("Refusing participant {}: {}", participant_id, reason);
+            let _ = tx.send(Message::text(
+                serde_json::to_string(&ServerMessage::error(
+                    ErrorCode::Unavailable,
+                    "Server is at capacity",
+                ))
+                .unwrap_or_default(),
+            ));
+            drop(tx);
+            let _ = send_task.await;
+            return Ok(());
+        }
+    }
+
+    // При ручном допуске новый участник ждет решения ведущего до создания Peer:
+    // в комнате его нет, медиа не согласуется. Без токена переподключения вход
+    // с id участника, который уже в комнате, тоже требует решения ведущего
+    let admission = match room.config.admission {
+        Admission::Manual if !resume => {
+            room.request_admission(&participant_id, &name).await
+        }
+        _ => None,
+    };
+    if let Some(mut decision) = admission {
+        let deadline = tokio::time::sleep(Duration::from_secs(room.config.admission_timeout_secs));
+        tokio::pin!(deadline);
+        let refusal = loop {
+            tokio::select! {
+                approved = &mut decision => break match approved {
+                    Ok(true) => None,
+                    // Отказ ведущего или заявку заменил новый вход с тем же id
+                    Ok(false) | Err(_) => {
+                        Some(ServerMessage::error(ErrorCode::Forbidden, "admission_denied"))
+                    }
+                },
+                _ = &mut deadline => {
+                    break Some(ServerMessage::error(ErrorCode::Timeout, "admission_timeout"));
+                }
+                msg = ws_stream.next() => match msg {
+                    Some(Ok(Message::Ping(data))) => {
+                        let _ = tx.send(Message::Pong(data));
+                    }
+                    Some(Ok(Message::Text(_) | Message::Pong(_))) => {
+                        debug!("Ignoring message from {} waiting for admission", participant_id);
+                    }
+                    _ => {
+                        info!("Participant {} left while waiting for admission", participant_id);
+                        drop(decision);
+                        room.cancel_admission(&participant_id);
+                        room_manager.cleanup_empty_room(&room_id).await;
+                        send_task.abort();
+                        return Ok(());
+                    }
+                },
+            }
+        };
+        drop(decision);
+        room.cancel_admission(&participant_id);
+
+        if let Some(refusal) = refusal {
+            warn!("Participant {} was not admitted to room {}", participant_id, room_id);
+            let _ = tx.send(Message::text(serde_json::to_string(&refusal)?));
+            let _ = tx.send(Message::Close(None));
+            room_manager.cleanup_empty_room(&room_id).await;
+            drop(tx);
+            let _ = send_task.await;
+            return Ok(());
+        }
+    }
+
+    // Пока шли проверки, пароль мог задать другой первый участник
+    if !resume && !room.authorize(password.as_deref()) {
+        warn!("Wrong password from participant {} for room {}", participant_id, room_id);
+        let _ = tx.send(Message::text(serde_json::to_string(&ServerMessage::error(
+            ErrorCode::Unauthorized,
+            "Invalid room password",
+        ))?));
+        room_manager.cleanup_empty_room(&room_id).await;
+        drop(tx);
+        let _ = send_task.await;
+        return Ok(());
+    }
+
+    // Прежнее соединение заменяется только после допуска
+    if stale.is_some() && !resuming {
+        info!("Replacing previous connection of participant {}", participant_id);
+        room.remove_peer(&participant_id).await?;
+    }
+
+    // Создаем Peer с ICE серверами из конфигурации; временные учетные данные TURN
+    // те же, что получит клиент в `Joined`
+    let ice_servers = config.ice_servers_for(&participant_id, std::time::SystemTime::now());
+    let mut builder = PeerBuilder::new(participant_id.clone(), name.clone(), tx.clone())
+        .with_ice_servers(
+            ice_servers
+                .iter()
+                .map(|server| server.to_rtc_ice_server())
+                .collect(),
+        )
+        .with_candidate_preferences(config.ice_candidate_preferences.clone())
+        .with_candidate_grace(Duration::from_millis(config.candidate_grace_ms))
+        .with_frame_marking(config.frame_marking)
+        .with_audio_level(room.config.noise_gate_threshold_dbov.is_some())
+        .with_allowed_codecs(room.config.allowed_codecs.clone())
+        .with_codec_preferences(config.codecs.clone())
+        .with_max_video_resolution(room.config.max_video_resolution)
+        .with_outbound_queue(config.outbound_queue_size, config.outbound_drop_policy)
+        .with_outbound_class_weights(config.outbound_class_weights)
+        .with_max_transceivers(config.max_audio_transceivers, config.max_video_transceivers)
+        .with_congestion_thresholds(
+            config.bandwidth_down_kbps * 1000,
+            config.bandwidth_up_kbps * 1000,
+        )
+        .with_max_ice_restarts(config.max_ice_restarts)
+        .with_host_candidate_filter(config.host_candidate_filter())
+        .with_public_ip(config.public_ip);
+    if let Some((port_min, port_max)) = config.ice_udp_port_range() {
+        builder = builder.with_udp_port_range(port_min, port_max);
+    }
+    if config.connection_state_notifications {
+        builder = builder.with_connection_state_notifications(Duration::from_millis(
+            config.connection_state_debounce_ms,
+        ));
+    }
+    let peer = match builder.build().await {
         Ok(peer) => Arc::new(peer),
         Err(e) => {
             error!("Failed to create peer: {}", e);
@@ -171,47 +639,89 @@ async fn handle_connection(
     // Настраиваем обработчик входящих треков
     let room_clone = room.clone();
     let peer_id_clone = participant_id.clone();
+    let span = peer.span.clone();
 
     peer.pc
         .on_track(Box::new(move |track, _receiver, _transceiver| {
             let room = room_clone.clone();
             let from_id = peer_id_clone.clone();
             let track = track.clone();
+            let span = span.clone();
 
-            tokio::spawn(async move {
-                if let Err(e) = room.handle_incoming_track(from_id, track).await {
-                    error!("Error handling track: {}", e);
+            tokio::spawn(
+                async move {
+                    if let Err(e) = room.handle_incoming_track(from_id, track).await {
+                        error!("Error handling track: {}", e);
+                    }
                 }
-            });
+                .instrument(span),
+            );
 
             Box::pin(async {})
         }));
 
+    // Сообщения data channels пересылаются остальным участникам комнаты
+    let room_clone = room.clone();
+    let peer_id_clone = participant_id.clone();
+    let span = peer.span.clone();
+    peer.on_data_channel(move |label, msg| {
+        let room = room_clone.clone();
+        let from_id = peer_id_clone.clone();
+        Box::pin(
+            async move {
+                if let Err(e) = room.relay_data(&from_id, &label, msg.data, msg.is_string).await {
+                    warn!("Dropped data channel message from peer {}: {}", from_id, e);
+                }
+            }
+            .instrument(span.clone()),
+        )
+    });
+
     // Получаем список существующих участников
-    let existing_peers = room.get_all_peers().await;
-    let mut participants_info = Vec::new();
-
-    for existing_peer in &existing_peers {
-        if existing_peer.id != participant_id {
-            let (muted, video_on, screen_sharing) = existing_peer.get_state().await;
-            participants_info.push(ParticipantInfo::with_state(
-                existing_peer.id.clone(),
-                existing_peer.name.clone(),
-                muted,
-                video_on,
-                screen_sharing,
-            ));
-        }
-    }
+    let participants_info = room.participants_info(&participant_id).await;
 
-    // Добавляем участника в комнату
-    room.add_peer(peer.clone()).await?;
+    // Добавляем участника в комнату; если сессия закончилась, пока строился Peer,
+    // это обычный вход
+    let resumed = resuming && room.resume_peer(peer.clone()).await;
+    if resumed {
+        info!("Participant {} resumed session in room {}", participant_id, room_id);
+    } else if let Err(e) = room.add_peer(peer.clone()).await {
+        let Some(full) = e.downcast_ref::<room::RoomFull>() else {
+            return Err(e);
+        };
+        error!("{}", full);
+        peer.send_message(ServerMessage::error(ErrorCode::RoomFull, "Room is full"))?;
+        let _ = peer.close().await;
+        room_manager.cleanup_empty_room(&room_id).await;
+        return Ok(());
+    }
 
     // Отправляем подтверждение присоединения
+    let reconnect_token = reconnect_tokens.issue(
+        ReconnectGrant {
+            room_id: room_id.clone(),
+            participant_id: participant_id.clone(),
+            name: name.clone(),
+        },
+        std::time::Instant::now(),
+    );
     peer.send_message(ServerMessage::Joined {
         your_id: participant_id.clone(),
         participants: participants_info,
+        spotlight: room.spotlight(),
+        reconnect_token: Some(reconnect_token),
+        ice_servers: ice_servers.iter().map(|server| server.to_client()).collect(),
     })?;
+    if resumed {
+        // Клиент восстанавливает свое состояние (mute, видео) по данным сервера
+        let (muted, video_on, screen_sharing) = peer.get_state().await;
+        peer.send_message(ServerMessage::StateUpdate {
+            participant_id: participant_id.clone(),
+            muted,
+            video_on,
+            screen_sharing,
+        })?;
+    }
 
     info!(
         "Peer {} successfully joined room {} ({} participants)",
@@ -224,20 +734,122 @@ async fn handle_connection(
     let peer_for_loop = peer.clone();
     let room_for_loop = room.clone();
 
-    while let Some(msg_result) = ws_stream.next().await {
+    // Клиент, не приславший offer вовремя, освобождает место в комнате
+    let mut offer_pending = config.offer_timeout_secs > 0;
+    // Сервер сам завершил сессию и поставил в очередь прощальные сообщения
+    let mut server_closed = false;
+    let offer_watchdog = peer.wait_offer(Duration::from_secs(config.offer_timeout_secs));
+    tokio::pin!(offer_watchdog);
+
+    // WebSocket ping отличает зависшее TCP соединение от просто молчащего клиента
+    let ping_enabled = config.ws_ping_interval_secs > 0;
+    let ping_period = Duration::from_secs(config.ws_ping_interval_secs.max(1));
+    let mut ping_ticker =
+        tokio::time::interval_at(tokio::time::Instant::now() + ping_period, ping_period);
+    let mut awaiting_pong = false;
+    // Неразбираемые сообщения клиента за соединение
+    let mut malformed_messages = 0u32;
+
+    loop {
+        let msg_result = tokio::select! {
+            msg = ws_stream.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = peer.ws_tx.unresponsive() => {
+                warn!(
+                    "Peer {} is not reading WebSocket messages (send queue full), closing connection",
+                    participant_id
+                );
+                break;
+            }
+            _ = ping_ticker.tick(), if ping_enabled => {
+                if awaiting_pong {
+                    warn!(
+                        "Peer {} did not answer WebSocket ping within {}s, closing connection",
+                        participant_id, config.ws_ping_interval_secs
+                    );
+                    break;
+                }
+                awaiting_pong = true;
+                let _ = peer.ws_tx.send(Message::Ping(Default::default()));
+                continue;
+            }
+            received = &mut offer_watchdog, if offer_pending => {
+                offer_pending = false;
+                if received {
+                    continue;
+                }
+                warn!(
+                    "Peer {} sent no offer within {}s, closing connection",
+                    participant_id, config.offer_timeout_secs
+                );
+                let _ =
+                    peer.send_message(ServerMessage::error(ErrorCode::Timeout, "offer_timeout"));
+                let _ = peer.ws_tx.send(Message::Close(None));
+                server_closed = true;
+                break;
+            }
+        };
+
+        // Любое сообщение, а не только pong, показывает, что соединение живо
+        if msg_result.is_ok() {
+            peer.activity.touch();
+            awaiting_pong = false;
+        }
+
         match msg_result {
             Ok(Message::Text(text)) => {
                 match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(ClientMessage::Leave) => {
+                        let removed = room_manager
+                            .remove_participant(&room_id, &participant_id)
+                            .await?;
+                        info!("Peer {} left room {}", participant_id, room_id);
+                        let _ = peer.send_message(ServerMessage::LeftRoom {
+                            success: removed,
+                            message: if removed {
+                                "Left room".to_string()
+                            } else {
+                                "Participant is not in the room".to_string()
+                            },
+                        });
+                        let _ = peer.ws_tx.send(Message::Close(None));
+                        server_closed = true;
+                        break;
+                    }
                     Ok(client_msg) => {
-                        if let Err(e) =
-                            handle_client_message(client_msg, peer_for_loop.clone(), room_for_loop.clone())
-                                .await
+                        if let Err(e) = handle_client_message(
+                            client_msg,
+                            peer_for_loop.clone(),
+                            room_for_loop.clone(),
+                            &handshakes,
+                        )
+                        .await
                         {
                             error!("Error handling message: {}", e);
+                            let _ = peer.send_message(ServerMessage::error(
+                                ErrorCode::Internal,
+                                e.to_string(),
+                            ));
                         }
                     }
                     Err(e) => {
-                        warn!("Failed to parse client message: {}", e);
+                        warn!("Failed to parse message from peer {}: {}", participant_id, e);
+                        metrics.increment_malformed_messages();
+                        malformed_messages += 1;
+                        let _ = peer.send_message(ServerMessage::malformed(&e));
+                        if config.max_malformed_messages > 0
+                            && malformed_messages > config.max_malformed_messages
+                        {
+                            warn!(
+                                "Peer {} sent {} malformed messages, closing connection",
+                                participant_id, malformed_messages
+                            );
+                            let _ = peer.ws_tx.send(Message::Close(None));
+                            server_closed = true;
+                            break;
+                        }
                     }
                 }
             }
@@ -249,7 +861,7 @@ async fn handle_connection(
                 let _ = peer_for_loop.ws_tx.send(Message::Pong(data));
             }
             Ok(Message::Pong(_)) => {
-                // Pong received
+                // Ответ на ping сервера учтен выше
             }
             Err(e) => {
                 error!("WebSocket error for {}: {}", participant_id, e);
@@ -261,85 +873,250 @@ async fn handle_connection(
 
     // Очистка при отключении
     info!("Peer {} disconnecting from room {}", participant_id, room_id);
-    room.remove_peer(&participant_id).await?;
+    // Если участник уже переподключился, комнату занимает новое соединение
+    if room
+        .get_peer(&participant_id)
+        .await
+        .is_some_and(|current| Arc::ptr_eq(&current, &peer))
+    {
+        room.remove_peer(&participant_id).await?;
+    }
 
     // Очищаем комнату если она пуста
     room_manager.cleanup_empty_room(&room_id).await;
 
+    // Даем отправить прощальные сообщения (ошибку, left_room) перед закрытием
+    if server_closed {
+        let _ = tokio::time::timeout(Duration::from_secs(1), &mut send_task).await;
+    }
     send_task.abort();
 
     Ok(())
 }
 
+/// Закрывает соединение сразу после WebSocket handshake с кодом policy violation
+async fn reject_connection(stream: TcpStream) {
+    let Ok(Ok(mut ws_stream)) =
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, tokio_tungstenite::accept_async(stream)).await
+    else {
+        return;
+    };
+    let _ = ws_stream
+        .close(Some(CloseFrame {
+            code: CloseCode::Policy,
+            reason: "Too many connections".into(),
+        }))
+        .await;
+}
+
 async fn handle_client_message(
     msg: ClientMessage,
     peer: Arc<Peer>,
     room: Arc<room::Room>,
+    handshakes: &HandshakeLimiter,
 ) -> Result<()> {
     match msg {
-        ClientMessage::Offer { sdp } => {
+        ClientMessage::Offer { sdp, seq } => {
             info!("Received offer from peer {}", peer.id);
-            let answer_sdp = peer.handle_offer(sdp).await?;
-            peer.send_message(ServerMessage::Answer { sdp: answer_sdp })?;
+            if let Err(reason) = codecs::check_offer(&sdp, &peer.media_options.allowed_codecs) {
+                warn!("Rejecting offer from peer {}: {}", peer.id, reason);
+                peer.send_message(ServerMessage::error(ErrorCode::UnsupportedMedia, reason))?;
+                return Ok(());
+            }
+            // Встречный offer: сервер не уступает, клиент сначала отвечает на offer сервера
+            if peer.has_pending_offer() {
+                warn!("Offer from peer {} collides with a pending server offer", peer.id);
+                peer.send_message(ServerMessage::error(
+                    ErrorCode::Conflict,
+                    "Answer the pending server offer first",
+                ))?;
+                return Ok(());
+            }
+            // Слот удерживается до завершения handshake, чтобы ограничить нагрузку на CPU
+            let permit = handshakes.acquire().await;
+            let Some((seq, answer_sdp)) = peer.handle_offer_sequenced(sdp, seq).await? else {
+                // Устаревший offer: ответ получит более новый
+                return Ok(());
+            };
+            peer.send_message(ServerMessage::Answer {
+                sdp: answer_sdp,
+                seq: Some(seq),
+            })?;
+            let handshake_peer = peer.clone();
+            tokio::spawn(async move {
+                handshake_peer.wait_handshake(HANDSHAKE_TIMEOUT).await;
+                drop(permit);
+            });
+            // Треки, добавленные после согласования, клиент получает в offer сервера
+            peer.renegotiate().await?;
         }
 
         ClientMessage::Answer { sdp } => {
-            info!("Received answer from peer {} (unexpected)", peer.id);
-            // В SFU модели клиент обычно не отправляет answer, но обрабатываем на всякий случай
-            let answer = webrtc::peer_connection::sdp::session_description::RTCSessionDescription::answer(sdp)?;
-            peer.pc.set_remote_description(answer).await?;
+            info!("Received answer from peer {}", peer.id);
+            if let Err(e) = peer.handle_answer(sdp).await {
+                warn!("Rejected answer from peer {}: {}", peer.id, e);
+                peer.send_message(ServerMessage::error(ErrorCode::BadRequest, e.to_string()))?;
+            }
         }
 
         ClientMessage::Candidate { candidate } => {
             peer.add_ice_candidate(candidate).await?;
         }
 
+        ClientMessage::IceRestart => {
+            info!("Peer {} requested an ICE restart", peer.id);
+            if let Err(e) = peer.restart_ice().await {
+                warn!("Rejected ICE restart for peer {}: {}", peer.id, e);
+                peer.send_message(ServerMessage::error(ErrorCode::Conflict, e.to_string()))?;
+            }
+        }
+
         ClientMessage::StateUpdate {
             muted,
             video_on,
             screen_sharing,
         } => {
+            // Пока ведущий не снял принудительное отключение, включить микрофон нельзя
+            let unmute_refused = !muted && room.is_force_muted(&peer.id);
+            let muted = muted || unmute_refused;
+            let video_was_on = *peer.video_on.read().await;
             peer.update_state(muted, video_on, screen_sharing).await;
 
+            // Пока камера была выключена, видео не пересылалось: получателям нужен ключевой кадр
+            if video_on && !video_was_on {
+                if let Err(e) = peer.request_keyframe().await {
+                    debug!("Failed to request keyframe from peer {}: {}", peer.id, e);
+                }
+            }
+
+            let update = ServerMessage::StateUpdate {
+                participant_id: peer.id.clone(),
+                muted,
+                video_on,
+                screen_sharing,
+            };
+            // Отказ сообщаем и самому участнику, чтобы его интерфейс показал mute
+            if unmute_refused {
+                info!("Peer {} is force-muted, ignoring unmute", peer.id);
+                peer.send_message(update.clone())?;
+            }
+
             // Транслируем обновление состояния другим участникам
-            room.broadcast_message(
-                &peer.id,
-                ServerMessage::StateUpdate {
-                    participant_id: peer.id.clone(),
-                    muted,
-                    video_on,
-                    screen_sharing,
-                },
-            )
-            .await;
+            room.broadcast_message(&peer.id, update).await;
         }
 
-        ClientMessage::StartScreenShare => {
-            info!("Peer {} started screen sharing", peer.id);
-            peer.update_state(*peer.muted.read().await, *peer.video_on.read().await, true)
-                .await;
+        ClientMessage::Rename { name } => {
+            if let Err(e) = room.rename(&peer, &name).await {
+                warn!("Rejected rename of peer {}: {}", peer.id, e);
+                peer.send_message(ServerMessage::error(ErrorCode::BadRequest, e.to_string()))?;
+            }
+        }
+
+        ClientMessage::RaiseHand { raised } => {
+            peer.update_hand(raised).await;
 
             room.broadcast_message(
                 &peer.id,
-                ServerMessage::ScreenShareStarted {
+                ServerMessage::HandState {
                     participant_id: peer.id.clone(),
+                    raised,
                 },
             )
             .await;
         }
 
+        ClientMessage::SetLayer { target_id, layer } => {
+            if let Err(e) = room.set_layer(&peer.id, &target_id, layer).await {
+                warn!("Rejected layer selection by peer {}: {}", peer.id, e);
+                peer.send_message(ServerMessage::error(ErrorCode::BadRequest, e.to_string()))?;
+            }
+        }
+
+        ClientMessage::SetReceiveLimit { kbps } => {
+            if kbps == Some(0) {
+                peer.send_message(ServerMessage::error(
+                    ErrorCode::BadRequest,
+                    "Receive limit must be greater than 0",
+                ))?;
+                return Ok(());
+            }
+            info!("Peer {} set receive limit to {:?} kbps", peer.id, kbps);
+            peer.set_max_send_kbps(kbps);
+        }
+
+        ClientMessage::Subscribe { audio, video } => {
+            room.subscribe(&peer.id, audio, video).await;
+        }
+
+        ClientMessage::SetTrackPaused {
+            source_id,
+            kind,
+            paused,
+        } => {
+            if let Err(e) = room.set_track_paused(&peer.id, &source_id, kind, paused).await {
+                warn!("Rejected track pause by peer {}: {}", peer.id, e);
+                peer.send_message(ServerMessage::error(ErrorCode::BadRequest, e.to_string()))?;
+            }
+        }
+
+        ClientMessage::SetSkipSilence { skip } => {
+            if let Err(e) = room.set_skip_silence(&peer, skip) {
+                peer.send_message(ServerMessage::error(ErrorCode::Forbidden, e.to_string()))?;
+            }
+        }
+
+        ClientMessage::SetRecording { enabled } => {
+            if let Err(e) = room.set_recording(&peer.id, enabled).await {
+                peer.send_message(ServerMessage::error(ErrorCode::Forbidden, e.to_string()))?;
+            }
+        }
+
+        ClientMessage::GetStats => {
+            let stats = peer.rtp_stats().await;
+            peer.send_message(ServerMessage::Stats { stats })?;
+        }
+
+        ClientMessage::StartScreenShare => {
+            if !room.config.screen_sharing_enabled {
+                warn!(
+                    "Peer {} tried to share screen in room {} where it is disabled",
+                    peer.id, room.id
+                );
+                peer.send_message(ServerMessage::error(
+                    ErrorCode::Forbidden,
+                    "Screen sharing is disabled in this room",
+                ))?;
+                return Ok(());
+            }
+            if let Err(e) = room.start_screen_share(&peer).await {
+                peer.send_message(ServerMessage::error(ErrorCode::Conflict, e.to_string()))?;
+            }
+        }
+
         ClientMessage::StopScreenShare => {
-            info!("Peer {} stopped screen sharing", peer.id);
-            peer.update_state(*peer.muted.read().await, *peer.video_on.read().await, false)
-                .await;
+            if let Err(e) = room.stop_screen_share(&peer).await {
+                peer.send_message(ServerMessage::error(ErrorCode::Conflict, e.to_string()))?;
+            }
+        }
 
-            room.broadcast_message(
-                &peer.id,
-                ServerMessage::ScreenShareStopped {
-                    participant_id: peer.id.clone(),
-                },
-            )
-            .await;
+        ClientMessage::Chat { text } => {
+            if let Err(e) = room.send_chat(&peer, text).await {
+                warn!("Rejected chat message from peer {}: {}", peer.id, e);
+                peer.send_message(ServerMessage::error(ErrorCode::PayloadTooLarge, e.to_string()))?;
+            }
+        }
+
+        ClientMessage::Reaction { emoji } => {
+            if !peer.reaction_limiter.check_rate_limit().await {
+                debug!("Peer {} is sending reactions too fast", peer.id);
+                peer.send_message(ServerMessage::error(
+                    ErrorCode::RateLimited,
+                    "Too many reactions",
+                ))?;
+            } else if let Err(e) = room.send_reaction(&peer, emoji).await {
+                warn!("Rejected reaction from peer {}: {}", peer.id, e);
+                peer.send_message(ServerMessage::error(ErrorCode::BadRequest, e.to_string()))?;
+            }
         }
 
         ClientMessage::Ping => {
@@ -347,29 +1124,201 @@ async fn handle_client_message(
         }
 
         ClientMessage::GetParticipants => {
-            let peers = room.get_all_peers().await;
-            let mut participants = Vec::new();
-
-            for p in peers {
-                if p.id != peer.id {
-                    let (muted, video_on, screen_sharing) = p.get_state().await;
-                    participants.push(ParticipantInfo::with_state(
-                        p.id.clone(),
-                        p.name.clone(),
-                        muted,
-                        video_on,
-                        screen_sharing,
-                    ));
-                }
-            }
-
+            let participants = room.participants_info(&peer.id).await;
             peer.send_message(ServerMessage::Participants { participants })?;
         }
 
-        ClientMessage::Join { .. } => {
+        ClientMessage::Join { .. } | ClientMessage::Rejoin { .. } => {
             warn!("Received duplicate join message from peer {}", peer.id);
         }
+
+        ClientMessage::Leave => {
+            // Выход обрабатывается в цикле соединения, который после него завершается
+        }
+
+        ClientMessage::SetRoomLocked { locked } => {
+            if let Err(e) = room.set_locked(&peer.id, locked).await {
+                warn!("Rejected room lock change by peer {}: {}", peer.id, e);
+                peer.send_message(ServerMessage::error(ErrorCode::Forbidden, e.to_string()))?;
+            }
+        }
+
+        ClientMessage::Admit {
+            participant_id,
+            approved,
+        } => {
+            if let Err(e) = room.admit(&peer.id, &participant_id, approved) {
+                warn!("Rejected admission decision by peer {}: {}", peer.id, e);
+                peer.send_message(ServerMessage::error(ErrorCode::Forbidden, e.to_string()))?;
+            }
+        }
+
+        ClientMessage::Spotlight { participant_id } => {
+            if let Err(e) = room.set_spotlight(&peer.id, participant_id).await {
+                peer.send_message(ServerMessage::error(ErrorCode::Forbidden, e.to_string()))?;
+            }
+        }
+
+        ClientMessage::RestoreTrack {
+            participant_id,
+            track_id,
+        } => {
+            if let Err(e) = room.restore_track(&peer.id, &participant_id, &track_id).await {
+                peer.send_message(ServerMessage::error(ErrorCode::Forbidden, e.to_string()))?;
+            }
+        }
+
+        ClientMessage::Kick { target_id } => {
+            if let Err(e) = room.kick(&peer.id, &target_id).await {
+                warn!("Rejected kick of {} by peer {}: {}", target_id, peer.id, e);
+                peer.send_message(ServerMessage::error(ErrorCode::Forbidden, e.to_string()))?;
+            }
+        }
+
+        ClientMessage::ForceMute { target_id, muted } => {
+            if let Err(e) = room.force_mute(&peer.id, &target_id, muted).await {
+                warn!("Rejected force mute of {} by peer {}: {}", target_id, peer.id, e);
+                peer.send_message(ServerMessage::error(ErrorCode::Forbidden, e.to_string()))?;
+            }
+        }
+
+        ClientMessage::GetEventLog => match room.event_log_for(&peer.id) {
+            Ok(events) => peer.send_message(ServerMessage::EventLog { events })?,
+            Err(e) => {
+                peer.send_message(ServerMessage::error(ErrorCode::Forbidden, e.to_string()))?;
+            }
+        },
+
+        ClientMessage::GetDiagnostics { .. }
+        | ClientMessage::ListRooms { .. }
+        | ClientMessage::WatchStats { .. } => {
+            warn!("Peer {} sent an admin request inside a session, ignoring", peer.id);
+        }
     }
 
     Ok(())
 }
+
+/// Проверяет токен администратора для служебных запросов
+fn is_admin(config: &ServerConfig, token: Option<&str>) -> bool {
+    matches!(
+        (config.admin_token.as_deref(), token),
+        (Some(expected), Some(token)) if expected == token
+    )
+}
+
+fn unauthorized() -> ServerMessage {
+    ServerMessage::error(ErrorCode::Unauthorized, "Unauthorized")
+}
+
+/// Формирует ответ на служебный запрос диагностического пакета
+async fn diagnostics_reply(
+    room_manager: &RoomManager,
+    config: &ServerConfig,
+    room_id: &str,
+    participant_id: &str,
+    token: Option<&str>,
+) -> ServerMessage {
+    if !is_admin(config, token) {
+        warn!("Rejected diagnostics request for {}/{}", room_id, participant_id);
+        return unauthorized();
+    }
+
+    let peer = match room_manager.get_room(room_id).await {
+        Some(room) => room.get_peer(participant_id).await,
+        None => None,
+    };
+
+    match peer {
+        Some(peer) => {
+            info!("Exporting diagnostics for {} in room {}", participant_id, room_id);
+            ServerMessage::Diagnostics {
+                bundle: peer.diagnostics_bundle(room_id).await,
+            }
+        }
+        None => ServerMessage::error(ErrorCode::NotFound, "Participant not found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Принимает одно соединение и обслуживает его как сервер с конфигурацией `config`
+    async fn serve_one(
+        config: ServerConfig,
+    ) -> (
+        tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<TcpStream>>,
+        tokio::task::JoinHandle<Result<()>>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            handle_connection(
+                stream,
+                MetricsCounter::new(),
+                Arc::new(RoomManager::new()),
+                Arc::new(config),
+                Arc::new(HandshakeLimiter::new(1)),
+                Arc::new(ReconnectTokens::new(Duration::from_secs(60), MAX_RECONNECT_TOKENS)),
+                None,
+            )
+            .await
+        });
+
+        let (mut client, _) =
+            tokio_tungstenite::connect_async(format!("ws://{}", addr)).await.unwrap();
+        let join = ClientMessage::Join {
+            room: "pings".to_string(),
+            participant: "alice".to_string(),
+            name: "Alice".to_string(),
+            password: None,
+            resume_sid: None,
+            reconnect_token: None,
+        };
+        client
+            .send(Message::text(serde_json::to_string(&join).unwrap()))
+            .await
+            .unwrap();
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_ping_closes_connection() {
+        let config = ServerConfig {
+            ws_ping_interval_secs: 1,
+            offer_timeout_secs: 60,
+            ..ServerConfig::default()
+        };
+        // Клиент ничего не читает и поэтому не отвечает на ping
+        let (_client, server) = serve_one(config).await;
+
+        let result = tokio::time::timeout(Duration::from_secs(5), server).await;
+        assert!(result.is_ok(), "connection without pong was not closed");
+    }
+
+    #[tokio::test]
+    async fn test_ping_message_answered_with_pong() {
+        let config = ServerConfig {
+            offer_timeout_secs: 60,
+            ..ServerConfig::default()
+        };
+        let (mut client, _server) = serve_one(config).await;
+        let ping = serde_json::to_string(&ClientMessage::Ping).unwrap();
+        client.send(Message::text(ping)).await.unwrap();
+
+        let pong = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(Ok(msg)) = client.next().await {
+                if let Message::Text(text) = msg {
+                    if let Ok(ServerMessage::Pong) = serde_json::from_str(&text) {
+                        return true;
+                    }
+                }
+            }
+            false
+        })
+        .await;
+        assert_eq!(pong.ok(), Some(true));
+    }
+}
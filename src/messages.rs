@@ -1,5 +1,11 @@
 use serde::{Deserialize, Serialize};
 
+use crate::archive::RoomEvent;
+use crate::diagnostics::DiagnosticsBundle;
+use crate::peer::TrackType;
+use crate::signaling::SendPriority;
+use crate::simulcast::SimulcastLayer;
+
 /// Сообщения от клиента к серверу
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
@@ -10,13 +16,31 @@ pub enum ClientMessage {
         room: String,
         participant: String,
         name: String,
+        /// Пароль комнаты; первый вошедший задает его, если в конфигурации пароля нет
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+        /// Id участника, сессию которого возобновить после обрыва (вместо `participant`)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_sid: Option<String>,
+        /// Токен из `Joined` этой сессии: без него `resume_sid` не принимается
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reconnect_token: Option<String>,
     },
 
-    /// WebRTC Offer
+    /// Переподключение по одноразовому токену из `Joined` (вместо `Join`)
+    #[serde(rename = "rejoin")]
+    Rejoin { token: String },
+
+    /// WebRTC Offer. `seq` - возрастающий номер offer клиента: устаревшие offer
+    /// (с номером меньше последнего полученного) отбрасываются
     #[serde(rename = "offer")]
-    Offer { sdp: String },
+    Offer {
+        sdp: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+    },
 
-    /// WebRTC Answer (в некоторых случаях клиент может отправлять answer)
+    /// Ответ клиента на offer сервера (renegotiation)
     #[serde(rename = "answer")]
     Answer { sdp: String },
 
@@ -24,6 +48,11 @@ pub enum ClientMessage {
     #[serde(rename = "candidate")]
     Candidate { candidate: String },
 
+    /// Запрос ICE restart (например, после смены сети); сервер отвечает `offer`
+    /// с новыми ICE учетными данными
+    #[serde(rename = "ice_restart")]
+    IceRestart,
+
     /// Обновление состояния участника (mute, video, screen sharing)
     #[serde(rename = "state_update")]
     StateUpdate {
@@ -40,6 +69,63 @@ pub enum ClientMessage {
     #[serde(rename = "stop_screen_share")]
     StopScreenShare,
 
+    /// Поднять или опустить руку
+    #[serde(rename = "raise_hand")]
+    RaiseHand { raised: bool },
+
+    /// Текстовое сообщение в чат комнаты
+    #[serde(rename = "chat")]
+    Chat { text: String },
+
+    /// Эмодзи реакция, показываемая всем участникам
+    #[serde(rename = "reaction")]
+    Reaction { emoji: String },
+
+    /// Выбрать качество simulcast видео, которое участник получает от `target_id`
+    #[serde(rename = "set_layer")]
+    SetLayer {
+        target_id: String,
+        layer: SimulcastLayer,
+    },
+
+    /// Ограничить скорость пересылки участнику (кбит/с); без `kbps` лимит снимается
+    #[serde(rename = "set_receive_limit")]
+    SetReceiveLimit {
+        #[serde(default)]
+        kbps: Option<u32>,
+    },
+
+    /// Получать аудио и видео только от перечисленных участников;
+    /// без списка - от всех (по умолчанию)
+    #[serde(rename = "subscribe")]
+    Subscribe {
+        #[serde(default)]
+        audio: Option<Vec<String>>,
+        #[serde(default)]
+        video: Option<Vec<String>>,
+    },
+
+    /// Приостановить или возобновить получение трека участника (`audio`, `camera`
+    /// или `screen`) без renegotiation
+    #[serde(rename = "set_track_paused")]
+    SetTrackPaused {
+        source_id: String,
+        kind: TrackType,
+        paused: bool,
+    },
+
+    /// Не получать пакеты тишины (Opus DTX), если комната это разрешает
+    #[serde(rename = "set_skip_silence")]
+    SetSkipSilence { skip: bool },
+
+    /// Включить или остановить запись комнаты (только для ведущего)
+    #[serde(rename = "set_recording")]
+    SetRecording { enabled: bool },
+
+    /// Запрос RTP статистики своего соединения с сервером
+    #[serde(rename = "get_stats")]
+    GetStats,
+
     /// Ping для проверки соединения
     #[serde(rename = "ping")]
     Ping,
@@ -47,6 +133,68 @@ pub enum ClientMessage {
     /// Запрос списка участников
     #[serde(rename = "get_participants")]
     GetParticipants,
+
+    /// Явный выход из комнаты; сервер отвечает `left_room` и закрывает сессию
+    #[serde(rename = "leave")]
+    Leave,
+
+    /// Запрос диагностического пакета сессии (только для администратора,
+    /// отправляется первым сообщением вместо join)
+    #[serde(rename = "get_diagnostics")]
+    GetDiagnostics {
+        room: String,
+        participant: String,
+        token: Option<String>,
+    },
+
+    /// Список комнат с участниками (только для администратора,
+    /// отправляется первым сообщением вместо join)
+    #[serde(rename = "list_rooms")]
+    ListRooms { token: Option<String> },
+
+    /// Подписка на периодическую статистику комнаты (только для администратора,
+    /// отправляется первым сообщением вместо join)
+    #[serde(rename = "watch_stats")]
+    WatchStats { room: String, token: Option<String> },
+
+    /// Выбрать участника в центр внимания для всех (только для ведущего);
+    /// `None` снимает выбор
+    #[serde(rename = "spotlight")]
+    Spotlight { participant_id: Option<String> },
+
+    /// Снять серверное отключение трека участника (только для ведущего)
+    #[serde(rename = "restore_track")]
+    RestoreTrack {
+        participant_id: String,
+        track_id: String,
+    },
+
+    /// Удалить участника из комнаты (только для ведущего)
+    #[serde(rename = "kick")]
+    Kick { target_id: String },
+
+    /// Принудительно выключить или включить микрофон участника (только для ведущего)
+    #[serde(rename = "force_mute")]
+    ForceMute { target_id: String, muted: bool },
+
+    /// Журнал последних событий участников комнаты (только для ведущего)
+    #[serde(rename = "get_event_log")]
+    GetEventLog,
+
+    /// Сменить свое отображаемое имя
+    #[serde(rename = "rename")]
+    Rename { name: String },
+
+    /// Закрыть или открыть комнату для новых участников (только для ведущего)
+    #[serde(rename = "set_room_locked")]
+    SetRoomLocked { locked: bool },
+
+    /// Решение по заявке на вход при ручном допуске (только для ведущего)
+    #[serde(rename = "admit")]
+    Admit {
+        participant_id: String,
+        approved: bool,
+    },
 }
 
 /// Сообщения от сервера к клиенту
@@ -58,13 +206,27 @@ pub enum ServerMessage {
     Joined {
         your_id: String,
         participants: Vec<ParticipantInfo>,
+        /// Участник в центре внимания, если выбран
+        #[serde(default)]
+        spotlight: Option<String>,
+        /// Одноразовый токен для `Rejoin` после обрыва соединения
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reconnect_token: Option<String>,
+        /// ICE серверы для соединения клиента, с временными учетными данными TURN
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        ice_servers: Vec<IceServerConfig>,
     },
 
-    /// WebRTC Answer
+    /// WebRTC Answer; `seq` - номер offer, на который дан ответ
     #[serde(rename = "answer")]
-    Answer { sdp: String },
+    Answer {
+        sdp: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+    },
 
-    /// WebRTC Offer (для renegotiation)
+    /// Offer сервера (renegotiation): после согласования добавились слоты для
+    /// медиа других участников; клиент отвечает `answer`
     #[serde(rename = "offer")]
     Offer { sdp: String },
 
@@ -80,6 +242,14 @@ pub enum ServerMessage {
     #[serde(rename = "participant_left")]
     ParticipantLeft { participant_id: String },
 
+    /// Участник сменил отображаемое имя
+    #[serde(rename = "participant_renamed")]
+    ParticipantRenamed { participant_id: String, name: String },
+
+    /// Участник переподключился с сохранением сессии: его треки придут заново
+    #[serde(rename = "participant_reconnected")]
+    ParticipantReconnected { participant_id: String },
+
     /// Обновление состояния участника
     #[serde(rename = "state_update")]
     StateUpdate {
@@ -89,6 +259,10 @@ pub enum ServerMessage {
         screen_sharing: bool,
     },
 
+    /// Результат явного выхода из комнаты
+    #[serde(rename = "left_room")]
+    LeftRoom { success: bool, message: String },
+
     /// Список участников комнаты
     #[serde(rename = "participants")]
     Participants {
@@ -103,17 +277,187 @@ pub enum ServerMessage {
     #[serde(rename = "screen_share_stopped")]
     ScreenShareStopped { participant_id: String },
 
+    /// Участник поднял или опустил руку
+    #[serde(rename = "hand_state")]
+    HandState { participant_id: String, raised: bool },
+
+    /// Сообщение чата от участника; время проставляет сервер (мс Unix)
+    #[serde(rename = "chat")]
+    Chat {
+        from_id: String,
+        from_name: String,
+        text: String,
+        ts: u64,
+    },
+
+    /// Эмодзи реакция участника; время проставляет сервер (мс Unix)
+    #[serde(rename = "reaction")]
+    Reaction {
+        participant_id: String,
+        emoji: String,
+        ts: u64,
+    },
+
     /// Pong ответ на ping
     #[serde(rename = "pong")]
     Pong,
 
-    /// Сообщение об ошибке
+    /// Сообщение об ошибке; `code` - HTTP-подобный код из `ErrorCode`,
+    /// по которому клиент выбирает реакцию, `message` - текст для человека
     #[serde(rename = "error")]
     Error { message: String, code: Option<u32> },
 
     /// ICE gathering завершен
     #[serde(rename = "ice_gathering_complete")]
     IceGatheringComplete,
+
+    /// В комнате набралось минимальное число участников, медиа пересылается
+    #[serde(rename = "room_started")]
+    RoomStarted,
+
+    /// Участников стало меньше минимума, пересылка медиа приостановлена
+    #[serde(rename = "room_paused")]
+    RoomPaused,
+
+    /// Диагностический пакет сессии
+    #[serde(rename = "diagnostics")]
+    Diagnostics { bundle: DiagnosticsBundle },
+
+    /// Активные комнаты и их участники
+    #[serde(rename = "rooms")]
+    Rooms { rooms: Vec<RoomListing> },
+
+    /// Очередной снимок статистики комнаты для подписчика `watch_stats`
+    #[serde(rename = "room_stats")]
+    RoomStats { stats: RoomStats },
+
+    /// Ведущий включил или остановил запись комнаты
+    #[serde(rename = "recording_changed")]
+    RecordingChanged { enabled: bool },
+
+    /// RTP статистика соединения участника в ответ на `get_stats`
+    #[serde(rename = "stats")]
+    Stats { stats: PeerStats },
+
+    /// Изменилось состояние соединения участника с сервером
+    #[serde(rename = "connection_state_changed")]
+    ConnectionStateChanged { state: String, ice_state: String },
+
+    /// Ведущий сменил участника в центре внимания
+    #[serde(rename = "spotlight_changed")]
+    SpotlightChanged { participant_id: Option<String> },
+
+    /// Сменился активный говорящий; `None` - в комнате тишина
+    #[serde(rename = "active_speaker")]
+    ActiveSpeaker { participant_id: Option<String> },
+
+    /// Сервер перестал пересылать трек участника (например, из-за флуда пакетами)
+    #[serde(rename = "track_server_muted")]
+    TrackServerMuted {
+        participant_id: String,
+        track_id: String,
+        reason: String,
+    },
+
+    /// Ведущий восстановил пересылку трека
+    #[serde(rename = "track_restored")]
+    TrackRestored {
+        participant_id: String,
+        track_id: String,
+    },
+
+    /// Участник удален из комнаты ведущим; после этого сервер закрывает соединение
+    #[serde(rename = "kicked")]
+    Kicked { reason: String },
+
+    /// Участник ждет допуска в комнату (ручной допуск); ведущий отвечает `admit`
+    #[serde(rename = "join_request")]
+    JoinRequest { participant_id: String, name: String },
+
+    /// Ведущий закрыл или открыл комнату для новых участников
+    #[serde(rename = "room_locked")]
+    RoomLocked { locked: bool },
+
+    /// Журнал событий комнаты в ответ на `get_event_log`, от старых к новым
+    #[serde(rename = "event_log")]
+    EventLog { events: Vec<RoomEvent> },
+}
+
+/// Коды ошибок `ServerMessage::Error` (совпадают с кодами HTTP)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// Некорректное сообщение или параметры
+    BadRequest,
+    /// Неверный пароль, токен или отсутствие прав администратора
+    Unauthorized,
+    /// Действие доступно только ведущему или запрещено в комнате
+    Forbidden,
+    /// В комнате нет мест
+    RoomFull,
+    NotFound,
+    /// Клиент не успел выполнить шаг сигналинга
+    Timeout,
+    /// Действие противоречит текущему состоянию сессии
+    Conflict,
+    PayloadTooLarge,
+    /// Ведущий закрыл комнату для новых участников
+    Locked,
+    /// Ни один кодек offer не разрешен в комнате
+    UnsupportedMedia,
+    /// Клиент отправляет сообщения слишком часто
+    RateLimited,
+    Internal,
+    /// Сервер перегружен или останавливается
+    Unavailable,
+}
+
+impl ErrorCode {
+    pub fn code(self) -> u32 {
+        match self {
+            Self::BadRequest => 400,
+            Self::Unauthorized => 401,
+            Self::Forbidden | Self::RoomFull => 403,
+            Self::NotFound => 404,
+            Self::Timeout => 408,
+            Self::Conflict => 409,
+            Self::PayloadTooLarge => 413,
+            Self::UnsupportedMedia => 415,
+            Self::Locked => 423,
+            Self::RateLimited => 429,
+            Self::Internal => 500,
+            Self::Unavailable => 503,
+        }
+    }
+}
+
+impl ServerMessage {
+    /// Ошибка с кодом для клиента и текстом для человека
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        ServerMessage::Error {
+            message: message.into(),
+            code: Some(code.code()),
+        }
+    }
+
+    /// Ответ на сообщение клиента, которое не удалось разобрать
+    pub fn malformed(error: &serde_json::Error) -> Self {
+        Self::error(ErrorCode::BadRequest, format!("Malformed message: {}", error))
+    }
+
+    /// Периодическое и устаревающее состояние можно отбросить у медленного клиента:
+    /// следующее обновление его заменит
+    pub fn priority(&self) -> SendPriority {
+        match self {
+            ServerMessage::StateUpdate { .. }
+            | ServerMessage::Reaction { .. }
+            | ServerMessage::Pong
+            | ServerMessage::RoomStats { .. }
+            | ServerMessage::Stats { .. }
+            | ServerMessage::ConnectionStateChanged { .. }
+            | ServerMessage::ActiveSpeaker { .. } => SendPriority::Droppable,
+            _ => SendPriority::Critical,
+        }
+    }
 }
 
 /// Информация об участнике
@@ -124,9 +468,12 @@ pub struct ParticipantInfo {
     pub muted: bool,
     pub video_on: bool,
     pub screen_sharing: bool,
+    #[serde(default)]
+    pub hand_raised: bool,
 }
 
 impl ParticipantInfo {
+    #[allow(dead_code)]
     pub fn new(id: String, name: String) -> Self {
         Self {
             id,
@@ -134,6 +481,7 @@ impl ParticipantInfo {
             muted: false,
             video_on: true,
             screen_sharing: false,
+            hand_raised: false,
         }
     }
 
@@ -150,8 +498,14 @@ impl ParticipantInfo {
             muted,
             video_on,
             screen_sharing,
+            hand_raised: false,
         }
     }
+
+    pub fn with_hand_raised(mut self, hand_raised: bool) -> Self {
+        self.hand_raised = hand_raised;
+        self
+    }
 }
 
 /// Конфигурация ICE серверов для передачи клиенту
@@ -164,12 +518,52 @@ pub struct IceServerConfig {
     pub credential: Option<String>,
 }
 
+/// Активная комната в ответе на `list_rooms`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RoomListing {
+    pub room_id: String,
+    pub participant_count: usize,
+    pub participants: Vec<String>,
+}
+
 /// Статистика для мониторинга
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct RoomStats {
     pub room_id: String,
     pub participant_count: usize,
     pub active_tracks: usize,
+    /// Сумма RTP статистики участников комнаты
+    #[serde(default)]
+    pub rtp: PeerStats,
+}
+
+/// RTP статистика соединения участника с сервером (из getStats webrtc-rs).
+/// `received` - медиа от участника, `sent` - пересланное ему сервером
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerStats {
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    /// Потери отправленных сервером пакетов по RTCP отчетам участника
+    pub packets_lost: i64,
+    /// NACK запросы участника на повтор пакетов
+    pub nack_count: u64,
+    /// Последний RTT по RTCP отчетам (миллисекунды); для суммы - худший
+    pub round_trip_time_ms: Option<u64>,
+}
+
+impl PeerStats {
+    /// Прибавляет статистику другого соединения (для сводки по комнате)
+    pub fn merge(&mut self, other: &PeerStats) {
+        self.packets_received += other.packets_received;
+        self.bytes_received += other.bytes_received;
+        self.packets_sent += other.packets_sent;
+        self.bytes_sent += other.bytes_sent;
+        self.packets_lost += other.packets_lost;
+        self.nack_count += other.nack_count;
+        self.round_trip_time_ms = self.round_trip_time_ms.max(other.round_trip_time_ms);
+    }
 }
 
 #[cfg(test)]
@@ -182,6 +576,9 @@ mod tests {
             room: "room1".to_string(),
             participant: "user123".to_string(),
             name: "John Doe".to_string(),
+            password: None,
+            resume_sid: None,
+            reconnect_token: None,
         };
 
         let json = serde_json::to_string(&msg).unwrap();
@@ -195,13 +592,45 @@ mod tests {
         let msg: ClientMessage = serde_json::from_str(json).unwrap();
 
         match msg {
-            ClientMessage::Offer { sdp } => {
+            ClientMessage::Offer { sdp, seq } => {
                 assert_eq!(sdp, "v=0\r\n...");
+                assert_eq!(seq, None);
             }
             _ => panic!("Wrong message type"),
         }
     }
 
+    #[test]
+    fn test_peer_stats_merge_and_messages() {
+        let msg: ClientMessage = serde_json::from_str(r#"{"type":"get_stats"}"#).unwrap();
+        assert!(matches!(msg, ClientMessage::GetStats));
+
+        let mut total = PeerStats {
+            packets_received: 10,
+            bytes_received: 1000,
+            packets_lost: 2,
+            round_trip_time_ms: Some(40),
+            ..Default::default()
+        };
+        total.merge(&PeerStats {
+            packets_sent: 5,
+            bytes_sent: 500,
+            packets_lost: 1,
+            nack_count: 3,
+            round_trip_time_ms: Some(120),
+            ..Default::default()
+        });
+        assert_eq!(total.packets_received, 10);
+        assert_eq!(total.packets_sent, 5);
+        assert_eq!(total.packets_lost, 3);
+        assert_eq!(total.nack_count, 3);
+        assert_eq!(total.round_trip_time_ms, Some(120));
+
+        let json = serde_json::to_string(&ServerMessage::Stats { stats: total }).unwrap();
+        assert!(json.contains("\"type\":\"stats\""));
+        assert!(json.contains("\"round_trip_time_ms\":120"));
+    }
+
     #[test]
     fn test_participant_info() {
         let info = ParticipantInfo::new("user1".to_string(), "Alice".to_string());
@@ -217,9 +646,90 @@ mod tests {
         let msg = ServerMessage::Joined {
             your_id: "abc123".to_string(),
             participants: vec![],
+            spotlight: None,
+            reconnect_token: None,
+            ice_servers: vec![],
         };
 
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains("\"type\":\"joined\""));
     }
+
+    #[test]
+    fn test_malformed_message_reply() {
+        for garbage in ["not json", r#"{"type":"teleport"}"#, r#"{"type":"chat"}"#] {
+            let error = serde_json::from_str::<ClientMessage>(garbage).unwrap_err();
+            let ServerMessage::Error { message, code } = ServerMessage::malformed(&error) else {
+                panic!("malformed reply is not an error");
+            };
+            assert_eq!(code, Some(400));
+            assert_eq!(message, format!("Malformed message: {}", error));
+        }
+    }
+
+    #[test]
+    fn test_error_codes_for_reject_paths() {
+        // Отказы при входе: неверный запрос, пароль, заполненная комната, лимит, перегрузка
+        for (code, expected) in [
+            (ErrorCode::BadRequest, 400),
+            (ErrorCode::Unauthorized, 401),
+            (ErrorCode::RoomFull, 403),
+            (ErrorCode::Locked, 423),
+            (ErrorCode::RateLimited, 429),
+            (ErrorCode::Internal, 500),
+            (ErrorCode::Unavailable, 503),
+        ] {
+            let json = serde_json::to_value(ServerMessage::error(code, "Room is full")).unwrap();
+            assert_eq!(
+                json,
+                serde_json::json!({"type": "error", "message": "Room is full", "code": expected})
+            );
+        }
+    }
+
+    #[test]
+    fn test_join_round_trip() {
+        let json = r#"{"type":"join","room":"room1","participant":"user123","name":"John Doe"}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        let reencoded = serde_json::to_value(&msg).unwrap();
+        assert_eq!(reencoded, serde_json::from_str::<serde_json::Value>(json).unwrap());
+
+        match serde_json::from_value::<ClientMessage>(reencoded).unwrap() {
+            ClientMessage::Join {
+                room,
+                participant,
+                name,
+                password,
+                resume_sid,
+                reconnect_token,
+            } => {
+                assert_eq!(room, "room1");
+                assert_eq!(participant, "user123");
+                assert_eq!(name, "John Doe");
+                assert_eq!(password, None);
+                assert_eq!(resume_sid, None);
+                assert_eq!(reconnect_token, None);
+            }
+            _ => panic!("Wrong message type"),
+        }
+
+        // Joined без необязательных полей сохраняет прежнюю форму
+        let joined = serde_json::to_value(ServerMessage::Joined {
+            your_id: "user123".to_string(),
+            participants: vec![],
+            spotlight: None,
+            reconnect_token: None,
+            ice_servers: vec![],
+        })
+        .unwrap();
+        assert_eq!(
+            joined,
+            serde_json::json!({
+                "type": "joined",
+                "your_id": "user123",
+                "participants": [],
+                "spotlight": null
+            })
+        );
+    }
 }
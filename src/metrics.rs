@@ -0,0 +1,81 @@
+use anyhow::Result;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use log::info;
+use tokio::net::TcpListener;
+
+use crate::utils::MetricsCounter;
+
+/// Content-Type текстового формата Prometheus
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// HTTP сервер с `/metrics` для Prometheus
+pub async fn serve(listener: TcpListener, metrics: MetricsCounter) -> Result<()> {
+    info!("Metrics endpoint listening on {}", listener.local_addr()?);
+    let app = Router::new().route(
+        "/metrics",
+        get(move || {
+            let metrics = metrics.clone();
+            async move {
+                (
+                    [(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)],
+                    metrics.to_prometheus(),
+                )
+                    .into_response()
+            }
+        }),
+    );
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn test_metrics_endpoint_exposition_format() {
+        let metrics = MetricsCounter::new();
+        metrics.increment_packets_sent(3);
+        metrics.increment_bytes_sent(1200);
+        metrics.increment_connections();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, metrics));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert!(head.to_ascii_lowercase().contains("content-type: text/plain; version=0.0.4"));
+
+        // Каждая строка - комментарий HELP/TYPE или `имя значение`
+        let mut samples = std::collections::HashMap::new();
+        for line in body.lines().filter(|line| !line.is_empty()) {
+            if let Some(comment) = line.strip_prefix("# ") {
+                assert!(comment.starts_with("HELP ") || comment.starts_with("TYPE "));
+                continue;
+            }
+            let (name, value) = line.split_once(' ').unwrap();
+            assert!(name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':'));
+            samples.insert(name.to_string(), value.parse::<f64>().unwrap());
+        }
+        assert_eq!(samples["rrtc_packets_sent_total"], 3.0);
+        assert_eq!(samples["rrtc_bytes_sent_total"], 1200.0);
+        assert_eq!(samples["rrtc_connections_active"], 1.0);
+        assert!(samples.contains_key("rrtc_uptime_seconds"));
+    }
+}
@@ -0,0 +1,517 @@
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use webrtc::rtp::packet::Packet;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
+use webrtc::track::track_local::{TrackLocal, TrackLocalWriter};
+
+use crate::utils::MetricsCounter;
+
+/// Политика выбора пакета для отбрасывания при переполнении очереди
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropPolicy {
+    /// Отбрасывать самый старый пакет независимо от типа
+    DropOldest,
+    /// Сначала старые дельта-кадры видео, затем ключевые кадры, аудио - в последнюю очередь
+    #[default]
+    MediaAware,
+}
+
+impl std::str::FromStr for DropPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop_oldest" => Ok(Self::DropOldest),
+            "media_aware" => Ok(Self::MediaAware),
+            other => Err(format!("unknown drop policy: {}", other)),
+        }
+    }
+}
+
+/// Класс трафика для приоритетной отправки: аудио, видео, остальное (данные)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrafficClass {
+    Audio,
+    Video,
+    Data,
+}
+
+impl TrafficClass {
+    /// Классы в порядке приоритета
+    const ALL: [TrafficClass; 3] = [TrafficClass::Audio, TrafficClass::Video, TrafficClass::Data];
+
+    fn of(kind: RTPCodecType) -> Self {
+        match kind {
+            RTPCodecType::Audio => Self::Audio,
+            RTPCodecType::Video => Self::Video,
+            _ => Self::Data,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Веса классов трафика: сколько пакетов класса отправляется за один цикл,
+/// прежде чем очередь перейдет к менее приоритетным классам
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClassWeights {
+    pub audio: u32,
+    pub video: u32,
+    pub data: u32,
+}
+
+impl Default for ClassWeights {
+    fn default() -> Self {
+        Self {
+            audio: 8,
+            video: 4,
+            data: 1,
+        }
+    }
+}
+
+impl ClassWeights {
+    fn get(&self, class: TrafficClass) -> u32 {
+        match class {
+            TrafficClass::Audio => self.audio,
+            TrafficClass::Video => self.video,
+            TrafficClass::Data => self.data,
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.audio == 0 || self.video == 0 || self.data == 0 {
+            anyhow::bail!("Outbound class weights must be greater than 0");
+        }
+        Ok(())
+    }
+}
+
+/// Пакет, ожидающий отправки участнику
+pub struct OutboundPacket {
+    pub track: Arc<TrackLocalStaticRTP>,
+    pub packet: Packet,
+    pub kind: RTPCodecType,
+    /// Пакет принадлежит ключевому кадру
+    pub keyframe: bool,
+    /// Когда пакет получен от издателя (для задержки пересылки)
+    pub received_at: Instant,
+}
+
+impl OutboundPacket {
+    /// Чем меньше ранг, тем раньше пакет отбрасывается
+    fn drop_rank(&self) -> u8 {
+        match (self.kind, self.keyframe) {
+            (RTPCodecType::Audio, _) => 2,
+            (_, true) => 1,
+            (_, false) => 0,
+        }
+    }
+}
+
+/// Счетчики отброшенных пакетов по категориям
+#[derive(Debug, Default)]
+pub struct DropCounters {
+    pub audio: AtomicU64,
+    pub video_delta: AtomicU64,
+    pub video_keyframe: AtomicU64,
+}
+
+impl DropCounters {
+    /// Учитывает отброшенный пакет у участника и, если задан, в счетчиках сервера
+    fn record(&self, packet: &OutboundPacket, metrics: Option<&MetricsCounter>) {
+        let counter = match packet.drop_rank() {
+            2 => &self.audio,
+            1 => &self.video_keyframe,
+            _ => &self.video_delta,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if let Some(metrics) = metrics {
+            match packet.drop_rank() {
+                2 => metrics.increment_outbound_audio_dropped(),
+                1 => metrics.increment_outbound_video_keyframe_dropped(),
+                _ => metrics.increment_outbound_video_delta_dropped(),
+            }
+        }
+    }
+}
+
+/// Окно, в котором измеряется скорость отправки участнику
+const SEND_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Грубое ограничение скорости отправки участнику (`set_receive_limit`) до
+/// TWCC адаптации: байты считаются в скользящем окне, сверх лимита отбрасываются
+/// дельта-кадры видео, ключевые кадры - только сверх двойного лимита, чтобы
+/// получатель мог восстановить картинку. Аудио не ограничивается
+#[derive(Debug, Default)]
+pub struct SendRateCap {
+    limit_bps: Option<u64>,
+    sent: VecDeque<(Instant, u64)>,
+    window_bytes: u64,
+}
+
+impl SendRateCap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn limit_bps(&self) -> Option<u64> {
+        self.limit_bps
+    }
+
+    pub fn set_limit_bps(&mut self, limit_bps: Option<u64>) {
+        self.limit_bps = limit_bps;
+    }
+
+    /// Решает, отправлять ли пакет размером `bytes`; отправленные пакеты учитываются
+    pub fn allow(&mut self, bytes: u64, kind: RTPCodecType, keyframe: bool, now: Instant) -> bool {
+        self.expire(now);
+        if let (Some(limit), RTPCodecType::Video) = (self.limit_bps, kind) {
+            let budget = limit / 8 * SEND_RATE_WINDOW.as_secs();
+            let budget = if keyframe { budget * 2 } else { budget };
+            if self.window_bytes + bytes > budget {
+                return false;
+            }
+        }
+        self.sent.push_back((now, bytes));
+        self.window_bytes += bytes;
+        true
+    }
+
+    /// Скорость отправки за последнее окно (бит/с)
+    pub fn rate_bps(&mut self, now: Instant) -> u64 {
+        self.expire(now);
+        self.window_bytes * 8 / SEND_RATE_WINDOW.as_secs()
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(at, bytes)) = self.sent.front() {
+            if now.duration_since(at) < SEND_RATE_WINDOW {
+                break;
+            }
+            self.sent.pop_front();
+            self.window_bytes -= bytes;
+        }
+    }
+}
+
+/// Ограниченная очередь исходящих пакетов одного участника.
+/// Пересылка в комнате только ставит пакеты в очередь, отдельная задача
+/// пишет их в локальные треки, поэтому медленный получатель не тормозит остальных.
+/// Пакеты отправляются по классам: в каждом цикле сначала аудио, затем видео,
+/// затем данные, каждый класс - не больше своего веса.
+pub struct OutboundQueue {
+    packets: Mutex<VecDeque<OutboundPacket>>,
+    capacity: usize,
+    policy: DropPolicy,
+    weights: ClassWeights,
+    /// Сколько пакетов каждый класс еще может отправить в текущем цикле
+    credits: Mutex<[u32; 3]>,
+    notify: Notify,
+    closed: AtomicBool,
+    pub drops: DropCounters,
+    /// Куда записываются задержка пересылки и ошибки записи в трек
+    metrics: OnceLock<MetricsCounter>,
+}
+
+impl OutboundQueue {
+    pub fn new(capacity: usize, policy: DropPolicy) -> Self {
+        Self {
+            packets: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: capacity.max(1),
+            policy,
+            weights: ClassWeights::default(),
+            credits: Mutex::new([0; 3]),
+            notify: Notify::new(),
+            closed: AtomicBool::new(false),
+            drops: DropCounters::default(),
+            metrics: OnceLock::new(),
+        }
+    }
+
+    /// Учитывать задержку пересылки, ошибки записи и отброшенные пакеты в счетчиках сервера
+    pub fn report_to(&self, metrics: MetricsCounter) {
+        let _ = self.metrics.set(metrics);
+    }
+
+    /// Ставит пакет в очередь; при переполнении отбрасывает пакет по политике
+    pub fn push(&self, packet: OutboundPacket) {
+        if self.closed.load(Ordering::Relaxed) {
+            return;
+        }
+
+        {
+            let mut packets = self.packets.lock().unwrap();
+            packets.push_back(packet);
+            if packets.len() > self.capacity {
+                let victim = match self.policy {
+                    DropPolicy::DropOldest => 0,
+                    // min_by_key возвращает первый минимум, то есть самый старый пакет ранга
+                    DropPolicy::MediaAware => packets
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, p)| p.drop_rank())
+                        .map(|(i, _)| i)
+                        .unwrap_or(0),
+                };
+                if let Some(dropped) = packets.remove(victim) {
+                    self.drops.record(&dropped, self.metrics.get());
+                }
+            }
+        }
+
+        self.notify.notify_one();
+    }
+
+    /// Задает веса классов трафика
+    pub fn with_class_weights(mut self, weights: ClassWeights) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    /// Извлекает самый старый пакет самого приоритетного класса, у которого
+    /// остались кредиты; когда кредиты ожидающих классов исчерпаны, начинается новый цикл
+    pub fn pop(&self) -> Option<OutboundPacket> {
+        let mut packets = self.packets.lock().unwrap();
+        let mut credits = self.credits.lock().unwrap();
+
+        for _ in 0..2 {
+            for class in TrafficClass::ALL {
+                if credits[class.index()] == 0 {
+                    continue;
+                }
+                if let Some(position) = packets
+                    .iter()
+                    .position(|p| TrafficClass::of(p.kind) == class)
+                {
+                    credits[class.index()] -= 1;
+                    return packets.remove(position);
+                }
+            }
+
+            if packets.is_empty() {
+                return None;
+            }
+            for class in TrafficClass::ALL {
+                credits[class.index()] = self.weights.get(class).max(1);
+            }
+        }
+
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Останавливает задачу отправки и очищает очередь
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.packets.lock().unwrap().clear();
+        self.notify.notify_one();
+    }
+
+    /// Запускает задачу, которая пишет пакеты из очереди в треки
+    pub fn spawn_writer(self: &Arc<Self>, peer_id: String) {
+        let queue = self.clone();
+        tokio::spawn(async move {
+            while !queue.closed.load(Ordering::Relaxed) {
+                let Some(item) = queue.pop() else {
+                    queue.notify.notified().await;
+                    continue;
+                };
+
+                let result = item.track.write_rtp(&item.packet).await;
+                if let Some(metrics) = queue.metrics.get() {
+                    metrics.observe_forward_latency(item.received_at.elapsed());
+                }
+                if let Err(e) = result {
+                    queue.record_write_error(&peer_id, item.track.id(), &e);
+                }
+            }
+            debug!("Outbound writer for peer {} stopped", peer_id);
+        });
+    }
+
+    /// Учитывает ошибку записи пакета в трек. Закрытое соединение - штатное
+    /// завершение, остальные ошибки считаются потерей пакета
+    fn record_write_error(&self, peer_id: &str, track_id: &str, error: &webrtc::Error) {
+        let error_str = error.to_string();
+        if error_str.contains("InvalidState") {
+            // Соединение закрыто, это нормально
+            debug!("Track closed for peer {}", peer_id);
+            return;
+        }
+        error!(
+            "Error writing RTP packet to peer {} track {}: {}",
+            peer_id, track_id, error_str
+        );
+        if let Some(metrics) = self.metrics.get() {
+            metrics.increment_packets_dropped();
+        }
+    }
+
+    /// Сводка счетчиков отброшенных пакетов
+    pub fn drop_stats(&self) -> String {
+        format!(
+            "dropped audio={}, video_delta={}, video_keyframe={}",
+            self.drops.audio.load(Ordering::Relaxed),
+            self.drops.video_delta.load(Ordering::Relaxed),
+            self.drops.video_keyframe.load(Ordering::Relaxed)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+
+    fn packet(
+        track: &Arc<TrackLocalStaticRTP>,
+        kind: RTPCodecType,
+        keyframe: bool,
+        seq: u16,
+    ) -> OutboundPacket {
+        let mut packet = Packet::default();
+        packet.header.sequence_number = seq;
+        OutboundPacket {
+            track: track.clone(),
+            packet,
+            kind,
+            keyframe,
+            received_at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_media_aware_drop_policy() {
+        let track = Arc::new(TrackLocalStaticRTP::new(
+            RTCRtpCodecCapability {
+                mime_type: "video/VP8".to_owned(),
+                ..Default::default()
+            },
+            "video".to_owned(),
+            "test".to_owned(),
+        ));
+        let queue = OutboundQueue::new(4, DropPolicy::MediaAware);
+        let metrics = MetricsCounter::new();
+        queue.report_to(metrics.clone());
+
+        queue.push(packet(&track, RTPCodecType::Audio, false, 1));
+        queue.push(packet(&track, RTPCodecType::Video, true, 2));
+        queue.push(packet(&track, RTPCodecType::Video, false, 3));
+        queue.push(packet(&track, RTPCodecType::Audio, false, 4));
+        // Очередь полна: первым уходит дельта-кадр, затем ключевой кадр
+        queue.push(packet(&track, RTPCodecType::Audio, false, 5));
+        queue.push(packet(&track, RTPCodecType::Audio, false, 6));
+
+        assert_eq!(queue.drops.video_delta.load(Ordering::Relaxed), 1);
+        assert_eq!(queue.drops.video_keyframe.load(Ordering::Relaxed), 1);
+        assert_eq!(queue.drops.audio.load(Ordering::Relaxed), 0);
+        // Те же отбрасывания видны в счетчиках сервера
+        assert_eq!(metrics.get_outbound_video_delta_dropped(), 1);
+        assert_eq!(metrics.get_outbound_video_keyframe_dropped(), 1);
+        assert_eq!(metrics.get_outbound_audio_dropped(), 0);
+        assert!(metrics
+            .to_prometheus()
+            .contains("rrtc_outbound_video_keyframe_dropped_total 1"));
+
+        let remaining: Vec<u16> = std::iter::from_fn(|| queue.pop())
+            .map(|p| p.packet.header.sequence_number)
+            .collect();
+        assert_eq!(remaining, vec![1, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_write_errors_counted_as_dropped() {
+        let metrics = MetricsCounter::new();
+        let queue = OutboundQueue::new(4, DropPolicy::MediaAware);
+        queue.report_to(metrics.clone());
+
+        // Закрытое соединение - не потеря
+        let closed = webrtc::Error::new("InvalidState: connection closed".to_owned());
+        queue.record_write_error("bob", "video", &closed);
+        assert_eq!(metrics.get_packets_dropped(), 0);
+
+        queue.record_write_error("bob", "video", &webrtc::Error::ErrClosedPipe);
+        queue.record_write_error("bob", "audio", &webrtc::Error::ErrClosedPipe);
+        assert_eq!(metrics.get_packets_dropped(), 2);
+        assert!(metrics.format_stats().contains("Packets Dropped: 2"));
+        assert!(metrics.to_prometheus().contains("rrtc_packets_dropped_total 2"));
+    }
+
+    #[test]
+    fn test_send_rate_cap_drops_video_over_limit() {
+        let start = Instant::now();
+        let mut cap = SendRateCap::new();
+        // 80 кбит/с = 10 000 байт за окно
+        cap.set_limit_bps(Some(80_000));
+
+        let allowed = (0..12)
+            .filter(|_| cap.allow(1000, RTPCodecType::Video, false, start))
+            .count();
+        assert_eq!(allowed, 10);
+        // Ключевые кадры проходят до двойного лимита, аудио - всегда
+        assert!(cap.allow(1000, RTPCodecType::Video, true, start));
+        assert!(cap.allow(1000, RTPCodecType::Audio, false, start));
+        assert_eq!(cap.rate_bps(start), 96_000);
+
+        // Окно сдвинулось: видео снова отправляется
+        let later = start + SEND_RATE_WINDOW;
+        assert!(cap.allow(1000, RTPCodecType::Video, false, later));
+        assert_eq!(cap.rate_bps(later), 8_000);
+    }
+
+    #[test]
+    fn test_audio_dequeued_before_video_and_data() {
+        let track = Arc::new(TrackLocalStaticRTP::new(
+            RTCRtpCodecCapability {
+                mime_type: "video/VP8".to_owned(),
+                ..Default::default()
+            },
+            "video".to_owned(),
+            "test".to_owned(),
+        ));
+        let queue =
+            OutboundQueue::new(16, DropPolicy::MediaAware).with_class_weights(ClassWeights {
+                audio: 4,
+                video: 2,
+                data: 1,
+            });
+
+        // Одновременный backlog всех классов, аудио пришло последним
+        for seq in 1..=3 {
+            queue.push(packet(&track, RTPCodecType::Unspecified, false, seq));
+        }
+        for seq in 11..=14 {
+            queue.push(packet(&track, RTPCodecType::Video, false, seq));
+        }
+        for seq in 21..=23 {
+            queue.push(packet(&track, RTPCodecType::Audio, false, seq));
+        }
+
+        let order: Vec<u16> = std::iter::from_fn(|| queue.pop())
+            .map(|p| p.packet.header.sequence_number)
+            .collect();
+        assert_eq!(order, vec![21, 22, 23, 11, 12, 1, 13, 14, 2, 3]);
+
+        // Новое аудио обслуживается раньше оставшегося видео
+        queue.push(packet(&track, RTPCodecType::Video, false, 15));
+        queue.push(packet(&track, RTPCodecType::Audio, false, 24));
+        assert_eq!(queue.pop().unwrap().packet.header.sequence_number, 24);
+    }
+}
@@ -1,29 +1,65 @@
 use anyhow::Result;
-use log::{debug, info, warn};
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn, Instrument};
+use std::net::IpAddr;
 use std::sync::Arc;
-use tokio::sync::{mpsc, RwLock};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch, Mutex, RwLock};
 use tokio_tungstenite::tungstenite::Message;
 use webrtc::api::interceptor_registry::register_default_interceptors;
 use webrtc::api::media_engine::MediaEngine;
+use webrtc::api::setting_engine::SettingEngine;
 use webrtc::api::APIBuilder;
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::data_channel::data_channel_state::RTCDataChannelState;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::ice::udp_network::{EphemeralUDP, UDPNetwork};
 use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
+use webrtc::ice_transport::ice_candidate_type::RTCIceCandidateType;
 use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
 use webrtc::ice_transport::ice_server::RTCIceServer;
 use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::offer_answer_options::RTCOfferOptions;
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::signaling_state::RTCSignalingState;
 use webrtc::peer_connection::RTCPeerConnection;
-use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+use webrtc::rtcp::packet::Packet as RtcpPacket;
+use webrtc::rtcp::payload_feedbacks::full_intra_request::FullIntraRequest;
+use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use webrtc::rtcp::payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate;
+use webrtc::rtcp::transport_feedbacks::transport_layer_cc::TransportLayerCc;
+use webrtc::rtcp::transport_feedbacks::transport_layer_nack::TransportLayerNack;
+use webrtc::rtp_transceiver::rtp_codec::{RTCRtpHeaderExtensionCapability, RTPCodecType};
+use webrtc::stats::StatsReportType;
 use webrtc::rtp_transceiver::rtp_sender::RTCRtpSender;
 use webrtc::rtp_transceiver::rtp_transceiver_direction::RTCRtpTransceiverDirection;
 use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
 use webrtc::track::track_local::TrackLocal;
 use interceptor::registry::Registry;
+use interceptor::twcc::sender::Sender as TwccSender;
 
-use crate::messages::ServerMessage;
+use crate::audio::AUDIO_LEVEL_URI;
+use crate::bandwidth::{BandwidthEstimator, CongestionThresholds};
+use crate::codecs::{
+    limit_video_resolution, preferred_mime_type, register_codecs, CodecPreferences,
+    VideoResolution,
+};
+use crate::diagnostics::{DiagnosticsBundle, QualitySample, SessionDiagnostics};
+use crate::ice::{prioritize_candidate, HostCandidateFilter, IceCandidatePreference};
+use crate::messages::{PeerStats, ServerMessage};
+use crate::outbound::{ClassWeights, DropPolicy, OutboundQueue, SendRateCap};
+use crate::signaling::WsSender;
+use crate::simulcast::FRAME_MARKING_URI;
+use crate::utils::{ActivityClock, MetricsCounter, RateLimiter};
 
 /// Типы треков для различения камеры и экрана
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TrackType {
     Camera,
     Screen,
@@ -61,17 +97,344 @@ pub struct LocalTrack {
     pub track_type: TrackType,
 }
 
+/// Размер очереди исходящих пакетов по умолчанию
+pub const DEFAULT_OUTBOUND_QUEUE_SIZE: usize = 256;
+/// Сколько видео слотов на отправку участнику согласуется по умолчанию (не больше, чем в offer)
+pub const DEFAULT_MAX_VIDEO_TRANSCEIVERS: usize = 4;
+/// Сколько аудио слотов на отправку участнику согласуется по умолчанию
+pub const DEFAULT_MAX_AUDIO_TRANSCEIVERS: usize = 2;
+/// Жесткий предел слотов одного типа на участника
+pub const MAX_TRANSCEIVERS_PER_KIND: usize = 16;
+/// Сколько реакций участник может отправить за `REACTION_RATE_WINDOW`
+pub const REACTION_RATE_LIMIT: usize = 5;
+pub const REACTION_RATE_WINDOW: Duration = Duration::from_secs(2);
+/// Не чаще одного запроса ключевого кадра (PLI) у издателя за этот интервал
+pub const KEYFRAME_REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Параметры media engine и сети, с которыми создается PeerConnection
+#[derive(Debug, Clone)]
+pub struct PeerMediaOptions {
+    /// Согласовывать RTP расширение frame-marking для видео
+    pub frame_marking: bool,
+    /// Согласовывать RTP расширение уровня звука (нужно для шумового порога)
+    pub audio_level: bool,
+    /// Разрешенные кодеки (имена вида "H264", "opus"); пусто - все поддерживаемые
+    pub allowed_codecs: Vec<String>,
+    /// Порядок кодеков сервера; согласуются только перечисленные
+    pub codec_preferences: CodecPreferences,
+    /// Размер очереди исходящих пакетов (в пакетах)
+    pub outbound_queue_size: usize,
+    /// Что отбрасывать при переполнении очереди исходящих пакетов
+    pub drop_policy: DropPolicy,
+    /// Веса классов трафика в очереди исходящих пакетов
+    pub class_weights: ClassWeights,
+    /// Диапазон локальных UDP портов для ICE (включительно); `None` - эфемерные порты ОС
+    pub udp_port_range: Option<(u16, u16)>,
+    /// Максимум видео треков, которые сервер отправляет участнику
+    pub max_video_transceivers: usize,
+    /// Максимум аудио треков, которые сервер отправляет участнику
+    pub max_audio_transceivers: usize,
+    /// Ограничение разрешения видео, которое участник может отправлять
+    pub max_video_resolution: Option<VideoResolution>,
+    /// Пороги TWCC оценки, по которым участнику как получателю выбирается нижний слой
+    pub congestion_thresholds: CongestionThresholds,
+    /// Сколько ICE restart подряд допускается без восстановления соединения
+    pub max_ice_restarts: u32,
+    /// Какие адреса интерфейсов объявлять host кандидатами
+    pub host_candidate_filter: HostCandidateFilter,
+    /// Публичный IP при 1:1 NAT, объявляемый srflx кандидатом
+    pub public_ip: Option<IpAddr>,
+}
+
+impl Default for PeerMediaOptions {
+    fn default() -> Self {
+        Self {
+            frame_marking: false,
+            audio_level: false,
+            allowed_codecs: Vec::new(),
+            codec_preferences: CodecPreferences::default(),
+            outbound_queue_size: DEFAULT_OUTBOUND_QUEUE_SIZE,
+            drop_policy: DropPolicy::default(),
+            class_weights: ClassWeights::default(),
+            udp_port_range: None,
+            max_video_transceivers: DEFAULT_MAX_VIDEO_TRANSCEIVERS,
+            max_audio_transceivers: DEFAULT_MAX_AUDIO_TRANSCEIVERS,
+            max_video_resolution: None,
+            congestion_thresholds: CongestionThresholds::default(),
+            max_ice_restarts: DEFAULT_MAX_ICE_RESTARTS,
+            host_candidate_filter: HostCandidateFilter::default(),
+            public_ip: None,
+        }
+    }
+}
+
+/// Сколько ICE restart подряд допускается по умолчанию
+pub const DEFAULT_MAX_ICE_RESTARTS: u32 = 3;
+
+/// Максимальное количество буферизованных ICE кандидатов
+const MAX_PENDING_CANDIDATES: usize = 32;
+/// Сколько по умолчанию хранятся кандидаты, пришедшие раньше offer
+pub const DEFAULT_CANDIDATE_GRACE: Duration = Duration::from_secs(10);
+
+/// ICE кандидаты клиента, полученные до установки remote description.
+/// Применяются после обработки offer; буфер ограничен по размеру и времени.
+pub struct PendingCandidates {
+    candidates: Mutex<VecDeque<(Instant, String)>>,
+    grace: Duration,
+}
+
+impl PendingCandidates {
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            candidates: Mutex::new(VecDeque::new()),
+            grace,
+        }
+    }
+
+    #[cfg(test)]
+    pub async fn len(&self) -> usize {
+        self.candidates.lock().await.len()
+    }
+}
+
+/// Последнее известное серверу состояние соединения участника
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ConnectionStateSnapshot {
+    state: String,
+    ice_state: String,
+}
+
+struct NotifierState {
+    current: ConnectionStateSnapshot,
+    sent: Option<ConnectionStateSnapshot>,
+    pending: bool,
+}
+
+/// Уведомляет клиента об изменениях состояния PC/ICE.
+/// Быстрые переходы в пределах окна debounce схлопываются в одно сообщение
+/// с итоговым состоянием.
+#[derive(Clone)]
+pub struct ConnectionStateNotifier {
+    ws_tx: WsSender,
+    debounce: Duration,
+    inner: Arc<Mutex<NotifierState>>,
+}
+
+impl ConnectionStateNotifier {
+    pub fn new(ws_tx: WsSender, debounce: Duration) -> Self {
+        Self {
+            ws_tx,
+            debounce,
+            inner: Arc::new(Mutex::new(NotifierState {
+                current: ConnectionStateSnapshot {
+                    state: RTCPeerConnectionState::New.to_string(),
+                    ice_state: RTCIceConnectionState::New.to_string(),
+                },
+                sent: None,
+                pending: false,
+            })),
+        }
+    }
+
+    pub async fn set_peer_state(&self, state: RTCPeerConnectionState) {
+        let mut inner = self.inner.lock().await;
+        inner.current.state = state.to_string();
+        self.schedule(&mut inner);
+    }
+
+    pub async fn set_ice_state(&self, state: RTCIceConnectionState) {
+        let mut inner = self.inner.lock().await;
+        inner.current.ice_state = state.to_string();
+        self.schedule(&mut inner);
+    }
+
+    fn schedule(&self, inner: &mut NotifierState) {
+        if inner.pending {
+            return;
+        }
+        inner.pending = true;
+
+        let notifier = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(notifier.debounce).await;
+            notifier.flush().await;
+        });
+    }
+
+    async fn flush(&self) {
+        let mut inner = self.inner.lock().await;
+        inner.pending = false;
+
+        if inner.sent.as_ref() == Some(&inner.current) {
+            return;
+        }
+        inner.sent = Some(inner.current.clone());
+
+        let msg = ServerMessage::ConnectionStateChanged {
+            state: inner.current.state.clone(),
+            ice_state: inner.current.ice_state.clone(),
+        };
+        if let Ok(json_str) = serde_json::to_string(&msg) {
+            let _ = self.ws_tx.send_with(Message::text(json_str), msg.priority());
+        }
+    }
+}
+
+/// Считает аудио и видео секции (`m=audio`, `m=video`) в SDP
+fn count_media_sections(sdp: &str) -> (usize, usize) {
+    sdp.lines().fold((0, 0), |(audio, video), line| {
+        if line.starts_with("m=audio ") {
+            (audio + 1, video)
+        } else if line.starts_with("m=video ") {
+            (audio, video + 1)
+        } else {
+            (audio, video)
+        }
+    })
+}
+
+/// Id локального трека для слота: первый слот сохраняет прежний формат `<kind>-<peer>`
+fn slot_track_id(kind: &str, peer_id: &str, slot: usize) -> String {
+    if slot == 0 {
+        format!("{}-{}", kind, peer_id)
+    } else {
+        format!("{}-{}-{}", kind, peer_id, slot)
+    }
+}
+
+/// Обработчик сообщений из data channels участника: (label канала, сообщение)
+pub type DataHandler =
+    Arc<dyn Fn(String, DataChannelMessage) -> BoxFuture<'static, ()> + Send + Sync>;
+
+type DataChannels = Arc<RwLock<HashMap<String, Arc<RTCDataChannel>>>>;
+/// Сообщения для data channels, которые открывает сервер: label -> (данные, текст ли)
+type PendingData = Arc<std::sync::Mutex<HashMap<String, Vec<(Bytes, bool)>>>>;
+type ResolvedTracks = HashMap<String, HashMap<String, (String, Arc<TrackLocalStaticRTP>)>>;
+
+/// Подключает data channel участника: сообщения уходят в обработчик, закрытый канал забывается
+fn attach_data_channel(
+    peer_id: &str,
+    channels: &DataChannels,
+    pending: &PendingData,
+    handler: &Arc<std::sync::Mutex<Option<DataHandler>>>,
+    dc: &RTCDataChannel,
+) {
+    let label = dc.label().to_string();
+    debug!("Peer {} data channel {} attached", peer_id, label);
+
+    let handler = handler.clone();
+    let message_label = label.clone();
+    dc.on_message(Box::new(move |msg| {
+        let handler = handler.lock().unwrap().clone();
+        let label = message_label.clone();
+        Box::pin(async move {
+            if let Some(handler) = handler {
+                handler(label, msg).await;
+            }
+        })
+    }));
+
+    let channels = channels.clone();
+    let pending = pending.clone();
+    dc.on_close(Box::new(move || {
+        let channels = channels.clone();
+        let label = label.clone();
+        // Канал закрылся, не открывшись: ожидавшие сообщения доставить некуда
+        pending.lock().unwrap().remove(&label);
+        Box::pin(async move {
+            channels.write().await.remove(&label);
+        })
+    }));
+}
+
+/// Отправляет сообщение в открытый data channel
+async fn send_on_channel(dc: &RTCDataChannel, data: &Bytes, is_string: bool) -> Result<()> {
+    if is_string {
+        dc.send_text(String::from_utf8_lossy(data)).await?;
+    } else {
+        dc.send(data).await?;
+    }
+    Ok(())
+}
+
 /// Peer представляет одного участника в комнате
 #[derive(Clone)]
 pub struct Peer {
     pub id: String,
-    pub name: String,
+    /// Отображаемое имя; участник может сменить его во время звонка (`rename`)
+    name: Arc<std::sync::RwLock<String>>,
     pub pc: Arc<RTCPeerConnection>,
-    pub ws_tx: mpsc::UnboundedSender<Message>,
+    pub ws_tx: WsSender,
     pub muted: Arc<RwLock<bool>>,
     pub video_on: Arc<RwLock<bool>>,
     pub screen_sharing: Arc<RwLock<bool>>,
+    pub hand_raised: Arc<RwLock<bool>>,
     pub local_tracks: Arc<RwLock<Vec<LocalTrack>>>,
+    pub diagnostics: Arc<SessionDiagnostics>,
+    pub media_options: PeerMediaOptions,
+    pub outbound: Arc<OutboundQueue>,
+    pub pending_candidates: Arc<PendingCandidates>,
+    /// Последняя REMB оценка пропускной способности участника как получателя (бит/с, 0 - нет)
+    pub receive_estimate: Arc<AtomicU64>,
+    /// Оценка пропускной способности участника как получателя по TWCC обратной связи
+    bandwidth: Arc<std::sync::Mutex<BandwidthEstimator>>,
+    /// Оценка ниже порога: участнику пересылаются нижние simulcast слои
+    congested: Arc<AtomicBool>,
+    /// Участник отказался от пакетов тишины (Opus DTX), см. `set_skip_silence`
+    skip_silence: Arc<AtomicBool>,
+    /// Пересылаются ли звук и камера участника: копия `muted`/`video_on` и
+    /// принудительного отключения звука, читаемая на пути пакета без блокировок
+    audio_forwarded: Arc<AtomicBool>,
+    camera_forwarded: Arc<AtomicBool>,
+    force_muted: Arc<AtomicBool>,
+    /// Ограничение частоты реакций, общее для всех сообщений участника
+    pub reaction_limiter: Arc<RateLimiter>,
+    /// Скорость пересылки участнику и ее лимит (`set_receive_limit`)
+    send_rate: Arc<std::sync::Mutex<SendRateCap>>,
+    /// Последняя активность участника: сообщения WebSocket, RTP и RTCP пакеты
+    pub activity: Arc<ActivityClock>,
+    /// SSRC видео, которое участник публикует (для запросов ключевых кадров)
+    incoming_video_ssrcs: Arc<std::sync::Mutex<HashSet<u32>>>,
+    /// Счетчики сервера, переданные комнатой (`report_to`)
+    metrics: Arc<std::sync::OnceLock<MetricsCounter>>,
+    /// Когда у участника последний раз запрашивался ключевой кадр
+    last_keyframe_request: Arc<std::sync::Mutex<Option<Instant>>>,
+    /// Запросы ключевых кадров от участника как получателя: id издателя
+    keyframe_requests_tx: mpsc::UnboundedSender<String>,
+    keyframe_requests_rx: Arc<std::sync::Mutex<Option<mpsc::UnboundedReceiver<String>>>>,
+    /// Data channels участника по label (открытые клиентом или сервером)
+    data_channels: DataChannels,
+    /// Сообщения, ожидающие открытия data channel сервером
+    pending_data: PendingData,
+    /// Куда передаются сообщения из data channels участника
+    data_handler: Arc<std::sync::Mutex<Option<DataHandler>>>,
+    /// Какой локальный трек (индекс в `local_tracks`) занят источником (участник, трек)
+    slot_assignments: Arc<std::sync::Mutex<HashMap<(String, String), usize>>>,
+    /// Локальные треки, уже найденные для источников: участник -> id трека ->
+    /// (кодек источника, трек). Путь пакета обходится без поиска слота и аллокаций
+    resolved_tracks: Arc<std::sync::Mutex<ResolvedTracks>>,
+    /// Кодеки источников (MIME в нижнем регистре), которые участник не согласовал
+    unsupported_codecs: Arc<std::sync::Mutex<HashSet<String>>>,
+    /// Номер последнего полученного offer
+    latest_offer_seq: Arc<AtomicU64>,
+    /// Номер последнего примененного offer; блокировка упорядочивает обработку offer
+    /// клиента и offer/answer сервера
+    applied_offer_seq: Arc<Mutex<u64>>,
+    /// Добавлены слоты, которые еще не согласованы с клиентом offer от сервера
+    renegotiation_needed: Arc<AtomicBool>,
+    /// Идет добавление слота (см. `add_slot`)
+    adding_slot: Arc<AtomicBool>,
+    /// Получен ли offer от клиента
+    offer_received: Arc<watch::Sender<bool>>,
+    /// Текущее состояние PeerConnection (для ожидания завершения handshake)
+    connection_state: Arc<watch::Sender<RTCPeerConnectionState>>,
+    /// Текущее состояние ICE (для перезапуска ICE при обрыве)
+    ice_state: Arc<watch::Sender<RTCIceConnectionState>>,
+    /// ICE restart с последнего установленного соединения
+    ice_restarts: Arc<AtomicU32>,
+    /// Span соединения, в котором создан участник (`peer` с `room_id` и `participant_id`):
+    /// в нем выполняются фоновые задачи и обработчики событий PeerConnection
+    pub span: tracing::Span,
 }
 
 impl Peer {
@@ -79,21 +442,65 @@ impl Peer {
     pub async fn new(
         id: String,
         name: String,
-        ws_tx: mpsc::UnboundedSender<Message>,
+        ws_tx: WsSender,
         ice_servers: Option<Vec<RTCIceServer>>,
+        options: PeerMediaOptions,
     ) -> Result<Self> {
+        let (keyframe_requests_tx, keyframe_requests_rx) = mpsc::unbounded_channel();
+
         // Настройка Media Engine
         let mut media_engine = MediaEngine::default();
-        media_engine.register_default_codecs()?;
+        register_codecs(
+            &mut media_engine,
+            &options.allowed_codecs,
+            &options.codec_preferences,
+        )?;
+
+        if options.frame_marking {
+            media_engine.register_header_extension(
+                RTCRtpHeaderExtensionCapability {
+                    uri: FRAME_MARKING_URI.to_owned(),
+                },
+                RTPCodecType::Video,
+                None,
+            )?;
+        }
+
+        if options.audio_level {
+            media_engine.register_header_extension(
+                RTCRtpHeaderExtensionCapability {
+                    uri: AUDIO_LEVEL_URI.to_owned(),
+                },
+                RTPCodecType::Audio,
+                None,
+            )?;
+        }
 
         // Настройка Interceptor Registry
         let mut registry = Registry::new();
         registry = register_default_interceptors(registry, &mut media_engine)?;
+        // Транспортные номера в исходящих пакетах: без них получатель не шлет TWCC
+        registry.add(Box::new(TwccSender::builder()));
+
+        // Настройка сети: ограничение диапазона UDP портов для правил firewall
+        let mut setting_engine = SettingEngine::default();
+        if let Some((port_min, port_max)) = options.udp_port_range {
+            setting_engine.set_udp_network(UDPNetwork::Ephemeral(EphemeralUDP::new(
+                port_min, port_max,
+            )?));
+        }
+        let host_filter = options.host_candidate_filter;
+        setting_engine.set_ip_filter(Box::new(move |ip| host_filter.allows(ip)));
+        if let Some(public_ip) = options.public_ip {
+            setting_engine
+                .set_nat_1to1_ips(vec![public_ip.to_string()], RTCIceCandidateType::Srflx);
+        }
 
         // Создание API
         let api = APIBuilder::new()
             .with_media_engine(media_engine)
             .with_interceptor_registry(registry)
+            .with_setting_engine(setting_engine)
             .build();
 
         // Конфигурация ICE серверов
@@ -126,61 +533,152 @@ impl Peer {
 
         info!("Created peer connection for {}", id);
 
+        let outbound = Arc::new(
+            OutboundQueue::new(options.outbound_queue_size, options.drop_policy)
+                .with_class_weights(options.class_weights),
+        );
+        outbound.spawn_writer(id.clone());
+
         Ok(Peer {
             id,
-            name,
+            name: Arc::new(std::sync::RwLock::new(name)),
             pc: peer_connection,
             ws_tx,
             muted: Arc::new(RwLock::new(false)),
             video_on: Arc::new(RwLock::new(true)),
             screen_sharing: Arc::new(RwLock::new(false)),
+            hand_raised: Arc::new(RwLock::new(false)),
             local_tracks: Arc::new(RwLock::new(Vec::new())),
+            diagnostics: Arc::new(SessionDiagnostics::new()),
+            media_options: options,
+            outbound,
+            pending_candidates: Arc::new(PendingCandidates::new(DEFAULT_CANDIDATE_GRACE)),
+            receive_estimate: Arc::new(AtomicU64::new(0)),
+            bandwidth: Arc::new(std::sync::Mutex::new(BandwidthEstimator::new(Instant::now()))),
+            congested: Arc::new(AtomicBool::new(false)),
+            skip_silence: Arc::new(AtomicBool::new(false)),
+            audio_forwarded: Arc::new(AtomicBool::new(true)),
+            camera_forwarded: Arc::new(AtomicBool::new(true)),
+            force_muted: Arc::new(AtomicBool::new(false)),
+            reaction_limiter: Arc::new(RateLimiter::new(
+                REACTION_RATE_LIMIT,
+                REACTION_RATE_WINDOW,
+            )),
+            send_rate: Arc::new(std::sync::Mutex::new(SendRateCap::new())),
+            activity: Arc::new(ActivityClock::new()),
+            incoming_video_ssrcs: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            metrics: Arc::new(std::sync::OnceLock::new()),
+            last_keyframe_request: Arc::new(std::sync::Mutex::new(None)),
+            keyframe_requests_tx,
+            keyframe_requests_rx: Arc::new(std::sync::Mutex::new(Some(keyframe_requests_rx))),
+            data_channels: Arc::new(RwLock::new(HashMap::new())),
+            pending_data: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            data_handler: Arc::new(std::sync::Mutex::new(None)),
+            slot_assignments: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            resolved_tracks: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            unsupported_codecs: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            latest_offer_seq: Arc::new(AtomicU64::new(0)),
+            applied_offer_seq: Arc::new(Mutex::new(0)),
+            renegotiation_needed: Arc::new(AtomicBool::new(false)),
+            adding_slot: Arc::new(AtomicBool::new(false)),
+            offer_received: Arc::new(watch::channel(false).0),
+            connection_state: Arc::new(watch::channel(RTCPeerConnectionState::New).0),
+            ice_state: Arc::new(watch::channel(RTCIceConnectionState::New).0),
+            ice_restarts: Arc::new(AtomicU32::new(0)),
+            span: tracing::Span::current(),
         })
     }
 
-    /// Настраивает обработчики событий для PeerConnection
-    pub async fn setup_handlers(&self) -> Result<()> {
+    /// Настраивает обработчики событий для PeerConnection.
+    /// Если передан `notifier`, изменения состояния соединения отправляются клиенту.
+    pub async fn setup_handlers(
+        &self,
+        notifier: Option<ConnectionStateNotifier>,
+        candidate_preferences: Vec<IceCandidatePreference>,
+    ) -> Result<()> {
         let peer_id = self.id.clone();
         let ws_tx = self.ws_tx.clone();
+        let diagnostics = self.diagnostics.clone();
+        let candidate_preferences = Arc::new(candidate_preferences);
+        let span = self.span.clone();
 
         // Обработчик ICE кандидатов
         self.pc
             .on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
                 let tx = ws_tx.clone();
                 let peer_id = peer_id.clone();
-                Box::pin(async move {
-                    if let Some(c) = candidate {
-                        debug!("Peer {} generated ICE candidate", peer_id);
-                        if let Ok(json) = c.to_json() {
-                            let msg = ServerMessage::Candidate {
-                                candidate: json.candidate,
-                            };
+                let diagnostics = diagnostics.clone();
+                let candidate_preferences = candidate_preferences.clone();
+                let span = span.clone();
+                Box::pin(
+                    async move {
+                        if let Some(c) = candidate {
+                            debug!("Peer {} generated ICE candidate {}", peer_id, c);
+                            if let Ok(json) = c.to_json() {
+                                let candidate =
+                                    prioritize_candidate(&json.candidate, &candidate_preferences);
+                                diagnostics.record_local_candidate(candidate.clone()).await;
+                                let msg = ServerMessage::Candidate { candidate };
+                                if let Ok(json_str) = serde_json::to_string(&msg) {
+                                    let _ = tx.send(Message::text(json_str));
+                                }
+                            }
+                        } else {
+                            // Кандидатов больше не будет: клиент может не ждать trickle
+                            debug!("Peer {} ICE gathering complete", peer_id);
+                            let msg = ServerMessage::IceGatheringComplete;
                             if let Ok(json_str) = serde_json::to_string(&msg) {
                                 let _ = tx.send(Message::text(json_str));
                             }
                         }
-                    } else {
-                        debug!("Peer {} ICE gathering complete", peer_id);
                     }
-                })
+                    .instrument(span),
+                )
             }));
 
 
         // Обработчик состояния соединения
         let peer_id_clone = self.id.clone();
+        let pc_notifier = notifier.clone();
+        let pc_diagnostics = self.diagnostics.clone();
+        let state_tx = self.connection_state.clone();
+        let pc_span = self.span.clone();
         self.pc.on_peer_connection_state_change(Box::new(
             move |state: RTCPeerConnectionState| {
-                info!("Peer {} connection state: {:?}", peer_id_clone, state);
-                Box::pin(async {})
+                pc_span.in_scope(|| {
+                    info!("Peer {} connection state: {:?}", peer_id_clone, state);
+                });
+                state_tx.send_replace(state);
+                let notifier = pc_notifier.clone();
+                let diagnostics = pc_diagnostics.clone();
+                Box::pin(async move {
+                    diagnostics.record_state("peer_connection", state.to_string()).await;
+                    if let Some(notifier) = notifier {
+                        notifier.set_peer_state(state).await;
+                    }
+                })
             },
         ));
 
         // Обработчик ICE connection state
         let peer_id_clone2 = self.id.clone();
+        let ice_diagnostics = self.diagnostics.clone();
+        let ice_state_tx = self.ice_state.clone();
+        let ice_span = self.span.clone();
         self.pc
             .on_ice_connection_state_change(Box::new(move |state: RTCIceConnectionState| {
-                info!("Peer {} ICE connection state: {:?}", peer_id_clone2, state);
-                Box::pin(async {})
+                ice_span.in_scope(|| {
+                    info!("Peer {} ICE connection state: {:?}", peer_id_clone2, state);
+                });
+                ice_state_tx.send_replace(state);
+                let notifier = notifier.clone();
+                let diagnostics = ice_diagnostics.clone();
+                Box::pin(async move {
+                    diagnostics.record_state("ice", state.to_string()).await;
+                    if let Some(notifier) = notifier {
+                        notifier.set_ice_state(state).await;
+                    }
+                })
             }));
 
         Ok(())
@@ -195,7 +693,7 @@ impl Peer {
     ) -> Result<Arc<TrackLocalStaticRTP>> {
         let track_id_owned = track_id.to_string();
         let (mime_type, _kind) = match track_type {
-            TrackType::Audio => ("audio/opus", RTPCodecType::Audio),
+            TrackType::Audio => (codec, RTPCodecType::Audio),
             TrackType::Camera | TrackType::Screen => (codec, RTPCodecType::Video),
         };
 
@@ -213,25 +711,95 @@ impl Peer {
             .add_track(track.clone() as Arc<dyn TrackLocal + Send + Sync>)
             .await?;
 
-        // Клонируем для сохранения
-        let rtp_sender_clone = rtp_sender.clone();
+        // Сохраняем информацию о треке; его индекс - номер слота
+        let slot = {
+            let mut local_tracks = self.local_tracks.write().await;
+            local_tracks.push(LocalTrack {
+                track: track.clone(),
+                sender: rtp_sender.clone(),
+                track_type,
+            });
+            local_tracks.len() - 1
+        };
+        // Трек, добавленный после согласования, нужно согласовать offer сервера
+        if self.pc.remote_description().await.is_some() {
+            self.renegotiation_needed.store(true, Ordering::SeqCst);
+        }
 
-        // Читаем RTCP пакеты (для keep-alive)
+        // Читаем RTCP пакеты: keep-alive, REMB и TWCC оценки пропускной способности
+        // получателя и запросы ключевых кадров, которые передаются издателю, занимающему слот.
+        // На NACK отвечает NACK responder из интерсепторов по буферу отправленных пакетов
         let peer_id = self.id.clone();
-        tokio::spawn(async move {
-            let mut rtcp_buf = vec![0u8; 1500];
-            while let Ok((_, _)) = rtp_sender.read(&mut rtcp_buf).await {
-                // Обработка RTCP пакетов
+        let receive_estimate = self.receive_estimate.clone();
+        let bandwidth = self.bandwidth.clone();
+        let congested = self.congested.clone();
+        let thresholds = self.media_options.congestion_thresholds;
+        let slot_assignments = self.slot_assignments.clone();
+        let keyframe_requests = self.keyframe_requests_tx.clone();
+        let activity = self.activity.clone();
+        tokio::spawn(
+            async move {
+                let (mut forwarded, mut nacks) = (0u64, 0u64);
+                while let Ok((packets, _)) = rtp_sender.read_rtcp().await {
+                    // Отчеты получателя: участник на связи, даже если ничего не публикует
+                    activity.touch();
+                    for packet in packets {
+                        let packet = packet.as_any();
+                        if let Some(remb) =
+                            packet.downcast_ref::<ReceiverEstimatedMaximumBitrate>()
+                        {
+                            receive_estimate.store(remb.bitrate as u64, Ordering::Relaxed);
+                        } else if let Some(feedback) = packet.downcast_ref::<TransportLayerCc>() {
+                            let estimate =
+                                bandwidth.lock().unwrap().on_feedback(feedback, Instant::now());
+                            if let Some(estimate) = estimate {
+                                let was = congested.load(Ordering::Relaxed);
+                                let now = thresholds.is_congested(was, estimate);
+                                if now != was {
+                                    congested.store(now, Ordering::Relaxed);
+                                    let change = if now {
+                                        "switching to low layers"
+                                    } else {
+                                        "congestion cleared"
+                                    };
+                                    info!(
+                                        "Peer {} bandwidth estimate {} kbps, {}",
+                                        peer_id,
+                                        estimate / 1000,
+                                        change
+                                    );
+                                }
+                            }
+                        } else if packet.is::<PictureLossIndication>()
+                            || packet.is::<FullIntraRequest>()
+                        {
+                            let source = slot_assignments
+                                .lock()
+                                .unwrap()
+                                .iter()
+                                .find(|(_, &assigned)| assigned == slot)
+                                .map(|((source_peer, _), _)| source_peer.clone());
+                            if let Some(source) = source {
+                                forwarded += 1;
+                                debug!(
+                                    "Peer {} requested a keyframe from {} ({} forwarded)",
+                                    peer_id, source, forwarded
+                                );
+                                let _ = keyframe_requests.send(source);
+                            }
+                        } else if packet.is::<TransportLayerNack>() {
+                            nacks += 1;
+                        }
+                    }
+                }
+                debug!(
+                    "RTCP reader for peer {} track {} stopped \
+                     ({} keyframe requests forwarded, {} NACKs)",
+                    peer_id, track_id_owned, forwarded, nacks
+                );
             }
-            debug!("RTCP reader for peer {} track {} stopped", peer_id, track_id_owned);
-        });
-
-        // Сохраняем информацию о треке
-        self.local_tracks.write().await.push(LocalTrack {
-            track: track.clone(),
-            sender: rtp_sender_clone,
-            track_type,
-        });
+            .instrument(self.span.clone()),
+        );
 
         info!(
             "Added local track {:?} for peer {}: {}",
@@ -244,13 +812,48 @@ impl Peer {
     /// Обрабатывает offer от клиента и создает answer
     /// Также создает исходящие треки для пересылки медиа от других участников
     pub async fn handle_offer(&self, sdp: String) -> Result<String> {
+        self.diagnostics.record_offer(&sdp).await;
+
+        // Встречные offer: webrtc-rs не поддерживает rollback, поэтому offer сервера
+        // остается в силе, а клиент должен сначала ответить на него
+        if self.has_pending_offer() {
+            anyhow::bail!("Offer collides with a pending server offer");
+        }
+        let sdp_text = sdp.clone();
         let offer = RTCSessionDescription::offer(sdp)?;
         self.pc.set_remote_description(offer).await?;
+        self.offer_received.send_replace(true);
+        self.flush_pending_candidates().await;
 
-        // Создаем исходящие треки для получения медиа от других участников
-        // Один аудио трек и один видео трек для камеры
-        self.add_local_track("audio/opus", &format!("audio-{}", self.id), TrackType::Audio).await?;
-        self.add_local_track("video/VP8", &format!("video-{}", self.id), TrackType::Camera).await?;
+        // Создаем исходящие треки для получения медиа от других участников:
+        // столько аудио и видео, сколько секций в offer (минимум по одному, не больше лимита).
+        // При повторном offer добавляются только недостающие треки.
+        let (audio_sections, video_sections) = count_media_sections(&sdp_text);
+        let audio_target = audio_sections.clamp(1, self.media_options.max_audio_transceivers.max(1));
+        let video_target = video_sections.clamp(1, self.media_options.max_video_transceivers.max(1));
+        let (audio_existing, video_existing) = {
+            let local_tracks = self.local_tracks.read().await;
+            let audio = local_tracks
+                .iter()
+                .filter(|lt| lt.track_type == TrackType::Audio)
+                .count();
+            (audio, local_tracks.len() - audio)
+        };
+
+        let (allowed, preferences) = (
+            &self.media_options.allowed_codecs,
+            &self.media_options.codec_preferences,
+        );
+        let audio_mime = preferred_mime_type(RTPCodecType::Audio, allowed, preferences);
+        let video_mime = preferred_mime_type(RTPCodecType::Video, allowed, preferences);
+        for n in audio_existing..audio_target {
+            let track_id = slot_track_id("audio", &self.id, n);
+            self.add_local_track(&audio_mime, &track_id, TrackType::Audio).await?;
+        }
+        for n in video_existing..video_target {
+            let track_id = slot_track_id("video", &self.id, n);
+            self.add_local_track(&video_mime, &track_id, TrackType::Camera).await?;
+        }
 
         info!("Created outgoing tracks for peer {} to receive media from others", self.id);
 
@@ -263,15 +866,436 @@ impl Peer {
         }
 
         let answer = self.pc.create_answer(None).await?;
-        let answer_sdp = answer.sdp.clone();
+        let answer_sdp = self.sdp_for_client(&answer.sdp);
         self.pc.set_local_description(answer).await?;
+        self.diagnostics.record_answer(&answer_sdp).await;
 
         info!("Created answer for peer {} with sendrecv transceivers", self.id);
         Ok(answer_sdp)
     }
 
+    /// Answer или offer в том виде, в котором он отправляется клиенту. Ограничение разрешения
+    /// передается через параметры кодеков; webrtc-rs не принимает измененный SDP
+    /// как local description, поэтому меняется только копия для клиента.
+    fn sdp_for_client(&self, sdp: &str) -> String {
+        match &self.media_options.max_video_resolution {
+            Some(limit) => limit_video_resolution(sdp, limit),
+            None => sdp.to_string(),
+        }
+    }
+
+    /// Обрабатывает offer с учетом порядка: offer без номера получает следующий номер.
+    /// Offer обрабатываются по одному; устаревший offer (новее уже получен) отбрасывается.
+    /// Возвращает номер и answer для отправки клиенту: для устаревшего offer, если более
+    /// новый уже применен, - текущий answer; `None`, если более новый еще обрабатывается.
+    pub async fn handle_offer_sequenced(
+        &self,
+        sdp: String,
+        seq: Option<u64>,
+    ) -> Result<Option<(u64, String)>> {
+        let seq = match seq {
+            Some(seq) => {
+                self.latest_offer_seq.fetch_max(seq, Ordering::SeqCst);
+                seq
+            }
+            None => self.latest_offer_seq.fetch_add(1, Ordering::SeqCst) + 1,
+        };
+
+        let mut applied = self.applied_offer_seq.lock().await;
+        if seq <= *applied || seq < self.latest_offer_seq.load(Ordering::SeqCst) {
+            warn!(
+                "Discarding stale offer #{} from peer {} (latest #{})",
+                seq,
+                self.id,
+                self.latest_offer_seq.load(Ordering::SeqCst)
+            );
+            if *applied >= self.latest_offer_seq.load(Ordering::SeqCst) {
+                if let Some(answer) = self.pc.local_description().await {
+                    return Ok(Some((*applied, self.sdp_for_client(&answer.sdp))));
+                }
+            }
+            return Ok(None);
+        }
+
+        let answer = self.handle_offer(sdp).await?;
+        *applied = seq;
+        Ok(Some((seq, answer)))
+    }
+
+    /// Можно ли добавить участнику слот `kind`: соединение согласовано, лимит слотов
+    /// не достигнут и другой слот сейчас не добавляется
+    pub async fn can_add_slot(&self, kind: RTPCodecType) -> bool {
+        if self.adding_slot.load(Ordering::SeqCst) || self.pc.remote_description().await.is_none() {
+            return false;
+        }
+        let limit = match kind {
+            RTPCodecType::Audio => self.media_options.max_audio_transceivers,
+            _ => self.media_options.max_video_transceivers,
+        };
+        let slots = self
+            .local_tracks
+            .read()
+            .await
+            .iter()
+            .filter(|lt| lt.track.kind() == kind)
+            .count();
+        slots < limit
+    }
+
+    /// Добавляет слот для источника, которому не хватило свободных (например,
+    /// демонстрации экрана), и согласует его с клиентом offer от сервера.
+    /// `false`, если слот добавить нельзя (см. `can_add_slot`)
+    pub async fn add_slot(&self, track_type: TrackType) -> Result<bool> {
+        let kind = match track_type {
+            TrackType::Audio => RTPCodecType::Audio,
+            TrackType::Camera | TrackType::Screen => RTPCodecType::Video,
+        };
+        if !self.can_add_slot(kind).await || self.adding_slot.swap(true, Ordering::SeqCst) {
+            return Ok(false);
+        }
+
+        let result = async {
+            let (prefix, n) = {
+                let local_tracks = self.local_tracks.read().await;
+                let n = local_tracks.iter().filter(|lt| lt.track.kind() == kind).count();
+                (if kind == RTPCodecType::Audio { "audio" } else { "video" }, n)
+            };
+            let mime = preferred_mime_type(
+                kind,
+                &self.media_options.allowed_codecs,
+                &self.media_options.codec_preferences,
+            );
+            let track_id = slot_track_id(prefix, &self.id, n);
+            self.add_local_track(&mime, &track_id, track_type).await?;
+            info!("Added {} slot {} for peer {}, renegotiating", prefix, n, self.id);
+            self.renegotiate().await
+        }
+        .await;
+        self.adding_slot.store(false, Ordering::SeqCst);
+        result.map(|_| true)
+    }
+
+    /// Отправляет клиенту offer сервера, если есть несогласованные слоты и
+    /// нет незавершенного обмена offer/answer. Клиент отвечает `answer`
+    pub async fn renegotiate(&self) -> Result<()> {
+        let _order = self.applied_offer_seq.lock().await;
+        if self.pc.signaling_state() != RTCSignalingState::Stable
+            || self.pc.remote_description().await.is_none()
+            || !self.renegotiation_needed.swap(false, Ordering::SeqCst)
+        {
+            return Ok(());
+        }
+
+        let result = async {
+            let offer = self.pc.create_offer(None).await?;
+            let sdp = self.sdp_for_client(&offer.sdp);
+            self.pc.set_local_description(offer).await?;
+            self.send_message(ServerMessage::Offer { sdp })
+        }
+        .await;
+        if result.is_err() {
+            self.renegotiation_needed.store(true, Ordering::SeqCst);
+        } else {
+            info!("Sent renegotiation offer to peer {}", self.id);
+        }
+        result
+    }
+
+    /// Перезапускает ICE: отправляет клиенту offer с новыми ICE учетными данными,
+    /// после чего собираются новые кандидаты. Ошибка, если попытки исчерпаны
+    /// (`PeerMediaOptions::max_ice_restarts`) или соединение еще не согласовано
+    pub async fn restart_ice(&self) -> Result<()> {
+        let _order = self.applied_offer_seq.lock().await;
+        if self.pc.remote_description().await.is_none() {
+            anyhow::bail!("Cannot restart ICE before the initial negotiation");
+        }
+        let attempt = self.ice_restarts.fetch_add(1, Ordering::SeqCst) + 1;
+        if attempt > self.media_options.max_ice_restarts {
+            anyhow::bail!(
+                "ICE restart limit of {} reached",
+                self.media_options.max_ice_restarts
+            );
+        }
+
+        // Offer с ICE restart заменяет неотвеченный offer сервера и включает все треки
+        let options = RTCOfferOptions {
+            ice_restart: true,
+            ..Default::default()
+        };
+        let offer = self.pc.create_offer(Some(options)).await?;
+        let sdp = self.sdp_for_client(&offer.sdp);
+        self.pc.set_local_description(offer).await?;
+        self.renegotiation_needed.store(false, Ordering::SeqCst);
+        self.send_message(ServerMessage::Offer { sdp })?;
+
+        info!(
+            "Restarting ICE for peer {} (attempt {} of {})",
+            self.id, attempt, self.media_options.max_ice_restarts
+        );
+        Ok(())
+    }
+
+    /// Следит за ICE соединением до закрытия: при обрыве (`Failed`) сам перезапускает
+    /// ICE, не дожидаясь клиента; счетчик попыток сбрасывается при восстановлении
+    pub async fn restart_ice_on_failure(&self) {
+        let mut ice_rx = self.ice_state.subscribe();
+        let mut state_rx = self.connection_state.subscribe();
+        loop {
+            tokio::select! {
+                changed = ice_rx.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                }
+                _ = state_rx.wait_for(|s| *s == RTCPeerConnectionState::Closed) => break,
+            }
+            let state = *ice_rx.borrow_and_update();
+            match state {
+                RTCIceConnectionState::Connected | RTCIceConnectionState::Completed => {
+                    self.ice_restarts.store(0, Ordering::SeqCst);
+                }
+                RTCIceConnectionState::Failed => {
+                    if let Err(e) = self.restart_ice().await {
+                        warn!("Peer {} ICE failed, not restarting: {}", self.id, e);
+                    }
+                }
+                RTCIceConnectionState::Closed => break,
+                _ => {}
+            }
+        }
+    }
+
+    /// Ожидает ли offer сервера ответа клиента
+    pub fn has_pending_offer(&self) -> bool {
+        self.pc.signaling_state() == RTCSignalingState::HaveLocalOffer
+    }
+
+    /// Применяет answer клиента на offer сервера; слоты, добавленные за это время,
+    /// согласуются следующим offer
+    pub async fn handle_answer(&self, sdp: String) -> Result<()> {
+        {
+            let _order = self.applied_offer_seq.lock().await;
+            if !self.has_pending_offer() {
+                anyhow::bail!("Unexpected answer: no server offer is pending");
+            }
+            self.pc
+                .set_remote_description(RTCSessionDescription::answer(sdp)?)
+                .await?;
+        }
+        self.renegotiate().await
+    }
+
+    /// Возвращает локальный трек, через который участнику отправляется источник
+    /// `source_track` участника `source_peer`. Источник закрепляется за свободным слотом
+    /// своего типа при первом пакете; `Ok(None)`, если свободных слотов нет.
+    /// Пакеты пишутся с payload type, который получатель согласовал для кодека трека,
+    /// поэтому трек слота с другим кодеком заменяется треком с кодеком источника
+    /// `mime_type`; ошибка - получатель этот кодек не согласовал.
+    pub async fn local_track_for(
+        &self,
+        source_peer: &str,
+        source_track: &str,
+        track_type: TrackType,
+        kind: RTPCodecType,
+        mime_type: &str,
+    ) -> Result<Option<Arc<TrackLocalStaticRTP>>> {
+        if let Some(track) = self.resolved_track(source_peer, source_track, mime_type) {
+            return Ok(Some(track));
+        }
+
+        let codec = mime_type.to_ascii_lowercase();
+        if self.unsupported_codecs.lock().unwrap().contains(&codec) {
+            anyhow::bail!("Peer {} did not negotiate {}", self.id, mime_type);
+        }
+
+        let key = (source_peer.to_string(), source_track.to_string());
+        let (slot, local) = {
+            let local_tracks = self.local_tracks.read().await;
+            let mut assignments = self.slot_assignments.lock().unwrap();
+
+            let slot = match assignments.get(&key) {
+                Some(&slot) => slot,
+                None => {
+                    let free = |lt: &(usize, &LocalTrack)| {
+                        lt.1.track.kind() == kind
+                            && !assignments.values().any(|&slot| slot == lt.0)
+                    };
+                    // Сначала слот того же типа, затем любой свободный слот того же медиа
+                    let Some(slot) = local_tracks
+                        .iter()
+                        .enumerate()
+                        .filter(free)
+                        .find(|(_, lt)| lt.track_type == track_type)
+                        .or_else(|| local_tracks.iter().enumerate().find(free))
+                        .map(|(slot, _)| slot)
+                    else {
+                        return Ok(None);
+                    };
+
+                    debug!(
+                        "Peer {} slot {} assigned to track {} of {}",
+                        self.id, slot, source_track, source_peer
+                    );
+                    assignments.insert(key.clone(), slot);
+                    slot
+                }
+            };
+            match local_tracks.get(slot) {
+                Some(local) => (slot, local.clone()),
+                None => return Ok(None),
+            }
+        };
+
+        if local.track.codec().mime_type.eq_ignore_ascii_case(mime_type) {
+            self.remember_track(source_peer, source_track, mime_type, &local.track);
+            return Ok(Some(local.track));
+        }
+
+        let track = Arc::new(TrackLocalStaticRTP::new(
+            webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability {
+                mime_type: mime_type.to_owned(),
+                ..Default::default()
+            },
+            local.track.id().to_owned(),
+            local.track.stream_id().to_owned(),
+        ));
+        if let Err(e) = local
+            .sender
+            .replace_track(Some(track.clone() as Arc<dyn TrackLocal + Send + Sync>))
+            .await
+        {
+            warn!(
+                "Peer {} cannot receive {} from {}: {}",
+                self.id, mime_type, source_peer, e
+            );
+            self.unsupported_codecs.lock().unwrap().insert(codec);
+            self.slot_assignments.lock().unwrap().remove(&key);
+            return Err(e.into());
+        }
+
+        debug!(
+            "Peer {} slot {} switched from {} to {}",
+            self.id,
+            slot,
+            local.track.codec().mime_type,
+            mime_type
+        );
+        if let Some(local) = self.local_tracks.write().await.get_mut(slot) {
+            local.track = track.clone();
+        }
+        self.remember_track(source_peer, source_track, mime_type, &track);
+        Ok(Some(track))
+    }
+
+    /// Найденный ранее локальный трек источника, если кодек источника не менялся
+    fn resolved_track(
+        &self,
+        source_peer: &str,
+        source_track: &str,
+        mime_type: &str,
+    ) -> Option<Arc<TrackLocalStaticRTP>> {
+        let resolved = self.resolved_tracks.lock().unwrap();
+        let (codec, track) = resolved.get(source_peer)?.get(source_track)?;
+        codec.eq_ignore_ascii_case(mime_type).then(|| track.clone())
+    }
+
+    fn remember_track(
+        &self,
+        source_peer: &str,
+        source_track: &str,
+        mime_type: &str,
+        track: &Arc<TrackLocalStaticRTP>,
+    ) {
+        // Слот могли освободить, пока трек искался: запоминаем только закрепленный
+        let key = (source_peer.to_string(), source_track.to_string());
+        if !self.slot_assignments.lock().unwrap().contains_key(&key) {
+            return;
+        }
+        self.resolved_tracks
+            .lock()
+            .unwrap()
+            .entry(key.0)
+            .or_default()
+            .insert(key.1, (mime_type.to_string(), track.clone()));
+    }
+
+    /// Ограничивает скорость пересылки участнику (кбит/с); `None` снимает лимит
+    pub fn set_max_send_kbps(&self, kbps: Option<u32>) {
+        let limit = kbps.map(|kbps| u64::from(kbps) * 1000);
+        self.send_rate.lock().unwrap().set_limit_bps(limit);
+    }
+
+    pub fn max_send_kbps(&self) -> Option<u32> {
+        let limit = self.send_rate.lock().unwrap().limit_bps();
+        limit.map(|bps| (bps / 1000) as u32)
+    }
+
+    /// Учитывает пакет для отправки участнику; `false`, если пакет превышает лимит
+    /// скорости и его нужно отбросить (см. `SendRateCap`)
+    pub fn allow_send(&self, bytes: u64, kind: RTPCodecType, keyframe: bool) -> bool {
+        self.send_rate
+            .lock()
+            .unwrap()
+            .allow(bytes, kind, keyframe, Instant::now())
+    }
+
+    /// Скорость пересылки участнику за последнюю секунду (бит/с)
+    pub fn send_rate_bps(&self) -> u64 {
+        self.send_rate.lock().unwrap().rate_bps(Instant::now())
+    }
+
+    /// Освобождает слоты, занятые треками ушедшего участника
+    pub fn release_slots(&self, source_peer: &str) {
+        self.slot_assignments
+            .lock()
+            .unwrap()
+            .retain(|(peer_id, _), _| peer_id != source_peer);
+        self.resolved_tracks.lock().unwrap().remove(source_peer);
+    }
+
+    /// Освобождает слоты, занятые треками `track_type` участника (например, его
+    /// демонстрацией экрана); согласованные слоты остаются для следующих источников
+    pub fn release_source_slots(&self, source_peer: &str, track_type: TrackType) {
+        self.slot_assignments.lock().unwrap().retain(|(peer_id, track_id), _| {
+            peer_id != source_peer || TrackType::from_track_id(track_id) != track_type
+        });
+        if let Some(tracks) = self.resolved_tracks.lock().unwrap().get_mut(source_peer) {
+            tracks.retain(|track_id, _| TrackType::from_track_id(track_id) != track_type);
+        }
+    }
+
+    /// Есть ли у участника слот `kind`, не занятый ни одним источником
+    pub async fn has_free_slot(&self, kind: RTPCodecType) -> bool {
+        let local_tracks = self.local_tracks.read().await;
+        let assignments = self.slot_assignments.lock().unwrap();
+        local_tracks
+            .iter()
+            .enumerate()
+            .any(|(slot, lt)| lt.track.kind() == kind && !assignments.values().any(|&s| s == slot))
+    }
+
     /// Добавляет ICE кандидата
     pub async fn add_ice_candidate(&self, candidate: String) -> Result<()> {
+        self.diagnostics.record_remote_candidate(candidate.clone()).await;
+
+        // Кандидат пришел раньше offer: откладываем до установки remote description
+        {
+            let mut pending = self.pending_candidates.candidates.lock().await;
+            if self.pc.remote_description().await.is_none() {
+                if pending.len() >= MAX_PENDING_CANDIDATES {
+                    pending.pop_front();
+                    warn!("Pending candidate buffer full for peer {}, dropping oldest", self.id);
+                }
+                pending.push_back((Instant::now(), candidate));
+                debug!("Buffered early ICE candidate for peer {}", self.id);
+                return Ok(());
+            }
+        }
+
+        self.apply_ice_candidate(candidate).await
+    }
+
+    async fn apply_ice_candidate(&self, candidate: String) -> Result<()> {
         let ice_candidate = RTCIceCandidateInit {
             candidate,
             ..Default::default()
@@ -281,11 +1305,261 @@ impl Peer {
         Ok(())
     }
 
+    /// Применяет кандидаты, полученные до offer; просроченные отбрасываются
+    async fn flush_pending_candidates(&self) {
+        let pending: Vec<_> = self.pending_candidates.candidates.lock().await.drain(..).collect();
+        for (received_at, candidate) in pending {
+            if received_at.elapsed() > self.pending_candidates.grace {
+                warn!("Dropping expired early ICE candidate for peer {}", self.id);
+                continue;
+            }
+            if let Err(e) = self.apply_ice_candidate(candidate).await {
+                warn!("Failed to apply buffered ICE candidate for peer {}: {}", self.id, e);
+            }
+        }
+    }
+
+    /// Ждет первого offer от клиента не дольше `timeout`; `false`, если offer не пришел
+    pub async fn wait_offer(&self, timeout: Duration) -> bool {
+        let mut offer_rx = self.offer_received.subscribe();
+        tokio::time::timeout(timeout, offer_rx.wait_for(|received| *received))
+            .await
+            .is_ok_and(|result| result.is_ok())
+    }
+
+    /// Ждет завершения ICE/DTLS handshake (соединение установлено или не удалось),
+    /// но не дольше `timeout`
+    pub async fn wait_handshake(&self, timeout: Duration) {
+        let mut state_rx = self.connection_state.subscribe();
+        let finished = state_rx.wait_for(|state| {
+            !matches!(
+                state,
+                RTCPeerConnectionState::New | RTCPeerConnectionState::Connecting
+            )
+        });
+        if tokio::time::timeout(timeout, finished).await.is_err() {
+            warn!("Handshake for peer {} did not finish in {:?}", self.id, timeout);
+        }
+    }
+
+    /// Оценка пропускной способности участника как получателя по TWCC (бит/с)
+    pub fn bandwidth_estimate(&self) -> u64 {
+        self.bandwidth.lock().unwrap().estimate()
+    }
+
+    /// Сколько участник может принять (бит/с) по его обратной связи: меньшая из REMB
+    /// и TWCC оценок; `None`, пока получатель не прислал ни одной
+    pub fn receive_capacity(&self) -> Option<u64> {
+        let remb = Some(self.receive_estimate.load(Ordering::Relaxed)).filter(|bps| *bps > 0);
+        let twcc = self.bandwidth.lock().unwrap().measured();
+        match (remb, twcc) {
+            (Some(remb), Some(twcc)) => Some(remb.min(twcc)),
+            (remb, twcc) => remb.or(twcc),
+        }
+    }
+
+    /// Перегружен ли канал к участнику (оценка ниже порога с учетом гистерезиса)
+    pub fn is_congested(&self) -> bool {
+        self.congested.load(Ordering::Relaxed)
+    }
+
+    /// Не пересылать участнику пакеты тишины (Opus DTX)
+    pub fn set_skip_silence(&self, skip: bool) {
+        self.skip_silence.store(skip, Ordering::Relaxed);
+    }
+
+    pub fn skips_silence(&self) -> bool {
+        self.skip_silence.load(Ordering::Relaxed)
+    }
+
+    /// Учитывать пересылку участнику и запросы ключевых кадров в счетчиках сервера
+    pub fn report_to(&self, metrics: MetricsCounter) {
+        self.outbound.report_to(metrics.clone());
+        let _ = self.metrics.set(metrics);
+    }
+
+    /// Запоминает SSRC видео, которое публикует участник
+    pub fn register_incoming_video(&self, ssrc: u32) {
+        self.incoming_video_ssrcs.lock().unwrap().insert(ssrc);
+    }
+
+    /// Запрашивает у участника ключевой кадр (PLI) для всех его видео потоков.
+    /// Не чаще раза в `KEYFRAME_REQUEST_INTERVAL`; `false`, если запрос пропущен
+    pub async fn request_keyframe(&self) -> Result<bool> {
+        let ssrcs: Vec<u32> = self.incoming_video_ssrcs.lock().unwrap().iter().copied().collect();
+        if ssrcs.is_empty() {
+            return Ok(false);
+        }
+        {
+            let now = Instant::now();
+            let mut last = self.last_keyframe_request.lock().unwrap();
+            if last.is_some_and(|at| now.duration_since(at) < KEYFRAME_REQUEST_INTERVAL) {
+                return Ok(false);
+            }
+            *last = Some(now);
+        }
+
+        debug!("Requesting keyframe from peer {} (ssrcs: {:?})", self.id, ssrcs);
+        let packets: Vec<Box<dyn RtcpPacket + Send + Sync>> = ssrcs
+            .into_iter()
+            .map(|media_ssrc| {
+                Box::new(PictureLossIndication {
+                    sender_ssrc: 0,
+                    media_ssrc,
+                }) as Box<dyn RtcpPacket + Send + Sync>
+            })
+            .collect();
+        self.pc.write_rtcp(&packets).await?;
+        if let Some(metrics) = self.metrics.get() {
+            metrics.increment_keyframes_requested();
+        }
+        Ok(true)
+    }
+
+    /// Просит комнату запросить ключевой кадр у издателя `source_peer` от имени
+    /// этого получателя (так же передается PLI/FIR от клиента)
+    pub fn request_keyframe_from(&self, source_peer: &str) {
+        let _ = self.keyframe_requests_tx.send(source_peer.to_string());
+    }
+
+    /// Запросы ключевых кадров, которые участник как получатель адресует издателям
+    /// (id издателя). Забрать приемник можно один раз
+    pub fn take_keyframe_requests(&self) -> Option<mpsc::UnboundedReceiver<String>> {
+        self.keyframe_requests_rx.lock().unwrap().take()
+    }
+
+    /// Принимает data channels, которые открывает клиент, и передает их сообщения
+    /// в `handler` (в порядке получения для каждого канала)
+    pub fn on_data_channel<F>(&self, handler: F)
+    where
+        F: Fn(String, DataChannelMessage) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+    {
+        *self.data_handler.lock().unwrap() = Some(Arc::new(handler));
+
+        let peer_id = self.id.clone();
+        let channels = self.data_channels.clone();
+        let pending = self.pending_data.clone();
+        let handler = self.data_handler.clone();
+        let span = self.span.clone();
+        self.pc.on_data_channel(Box::new(move |dc| {
+            let peer_id = peer_id.clone();
+            let channels = channels.clone();
+            let pending = pending.clone();
+            let handler = handler.clone();
+            Box::pin(
+                async move {
+                    info!("Peer {} opened data channel {}", peer_id, dc.label());
+                    attach_data_channel(&peer_id, &channels, &pending, &handler, &dc);
+                    channels.write().await.insert(dc.label().to_string(), dc);
+                }
+                .instrument(span.clone()),
+            )
+        }));
+    }
+
+    /// Отправляет сообщение в data channel участника с меткой `label`. Если такого
+    /// канала нет, но data channels уже согласованы, сервер открывает его сам, а
+    /// сообщение ставится в очередь и отправляется после открытия. `true`, только если
+    /// сообщение отправлено в открытый канал
+    pub async fn send_data(&self, label: &str, data: &Bytes, is_string: bool) -> Result<bool> {
+        let (dc, negotiated) = {
+            let channels = self.data_channels.read().await;
+            (channels.get(label).cloned(), !channels.is_empty())
+        };
+        if let Some(dc) = dc {
+            if dc.ready_state() == RTCDataChannelState::Open {
+                send_on_channel(&dc, data, is_string).await?;
+                return Ok(true);
+            }
+            // Канал, открываемый сервером, еще не готов: сообщение уйдет после открытия
+            if let Some(queue) = self.pending_data.lock().unwrap().get_mut(label) {
+                queue.push((data.clone(), is_string));
+            }
+            return Ok(false);
+        }
+        // Без data channel от клиента SCTP не согласован, открыть канал нельзя
+        if !negotiated {
+            return Ok(false);
+        }
+
+        {
+            let mut pending = self.pending_data.lock().unwrap();
+            // Канал уже создается другим сообщением
+            if let Some(queue) = pending.get_mut(label) {
+                queue.push((data.clone(), is_string));
+                return Ok(false);
+            }
+            pending.insert(label.to_string(), vec![(data.clone(), is_string)]);
+        }
+
+        let dc = match self.pc.create_data_channel(label, None).await {
+            Ok(dc) => dc,
+            Err(e) => {
+                self.pending_data.lock().unwrap().remove(label);
+                return Err(e.into());
+            }
+        };
+        attach_data_channel(
+            &self.id,
+            &self.data_channels,
+            &self.pending_data,
+            &self.data_handler,
+            &dc,
+        );
+        let opened = dc.clone();
+        let pending = self.pending_data.clone();
+        let peer_id = self.id.clone();
+        let span = self.span.clone();
+        dc.on_open(Box::new(move || {
+            let queued = pending.lock().unwrap().remove(opened.label()).unwrap_or_default();
+            Box::pin(
+                async move {
+                    for (data, is_string) in queued {
+                        if let Err(e) = send_on_channel(&opened, &data, is_string).await {
+                            debug!("Failed to send data to peer {}: {}", peer_id, e);
+                        }
+                    }
+                }
+                .instrument(span),
+            )
+        }));
+        self.data_channels.write().await.insert(label.to_string(), dc);
+        Ok(false)
+    }
+
+    /// Отправляет участнику как издателю один REMB с рекомендуемым битрейтом для всех
+    /// его видео потоков; `false`, если участник не публикует видео
+    pub async fn send_remb(&self, bitrate: u64) -> Result<bool> {
+        let ssrcs: Vec<u32> = self.incoming_video_ssrcs.lock().unwrap().iter().copied().collect();
+        if ssrcs.is_empty() {
+            return Ok(false);
+        }
+        self.pc
+            .write_rtcp(&[Box::new(ReceiverEstimatedMaximumBitrate {
+                sender_ssrc: 0,
+                bitrate: bitrate as f32,
+                ssrcs,
+            })])
+            .await?;
+        Ok(true)
+    }
+
+    /// Текущее отображаемое имя
+    pub fn name(&self) -> String {
+        self.name.read().unwrap().clone()
+    }
+
+    pub fn set_name(&self, name: String) {
+        *self.name.write().unwrap() = name;
+    }
+
     /// Обновляет состояние участника
     pub async fn update_state(&self, muted: bool, video_on: bool, screen_sharing: bool) {
         *self.muted.write().await = muted;
         *self.video_on.write().await = video_on;
         *self.screen_sharing.write().await = screen_sharing;
+        self.audio_forwarded.store(!muted, Ordering::Relaxed);
+        self.camera_forwarded.store(video_on, Ordering::Relaxed);
 
         info!(
             "Peer {} state updated: muted={}, video={}, screen={}",
@@ -293,6 +1567,30 @@ impl Peer {
         );
     }
 
+    /// Поднимает или опускает руку участника
+    pub async fn update_hand(&self, raised: bool) {
+        *self.hand_raised.write().await = raised;
+        info!("Peer {} hand raised: {}", self.id, raised);
+    }
+
+    /// Включает или снимает отключение звука участника ведущим
+    pub fn set_force_muted(&self, muted: bool) {
+        self.force_muted.store(muted, Ordering::Relaxed);
+    }
+
+    /// Пересылается ли трек `track_type` участника по его состоянию
+    /// (см. `Room::source_enabled`)
+    pub fn forwards(&self, track_type: TrackType) -> bool {
+        match track_type {
+            TrackType::Audio => {
+                self.audio_forwarded.load(Ordering::Relaxed)
+                    && !self.force_muted.load(Ordering::Relaxed)
+            }
+            TrackType::Camera => self.camera_forwarded.load(Ordering::Relaxed),
+            TrackType::Screen => true,
+        }
+    }
+
     /// Получает текущее состояние участника
     pub async fn get_state(&self) -> (bool, bool, bool) {
         let muted = *self.muted.read().await;
@@ -305,46 +1603,114 @@ impl Peer {
     pub fn send_message(&self, msg: ServerMessage) -> Result<()> {
         let json = serde_json::to_string(&msg)?;
         self.ws_tx
-            .send(Message::text(json))
-            .map_err(|e| anyhow::anyhow!("Failed to send message: {}", e))?;
-        Ok(())
+            .send_with(Message::text(json), msg.priority())
+            .map_err(|e| anyhow::anyhow!("Failed to send message: {}", e))
     }
 
     /// Закрывает peer connection
     pub async fn close(&self) -> Result<()> {
+        self.outbound.close();
         self.pc.close().await?;
         info!("Closed peer connection for {}", self.id);
         Ok(())
     }
 
     /// Получает статистику соединения (для отладки)
+    #[allow(dead_code)]
     pub async fn get_stats(&self) -> String {
         let state = self.pc.connection_state();
         let ice_state = self.pc.ice_connection_state();
         let ice_gathering_state = self.pc.ice_gathering_state();
 
         format!(
-            "Peer {}: state={:?}, ice={:?}, gathering={:?}",
-            self.id, state, ice_state, ice_gathering_state
+            "Peer {}: state={:?}, ice={:?}, gathering={:?}, outbound_queue={}, {}, \
+             bandwidth_estimate={}kbps, congested={}, send_rate={}kbps, send_limit={}",
+            self.id,
+            state,
+            ice_state,
+            ice_gathering_state,
+            self.outbound.len(),
+            self.outbound.drop_stats(),
+            self.bandwidth_estimate() / 1000,
+            self.is_congested(),
+            self.send_rate_bps() / 1000,
+            self.max_send_kbps()
+                .map_or_else(|| "none".to_string(), |kbps| format!("{}kbps", kbps))
         )
     }
+
+    /// RTP статистика соединения по всем потокам в обе стороны
+    pub async fn rtp_stats(&self) -> PeerStats {
+        let mut stats = PeerStats::default();
+        for report in self.pc.get_stats().await.reports.values() {
+            match report {
+                StatsReportType::InboundRTP(inbound) => {
+                    stats.packets_received += inbound.packets_received;
+                    stats.bytes_received += inbound.bytes_received;
+                }
+                StatsReportType::OutboundRTP(outbound) => {
+                    stats.packets_sent += outbound.packets_sent;
+                    stats.bytes_sent += outbound.bytes_sent;
+                    stats.nack_count += outbound.nack_count;
+                }
+                StatsReportType::RemoteInboundRTP(remote) => {
+                    stats.packets_lost += remote.packets_lost;
+                    let rtt = remote.round_trip_time.map(|secs| (secs * 1000.0) as u64);
+                    stats.round_trip_time_ms = stats.round_trip_time_ms.max(rtt);
+                }
+                _ => {}
+            }
+        }
+        stats
+    }
+
+    /// Собирает диагностический пакет сессии, добавляя свежий замер качества
+    pub async fn diagnostics_bundle(&self, room_id: &str) -> DiagnosticsBundle {
+        let stats = self.rtp_stats().await;
+        let sample = QualitySample {
+            packets_received: stats.packets_received,
+            packets_sent: stats.packets_sent,
+            bytes_sent: stats.bytes_sent,
+            ..Default::default()
+        };
+        self.diagnostics.record_quality(sample).await;
+
+        let selected_pair = self
+            .pc
+            .sctp()
+            .transport()
+            .ice_transport()
+            .get_selected_candidate_pair()
+            .await
+            .map(|pair| pair.to_string());
+
+        self.diagnostics.bundle(room_id, &self.id, selected_pair).await
+    }
 }
 
 /// Builder для создания Peer с кастомными настройками
 pub struct PeerBuilder {
     id: String,
     name: String,
-    ws_tx: mpsc::UnboundedSender<Message>,
+    ws_tx: WsSender,
     ice_servers: Option<Vec<RTCIceServer>>,
+    state_notify_debounce: Option<Duration>,
+    media_options: PeerMediaOptions,
+    candidate_preferences: Vec<IceCandidatePreference>,
+    candidate_grace: Duration,
 }
 
 impl PeerBuilder {
-    pub fn new(id: String, name: String, ws_tx: mpsc::UnboundedSender<Message>) -> Self {
+    pub fn new(id: String, name: String, ws_tx: WsSender) -> Self {
         Self {
             id,
             name,
             ws_tx,
             ice_servers: None,
+            state_notify_debounce: None,
+            media_options: PeerMediaOptions::default(),
+            candidate_preferences: Vec::new(),
+            candidate_grace: DEFAULT_CANDIDATE_GRACE,
         }
     }
 
@@ -353,6 +1719,7 @@ impl PeerBuilder {
         self
     }
 
+    #[allow(dead_code)]
     pub fn with_turn_server(
         mut self,
         url: String,
@@ -374,9 +1741,645 @@ impl PeerBuilder {
         self
     }
 
+    /// Включает уведомления клиента об изменениях состояния соединения
+    pub fn with_connection_state_notifications(mut self, debounce: Duration) -> Self {
+        self.state_notify_debounce = Some(debounce);
+        self
+    }
+
+    /// Включает согласование RTP расширения frame-marking
+    pub fn with_frame_marking(mut self, enabled: bool) -> Self {
+        self.media_options.frame_marking = enabled;
+        self
+    }
+
+    /// Задает предпочтения для приоритетов локальных ICE кандидатов
+    pub fn with_candidate_preferences(mut self, preferences: Vec<IceCandidatePreference>) -> Self {
+        self.candidate_preferences = preferences;
+        self
+    }
+
+    /// Сколько хранить ICE кандидаты, пришедшие раньше offer
+    pub fn with_candidate_grace(mut self, grace: Duration) -> Self {
+        self.candidate_grace = grace;
+        self
+    }
+
+    /// Настраивает очередь исходящих пакетов и политику отбрасывания
+    pub fn with_outbound_queue(mut self, size: usize, policy: DropPolicy) -> Self {
+        self.media_options.outbound_queue_size = size;
+        self.media_options.drop_policy = policy;
+        self
+    }
+
+    /// Сколько ICE restart подряд допускается без восстановления соединения
+    pub fn with_max_ice_restarts(mut self, max_ice_restarts: u32) -> Self {
+        self.media_options.max_ice_restarts = max_ice_restarts;
+        self
+    }
+
+    /// Объявлять `public_ip` srflx кандидатом (1:1 NAT)
+    pub fn with_public_ip(mut self, public_ip: Option<IpAddr>) -> Self {
+        self.media_options.public_ip = public_ip;
+        self
+    }
+
+    /// Какие адреса интерфейсов объявлять host кандидатами
+    pub fn with_host_candidate_filter(mut self, filter: HostCandidateFilter) -> Self {
+        self.media_options.host_candidate_filter = filter;
+        self
+    }
+
+    /// Веса классов трафика (аудио, видео, данные) в очереди исходящих пакетов
+    pub fn with_outbound_class_weights(mut self, weights: ClassWeights) -> Self {
+        self.media_options.class_weights = weights;
+        self
+    }
+
+    /// Включает согласование RTP расширения уровня звука
+    pub fn with_audio_level(mut self, enabled: bool) -> Self {
+        self.media_options.audio_level = enabled;
+        self
+    }
+
+    /// Ограничивает локальные UDP порты ICE диапазоном `port_min..=port_max`
+    pub fn with_udp_port_range(mut self, port_min: u16, port_max: u16) -> Self {
+        self.media_options.udp_port_range = Some((port_min, port_max));
+        self
+    }
+
+    /// Максимальное количество аудио и видео треков, отправляемых участнику
+    pub fn with_max_transceivers(mut self, audio: usize, video: usize) -> Self {
+        self.media_options.max_audio_transceivers = audio;
+        self.media_options.max_video_transceivers = video;
+        self
+    }
+
+    /// Пороги TWCC оценки (бит/с) для переключения участника на нижние simulcast слои
+    pub fn with_congestion_thresholds(mut self, down_bps: u64, up_bps: u64) -> Self {
+        self.media_options.congestion_thresholds = CongestionThresholds { down_bps, up_bps };
+        self
+    }
+
+    /// Ограничивает разрешение видео, которое участник может отправлять
+    pub fn with_max_video_resolution(mut self, limit: Option<VideoResolution>) -> Self {
+        self.media_options.max_video_resolution = limit;
+        self
+    }
+
+    /// Ограничивает согласуемые кодеки списком разрешенных
+    pub fn with_allowed_codecs(mut self, codecs: Vec<String>) -> Self {
+        self.media_options.allowed_codecs = codecs;
+        self
+    }
+
+    /// Порядок и состав кодеков сервера
+    pub fn with_codec_preferences(mut self, preferences: CodecPreferences) -> Self {
+        self.media_options.codec_preferences = preferences;
+        self
+    }
+
     pub async fn build(self) -> Result<Peer> {
-        let peer = Peer::new(self.id, self.name, self.ws_tx, self.ice_servers).await?;
-        peer.setup_handlers().await?;
+        let notifier = self
+            .state_notify_debounce
+            .map(|debounce| ConnectionStateNotifier::new(self.ws_tx.clone(), debounce));
+        let mut peer = Peer::new(
+            self.id,
+            self.name,
+            self.ws_tx,
+            self.ice_servers,
+            self.media_options,
+        )
+        .await?;
+        peer.pending_candidates = Arc::new(PendingCandidates::new(self.candidate_grace));
+        peer.setup_handlers(notifier, self.candidate_preferences).await?;
+
+        let watcher = peer.clone();
+        let span = peer.span.clone();
+        tokio::spawn(async move { watcher.restart_ice_on_failure().await }.instrument(span));
         Ok(peer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signaling::{ws_channel, DEFAULT_WS_SEND_QUEUE_SIZE};
+    use webrtc::api::media_engine::{MIME_TYPE_H264, MIME_TYPE_VP8};
+
+    fn parse(msg: Message) -> ServerMessage {
+        serde_json::from_str(msg.to_text().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_connection_state_notification() {
+        let (tx, mut rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let notifier = ConnectionStateNotifier::new(tx, Duration::from_millis(20));
+
+        // Быстрая серия переходов схлопывается в одно уведомление
+        notifier.set_ice_state(RTCIceConnectionState::Checking).await;
+        notifier.set_peer_state(RTCPeerConnectionState::Connecting).await;
+        notifier.set_peer_state(RTCPeerConnectionState::Connected).await;
+
+        let msg = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        match parse(msg) {
+            ServerMessage::ConnectionStateChanged { state, ice_state } => {
+                assert_eq!(state, "connected");
+                assert_eq!(ice_state, "checking");
+            }
+            other => panic!("Unexpected message: {:?}", other),
+        }
+
+        // Флап туда-обратно не порождает сообщения
+        notifier.set_peer_state(RTCPeerConnectionState::Disconnected).await;
+        notifier.set_peer_state(RTCPeerConnectionState::Connected).await;
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_diagnostics_bundle() {
+        let (tx, _rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let server = PeerBuilder::new("srv".to_string(), "Server".to_string(), tx)
+            .with_ice_servers(vec![])
+            .build()
+            .await
+            .unwrap();
+
+        let (client_tx, _client_rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let client = Peer::new(
+            "cli".to_string(),
+            "Client".to_string(),
+            client_tx,
+            Some(vec![]),
+            PeerMediaOptions::default(),
+        )
+        .await
+        .unwrap();
+        client
+            .pc
+            .add_transceiver_from_kind(RTPCodecType::Audio, None)
+            .await
+            .unwrap();
+        let offer = client.pc.create_offer(None).await.unwrap();
+
+        server.handle_offer(offer.sdp).await.unwrap();
+        server
+            .add_ice_candidate("candidate:1 1 udp 2130706431 192.0.2.1 50000 typ host".to_string())
+            .await
+            .unwrap();
+
+        let bundle = server.diagnostics_bundle("room1").await;
+        assert_eq!(bundle.room_id, "room1");
+        assert_eq!(bundle.participant_id, "srv");
+        let offer_sdp = bundle.offer_sdp.unwrap();
+        assert!(offer_sdp.contains("a=ice-pwd:<redacted>"));
+        assert!(bundle.answer_sdp.unwrap().contains("a=ice-pwd:<redacted>"));
+        assert_eq!(bundle.remote_candidates.len(), 1);
+        assert_eq!(bundle.quality_samples.len(), 1);
+
+        let json = serde_json::to_value(ServerMessage::Diagnostics {
+            bundle: server.diagnostics_bundle("room1").await,
+        })
+        .unwrap();
+        for section in ["offer_sdp", "answer_sdp", "local_candidates", "state_history"] {
+            assert!(json["bundle"].get(section).is_some(), "missing {}", section);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_candidate_before_offer_is_applied() {
+        let (tx, _rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let server = PeerBuilder::new("srv".to_string(), "Server".to_string(), tx)
+            .with_ice_servers(vec![])
+            .build()
+            .await
+            .unwrap();
+
+        let (client_tx, _client_rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let client = Peer::new(
+            "cli".to_string(),
+            "Client".to_string(),
+            client_tx,
+            Some(vec![]),
+            PeerMediaOptions::default(),
+        )
+        .await
+        .unwrap();
+        client
+            .pc
+            .add_transceiver_from_kind(RTPCodecType::Audio, None)
+            .await
+            .unwrap();
+        let offer = client.pc.create_offer(None).await.unwrap();
+
+        server
+            .add_ice_candidate("candidate:1 1 udp 2130706431 192.0.2.1 50000 typ host".to_string())
+            .await
+            .unwrap();
+        assert_eq!(server.pending_candidates.len().await, 1);
+
+        server.handle_offer(offer.sdp).await.unwrap();
+        assert_eq!(server.pending_candidates.len().await, 0);
+
+        // ICE агент добавляет удаленные кандидаты асинхронно
+        let mut applied = false;
+        for _ in 0..50 {
+            applied = server.pc.get_stats().await.reports.values().any(|report| {
+                matches!(report, StatsReportType::RemoteCandidate(c) if c.ip == "192.0.2.1")
+            });
+            if applied {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(applied);
+    }
+
+    #[tokio::test]
+    async fn test_udp_port_range() {
+        let (tx, mut rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let server = PeerBuilder::new("srv".to_string(), "Server".to_string(), tx)
+            .with_ice_servers(vec![])
+            .with_udp_port_range(41000, 41010)
+            .build()
+            .await
+            .unwrap();
+
+        let (client_tx, _client_rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let client = Peer::new(
+            "cli".to_string(),
+            "Client".to_string(),
+            client_tx,
+            Some(vec![]),
+            PeerMediaOptions::default(),
+        )
+        .await
+        .unwrap();
+        client
+            .pc
+            .add_transceiver_from_kind(RTPCodecType::Audio, None)
+            .await
+            .unwrap();
+        let offer = client.pc.create_offer(None).await.unwrap();
+
+        let mut gathered = server.pc.gathering_complete_promise().await;
+        server.handle_offer(offer.sdp).await.unwrap();
+        let _ = gathered.recv().await;
+
+        let sdp = server.pc.local_description().await.unwrap().sdp;
+        let ports: Vec<u16> = sdp
+            .lines()
+            .filter_map(|line| line.strip_prefix("a=candidate:"))
+            .filter_map(|candidate| candidate.split(' ').nth(5)?.parse().ok())
+            .collect();
+        assert!(!ports.is_empty());
+        assert!(ports.iter().all(|port| (41000..=41010).contains(port)), "{:?}", ports);
+
+        // Кандидаты сервера отправлены клиенту, за ними - конец сбора
+        let mut messages = Vec::new();
+        while let Ok(Some(msg)) = tokio::time::timeout(Duration::from_secs(1), rx.recv()).await {
+            let msg: ServerMessage = serde_json::from_str(msg.to_text().unwrap()).unwrap();
+            let complete = matches!(msg, ServerMessage::IceGatheringComplete);
+            messages.push(msg);
+            if complete {
+                break;
+            }
+        }
+        assert!(matches!(messages.first(), Some(ServerMessage::Candidate { .. })));
+        assert!(matches!(messages.last(), Some(ServerMessage::IceGatheringComplete)));
+    }
+
+    #[tokio::test]
+    async fn test_public_ip_advertised_as_srflx() {
+        let (tx, _rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let server = PeerBuilder::new("srv".to_string(), "Server".to_string(), tx)
+            .with_ice_servers(vec![])
+            .with_public_ip(Some("203.0.113.7".parse().unwrap()))
+            .build()
+            .await
+            .unwrap();
+
+        let (client_tx, _client_rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let client = Peer::new(
+            "cli".to_string(),
+            "Client".to_string(),
+            client_tx,
+            Some(vec![]),
+            PeerMediaOptions::default(),
+        )
+        .await
+        .unwrap();
+        client
+            .pc
+            .add_transceiver_from_kind(RTPCodecType::Audio, None)
+            .await
+            .unwrap();
+        let offer = client.pc.create_offer(None).await.unwrap();
+
+        let mut gathered = server.pc.gathering_complete_promise().await;
+        server.handle_offer(offer.sdp).await.unwrap();
+        let _ = gathered.recv().await;
+
+        let sdp = server.pc.local_description().await.unwrap().sdp;
+        assert!(
+            sdp.lines()
+                .any(|line| line.contains(" 203.0.113.7 ") && line.contains("typ srflx")),
+            "{}",
+            sdp
+        );
+    }
+
+    #[tokio::test]
+    async fn test_two_video_transceivers_negotiated() {
+        let (tx, _rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let server = PeerBuilder::new("srv".to_string(), "Server".to_string(), tx)
+            .with_ice_servers(vec![])
+            .build()
+            .await
+            .unwrap();
+
+        let (client_tx, _client_rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let client = Peer::new(
+            "cli".to_string(),
+            "Client".to_string(),
+            client_tx,
+            Some(vec![]),
+            PeerMediaOptions::default(),
+        )
+        .await
+        .unwrap();
+        for kind in [RTPCodecType::Audio, RTPCodecType::Video, RTPCodecType::Video] {
+            client.pc.add_transceiver_from_kind(kind, None).await.unwrap();
+        }
+        let offer = client.pc.create_offer(None).await.unwrap();
+
+        let answer = server.handle_offer(offer.sdp.clone()).await.unwrap();
+        assert_eq!(count_media_sections(&answer), (1, 2));
+        assert_eq!(server.local_tracks.read().await.len(), 3);
+
+        // Повторный offer не добавляет лишних треков
+        server.handle_offer(offer.sdp).await.unwrap();
+        assert_eq!(server.local_tracks.read().await.len(), 3);
+
+        // Каждая камера издателя получает свой слот, закрепленный за ней
+        let slot = |source: &'static str, track: &'static str, track_type, mime| {
+            let server = &server;
+            async move {
+                server
+                    .local_track_for(source, track, track_type, RTPCodecType::Video, mime)
+                    .await
+                    .unwrap()
+            }
+        };
+        let cam1 = slot("pub", "cam1", TrackType::Camera, MIME_TYPE_VP8).await.unwrap();
+        let cam2 = slot("pub", "cam2", TrackType::Camera, MIME_TYPE_VP8).await.unwrap();
+        assert_ne!(cam1.id(), cam2.id());
+        let again = slot("pub", "cam1", TrackType::Camera, MIME_TYPE_VP8).await.unwrap();
+        assert!(Arc::ptr_eq(&again, &cam1));
+        assert!(slot("pub", "cam3", TrackType::Camera, MIME_TYPE_VP8).await.is_none());
+
+        // Слоты ушедшего издателя освобождаются; слот получает кодек нового источника
+        server.release_slots("pub");
+        assert!(server.resolved_track("pub", "cam1", MIME_TYPE_VP8).is_none());
+        let screen = slot("other", "screen", TrackType::Screen, MIME_TYPE_H264).await.unwrap();
+        assert_eq!(screen.codec().mime_type, MIME_TYPE_H264);
+        assert!([cam1.id(), cam2.id()].contains(&screen.id()));
+        let local_tracks = server.local_tracks.read().await;
+        assert!(local_tracks.iter().any(|lt| Arc::ptr_eq(&lt.track, &screen)));
+    }
+
+    #[tokio::test]
+    async fn test_data_queued_until_server_channel_opens() {
+        let (tx, _rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let server = PeerBuilder::new("srv".to_string(), "Server".to_string(), tx)
+            .with_ice_servers(vec![])
+            .build()
+            .await
+            .unwrap();
+        let data = Bytes::from_static(b"hello");
+
+        // Без data channels от клиента доставить некуда
+        assert!(!server.send_data("files", &data, true).await.unwrap());
+        assert!(server.pending_data.lock().unwrap().is_empty());
+
+        let chat = server.pc.create_data_channel("chat", None).await.unwrap();
+        server.data_channels.write().await.insert("chat".to_string(), chat);
+
+        // Канал открывается сервером: сообщения не доставлены, а ждут открытия
+        assert!(!server.send_data("files", &data, true).await.unwrap());
+        assert!(!server.send_data("files", &data, false).await.unwrap());
+        assert!(server.data_channels.read().await.contains_key("files"));
+        assert_eq!(server.pending_data.lock().unwrap()["files"].len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_added_slot_renegotiated_by_server_offer() {
+        let (tx, mut rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let server = PeerBuilder::new("srv".to_string(), "Server".to_string(), tx)
+            .with_ice_servers(vec![])
+            .build()
+            .await
+            .unwrap();
+        let (client_tx, _client_rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let client = PeerBuilder::new("cli".to_string(), "Client".to_string(), client_tx)
+            .with_ice_servers(vec![])
+            .build()
+            .await
+            .unwrap();
+        for kind in [RTPCodecType::Audio, RTPCodecType::Video] {
+            client.pc.add_transceiver_from_kind(kind, None).await.unwrap();
+        }
+
+        // До первого согласования слоты создает offer клиента
+        assert!(!server.add_slot(TrackType::Screen).await.unwrap());
+
+        let offer = client.pc.create_offer(None).await.unwrap();
+        client.pc.set_local_description(offer.clone()).await.unwrap();
+        let answer = server.handle_offer(offer.sdp).await.unwrap();
+        client
+            .pc
+            .set_remote_description(RTCSessionDescription::answer(answer).unwrap())
+            .await
+            .unwrap();
+        assert!(server.handle_answer(String::new()).await.is_err());
+
+        // Демонстрации экрана не хватает слота: сервер добавляет его и присылает offer
+        assert!(server.add_slot(TrackType::Screen).await.unwrap());
+        let sdp = loop {
+            let Some(Message::Text(text)) = rx.recv().await else {
+                panic!("server offer was not sent");
+            };
+            if let Ok(ServerMessage::Offer { sdp }) = serde_json::from_str(&text) {
+                break sdp;
+            }
+        };
+        assert!(sdp.contains("video-srv-1"));
+
+        client
+            .pc
+            .set_remote_description(RTCSessionDescription::offer(sdp).unwrap())
+            .await
+            .unwrap();
+        let client_answer = client.pc.create_answer(None).await.unwrap();
+        client.pc.set_local_description(client_answer.clone()).await.unwrap();
+        server.handle_answer(client_answer.sdp).await.unwrap();
+        assert_eq!(server.pc.signaling_state(), RTCSignalingState::Stable);
+    }
+
+    #[tokio::test]
+    async fn test_ice_restart_limited() {
+        let (tx, mut rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let server = PeerBuilder::new("srv".to_string(), "Server".to_string(), tx)
+            .with_ice_servers(vec![])
+            .with_max_ice_restarts(2)
+            .build()
+            .await
+            .unwrap();
+        let (client_tx, _client_rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let client = PeerBuilder::new("cli".to_string(), "Client".to_string(), client_tx)
+            .with_ice_servers(vec![])
+            .build()
+            .await
+            .unwrap();
+        client
+            .pc
+            .add_transceiver_from_kind(RTPCodecType::Audio, None)
+            .await
+            .unwrap();
+
+        assert!(server.restart_ice().await.is_err());
+
+        let offer = client.pc.create_offer(None).await.unwrap();
+        client.pc.set_local_description(offer.clone()).await.unwrap();
+        let answer = server.handle_offer(offer.sdp).await.unwrap();
+        let ufrag = |sdp: &str| {
+            sdp.lines()
+                .find_map(|line| line.strip_prefix("a=ice-ufrag:"))
+                .map(str::to_string)
+        };
+
+        // Offer перезапуска несет новые ICE учетные данные
+        server.restart_ice().await.unwrap();
+        let restart_sdp = loop {
+            if let ServerMessage::Offer { sdp } = parse(rx.recv().await.unwrap()) {
+                break sdp;
+            }
+        };
+        assert_ne!(ufrag(&restart_sdp), ufrag(&answer));
+
+        server.restart_ice().await.unwrap();
+        assert!(server.restart_ice().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rapid_offers_apply_only_latest() {
+        let (tx, _rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let server = PeerBuilder::new("srv".to_string(), "Server".to_string(), tx)
+            .with_ice_servers(vec![])
+            .build()
+            .await
+            .unwrap();
+
+        let (client_tx, _client_rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let client = Peer::new(
+            "cli".to_string(),
+            "Client".to_string(),
+            client_tx,
+            Some(vec![]),
+            PeerMediaOptions::default(),
+        )
+        .await
+        .unwrap();
+        client
+            .pc
+            .add_transceiver_from_kind(RTPCodecType::Audio, None)
+            .await
+            .unwrap();
+        let first = client.pc.create_offer(None).await.unwrap().sdp;
+        client
+            .pc
+            .add_transceiver_from_kind(RTPCodecType::Video, None)
+            .await
+            .unwrap();
+        let second = client.pc.create_offer(None).await.unwrap().sdp;
+
+        // Второй offer начинает обрабатываться раньше первого
+        let (newest, stale) = tokio::join!(
+            server.handle_offer_sequenced(second, Some(2)),
+            server.handle_offer_sequenced(first, Some(1)),
+        );
+        let (seq, answer) = newest.unwrap().unwrap();
+        assert_eq!(seq, 2);
+        assert_eq!(count_media_sections(&answer), (1, 1));
+        let remote = server.pc.remote_description().await.unwrap().sdp;
+        assert_eq!(count_media_sections(&remote), (1, 1));
+
+        // Устаревший offer не применяется: клиент получает answer на последний
+        let (seq, stale_answer) = stale.unwrap().unwrap();
+        assert_eq!(seq, 2);
+        assert_eq!(count_media_sections(&stale_answer), (1, 1));
+        assert_eq!(*server.applied_offer_seq.lock().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_wait_offer_times_out_without_offer() {
+        let (tx, _rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let server = PeerBuilder::new("srv".to_string(), "Server".to_string(), tx)
+            .with_ice_servers(vec![])
+            .build()
+            .await
+            .unwrap();
+
+        // Участник, не приславший offer, выселяется по таймауту
+        assert!(!server.wait_offer(Duration::from_millis(50)).await);
+
+        let (client_tx, _client_rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let client = Peer::new(
+            "cli".to_string(),
+            "Client".to_string(),
+            client_tx,
+            Some(vec![]),
+            PeerMediaOptions::default(),
+        )
+        .await
+        .unwrap();
+        client
+            .pc
+            .add_transceiver_from_kind(RTPCodecType::Audio, None)
+            .await
+            .unwrap();
+        let offer = client.pc.create_offer(None).await.unwrap();
+
+        let (received, answer) = tokio::join!(
+            server.wait_offer(Duration::from_secs(5)),
+            server.handle_offer(offer.sdp)
+        );
+        answer.unwrap();
+        assert!(received);
+    }
+
+    #[tokio::test]
+    async fn test_request_keyframe_debounced() {
+        let (tx, _rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let publisher = PeerBuilder::new("pub".to_string(), "Publisher".to_string(), tx)
+            .with_ice_servers(vec![])
+            .build()
+            .await
+            .unwrap();
+
+        // Без видео запрашивать нечего, и REMB не к чему относить
+        assert!(!publisher.request_keyframe().await.unwrap());
+        assert!(!publisher.send_remb(1_000_000).await.unwrap());
+
+        publisher.register_incoming_video(1234);
+        // Транспорт еще не установлен, поэтому сама отправка может завершиться ошибкой,
+        // но попытка учитывается
+        assert!(!matches!(publisher.request_keyframe().await, Ok(false)));
+        // Повторный запрос в пределах интервала пропускается
+        assert!(!publisher.request_keyframe().await.unwrap());
+    }
+}
@@ -0,0 +1,217 @@
+use rand::Rng;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Максимальное количество хранимых токенов (выданных и использованных)
+pub const MAX_RECONNECT_TOKENS: usize = 10_000;
+
+/// Участник, для которого выдан токен переподключения
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconnectGrant {
+    pub room_id: String,
+    pub participant_id: String,
+    pub name: String,
+}
+
+/// Почему токен не принят
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    /// Токен уже использован (повтор или кража)
+    Reused,
+    /// Время жизни токена истекло
+    Expired,
+    /// Токен не выдавался или вытеснен из хранилища
+    Unknown,
+}
+
+impl TokenError {
+    /// Код ошибки для клиента (`ServerMessage::Error::message`)
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Reused => "token_reused",
+            Self::Expired => "token_expired",
+            Self::Unknown => "token_invalid",
+        }
+    }
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+struct TokenStore {
+    issued: HashMap<String, (ReconnectGrant, Instant)>,
+    /// Порядок выдачи для вытеснения самых старых токенов
+    issue_order: VecDeque<String>,
+    used: HashSet<String>,
+    use_order: VecDeque<String>,
+}
+
+/// Одноразовые токены переподключения. Токен - случайный nonce, который
+/// принимается один раз и только в пределах времени жизни.
+/// Хранилище ограничено: при переполнении вытесняются самые старые записи.
+pub struct ReconnectTokens {
+    lifetime: Duration,
+    capacity: usize,
+    store: Mutex<TokenStore>,
+}
+
+impl ReconnectTokens {
+    pub fn new(lifetime: Duration, capacity: usize) -> Self {
+        Self {
+            lifetime,
+            capacity: capacity.max(1),
+            store: Mutex::new(TokenStore {
+                issued: HashMap::new(),
+                issue_order: VecDeque::new(),
+                used: HashSet::new(),
+                use_order: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Выдает новый токен для участника
+    pub fn issue(&self, grant: ReconnectGrant, now: Instant) -> String {
+        let token: String = rand::thread_rng()
+            .gen::<[u8; 16]>()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        let mut store = self.store.lock().unwrap();
+        store.issued.insert(token.clone(), (grant, now));
+        store.issue_order.push_back(token.clone());
+        while store.issue_order.len() > self.capacity {
+            if let Some(oldest) = store.issue_order.pop_front() {
+                store.issued.remove(&oldest);
+            }
+        }
+        token
+    }
+
+    /// Принимает токен: после успешного использования, как и после истечения
+    /// срока, повторно он не принимается
+    pub fn redeem(&self, token: &str, now: Instant) -> Result<ReconnectGrant, TokenError> {
+        self.redeem_if(token, now, |_| true)
+    }
+
+    /// Принимает токен, только если он выдан участнику `participant_id` комнаты
+    /// `room_id`. Токен чужой сессии не принимается и не сгорает
+    pub fn redeem_for(
+        &self,
+        token: &str,
+        room_id: &str,
+        participant_id: &str,
+        now: Instant,
+    ) -> Result<ReconnectGrant, TokenError> {
+        self.redeem_if(token, now, |grant| {
+            grant.room_id == room_id && grant.participant_id == participant_id
+        })
+    }
+
+    fn redeem_if(
+        &self,
+        token: &str,
+        now: Instant,
+        matches: impl FnOnce(&ReconnectGrant) -> bool,
+    ) -> Result<ReconnectGrant, TokenError> {
+        let mut store = self.store.lock().unwrap();
+        if store.used.contains(token) {
+            return Err(TokenError::Reused);
+        }
+
+        if !store.issued.get(token).is_some_and(|(grant, _)| matches(grant)) {
+            return Err(TokenError::Unknown);
+        }
+        let (grant, issued_at) = store.issued.remove(token).ok_or(TokenError::Unknown)?;
+        store.issue_order.retain(|t| t != token);
+        store.used.insert(token.to_string());
+        store.use_order.push_back(token.to_string());
+        while store.use_order.len() > self.capacity {
+            if let Some(oldest) = store.use_order.pop_front() {
+                store.used.remove(&oldest);
+            }
+        }
+
+        if now.duration_since(issued_at) > self.lifetime {
+            return Err(TokenError::Expired);
+        }
+        Ok(grant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grant() -> ReconnectGrant {
+        ReconnectGrant {
+            room_id: "room1".to_string(),
+            participant_id: "alice".to_string(),
+            name: "Alice".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_reconnect_token_single_use() {
+        let tokens = ReconnectTokens::new(Duration::from_secs(30), 16);
+        let start = Instant::now();
+
+        let token = tokens.issue(grant(), start);
+        assert_eq!(
+            tokens.redeem(&token, start + Duration::from_secs(5)),
+            Ok(grant())
+        );
+        assert_eq!(
+            tokens.redeem(&token, start + Duration::from_secs(6)),
+            Err(TokenError::Reused)
+        );
+        assert_eq!(tokens.redeem("unknown", start), Err(TokenError::Unknown));
+    }
+
+    #[test]
+    fn test_reconnect_token_bound_to_participant() {
+        let tokens = ReconnectTokens::new(Duration::from_secs(30), 16);
+        let start = Instant::now();
+
+        let token = tokens.issue(grant(), start);
+        assert_eq!(
+            tokens.redeem_for(&token, "room1", "bob", start),
+            Err(TokenError::Unknown)
+        );
+        assert_eq!(
+            tokens.redeem_for(&token, "room1", "alice", start),
+            Ok(grant())
+        );
+        // Второе соединение с тем же токеном проигрывает гонку
+        assert_eq!(
+            tokens.redeem_for(&token, "room1", "alice", start),
+            Err(TokenError::Reused)
+        );
+    }
+
+    #[test]
+    fn test_reconnect_token_expires() {
+        let tokens = ReconnectTokens::new(Duration::from_secs(30), 16);
+        let start = Instant::now();
+
+        let token = tokens.issue(grant(), start);
+        assert_eq!(
+            tokens.redeem(&token, start + Duration::from_secs(31)),
+            Err(TokenError::Expired)
+        );
+        // Истекший токен тоже сгорает
+        assert_eq!(tokens.redeem(&token, start), Err(TokenError::Reused));
+
+        // Хранилище ограничено: старые токены вытесняются
+        let tokens = ReconnectTokens::new(Duration::from_secs(30), 2);
+        let first = tokens.issue(grant(), start);
+        tokens.issue(grant(), start);
+        tokens.issue(grant(), start);
+        assert_eq!(tokens.redeem(&first, start), Err(TokenError::Unknown));
+    }
+}
@@ -0,0 +1,386 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use webrtc::media::io::ivf_reader::IVFFileHeader;
+use webrtc::media::io::ivf_writer::IVFWriter;
+use webrtc::media::io::ogg_writer::OggWriter;
+use webrtc::media::io::Writer;
+use webrtc::rtp::packet::Packet;
+
+use crate::archive::safe_file_name;
+use crate::diagnostics::now_ms;
+
+/// Формат файла записи для кодека: расширение и FourCC для IVF (`None` - Ogg)
+type FileFormat = (&'static str, Option<[u8; 4]>);
+
+/// Формат записи кодека; `None`, если кодек не поддерживается
+fn file_format(mime_type: &str) -> Option<FileFormat> {
+    match mime_type.to_ascii_lowercase().as_str() {
+        "video/vp8" => Some(("ivf", Some(*b"VP80"))),
+        "video/vp9" => Some(("ivf", Some(*b"VP90"))),
+        "audio/opus" => Some(("ogg", None)),
+        _ => None,
+    }
+}
+
+/// Файл записи одного входящего трека
+struct TrackRecorder {
+    writer: Box<dyn Writer + Send>,
+    path: PathBuf,
+    /// Ogg пишет страницы по приросту RTP timestamp: переставленные пакеты пропускаются
+    last_timestamp: Option<u32>,
+    ordered_timestamps: bool,
+}
+
+impl TrackRecorder {
+    /// Создает файл `path` в формате, выбранном по кодеку трека
+    fn create(path: PathBuf, four_cc: Option<[u8; 4]>) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context("Failed to create recording directory")?;
+        }
+        let file = BufWriter::new(
+            File::create(&path)
+                .with_context(|| format!("Failed to create recording {}", path.display()))?,
+        );
+
+        let writer: Box<dyn Writer + Send> = match four_cc {
+            // Размер кадра в заголовке IVF справочный: плееры берут его из потока
+            Some(four_cc) => Box::new(IVFWriter::new(
+                file,
+                &IVFFileHeader {
+                    signature: *b"DKIF",
+                    version: 0,
+                    header_size: 32,
+                    four_cc,
+                    width: 640,
+                    height: 480,
+                    timebase_denominator: 30,
+                    timebase_numerator: 1,
+                    num_frames: 0,
+                    unused: 0,
+                },
+            )?),
+            None => Box::new(OggWriter::new(file, 48_000, 2)?),
+        };
+        Ok(Self {
+            writer,
+            path,
+            last_timestamp: None,
+            ordered_timestamps: four_cc.is_none(),
+        })
+    }
+
+    fn write(&mut self, packet: &Packet) -> Result<()> {
+        let timestamp = packet.header.timestamp;
+        if self.ordered_timestamps {
+            if let Some(last) = self.last_timestamp {
+                if (timestamp.wrapping_sub(last) as i32) < 0 {
+                    return Ok(());
+                }
+            }
+            self.last_timestamp = Some(timestamp);
+        }
+        self.writer.write_rtp(packet)?;
+        Ok(())
+    }
+
+    fn close(mut self) {
+        match self.writer.close() {
+            Ok(()) => info!("Finished recording {}", self.path.display()),
+            Err(e) => warn!("Failed to finalize recording {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+/// Команды потоку записи
+enum Command {
+    Open {
+        id: u64,
+        participant_id: String,
+        path: PathBuf,
+        four_cc: Option<[u8; 4]>,
+    },
+    Packet {
+        id: u64,
+        packet: Packet,
+    },
+    Close {
+        id: u64,
+    },
+    CloseParticipant(String),
+    CloseAll,
+    /// Ответить, когда все предыдущие команды выполнены
+    #[cfg(test)]
+    Sync(Sender<()>),
+}
+
+/// Поток записи: все операции с файлами выполняются здесь, а не в задачах пересылки
+fn run_writer(commands: Receiver<Command>) {
+    let mut tracks: HashMap<u64, (String, TrackRecorder)> = HashMap::new();
+    for command in commands {
+        match command {
+            Command::Open {
+                id,
+                participant_id,
+                path,
+                four_cc,
+            } => match TrackRecorder::create(path, four_cc) {
+                Ok(recorder) => {
+                    info!("Recording {} to {}", participant_id, recorder.path.display());
+                    tracks.insert(id, (participant_id, recorder));
+                }
+                Err(e) => warn!("Failed to start recording of {}: {:#}", participant_id, e),
+            },
+            Command::Packet { id, packet } => {
+                let Some((participant_id, recorder)) = tracks.get_mut(&id) else {
+                    continue;
+                };
+                if let Err(e) = recorder.write(&packet) {
+                    warn!("Failed to record {}: {}", participant_id, e);
+                    if let Some((_, recorder)) = tracks.remove(&id) {
+                        recorder.close();
+                    }
+                }
+            }
+            Command::Close { id } => {
+                if let Some((_, recorder)) = tracks.remove(&id) {
+                    recorder.close();
+                }
+            }
+            Command::CloseParticipant(participant_id) => {
+                let ids: Vec<u64> = tracks
+                    .iter()
+                    .filter(|(_, (owner, _))| *owner == participant_id)
+                    .map(|(id, _)| *id)
+                    .collect();
+                for id in ids {
+                    if let Some((_, recorder)) = tracks.remove(&id) {
+                        recorder.close();
+                    }
+                }
+            }
+            Command::CloseAll => {
+                tracks.drain().for_each(|(_, (_, recorder))| recorder.close());
+            }
+            #[cfg(test)]
+            Command::Sync(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+    tracks.drain().for_each(|(_, (_, recorder))| recorder.close());
+}
+
+/// Запись одного входящего трека; принадлежит задаче пересылки трека,
+/// поэтому пакет отправляется в поток записи без поиска по общей таблице
+pub struct TrackRecording {
+    participant_id: String,
+    label: String,
+    mime_type: String,
+    format: Option<FileFormat>,
+    /// Включение записи и номер файла, открытого в нем
+    open: Option<(u64, u64)>,
+    /// Предупреждение о неподдерживаемом кодеке уже в логе
+    skipped: bool,
+    commands: Sender<Command>,
+}
+
+impl Drop for TrackRecording {
+    fn drop(&mut self) {
+        if let Some((_, id)) = self.open {
+            let _ = self.commands.send(Command::Close { id });
+        }
+    }
+}
+
+/// Запись комнаты: каждый входящий трек пишется в отдельный файл
+/// (IVF для VP8/VP9, Ogg для Opus) в каталоге комнаты. Треки с другими кодеками
+/// пропускаются. Остановка записи дописывает и закрывает все файлы.
+/// Файлы пишет отдельный поток, задачи пересылки только передают ему пакеты
+pub struct RoomRecorder {
+    dir: PathBuf,
+    enabled: AtomicBool,
+    /// Номер включения записи: после остановки и нового включения треки
+    /// открывают новые файлы
+    session: AtomicU64,
+    next_track: AtomicU64,
+    commands: Sender<Command>,
+    /// Все созданные файлы записи
+    files: Mutex<Vec<String>>,
+}
+
+impl RoomRecorder {
+    /// Запись комнаты `room_id` в подкаталог `dir`
+    pub fn new(dir: &Path, room_id: &str, enabled: bool) -> Self {
+        let (commands, rx) = mpsc::channel();
+        let spawned = std::thread::Builder::new()
+            .name(format!("recorder-{}", safe_file_name(room_id)))
+            .spawn(move || run_writer(rx));
+        if let Err(e) = spawned {
+            warn!("Failed to start recording thread for room {}: {}", room_id, e);
+        }
+        Self {
+            dir: dir.join(safe_file_name(room_id)),
+            enabled: AtomicBool::new(enabled),
+            session: AtomicU64::new(0),
+            next_track: AtomicU64::new(0),
+            commands,
+            files: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Включает или останавливает запись; `false`, если состояние не изменилось
+    pub fn set_enabled(&self, enabled: bool) -> bool {
+        if self.enabled.swap(enabled, Ordering::Relaxed) == enabled {
+            return false;
+        }
+        if enabled {
+            self.session.fetch_add(1, Ordering::Relaxed);
+        } else {
+            let _ = self.commands.send(Command::CloseAll);
+        }
+        true
+    }
+
+    /// Запись трека участника для задачи пересылки. `label` попадает в имя файла:
+    /// `<участник>-<время>-<label>.<ivf|ogg>`
+    pub fn track(&self, participant_id: &str, label: &str, mime_type: &str) -> TrackRecording {
+        TrackRecording {
+            participant_id: participant_id.to_string(),
+            label: label.to_string(),
+            mime_type: mime_type.to_string(),
+            format: file_format(mime_type),
+            open: None,
+            skipped: false,
+            commands: self.commands.clone(),
+        }
+    }
+
+    /// Передает RTP пакет трека в поток записи; файл открывается по первому
+    /// пакету после включения записи
+    pub fn record(&self, track: &mut TrackRecording, packet: &Packet) {
+        if !self.is_enabled() {
+            return;
+        }
+        let Some((extension, four_cc)) = track.format else {
+            if !track.skipped {
+                track.skipped = true;
+                warn!(
+                    "Codec {} of {} from {} cannot be recorded, skipping track",
+                    track.mime_type, track.label, track.participant_id
+                );
+            }
+            return;
+        };
+
+        let session = self.session.load(Ordering::Relaxed);
+        let id = match track.open {
+            Some((opened_in, id)) if opened_in == session => id,
+            _ => {
+                let id = self.next_track.fetch_add(1, Ordering::Relaxed);
+                let path = self
+                    .dir
+                    .join(format!(
+                        "{}-{}-{}",
+                        safe_file_name(&track.participant_id),
+                        now_ms(),
+                        safe_file_name(&track.label)
+                    ))
+                    .with_extension(extension);
+                self.files.lock().unwrap().push(path.display().to_string());
+                let _ = self.commands.send(Command::Open {
+                    id,
+                    participant_id: track.participant_id.clone(),
+                    path,
+                    four_cc,
+                });
+                track.open = Some((session, id));
+                id
+            }
+        };
+        let _ = self.commands.send(Command::Packet {
+            id,
+            packet: packet.clone(),
+        });
+    }
+
+    /// Закрывает файлы участника (вышел из комнаты)
+    pub fn close_participant(&self, participant_id: &str) {
+        let _ = self
+            .commands
+            .send(Command::CloseParticipant(participant_id.to_string()));
+    }
+
+    /// Пути всех файлов, записанных в комнате
+    pub fn files(&self) -> Vec<String> {
+        self.files.lock().unwrap().clone()
+    }
+
+    /// Ждет, пока поток записи выполнит все переданные команды
+    #[cfg(test)]
+    fn sync(&self) {
+        let (done, rx) = mpsc::channel();
+        if self.commands.send(Command::Sync(done)).is_ok() {
+            let _ = rx.recv();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use webrtc::rtp::header::Header;
+
+    fn packet(timestamp: u32) -> Packet {
+        Packet {
+            header: Header {
+                timestamp,
+                ..Default::default()
+            },
+            payload: Bytes::from_static(&[0xfc, 0x01, 0x02]),
+        }
+    }
+
+    #[test]
+    fn test_recording_writes_supported_tracks_only() {
+        let dir = std::env::temp_dir().join(format!("rrtc-recording-{}", std::process::id()));
+        let recorder = RoomRecorder::new(&dir, "call/1", false);
+
+        let mut mic = recorder.track("alice", "audio", "audio/opus");
+        let mut cam = recorder.track("bob", "camera", "video/H264");
+
+        // Пока запись выключена, файлы не создаются
+        recorder.record(&mut mic, &packet(0));
+        assert!(recorder.files().is_empty());
+
+        assert!(recorder.set_enabled(true));
+        for timestamp in [960, 1920, 960, 2880] {
+            recorder.record(&mut mic, &packet(timestamp));
+        }
+        recorder.record(&mut cam, &packet(0));
+        assert_eq!(recorder.files().len(), 1);
+
+        // Остановка закрывает файл: Ogg завершается страницей конца потока
+        assert!(recorder.set_enabled(false));
+        assert!(!recorder.set_enabled(false));
+        recorder.sync();
+        let path = PathBuf::from(&recorder.files()[0]);
+        assert!(path.starts_with(dir.join("call_1")));
+        assert!(path.file_name().unwrap().to_string_lossy().starts_with("alice-"));
+        let content = std::fs::read(&path).unwrap();
+        assert!(content.starts_with(b"OggS"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
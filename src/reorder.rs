@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use webrtc::rtp::packet::Packet;
+
+/// Разрыв номеров (в любую сторону), после которого поток считается перезапущенным:
+/// буфер сбрасывается, а пакет пересылается сразу
+const LONG_GAP: i16 = 1000;
+
+/// Буфер переупорядочивания входящего RTP потока: пакеты выдаются по возрастанию
+/// sequence number. Недостающий пакет ждут, пока в буфере не больше `depth` номеров
+/// и пока самый старый пакет в буфере ждет меньше `max_hold`; после этого пропуск
+/// считается потерей. Пакеты, пришедшие после выдачи следующих номеров, отбрасываются
+#[derive(Debug)]
+pub struct ReorderBuffer {
+    depth: usize,
+    max_hold: Duration,
+    /// Номер следующего выдаваемого пакета; `None` до первого пакета
+    next_seq: Option<u16>,
+    /// `slots[i]` - пакет с номером `next_seq + i` и время его получения
+    slots: VecDeque<Option<(Packet, Instant)>>,
+    /// Отброшено опоздавших пакетов
+    late: u64,
+}
+
+impl ReorderBuffer {
+    pub fn new(depth: usize, max_hold: Duration) -> Self {
+        Self {
+            depth: depth.max(1),
+            max_hold,
+            next_seq: None,
+            slots: VecDeque::new(),
+            late: 0,
+        }
+    }
+
+    /// Сколько опоздавших пакетов отброшено
+    pub fn late_packets(&self) -> u64 {
+        self.late
+    }
+
+    /// Принимает пакет; возвращает пакеты, готовые к пересылке, по порядку
+    pub fn push(&mut self, packet: Packet, now: Instant) -> Vec<Packet> {
+        let seq = packet.header.sequence_number;
+        let mut released = Vec::new();
+        let Some(next) = self.next_seq else {
+            self.next_seq = Some(seq.wrapping_add(1));
+            released.push(packet);
+            return released;
+        };
+
+        let offset = seq.wrapping_sub(next) as i16;
+        if !(-LONG_GAP..=LONG_GAP).contains(&offset) {
+            self.flush_into(&mut released);
+            self.next_seq = Some(seq.wrapping_add(1));
+            released.push(packet);
+            return released;
+        }
+        if offset < 0 {
+            self.late += 1;
+            return released;
+        }
+
+        // Окно не вмещает пакет: недостающие номера в начале считаются потерянными
+        let mut offset = offset as usize;
+        while offset >= self.depth {
+            self.advance(&mut released);
+            offset -= 1;
+        }
+        if self.slots.len() <= offset {
+            self.slots.resize(offset + 1, None);
+        }
+        if self.slots[offset].is_none() {
+            self.slots[offset] = Some((packet, now));
+        }
+
+        self.release_ready(&mut released);
+        self.release_expired(now, &mut released);
+        released
+    }
+
+    /// Момент, когда буфер перестанет ждать недостающие пакеты перед первым
+    /// удерживаемым; `None`, если буфер пуст
+    pub fn deadline(&self) -> Option<Instant> {
+        self.slots
+            .iter()
+            .find_map(|slot| slot.as_ref())
+            .map(|(_, received_at)| *received_at + self.max_hold)
+    }
+
+    /// Выдает пакеты, ожидание перед которыми истекло к `now`, без нового пакета
+    pub fn expire(&mut self, now: Instant) -> Vec<Packet> {
+        let mut released = Vec::new();
+        self.release_expired(now, &mut released);
+        released
+    }
+
+    /// Выдает все удерживаемые пакеты по порядку (например, когда трек закончился)
+    pub fn flush(&mut self) -> Vec<Packet> {
+        let mut released = Vec::new();
+        self.flush_into(&mut released);
+        released
+    }
+
+    /// Выдает все пакеты из буфера по порядку, пропуская недостающие
+    fn flush_into(&mut self, released: &mut Vec<Packet>) {
+        while !self.slots.is_empty() {
+            self.advance(released);
+        }
+    }
+
+    /// Сдвигает окно на один номер: выдает пакет или пропускает недостающий
+    fn advance(&mut self, released: &mut Vec<Packet>) {
+        if let Some(slot) = self.slots.pop_front() {
+            released.extend(slot.map(|(packet, _)| packet));
+        }
+        self.next_seq = self.next_seq.map(|seq| seq.wrapping_add(1));
+    }
+
+    /// Выдает пакеты, идущие подряд с начала окна
+    fn release_ready(&mut self, released: &mut Vec<Packet>) {
+        while self.slots.front().is_some_and(|slot| slot.is_some()) {
+            self.advance(released);
+        }
+    }
+
+    /// Перестает ждать недостающие пакеты перед пакетом, который ждет дольше `max_hold`
+    fn release_expired(&mut self, now: Instant, released: &mut Vec<Packet>) {
+        while let Some(Some((_, received_at))) = self.slots.iter().find(|slot| slot.is_some()) {
+            if now.duration_since(*received_at) < self.max_hold {
+                break;
+            }
+            while self.slots.front().is_some_and(|slot| slot.is_none()) {
+                self.advance(released);
+            }
+            self.release_ready(released);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc::rtp::header::Header;
+
+    fn packet(sequence_number: u16) -> Packet {
+        Packet {
+            header: Header {
+                sequence_number,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn seqs(packets: Vec<Packet>) -> Vec<u16> {
+        packets.iter().map(|p| p.header.sequence_number).collect()
+    }
+
+    #[test]
+    fn test_reorder_releases_in_order_across_wraparound() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut buffer = ReorderBuffer::new(4, Duration::from_millis(50));
+
+        // Переставленные пакеты на переходе через 65535
+        let mut released = Vec::new();
+        for (i, seq) in [65533u16, 65535, 65534, 1, 0, 2].into_iter().enumerate() {
+            released.extend(seqs(buffer.push(packet(seq), at(i as u64))));
+        }
+        assert_eq!(released, vec![65533, 65534, 65535, 0, 1, 2]);
+
+        // Опоздавший повтор отбрасывается
+        assert!(buffer.push(packet(65535), at(10)).is_empty());
+        assert_eq!(buffer.late_packets(), 1);
+
+        // Потерянный пакет 3: следующие ждут, пока не переполнится окно
+        assert!(buffer.push(packet(4), at(20)).is_empty());
+        assert!(buffer.push(packet(5), at(21)).is_empty());
+        assert!(buffer.push(packet(6), at(22)).is_empty());
+        assert_eq!(seqs(buffer.push(packet(7), at(23))), vec![4, 5, 6, 7]);
+
+        // ...или пока старший пакет ждет дольше max_hold
+        assert!(buffer.push(packet(9), at(30)).is_empty());
+        assert_eq!(seqs(buffer.push(packet(10), at(80))), vec![9, 10]);
+
+        // Длинный разрыв сбрасывает буфер: поток продолжается с нового номера
+        assert!(buffer.push(packet(12), at(90)).is_empty());
+        assert_eq!(seqs(buffer.push(packet(30_000), at(91))), vec![12, 30_000]);
+        assert_eq!(seqs(buffer.push(packet(30_001), at(92))), vec![30_001]);
+    }
+
+    #[test]
+    fn test_reorder_releases_held_packets_by_time() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut buffer = ReorderBuffer::new(8, Duration::from_millis(50));
+
+        assert_eq!(seqs(buffer.push(packet(1), at(0))), vec![1]);
+        assert_eq!(buffer.deadline(), None);
+
+        // Пакет 2 потерян: 3 и 5 ждут, пока не истечет срок пакета 3
+        assert!(buffer.push(packet(3), at(10)).is_empty());
+        assert!(buffer.push(packet(5), at(20)).is_empty());
+        assert_eq!(buffer.deadline(), Some(at(60)));
+        assert!(buffer.expire(at(59)).is_empty());
+        assert_eq!(seqs(buffer.expire(at(60))), vec![3]);
+
+        // Пакет 5 ждет пакет 4 до своего срока; конец трека выдает его сразу
+        assert_eq!(buffer.deadline(), Some(at(70)));
+        assert_eq!(seqs(buffer.flush()), vec![5]);
+        assert_eq!(buffer.deadline(), None);
+    }
+}
@@ -0,0 +1,199 @@
+use log::info;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Снимок ресурсов хоста
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceReading {
+    pub total_memory_bytes: u64,
+    pub available_memory_bytes: u64,
+    pub cpus: usize,
+}
+
+impl ResourceReading {
+    /// Читает память из `/proc/meminfo` и число доступных CPU; `None` вне Linux
+    pub fn current() -> Option<Self> {
+        let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+        let field = |name: &str| -> Option<u64> {
+            meminfo
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .and_then(|rest| rest.split_whitespace().next()?.parse::<u64>().ok())
+                .map(|kb| kb * 1024)
+        };
+
+        Some(Self {
+            total_memory_bytes: field("MemTotal")?,
+            available_memory_bytes: field("MemAvailable")?,
+            cpus: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        })
+    }
+
+    /// Доля занятой памяти в процентах
+    pub fn memory_used_percent(&self) -> u64 {
+        if self.total_memory_bytes == 0 {
+            return 0;
+        }
+        100 - self.available_memory_bytes.min(self.total_memory_bytes) * 100
+            / self.total_memory_bytes
+    }
+}
+
+/// Параметры динамического лимита участников
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Сколько памяти закладывается на одного участника
+    pub memory_per_participant_bytes: u64,
+    pub participants_per_cpu: usize,
+    /// Порог занятой памяти (%), выше которого новые участники не принимаются
+    pub memory_watermark_percent: u8,
+}
+
+impl ResourceLimits {
+    /// Лимит участников на сервер для снимка ресурсов, снятого при `participants`
+    /// подключенных: их память уже вычтена из доступной, поэтому по памяти
+    /// к ним добавляется только то, что еще помещается
+    pub fn participant_limit(&self, reading: &ResourceReading, participants: usize) -> usize {
+        let headroom = reading.available_memory_bytes / self.memory_per_participant_bytes.max(1);
+        let by_memory = participants.saturating_add(headroom as usize);
+        let by_cpu = reading.cpus.max(1) * self.participants_per_cpu;
+        by_memory.min(by_cpu)
+    }
+}
+
+type ReadFn = Box<dyn Fn() -> Option<ResourceReading> + Send + Sync>;
+
+/// Ограничивает общее число участников сервера по ресурсам хоста.
+/// Лимит вычисляется при запуске и пересчитывается при каждом входе с учетом
+/// памяти, уже занятой подключенными участниками;
+/// при превышении порога памяти входы отклоняются независимо от лимита.
+pub struct ResourceMonitor {
+    limits: ResourceLimits,
+    read: ReadFn,
+    /// Лимит по последнему снимку ресурсов
+    limit: AtomicUsize,
+}
+
+impl ResourceMonitor {
+    pub fn new(limits: ResourceLimits) -> Self {
+        Self::with_reader(limits, Box::new(ResourceReading::current))
+    }
+
+    /// Монитор с источником показаний (для тестов)
+    pub fn with_reader(limits: ResourceLimits, read: ReadFn) -> Self {
+        let monitor = Self {
+            limits,
+            read,
+            limit: AtomicUsize::new(usize::MAX),
+        };
+        match (monitor.read)() {
+            Some(reading) => {
+                let limit = limits.participant_limit(&reading, 0);
+                monitor.limit.store(limit, Ordering::Relaxed);
+                info!(
+                    "Dynamic participant limit: {} ({} MiB available, {} CPUs)",
+                    limit,
+                    reading.available_memory_bytes / (1024 * 1024),
+                    reading.cpus
+                );
+            }
+            None => info!("Dynamic participant limit: resource readings unavailable, not limiting"),
+        }
+        monitor
+    }
+
+    /// Лимит участников по последнему снимку ресурсов
+    #[cfg(test)]
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Проверяет, можно ли принять еще одного участника при `participants` уже подключенных
+    pub fn admit(&self, participants: usize) -> Result<(), String> {
+        let Some(reading) = (self.read)() else {
+            return Ok(());
+        };
+        let limit = self.limits.participant_limit(&reading, participants);
+        self.limit.store(limit, Ordering::Relaxed);
+
+        let used = reading.memory_used_percent();
+        if used >= u64::from(self.limits.memory_watermark_percent) {
+            return Err(format!(
+                "Server memory usage {}% is above the {}% watermark",
+                used, self.limits.memory_watermark_percent
+            ));
+        }
+        if participants >= limit {
+            return Err(format!("Server participant limit {} reached", limit));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::Arc;
+
+    const MIB: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_joins_refused_above_resource_watermark() {
+        let available = Arc::new(AtomicU64::new(800 * MIB));
+        let reading = available.clone();
+        let monitor = ResourceMonitor::with_reader(
+            ResourceLimits {
+                memory_per_participant_bytes: 100 * MIB,
+                participants_per_cpu: 4,
+                memory_watermark_percent: 90,
+            },
+            Box::new(move || {
+                Some(ResourceReading {
+                    total_memory_bytes: 1000 * MIB,
+                    available_memory_bytes: reading.load(Ordering::Relaxed),
+                    cpus: 4,
+                })
+            }),
+        );
+
+        // 800 MiB / 100 MiB = 8, 4 CPU * 4 = 16
+        assert_eq!(monitor.limit(), 8);
+        assert!(monitor.admit(0).is_ok());
+
+        // Память почти закончилась: вход отклоняется даже ниже лимита
+        available.store(50 * MIB, Ordering::Relaxed);
+        assert!(monitor.admit(0).is_err());
+        assert_eq!(monitor.limit(), 0);
+    }
+
+    #[test]
+    fn test_connected_participants_not_counted_twice() {
+        let available = Arc::new(AtomicU64::new(1000 * MIB));
+        let reading = available.clone();
+        let monitor = ResourceMonitor::with_reader(
+            ResourceLimits {
+                memory_per_participant_bytes: 100 * MIB,
+                participants_per_cpu: 100,
+                memory_watermark_percent: 99,
+            },
+            Box::new(move || {
+                Some(ResourceReading {
+                    total_memory_bytes: 2000 * MIB,
+                    available_memory_bytes: reading.load(Ordering::Relaxed),
+                    cpus: 4,
+                })
+            }),
+        );
+        assert_eq!(monitor.limit(), 10);
+
+        // Каждый вошедший занимает свою долю памяти: лимит остается тем же
+        available.store(400 * MIB, Ordering::Relaxed);
+        assert!(monitor.admit(6).is_ok());
+        assert_eq!(monitor.limit(), 10);
+
+        available.store(100 * MIB, Ordering::Relaxed);
+        assert!(monitor.admit(9).is_ok());
+        available.store(50 * MIB, Ordering::Relaxed);
+        assert_eq!(monitor.admit(10), Err("Server participant limit 10 reached".to_string()));
+    }
+}
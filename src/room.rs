@@ -1,386 +1,3429 @@
 use anyhow::Result;
-use log::{debug, error, info, warn};
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use bytes::Bytes;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio_tungstenite::tungstenite::Message;
+use webrtc::api::media_engine::MIME_TYPE_OPUS;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
 use webrtc::track::track_remote::TrackRemote;
-use webrtc::track::track_local::TrackLocal;
-use webrtc::track::track_local::TrackLocalWriter;
+use webrtc::util::MarshalSize;
 
-use crate::messages::ServerMessage;
+use crate::archive::{
+    write_summary, RoomEvent, RoomEventKind, RoomEventLog, RoomHistory, RoomSummary,
+};
+use crate::diagnostics::now_ms;
+use crate::audio::{is_dtx_frame, AudioLevel, NoiseGate, SpeakerDetector, AUDIO_LEVEL_URI};
+use crate::bitrate::{aggregate_estimates, BitrateAggregation};
+use crate::config::RoomConfig;
+use crate::messages::{
+    ErrorCode, ParticipantInfo, PeerStats, RoomListing, RoomStats, ServerMessage,
+};
+use crate::outbound::OutboundPacket;
+use crate::recording::RoomRecorder;
+use crate::reorder::ReorderBuffer;
 use crate::peer::{Peer, TrackType};
+use crate::utils::{MetricsCounter, RateMeter};
+use crate::simulcast::{
+    order_layers, FrameInfo, LayerSwitch, SimulcastLayer, TrackRewriters, FRAME_MARKING_URI,
+};
+
+/// Как часто издателю отправляется REMB с оценкой получателей
+const BITRATE_FEEDBACK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Максимальный размер текста сообщения чата (байт)
+pub const MAX_CHAT_TEXT_BYTES: usize = 4096;
+
+/// Максимальный размер сообщения data channel, пересылаемого участникам (байт)
+pub const MAX_DATA_MESSAGE_BYTES: usize = 16 * 1024;
+
+/// Максимальный размер эмодзи реакции (байт): хватает на последовательности с ZWJ
+pub const MAX_REACTION_BYTES: usize = 16;
+
+/// Проверяет, что реакция состоит только из эмодзи: пиктограмм и
+/// модификаторов (ZWJ, селектор варианта, keycap, теги флагов)
+pub fn is_valid_reaction(emoji: &str) -> bool {
+    let pictograph = |c: char| {
+        matches!(
+            u32::from(c),
+            0x2300..=0x23FF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0x1F000..=0x1FAFF
+        )
+    };
+    let modifier =
+        |c: char| matches!(u32::from(c), 0x200D | 0x20E3 | 0xFE0F | 0xE0020..=0xE007F);
+
+    !emoji.is_empty()
+        && emoji.len() <= MAX_REACTION_BYTES
+        && emoji.chars().any(pictograph)
+        && emoji.chars().all(|c| pictograph(c) || modifier(c))
+}
+
+/// Максимальная длина отображаемого имени (символов)
+pub const MAX_NAME_CHARS: usize = 64;
+
+/// Отображаемое имя без управляющих символов и пробелов по краям;
+/// `None`, если имя пустое или длиннее `MAX_NAME_CHARS`
+pub fn clean_display_name(name: &str) -> Option<String> {
+    let name: String = name.chars().filter(|c| !c.is_control()).collect();
+    let name = name.trim();
+    (!name.is_empty() && name.chars().count() <= MAX_NAME_CHARS).then(|| name.to_string())
+}
+
+/// Как часто пересчитывается активный говорящий
+const ACTIVE_SPEAKER_INTERVAL: Duration = Duration::from_millis(300);
+
+/// Как часто комната ищет неактивных участников (не реже таймаута)
+const IDLE_SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Окно измерения частоты входящих пакетов для защиты от флуда
+const FLOOD_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Обнаруживает трек, частота пакетов которого слишком долго превышает порог
+struct FloodDetector {
+    meter: RateMeter,
+    max_pps: f64,
+    sustain: Duration,
+    over_since: Option<Instant>,
+}
+
+impl FloodDetector {
+    fn new(max_pps: u32, sustain: Duration) -> Self {
+        Self {
+            meter: RateMeter::new(FLOOD_RATE_WINDOW),
+            max_pps: f64::from(max_pps),
+            sustain,
+            over_since: None,
+        }
+    }
+
+    /// Учитывает пакет; возвращает true, если трек нужно отключить
+    fn record(&mut self, now: Instant) -> bool {
+        if let Some(rate) = self.meter.record(now) {
+            if rate > self.max_pps {
+                self.over_since.get_or_insert(now - FLOOD_RATE_WINDOW);
+            } else {
+                self.over_since = None;
+            }
+        }
+        self.over_since
+            .is_some_and(|since| now.duration_since(since) >= self.sustain)
+    }
+}
+
+/// Ошибка входа: комната заполнена
+#[derive(Debug)]
+pub struct RoomFull {
+    pub room_id: String,
+    pub max_participants: usize,
+}
+
+impl std::fmt::Display for RoomFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Room {} is full ({} participants)",
+            self.room_id, self.max_participants
+        )
+    }
+}
+
+impl std::error::Error for RoomFull {}
+
+/// Заявка на вход, ожидающая решения ведущего
+struct PendingJoin {
+    name: String,
+    decision: oneshot::Sender<bool>,
+}
+
+/// SSRC и stream id, которые участник публикует в комнату
+#[derive(Debug, Default)]
+struct PublishedSources {
+    ssrcs: HashSet<u32>,
+    stream_ids: HashSet<String>,
+}
+
+/// От каких источников участник получает аудио и видео; `None` - от всех
+#[derive(Debug, Default)]
+struct Subscription {
+    audio: Option<HashSet<String>>,
+    video: Option<HashSet<String>>,
+}
 
 /// Room представляет комнату с несколькими участниками
 pub struct Room {
     pub id: String,
+    pub config: RoomConfig,
+    /// Максимальное количество участников
+    pub max_participants: usize,
     peers: Arc<RwLock<HashMap<String, Arc<Peer>>>>,
+    /// Разрешена ли пересылка медиа (см. `RoomConfig::min_participants_to_start`)
+    forwarding: AtomicBool,
+    /// Ведущий закрыл комнату: новые участники не допускаются
+    locked: AtomicBool,
+    /// Участники, ждущие допуска ведущим (`Admission::Manual`); в `peers` их нет
+    pending_peers: Mutex<HashMap<String, PendingJoin>>,
+    /// Выбранный simulcast слой: получатель -> источник. Вложенные карты позволяют
+    /// проверять пакет без построения ключа
+    layer_switches: Mutex<HashMap<String, HashMap<String, LayerSwitch>>>,
+    /// Перезапись номеров simulcast треков по (источник, id трека); задачи слоев
+    /// получают ее один раз при старте
+    rtp_rewriters: Mutex<HashMap<(String, String), Arc<TrackRewriters>>>,
+    /// Качество, запрошенное получателем для источника (по умолчанию высшее)
+    layer_preferences: Mutex<HashMap<(String, String), SimulcastLayer>>,
+    /// Выборочные подписки получателей (`subscribe`); без записи - все источники
+    subscriptions: Mutex<HashMap<String, Subscription>>,
+    /// Треки, приостановленные получателем: получатель -> источник -> типы треков.
+    /// Вложенные карты позволяют проверять пакет без построения ключа
+    paused_tracks: Mutex<HashMap<String, HashMap<String, HashSet<TrackType>>>>,
+    /// RID опубликованных simulcast слоев источника, от низкого качества к высокому
+    simulcast_layers: Mutex<HashMap<String, Vec<String>>>,
+    /// Получатели с перегруженным каналом (по TWCC): им пересылается нижний слой
+    congested: Mutex<HashSet<String>>,
+    /// Источники, опубликованные каждым участником (для подавления эха)
+    published: Mutex<HashMap<String, PublishedSources>>,
+    /// Ведущий комнаты: первый вошедший участник
+    host_id: Mutex<Option<String>>,
+    /// Треки, отключенные сервером: участник -> id треков
+    server_muted: Mutex<HashMap<String, HashSet<String>>>,
+    /// Пароль комнаты и был ли уже кто-то допущен (первый вошедший может задать пароль)
+    access: Mutex<(Option<String>, bool)>,
+    /// Участники, чей звук отключил ведущий
+    force_muted: Mutex<HashSet<String>>,
+    /// Участник в центре внимания, общий для всех клиентов
+    spotlight: Mutex<Option<String>>,
+    /// История участников для итоговой сводки
+    history: Mutex<RoomHistory>,
+    /// Журнал последних событий участников (`RoomConfig::event_log_size`)
+    events: Mutex<RoomEventLog>,
+    /// Байт медиа, полученных от издателей
+    bytes_received: AtomicU64,
+    /// Определение активного говорящего (`RoomConfig::active_speaker_threshold_dbov`)
+    speakers: Option<Mutex<SpeakerDetector>>,
+    /// Запущена ли задача определения говорящего
+    speaker_task_started: AtomicBool,
+    /// Запущена ли задача обратной связи по битрейту издателям
+    bitrate_task_started: AtomicBool,
+    /// Счетчики пакетов и байт сервера
+    metrics: MetricsCounter,
+    /// Запись треков в файлы; `None`, если каталог записи не задан
+    recorder: Option<RoomRecorder>,
+    /// Span `room` для фоновых задач комнаты: их строки лога несут `room_id`
+    span: tracing::Span,
 }
 
 impl Room {
     /// Создает новую комнату
+    #[allow(dead_code)]
     pub fn new(id: String) -> Self {
+        Self::with_config(id, RoomConfig::default())
+    }
+
+    /// Создает новую комнату с заданной конфигурацией
+    pub fn with_config(id: String, mut config: RoomConfig) -> Self {
         info!("Creating new room: {}", id);
+        config.id = id.clone();
+        let forwarding = config.min_participants_to_start.is_none();
+        let max_participants = config.max_participants.unwrap_or(usize::MAX);
+        let speakers = config.active_speaker_threshold_dbov.map(|threshold| {
+            Mutex::new(SpeakerDetector::new(
+                threshold,
+                Duration::from_millis(config.active_speaker_window_ms),
+            ))
+        });
+        let access = Mutex::new((config.password.clone(), false));
+        let events = Mutex::new(RoomEventLog::new(config.event_log_size, Instant::now()));
+        // Комната переживает соединение, которое ее создало: span без родителя
+        let span = info_span!(parent: None, "room", room_id = %id);
         Self {
             id,
+            config,
+            max_participants,
             peers: Arc::new(RwLock::new(HashMap::new())),
+            forwarding: AtomicBool::new(forwarding),
+            locked: AtomicBool::new(false),
+            pending_peers: Mutex::new(HashMap::new()),
+            layer_switches: Mutex::new(HashMap::new()),
+            rtp_rewriters: Mutex::new(HashMap::new()),
+            layer_preferences: Mutex::new(HashMap::new()),
+            subscriptions: Mutex::new(HashMap::new()),
+            paused_tracks: Mutex::new(HashMap::new()),
+            simulcast_layers: Mutex::new(HashMap::new()),
+            congested: Mutex::new(HashSet::new()),
+            published: Mutex::new(HashMap::new()),
+            host_id: Mutex::new(None),
+            server_muted: Mutex::new(HashMap::new()),
+            force_muted: Mutex::new(HashSet::new()),
+            access,
+            spotlight: Mutex::new(None),
+            history: Mutex::new(RoomHistory::new()),
+            events,
+            bytes_received: AtomicU64::new(0),
+            speakers,
+            speaker_task_started: AtomicBool::new(false),
+            bitrate_task_started: AtomicBool::new(false),
+            metrics: MetricsCounter::new(),
+            recorder: None,
+            span,
+        }
+    }
+
+    /// Учитывает пересылку комнаты в общих счетчиках сервера
+    pub fn with_metrics(mut self, metrics: MetricsCounter) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Записывает треки комнаты в `dir`; запись начинается сразу, если она включена
+    /// в настройках комнаты (`RoomConfig::recording_enabled`)
+    pub fn with_recording_dir(mut self, dir: &Path) -> Self {
+        self.recorder = Some(RoomRecorder::new(dir, &self.id, self.config.recording_enabled));
+        self
+    }
+
+    /// Ограничивает число участников (не выше лимита из конфигурации комнаты)
+    pub fn with_max_participants(mut self, max_participants: usize) -> Self {
+        self.max_participants = self.max_participants.min(max_participants);
+        self
+    }
+
+    /// Проверяет пароль входа, ничего не меняя: пароль первого участника
+    /// закрепляется только после допуска (`authorize`)
+    pub fn check_password(&self, password: Option<&str>) -> bool {
+        match (self.access.lock().unwrap().0.as_deref(), password) {
+            (Some(expected), Some(password)) => expected == password,
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    /// Проверяет пароль допущенного участника и отмечает вход. Если пароль комнаты
+    /// не задан, пароль первого допущенного участника становится паролем комнаты.
+    /// Вызывается после всех проверок входа, чтобы отказ не закреплял пароль
+    pub fn authorize(&self, password: Option<&str>) -> bool {
+        let mut access = self.access.lock().unwrap();
+        let (room_password, claimed) = &mut *access;
+        let allowed = match (room_password.as_deref(), password) {
+            (Some(expected), Some(password)) => expected == password,
+            (Some(_), None) => false,
+            (None, Some(password)) if !*claimed => {
+                info!("Room {} is now password-protected", self.id);
+                *room_password = Some(password.to_string());
+                true
+            }
+            (None, _) => true,
+        };
+        *claimed |= allowed;
+        allowed
+    }
+
+    /// Запоминает SSRC и stream id входящего трека участника
+    pub fn register_source(&self, peer_id: &str, ssrc: u32, stream_id: &str) {
+        let mut published = self.published.lock().unwrap();
+        let sources = published.entry(peer_id.to_string()).or_default();
+        sources.ssrcs.insert(ssrc);
+        if !stream_id.is_empty() {
+            sources.stream_ids.insert(stream_id.to_string());
+        }
+    }
+
+    /// Проверяет, что пакет с данными SSRC/stream id опубликован самим получателем.
+    /// Защищает от пересылки участнику его собственного медиа при повторном
+    /// использовании id или после renegotiation.
+    pub fn is_own_source(&self, subscriber_id: &str, ssrc: u32, stream_id: &str) -> bool {
+        if !self.config.self_echo_suppression {
+            return false;
+        }
+
+        let published = self.published.lock().unwrap();
+        published.get(subscriber_id).is_some_and(|sources| {
+            sources.ssrcs.contains(&ssrc)
+                || (!stream_id.is_empty() && sources.stream_ids.contains(stream_id))
+        })
+    }
+
+    /// Текущий ведущий комнаты
+    pub fn host_id(&self) -> Option<String> {
+        self.host_id.lock().unwrap().clone()
+    }
+
+    pub fn is_host(&self, peer_id: &str) -> bool {
+        self.host_id.lock().unwrap().as_deref() == Some(peer_id)
+    }
+
+    /// Итоговая сводка комнаты по накопленной истории
+    pub fn summary(&self) -> RoomSummary {
+        let recording_files = self
+            .recorder
+            .as_ref()
+            .map(RoomRecorder::files)
+            .unwrap_or_default();
+        self.history.lock().unwrap().summary(
+            &self.id,
+            self.bytes_received.load(Ordering::Relaxed),
+            recording_files,
+        )
+    }
+
+    fn record_event(&self, kind: RoomEventKind, participant_id: &str) {
+        self.events
+            .lock()
+            .unwrap()
+            .record(kind, participant_id, Instant::now());
+    }
+
+    /// Последние события участников, от старых к новым
+    pub fn event_log(&self) -> Vec<RoomEvent> {
+        self.events.lock().unwrap().events()
+    }
+
+    /// Журнал событий по запросу участника (только ведущий)
+    pub fn event_log_for(&self, requester_id: &str) -> Result<Vec<RoomEvent>> {
+        if !self.is_host(requester_id) {
+            anyhow::bail!("Only the room host can read the event log");
+        }
+        Ok(self.event_log())
+    }
+
+    /// Закрыта ли комната для новых участников
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Пускает ли комната вход. Закрытая комната пускает только возобновление
+    /// сессии по токену переподключения: вход с id участника, который уже
+    /// в комнате, тоже считается новым
+    pub fn accepts_join(&self, verified_resume: bool) -> bool {
+        verified_resume || !self.is_locked()
+    }
+
+    /// Закрывает или открывает комнату для новых участников (только ведущий)
+    /// и уведомляет всех. Участники, уже находящиеся в комнате, остаются
+    pub async fn set_locked(&self, requester_id: &str, locked: bool) -> Result<()> {
+        if !self.is_host(requester_id) {
+            anyhow::bail!("Only the room host can lock the room");
+        }
+
+        if self.locked.swap(locked, Ordering::Relaxed) != locked {
+            info!(
+                "Host {} {} room {}",
+                requester_id,
+                if locked { "locked" } else { "unlocked" },
+                self.id
+            );
+            self.broadcast_message_to_all(ServerMessage::RoomLocked { locked })
+                .await;
+        }
+        Ok(())
+    }
+
+    /// Ставит участника в очередь на допуск и отправляет заявку ведущему.
+    /// Решение придет в возвращенный канал; `None`, если спросить некого
+    /// (в комнате нет ведущего) и участник входит сразу
+    pub async fn request_admission(
+        &self,
+        participant_id: &str,
+        name: &str,
+    ) -> Option<oneshot::Receiver<bool>> {
+        let host = self.get_peer(&self.host_id()?).await?;
+        let (decision, rx) = oneshot::channel();
+        self.pending_peers.lock().unwrap().insert(
+            participant_id.to_string(),
+            PendingJoin {
+                name: name.to_string(),
+                decision,
+            },
+        );
+
+        info!("Peer {} is waiting for admission to room {}", participant_id, self.id);
+        let _ = host.send_message(ServerMessage::JoinRequest {
+            participant_id: participant_id.to_string(),
+            name: name.to_string(),
+        });
+        Some(rx)
+    }
+
+    /// Допускает или отклоняет ожидающего участника (только ведущий)
+    pub fn admit(&self, requester_id: &str, participant_id: &str, approved: bool) -> Result<()> {
+        if !self.is_host(requester_id) {
+            anyhow::bail!("Only the room host can admit participants");
+        }
+        let pending = self.pending_peers.lock().unwrap().remove(participant_id);
+        let Some(pending) = pending.filter(|pending| !pending.decision.is_closed()) else {
+            anyhow::bail!("Participant {} is not waiting for admission", participant_id);
+        };
+
+        info!(
+            "Host {} {} peer {} in room {}",
+            requester_id,
+            if approved { "admitted" } else { "denied" },
+            participant_id,
+            self.id
+        );
+        let _ = pending.decision.send(approved);
+        Ok(())
+    }
+
+    /// Забывает заявку участника, который перестал ждать (таймаут или отключение)
+    pub fn cancel_admission(&self, participant_id: &str) {
+        self.pending_peers
+            .lock()
+            .unwrap()
+            .retain(|id, pending| id != participant_id || !pending.decision.is_closed());
+    }
+
+    /// Участник в центре внимания
+    pub fn spotlight(&self) -> Option<String> {
+        self.spotlight.lock().unwrap().clone()
+    }
+
+    /// Включает или останавливает запись (только ведущий) и уведомляет всех.
+    /// При остановке файлы дописываются и закрываются
+    pub async fn set_recording(&self, requester_id: &str, enabled: bool) -> Result<()> {
+        if !self.is_host(requester_id) {
+            anyhow::bail!("Only the room host can control recording");
+        }
+        let Some(recorder) = &self.recorder else {
+            anyhow::bail!("Recording is not configured on this server");
+        };
+
+        if recorder.set_enabled(enabled) {
+            info!(
+                "Host {} {} recording of room {}",
+                requester_id,
+                if enabled { "started" } else { "stopped" },
+                self.id
+            );
+            self.broadcast_message_to_all(ServerMessage::RecordingChanged { enabled })
+                .await;
+        }
+        Ok(())
+    }
+
+    /// Выбирает участника в центр внимания (только ведущий) и уведомляет всех
+    pub async fn set_spotlight(
+        &self,
+        requester_id: &str,
+        participant_id: Option<String>,
+    ) -> Result<()> {
+        if !self.is_host(requester_id) {
+            anyhow::bail!("Only the room host can change the spotlight");
+        }
+        if let Some(id) = &participant_id {
+            if self.get_peer(id).await.is_none() {
+                anyhow::bail!("Participant {} is not in the room", id);
+            }
+        }
+
+        *self.spotlight.lock().unwrap() = participant_id.clone();
+        self.broadcast_message_to_all(ServerMessage::SpotlightChanged { participant_id })
+            .await;
+        Ok(())
+    }
+
+    /// Отключен ли трек участника сервером
+    pub fn is_track_server_muted(&self, peer_id: &str, track_id: &str) -> bool {
+        self.server_muted
+            .lock()
+            .unwrap()
+            .get(peer_id)
+            .is_some_and(|tracks| tracks.contains(track_id))
+    }
+
+    /// Прекращает пересылку трека и уведомляет всех участников, включая владельца
+    pub async fn server_mute_track(&self, peer_id: &str, track_id: &str, reason: &str) {
+        let inserted = self
+            .server_muted
+            .lock()
+            .unwrap()
+            .entry(peer_id.to_string())
+            .or_default()
+            .insert(track_id.to_string());
+        if !inserted {
+            return;
+        }
+
+        warn!(
+            "Server-muted track {} of peer {} in room {}: {}",
+            track_id, peer_id, self.id, reason
+        );
+        self.broadcast_message_to_all(ServerMessage::TrackServerMuted {
+            participant_id: peer_id.to_string(),
+            track_id: track_id.to_string(),
+            reason: reason.to_string(),
+        })
+        .await;
+    }
+
+    /// Восстанавливает пересылку отключенного сервером трека (только ведущий)
+    pub async fn restore_track(
+        &self,
+        requester_id: &str,
+        peer_id: &str,
+        track_id: &str,
+    ) -> Result<()> {
+        if !self.is_host(requester_id) {
+            anyhow::bail!("Only the room host can restore tracks");
+        }
+
+        let removed = {
+            let mut server_muted = self.server_muted.lock().unwrap();
+            let removed = server_muted
+                .get_mut(peer_id)
+                .is_some_and(|tracks| tracks.remove(track_id));
+            if server_muted.get(peer_id).is_some_and(HashSet::is_empty) {
+                server_muted.remove(peer_id);
+            }
+            removed
+        };
+        if !removed {
+            anyhow::bail!("Track {} of participant {} is not muted", track_id, peer_id);
+        }
+
+        info!("Host {} restored track {} of peer {}", requester_id, track_id, peer_id);
+        self.broadcast_message_to_all(ServerMessage::TrackRestored {
+            participant_id: peer_id.to_string(),
+            track_id: track_id.to_string(),
+        })
+        .await;
+        Ok(())
+    }
+
+    /// Пересылает сообщение из data channel участника в каналы с той же меткой
+    /// у остальных участников. Возвращает число получателей, которым сообщение уже
+    /// отправлено; каналы, которые сервер только открывает, получат его после открытия
+    pub async fn relay_data(
+        &self,
+        from_id: &str,
+        label: &str,
+        data: Bytes,
+        is_string: bool,
+    ) -> Result<usize> {
+        if data.len() > MAX_DATA_MESSAGE_BYTES {
+            anyhow::bail!(
+                "Data channel message of {} bytes exceeds the {} byte limit",
+                data.len(),
+                MAX_DATA_MESSAGE_BYTES
+            );
+        }
+
+        let recipients: Vec<Arc<Peer>> = self
+            .peers
+            .read()
+            .await
+            .iter()
+            .filter(|(peer_id, _)| peer_id.as_str() != from_id)
+            .map(|(_, peer)| peer.clone())
+            .collect();
+
+        let mut delivered = 0;
+        for peer in recipients {
+            match peer.send_data(label, &data, is_string).await {
+                Ok(true) => delivered += 1,
+                Ok(false) => {}
+                Err(e) => debug!("Failed to relay data channel {} to {}: {}", label, peer.id, e),
+            }
+        }
+        Ok(delivered)
+    }
+
+    /// Удаляет участника из комнаты по решению ведущего: уведомляет его,
+    /// закрывает его соединения и сообщает остальным о выходе
+    pub async fn kick(&self, requester_id: &str, target_id: &str) -> Result<()> {
+        if !self.is_host(requester_id) {
+            anyhow::bail!("Only the room host can remove participants");
+        }
+        if requester_id == target_id {
+            anyhow::bail!("The room host cannot remove themselves");
+        }
+        let Some(target) = self.get_peer(target_id).await else {
+            anyhow::bail!("Participant {} is not in the room", target_id);
+        };
+
+        info!("Host {} removed peer {} from room {}", requester_id, target_id, self.id);
+        self.record_event(RoomEventKind::Kicked, target_id);
+        let _ = target.send_message(ServerMessage::Kicked {
+            reason: "Removed by the room host".to_string(),
+        });
+        let _ = target.ws_tx.send(Message::Close(None));
+        self.remove_peer(target_id).await?;
+        Ok(())
+    }
+
+    /// Отключает участников, неактивных дольше `timeout`: сообщает им об ошибке 408,
+    /// закрывает соединение и освобождает место в комнате. Возвращает их id
+    pub async fn close_idle_peers(&self, timeout: Duration, now: Instant) -> Result<Vec<String>> {
+        let idle: Vec<Arc<Peer>> = self
+            .peers
+            .read()
+            .await
+            .values()
+            .filter(|peer| peer.activity.idle_for(now) > timeout)
+            .cloned()
+            .collect();
+
+        let mut closed = Vec::with_capacity(idle.len());
+        for peer in idle {
+            warn!(
+                "Peer {} idle for over {}s, closing connection in room {}",
+                peer.id,
+                timeout.as_secs(),
+                self.id
+            );
+            let _ = peer.send_message(ServerMessage::error(ErrorCode::Timeout, "idle_timeout"));
+            let _ = peer.ws_tx.send(Message::Close(None));
+            self.remove_peer(&peer.id).await?;
+            closed.push(peer.id.clone());
+        }
+        Ok(closed)
+    }
+
+    /// Начинает демонстрацию экрана участника. Получателям без свободного видео слота
+    /// слот добавляется заранее и согласуется offer сервера, чтобы первые кадры экрана
+    /// не ждали renegotiation. Одновременно у участника может быть одна демонстрация
+    pub async fn start_screen_share(&self, peer: &Peer) -> Result<()> {
+        let (muted, video_on, screen_sharing) = peer.get_state().await;
+        if screen_sharing {
+            anyhow::bail!("Screen sharing is already active");
+        }
+        peer.update_state(muted, video_on, true).await;
+        info!("Peer {} started screen sharing in room {}", peer.id, self.id);
+        self.record_event(RoomEventKind::ScreenShareStarted, &peer.id);
+
+        for subscriber in self.get_all_peers().await {
+            if subscriber.id == peer.id || subscriber.has_free_slot(RTPCodecType::Video).await {
+                continue;
+            }
+            tokio::spawn(
+                async move {
+                    if let Err(e) = subscriber.add_slot(TrackType::Screen).await {
+                        warn!("Failed to add screen slot for peer {}: {}", subscriber.id, e);
+                    }
+                }
+                .in_current_span(),
+            );
+        }
+
+        self.broadcast_message(
+            &peer.id,
+            ServerMessage::ScreenShareStarted {
+                participant_id: peer.id.clone(),
+            },
+        )
+        .await;
+        Ok(())
+    }
+
+    /// Завершает демонстрацию экрана участника и освобождает ее слоты у получателей
+    pub async fn stop_screen_share(&self, peer: &Peer) -> Result<()> {
+        let (muted, video_on, screen_sharing) = peer.get_state().await;
+        if !screen_sharing {
+            anyhow::bail!("Screen sharing is not active");
+        }
+        peer.update_state(muted, video_on, false).await;
+        info!("Peer {} stopped screen sharing in room {}", peer.id, self.id);
+
+        for subscriber in self.get_all_peers().await {
+            subscriber.release_source_slots(&peer.id, TrackType::Screen);
+        }
+
+        self.broadcast_message(
+            &peer.id,
+            ServerMessage::ScreenShareStopped {
+                participant_id: peer.id.clone(),
+            },
+        )
+        .await;
+        Ok(())
+    }
+
+    /// Отключен ли звук участника ведущим
+    pub fn is_force_muted(&self, peer_id: &str) -> bool {
+        self.force_muted.lock().unwrap().contains(peer_id)
+    }
+
+    /// Пересылается ли трек `track_type` участника по его собственному состоянию:
+    /// звук при выключенном микрофоне и видео камеры при выключенной камере
+    /// не уходят получателям. Демонстрация экрана от камеры не зависит
+    #[allow(dead_code)]
+    pub async fn source_enabled(&self, peer_id: &str, track_type: TrackType) -> bool {
+        self.get_peer(peer_id)
+            .await
+            .is_some_and(|peer| peer.forwards(track_type))
+    }
+
+    /// Включает или снимает принудительное отключение звука участника (только ведущий).
+    /// Пока оно действует, аудио участника не пересылается, а попытки клиента
+    /// включить микрофон через `state_update` игнорируются. Снятие не включает
+    /// микрофон: участник остается с тем состоянием, которое выбрал сам
+    pub async fn force_mute(&self, requester_id: &str, target_id: &str, muted: bool) -> Result<()> {
+        if !self.is_host(requester_id) {
+            anyhow::bail!("Only the room host can mute participants");
+        }
+        let Some(target) = self.get_peer(target_id).await else {
+            anyhow::bail!("Participant {} is not in the room", target_id);
+        };
+
+        {
+            let mut force_muted = self.force_muted.lock().unwrap();
+            if muted {
+                force_muted.insert(target_id.to_string());
+            } else {
+                force_muted.remove(target_id);
+            }
         }
+        target.set_force_muted(muted);
+
+        info!(
+            "Host {} set force mute of peer {} to {}",
+            requester_id, target_id, muted
+        );
+        let (own_muted, video_on, screen_sharing) = target.get_state().await;
+        if muted && !own_muted {
+            target.update_state(true, video_on, screen_sharing).await;
+        }
+        self.broadcast_message_to_all(ServerMessage::StateUpdate {
+            participant_id: target_id.to_string(),
+            muted: muted || own_muted,
+            video_on,
+            screen_sharing,
+        })
+        .await;
+        Ok(())
+    }
+
+    /// Группа аудио подписок участника
+    fn audio_group(&self, peer_id: &str) -> Option<&str> {
+        self.config
+            .audio_groups
+            .iter()
+            .find(|(_, members)| members.iter().any(|m| m == peer_id))
+            .map(|(group, _)| group.as_str())
+            .or(self.config.default_audio_group.as_deref())
+    }
+
+    /// Слышит ли получатель аудио источника (см. `RoomConfig::audio_subscriptions`)
+    pub fn audio_allowed(&self, subscriber_id: &str, source_id: &str) -> bool {
+        let Some(allowed) = self
+            .audio_group(subscriber_id)
+            .and_then(|group| self.config.audio_subscriptions.get(group))
+        else {
+            return true;
+        };
+
+        self.audio_group(source_id)
+            .is_some_and(|group| allowed.iter().any(|g| g == group))
+    }
+
+    /// Задает, от каких источников участник получает аудио и видео; `None` - от всех.
+    /// У видео источников, которые получатель только что выбрал, запрашивается
+    /// ключевой кадр, чтобы картинка появилась сразу
+    pub async fn subscribe(
+        &self,
+        subscriber_id: &str,
+        audio: Option<Vec<String>>,
+        video: Option<Vec<String>>,
+    ) {
+        let audio = audio.map(|ids| ids.into_iter().collect::<HashSet<_>>());
+        let video = video.map(|ids| ids.into_iter().collect::<HashSet<_>>());
+        let previous = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .insert(subscriber_id.to_string(), Subscription { audio, video: video.clone() })
+            .and_then(|subscription| subscription.video);
+        debug!("Peer {} updated subscriptions in room {}", subscriber_id, self.id);
+
+        // До этого получатель видел всех: новых видео источников нет
+        let Some(previous) = previous else {
+            return;
+        };
+        let added: Vec<Arc<Peer>> = self
+            .peers
+            .read()
+            .await
+            .iter()
+            .filter(|(id, _)| id.as_str() != subscriber_id && !previous.contains(*id))
+            .filter(|(id, _)| video.as_ref().is_none_or(|video| video.contains(*id)))
+            .map(|(_, peer)| peer.clone())
+            .collect();
+        for source in added {
+            if let Err(e) = source.request_keyframe().await {
+                debug!("Keyframe request to {} failed: {}", source.id, e);
+            }
+        }
+    }
+
+    /// Подписан ли получатель на трек источника (см. `subscribe`)
+    pub fn is_subscribed(
+        &self,
+        subscriber_id: &str,
+        source_id: &str,
+        track_type: TrackType,
+    ) -> bool {
+        let subscriptions = self.subscriptions.lock().unwrap();
+        let Some(subscription) = subscriptions.get(subscriber_id) else {
+            return true;
+        };
+        let sources = match track_type {
+            TrackType::Audio => &subscription.audio,
+            TrackType::Camera | TrackType::Screen => &subscription.video,
+        };
+        sources.as_ref().is_none_or(|sources| sources.contains(source_id))
+    }
+
+    /// Приостанавливает или возобновляет пересылку получателю трека источника.
+    /// Слот получателя остается закрепленным, поэтому renegotiation не нужен;
+    /// при возобновлении видео у источника запрашивается ключевой кадр
+    pub async fn set_track_paused(
+        &self,
+        subscriber_id: &str,
+        source_id: &str,
+        kind: TrackType,
+        paused: bool,
+    ) -> Result<()> {
+        if subscriber_id == source_id {
+            anyhow::bail!("Cannot pause your own track");
+        }
+        let Some(source) = self.get_peer(source_id).await else {
+            anyhow::bail!("Participant {} is not in the room", source_id);
+        };
+
+        let changed = {
+            let mut paused_tracks = self.paused_tracks.lock().unwrap();
+            if paused {
+                paused_tracks
+                    .entry(subscriber_id.to_string())
+                    .or_default()
+                    .entry(source_id.to_string())
+                    .or_default()
+                    .insert(kind)
+            } else {
+                let Some(sources) = paused_tracks.get_mut(subscriber_id) else {
+                    return Ok(());
+                };
+                let Some(kinds) = sources.get_mut(source_id) else {
+                    return Ok(());
+                };
+                let removed = kinds.remove(&kind);
+                if kinds.is_empty() {
+                    sources.remove(source_id);
+                }
+                if sources.is_empty() {
+                    paused_tracks.remove(subscriber_id);
+                }
+                removed
+            }
+        };
+        if !changed {
+            return Ok(());
+        }
+        debug!(
+            "Peer {} {} {:?} of {} in room {}",
+            subscriber_id,
+            if paused { "paused" } else { "resumed" },
+            kind,
+            source_id,
+            self.id
+        );
+
+        if !paused && kind != TrackType::Audio {
+            if let Err(e) = source.request_keyframe().await {
+                debug!("Keyframe request to {} failed: {}", source_id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Приостановил ли получатель трек источника (см. `set_track_paused`)
+    pub fn is_track_paused(&self, subscriber_id: &str, source_id: &str, kind: TrackType) -> bool {
+        self.paused_tracks
+            .lock()
+            .unwrap()
+            .get(subscriber_id)
+            .and_then(|sources| sources.get(source_id))
+            .is_some_and(|kinds| kinds.contains(&kind))
+    }
+
+    /// Решает, пересылать ли получателю пакет simulcast слоя `layer` от источника
+    pub fn layer_allows(
+        &self,
+        subscriber_id: &str,
+        source_id: &str,
+        layer: &str,
+        frame: &FrameInfo,
+    ) -> bool {
+        let mut switches = self.layer_switches.lock().unwrap();
+        if let Some(switch) = switches
+            .get_mut(subscriber_id)
+            .and_then(|sources| sources.get_mut(source_id))
+        {
+            return switch.should_forward(layer, frame);
+        }
+
+        let mut switch = LayerSwitch::new(self.config.layer_switch_keyframe_only);
+        if let Some(target) = self.target_layer(subscriber_id, source_id) {
+            switch.set_target(&target);
+        }
+        let allowed = switch.should_forward(layer, frame);
+        switches
+            .entry(subscriber_id.to_string())
+            .or_default()
+            .insert(source_id.to_string(), switch);
+        allowed
+    }
+
+    /// Перезапись номеров и timestamp simulcast трека источника, общая для всех
+    /// его слоев: получатель видит один непрерывный поток при любых переключениях
+    fn track_rewriters(&self, source_id: &str, track_id: &str) -> Arc<TrackRewriters> {
+        self.rtp_rewriters
+            .lock()
+            .unwrap()
+            .entry((source_id.to_string(), track_id.to_string()))
+            .or_default()
+            .clone()
+    }
+
+    /// RID слоя, который получатель должен получать от источника
+    fn target_layer(&self, subscriber_id: &str, source_id: &str) -> Option<String> {
+        let layer = if self.congested.lock().unwrap().contains(subscriber_id) {
+            SimulcastLayer::Low
+        } else {
+            self
+            .layer_preferences
+            .lock()
+            .unwrap()
+            .get(&(subscriber_id.to_string(), source_id.to_string()))
+            .copied()
+            .unwrap_or_default()
+        };
+        let layers = self.simulcast_layers.lock().unwrap();
+        layer.pick(layers.get(source_id)?).map(str::to_string)
+    }
+
+    /// Перенацеливает пары (получатель, источник) после смены слоев источника,
+    /// выбора или перегрузки получателя
+    fn retarget_layers(&self, source_id: Option<&str>, subscriber_id: Option<&str>) {
+        let mut switches = self.layer_switches.lock().unwrap();
+        for (subscriber, sources) in switches.iter_mut() {
+            if subscriber_id.is_some_and(|id| id != subscriber) {
+                continue;
+            }
+            for (source, switch) in sources.iter_mut() {
+                if source_id.is_some_and(|id| id != source) {
+                    continue;
+                }
+                if let Some(target) = self.target_layer(subscriber, source) {
+                    switch.set_target(&target);
+                }
+            }
+        }
+    }
+
+    /// Учитывает simulcast слой `rid`, который начал или перестал публиковать источник
+    pub fn update_simulcast_layer(&self, source_id: &str, rid: &str, published: bool) {
+        {
+            let mut layers = self.simulcast_layers.lock().unwrap();
+            if published {
+                let rids = layers.entry(source_id.to_string()).or_default();
+                if !rids.iter().any(|r| r == rid) {
+                    rids.push(rid.to_string());
+                    order_layers(rids);
+                }
+            } else if let Some(rids) = layers.get_mut(source_id) {
+                rids.retain(|r| r != rid);
+            }
+        }
+        self.retarget_layers(Some(source_id), None);
+    }
+
+    /// Учитывает перегрузку канала получателя: перегруженному пересылаются нижние
+    /// слои всех источников независимо от его выбора. `true`, если состояние сменилось
+    pub fn update_congestion(&self, subscriber_id: &str, congested: bool) -> bool {
+        {
+            let mut set = self.congested.lock().unwrap();
+            if set.contains(subscriber_id) == congested {
+                return false;
+            }
+            if congested {
+                set.insert(subscriber_id.to_string());
+            } else {
+                set.remove(subscriber_id);
+            }
+        }
+        info!(
+            "Room {}: peer {} {} simulcast layers due to bandwidth",
+            self.id,
+            subscriber_id,
+            if congested { "limited to low" } else { "restored to selected" }
+        );
+        self.retarget_layers(None, Some(subscriber_id));
+        true
+    }
+
+    /// Участники, публикующие simulcast
+    fn simulcast_sources(&self) -> Vec<String> {
+        self.simulcast_layers.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Выбирает качество simulcast слоя, которое получатель принимает от источника.
+    /// Переключение происходит на границе кадра, издателю отправляется запрос
+    /// ключевого кадра, чтобы оно не ждало следующего
+    pub async fn set_layer(
+        &self,
+        subscriber_id: &str,
+        source_id: &str,
+        layer: SimulcastLayer,
+    ) -> Result<()> {
+        if subscriber_id == source_id {
+            anyhow::bail!("Cannot select a layer of your own video");
+        }
+        let Some(source) = self.get_peer(source_id).await else {
+            anyhow::bail!("Participant {} is not in the room", source_id);
+        };
+
+        self.layer_preferences
+            .lock()
+            .unwrap()
+            .insert((subscriber_id.to_string(), source_id.to_string()), layer);
+        self.retarget_layers(Some(source_id), Some(subscriber_id));
+        debug!(
+            "Peer {} selected {:?} layer of {} in room {}",
+            subscriber_id, layer, source_id, self.id
+        );
+
+        if let Err(e) = source.request_keyframe().await {
+            debug!("Keyframe request to {} failed: {}", source_id, e);
+        }
+        Ok(())
+    }
+
+    /// Пересылается ли медиа в комнате
+    pub fn is_forwarding(&self) -> bool {
+        self.forwarding.load(Ordering::Relaxed)
+    }
+
+    /// Открывает или закрывает пересылку медиа в зависимости от числа участников
+    fn update_start_gate(&self, peers: &HashMap<String, Arc<Peer>>) {
+        let Some(min) = self.config.min_participants_to_start else {
+            return;
+        };
+
+        let msg = if peers.len() >= min {
+            if self.forwarding.swap(true, Ordering::Relaxed) {
+                return;
+            }
+            info!("Room {} reached {} participants, starting media", self.id, min);
+            ServerMessage::RoomStarted
+        } else if self.config.pause_below_min_participants {
+            if !self.forwarding.swap(false, Ordering::Relaxed) {
+                return;
+            }
+            info!("Room {} dropped below {} participants, pausing media", self.id, min);
+            ServerMessage::RoomPaused
+        } else {
+            return;
+        };
+
+        for peer in peers.values() {
+            if let Err(e) = peer.send_message(msg.clone()) {
+                warn!("Failed to notify peer {}: {}", peer.id, e);
+            }
+        }
+    }
+
+    /// Добавляет участника в комнату; `RoomFull`, если комната заполнена
+    pub async fn add_peer(&self, peer: Arc<Peer>) -> Result<()> {
+        let peer_id = peer.id.clone();
+        let peer_name = peer.name();
+
+        // Лимит проверяется под блокировкой записи, чтобы одновременные входы не превысили его
+        let mut peers_guard = self.peers.write().await;
+        if !peers_guard.contains_key(&peer_id) && peers_guard.len() >= self.max_participants {
+            return Err(RoomFull {
+                room_id: self.id.clone(),
+                max_participants: self.max_participants,
+            }
+            .into());
+        }
+
+        peer.report_to(self.metrics.clone());
+
+        // Уведомляем существующих участников о новом
+        for (_, existing_peer) in peers_guard.iter() {
+            if let Err(e) = existing_peer.send_message(ServerMessage::ParticipantJoined {
+                id: peer_id.clone(),
+                name: peer_name.clone(),
+            }) {
+                warn!("Failed to notify peer {}: {}", existing_peer.id, e);
+            }
+        }
+
+        // Новому участнику нужны ключевые кадры от всех издателей видео
+        let publishers: Vec<Arc<Peer>> = peers_guard
+            .values()
+            .filter(|existing| existing.id != peer_id)
+            .cloned()
+            .collect();
+
+        // Запросы ключевых кадров от получателя передаются издателям
+        self.forward_keyframe_requests(&peer);
+
+        // Добавляем нового участника
+        peers_guard.insert(peer_id.clone(), peer);
+        self.host_id.lock().unwrap().get_or_insert_with(|| peer_id.clone());
+        self.history
+            .lock()
+            .unwrap()
+            .participant_joined(&peer_id, &peer_name, peers_guard.len());
+        self.record_event(RoomEventKind::Joined, &peer_id);
+        info!("Peer {} joined room {}", peer_id, self.id);
+        self.update_start_gate(&peers_guard);
+        drop(peers_guard);
+
+        for publisher in publishers {
+            if let Err(e) = publisher.request_keyframe().await {
+                debug!("Failed to request keyframe from peer {}: {}", publisher.id, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Возобновляет сессию участника новым соединением (переподключение с `resume_sid`
+    /// или токеном): прежнее соединение закрывается, а имя, mute/video и роли в комнате
+    /// сохраняются. Остальные получают `participant_reconnected` вместо выхода и входа.
+    /// Если сессию одновременно возобновляют два соединения, остается последнее,
+    /// а вытесненное получает ошибку 409 `session_resumed`. `false`, если участника
+    /// в комнате уже нет - тогда нужен обычный вход
+    pub async fn resume_peer(&self, peer: Arc<Peer>) -> bool {
+        let peer_id = peer.id.clone();
+        let mut peers_guard = self.peers.write().await;
+        let Some(stale) = peers_guard.get(&peer_id).cloned() else {
+            return false;
+        };
+
+        let (muted, video_on, _) = stale.get_state().await;
+        peer.update_state(muted, video_on, false).await;
+        peer.set_force_muted(self.is_force_muted(&peer_id));
+        *peer.hand_raised.write().await = *stale.hand_raised.read().await;
+        peer.report_to(self.metrics.clone());
+        self.forward_keyframe_requests(&peer);
+
+        peers_guard.insert(peer_id.clone(), peer);
+        // Треки прежнего соединения больше не придут: их SSRC, слои и слоты забываются
+        self.forget_connection(&peer_id);
+        if let Some(recorder) = &self.recorder {
+            recorder.close_participant(&peer_id);
+        }
+        let publishers: Vec<Arc<Peer>> = peers_guard
+            .values()
+            .filter(|other| other.id != peer_id)
+            .cloned()
+            .collect();
+        for other in &publishers {
+            other.release_slots(&peer_id);
+            let _ = other.send_message(ServerMessage::ParticipantReconnected {
+                participant_id: peer_id.clone(),
+            });
+        }
+        drop(peers_guard);
+
+        info!("Peer {} resumed its session in room {}", peer_id, self.id);
+        let _ = stale.send_message(ServerMessage::error(ErrorCode::Conflict, "session_resumed"));
+        let _ = stale.ws_tx.send(Message::Close(None));
+        if let Err(e) = stale.close().await {
+            warn!("Error closing stale connection of {}: {}", peer_id, e);
+        }
+
+        for publisher in publishers {
+            if let Err(e) = publisher.request_keyframe().await {
+                debug!("Failed to request keyframe from peer {}: {}", publisher.id, e);
+            }
+        }
+        true
+    }
+
+    /// Передает издателям запросы ключевых кадров от получателя `peer`
+    fn forward_keyframe_requests(&self, peer: &Peer) {
+        let Some(mut requests) = peer.take_keyframe_requests() else {
+            return;
+        };
+        let peers = self.peers.clone();
+        let room_id = self.id.clone();
+        let span = info_span!(parent: &self.span, "peer", participant_id = %peer.id);
+        tokio::spawn(
+            async move {
+                while let Some(source) = requests.recv().await {
+                    let publisher = peers.read().await.get(&source).cloned();
+                    if let Some(publisher) = publisher {
+                        if let Err(e) = publisher.request_keyframe().await {
+                            debug!(
+                                "Room {}: keyframe request to {} failed: {}",
+                                room_id, source, e
+                            );
+                        }
+                    }
+                }
+            }
+            .instrument(span),
+        );
+    }
+
+    /// Забывает состояние, привязанное к соединению участника: опубликованные SSRC,
+    /// simulcast слои с перезаписью номеров и отключенные сервером треки
+    fn forget_connection(&self, peer_id: &str) {
+        {
+            let mut switches = self.layer_switches.lock().unwrap();
+            switches.remove(peer_id);
+            switches.retain(|_, sources| {
+                sources.remove(peer_id);
+                !sources.is_empty()
+            });
+        }
+        self.rtp_rewriters.lock().unwrap().retain(|(source, _), rewriters| {
+            rewriters.forget(peer_id);
+            source != peer_id
+        });
+        self.simulcast_layers.lock().unwrap().remove(peer_id);
+        self.congested.lock().unwrap().remove(peer_id);
+        self.published.lock().unwrap().remove(peer_id);
+        self.server_muted.lock().unwrap().remove(peer_id);
+    }
+
+    /// Удаляет участника из комнаты; `false`, если участника в комнате не было
+    pub async fn remove_peer(&self, peer_id: &str) -> Result<bool> {
+        let mut peers_guard = self.peers.write().await;
+
+        let removed = peers_guard.remove(peer_id);
+        if let Some(peer) = &removed {
+            info!("Removing peer {} from room {}", peer_id, self.id);
+            self.history.lock().unwrap().participant_left(peer_id);
+            self.record_event(RoomEventKind::Left, peer_id);
+            if let Some(recorder) = &self.recorder {
+                recorder.close_participant(peer_id);
+            }
+
+            // Закрываем соединение
+            if let Err(e) = peer.close().await {
+                warn!("Error closing peer connection: {}", e);
+            }
+        }
+
+        self.forget_connection(peer_id);
+        self.layer_preferences
+            .lock()
+            .unwrap()
+            .retain(|(subscriber, source), _| subscriber != peer_id && source != peer_id);
+        self.subscriptions.lock().unwrap().remove(peer_id);
+        {
+            let mut paused_tracks = self.paused_tracks.lock().unwrap();
+            paused_tracks.remove(peer_id);
+            paused_tracks.retain(|_, sources| {
+                sources.remove(peer_id);
+                !sources.is_empty()
+            });
+        }
+        self.force_muted.lock().unwrap().remove(peer_id);
+        if let Some(speakers) = &self.speakers {
+            speakers.lock().unwrap().remove(peer_id);
+        }
+
+        // Участника не было в комнате (не успел войти или уже удален): уведомлять некого
+        if removed.is_none() {
+            debug!("Peer {} is not in room {}, skipping leave notification", peer_id, self.id);
+            return Ok(false);
+        }
+
+        // Ведущий ушел: роль переходит к участнику с наименьшим id,
+        // и ожидающие допуска заявки достаются новому ведущему
+        let new_host = {
+            let mut host_id = self.host_id.lock().unwrap();
+            if host_id.as_deref() == Some(peer_id) {
+                *host_id = peers_guard.keys().min().cloned();
+                host_id.clone()
+            } else {
+                None
+            }
+        };
+        if let Some(host) = new_host.and_then(|id| peers_guard.get(&id)) {
+            for (participant_id, pending) in self.pending_peers.lock().unwrap().iter() {
+                let _ = host.send_message(ServerMessage::JoinRequest {
+                    participant_id: participant_id.clone(),
+                    name: pending.name.clone(),
+                });
+            }
+        }
+
+        // Участник в центре внимания ушел: выбор снимается
+        let spotlight_cleared = {
+            let mut spotlight = self.spotlight.lock().unwrap();
+            let cleared = spotlight.as_deref() == Some(peer_id);
+            if cleared {
+                *spotlight = None;
+            }
+            cleared
+        };
+
+        // Уведомляем остальных участников
+        let leave_msg = ServerMessage::ParticipantLeft {
+            participant_id: peer_id.to_string(),
+        };
+
+        for (_, other_peer) in peers_guard.iter() {
+            other_peer.release_slots(peer_id);
+            if let Err(e) = other_peer.send_message(leave_msg.clone()) {
+                warn!("Failed to notify peer {}: {}", other_peer.id, e);
+            }
+            if spotlight_cleared {
+                let _ = other_peer.send_message(ServerMessage::SpotlightChanged {
+                    participant_id: None,
+                });
+            }
+        }
+
+        self.update_start_gate(&peers_guard);
+
+        Ok(true)
+    }
+
+    /// Состав комнаты для `Joined`: участники с текущими флагами mute/video/screen,
+    /// кроме `exclude_id` (самого входящего участника)
+    pub async fn participants_info(&self, exclude_id: &str) -> Vec<ParticipantInfo> {
+        let mut participants = Vec::new();
+        for peer in self.get_all_peers().await {
+            if peer.id == exclude_id {
+                continue;
+            }
+            let (muted, video_on, screen_sharing) = peer.get_state().await;
+            let hand_raised = *peer.hand_raised.read().await;
+            participants.push(
+                ParticipantInfo::with_state(
+                    peer.id.clone(),
+                    peer.name(),
+                    muted,
+                    video_on,
+                    screen_sharing,
+                )
+                .with_hand_raised(hand_raised),
+            );
+        }
+        participants.sort_by(|a, b| a.id.cmp(&b.id));
+        participants
+    }
+
+    /// Рекомендуемый битрейт для издателя по REMB и TWCC оценкам участников, получающих
+    /// его видео (политика `RoomConfig::bitrate_feedback`)
+    pub async fn publisher_bitrate_feedback(&self, publisher_id: &str) -> Option<u64> {
+        let estimates: Vec<u64> = self
+            .peers
+            .read()
+            .await
+            .iter()
+            .filter(|(id, _)| id.as_str() != publisher_id)
+            .filter(|(id, _)| self.is_subscribed(id, publisher_id, TrackType::Camera))
+            .filter_map(|(_, peer)| peer.receive_capacity())
+            .collect();
+
+        aggregate_estimates(
+            &estimates,
+            self.config.bitrate_feedback,
+            self.config.bitrate_feedback_percentile,
+        )
+    }
+
+    /// Получает участника по ID
+    pub async fn get_peer(&self, peer_id: &str) -> Option<Arc<Peer>> {
+        self.peers.read().await.get(peer_id).cloned()
+    }
+
+    /// Получает всех участников
+    pub async fn get_all_peers(&self) -> Vec<Arc<Peer>> {
+        self.peers.read().await.values().cloned().collect()
+    }
+
+    /// Возвращает количество участников
+    pub async fn peer_count(&self) -> usize {
+        self.peers.read().await.len()
+    }
+
+    /// Проверяет, пуста ли комната
+    pub async fn is_empty(&self) -> bool {
+        self.peers.read().await.is_empty()
+    }
+
+    /// Транслирует сообщение всем участникам кроме отправителя
+    pub async fn broadcast_message(&self, from_id: &str, msg: ServerMessage) {
+        let mut dead = Vec::new();
+        {
+            let peers_guard = self.peers.read().await;
+            for (id, peer) in peers_guard.iter() {
+                if id != from_id {
+                    if let Err(e) = peer.send_message(msg.clone()) {
+                        warn!("Failed to broadcast to peer {}: {}", id, e);
+                        if peer.ws_tx.is_closed() {
+                            dead.push(peer.clone());
+                        }
+                    }
+                }
+            }
+        }
+        self.remove_dead_peers(dead).await;
+    }
+
+    /// Транслирует сообщение всем участникам включая отправителя
+    pub async fn broadcast_message_to_all(&self, msg: ServerMessage) {
+        let mut dead = Vec::new();
+        {
+            let peers_guard = self.peers.read().await;
+            for (_, peer) in peers_guard.iter() {
+                if let Err(e) = peer.send_message(msg.clone()) {
+                    warn!("Failed to broadcast to peer {}: {}", peer.id, e);
+                    if peer.ws_tx.is_closed() {
+                        dead.push(peer.clone());
+                    }
+                }
+            }
+        }
+        self.remove_dead_peers(dead).await;
+    }
+
+    /// Удаляет участников, чье WebSocket соединение закрыто (рассылка им не прошла).
+    /// Вызывается после освобождения блокировки участников: `remove_peer` берет ее
+    /// на запись. Участник, успевший переподключиться под тем же id, не трогается
+    async fn remove_dead_peers(&self, dead: Vec<Arc<Peer>>) {
+        for peer in dead {
+            let current = self.get_peer(&peer.id).await;
+            if !current.is_some_and(|current| Arc::ptr_eq(&current, &peer)) {
+                continue;
+            }
+            info!("Peer {} disconnected, removing from room {}", peer.id, self.id);
+            if let Err(e) = self.remove_peer(&peer.id).await {
+                warn!("Failed to remove disconnected peer {}: {}", peer.id, e);
+            }
+        }
+    }
+
+    /// Рассылает сообщение чата остальным участникам. Имя отправителя берется
+    /// из его `Peer`, а не из данных клиента
+    pub async fn send_chat(&self, from: &Peer, text: String) -> Result<()> {
+        if text.len() > MAX_CHAT_TEXT_BYTES {
+            anyhow::bail!(
+                "Chat message is too long ({} bytes, max {})",
+                text.len(),
+                MAX_CHAT_TEXT_BYTES
+            );
+        }
+
+        self.broadcast_message(
+            &from.id,
+            ServerMessage::Chat {
+                from_id: from.id.clone(),
+                from_name: from.name(),
+                text,
+                ts: now_ms(),
+            },
+        )
+        .await;
+        Ok(())
+    }
+
+    /// Меняет отображаемое имя участника и сообщает его всем, включая самого участника.
+    /// Поздно вошедшие получают новое имя в составе комнаты
+    pub async fn rename(&self, peer: &Peer, name: &str) -> Result<()> {
+        let Some(name) = clean_display_name(name) else {
+            anyhow::bail!("Name must be 1 to {} characters long", MAX_NAME_CHARS);
+        };
+
+        info!("Peer {} renamed to {:?} in room {}", peer.id, name, self.id);
+        peer.set_name(name.clone());
+        self.broadcast_message_to_all(ServerMessage::ParticipantRenamed {
+            participant_id: peer.id.clone(),
+            name,
+        })
+        .await;
+        Ok(())
+    }
+
+    /// Включает или выключает для участника пропуск пакетов тишины (Opus DTX).
+    /// Доступно, только если комната разрешает `dtx_suppression`
+    pub fn set_skip_silence(&self, peer: &Peer, skip: bool) -> Result<()> {
+        if skip && !self.config.dtx_suppression {
+            anyhow::bail!("Silence suppression is disabled in this room");
+        }
+        debug!("Peer {} set skip silence to {} in room {}", peer.id, skip, self.id);
+        peer.set_skip_silence(skip);
+        Ok(())
+    }
+
+    /// Рассылает эмодзи реакцию остальным участникам
+    pub async fn send_reaction(&self, from: &Peer, emoji: String) -> Result<()> {
+        if !is_valid_reaction(&emoji) {
+            anyhow::bail!(
+                "Invalid reaction: expected a single emoji up to {} bytes",
+                MAX_REACTION_BYTES
+            );
+        }
+
+        self.broadcast_message(
+            &from.id,
+            ServerMessage::Reaction {
+                participant_id: from.id.clone(),
+                emoji,
+                ts: now_ms(),
+            },
+        )
+        .await;
+        Ok(())
+    }
+
+    /// Обрабатывает входящий трек от участника и маршрутизирует его другим
+    pub async fn handle_incoming_track(
+        self: &Arc<Self>,
+        from_peer_id: String,
+        track: Arc<TrackRemote>,
+    ) -> Result<()> {
+        let track_type = TrackType::from_track(&track.id(), track.kind());
+
+        info!(
+            "Room {}: Handling incoming {:?} track from peer {} (id: {}, kind: {:?})",
+            self.id,
+            track_type,
+            from_peer_id,
+            track.id(),
+            track.kind()
+        );
+
+        self.register_source(&from_peer_id, track.ssrc(), &track.stream_id());
+        if track_type != TrackType::Audio {
+            if let Some(peer) = self.get_peer(&from_peer_id).await {
+                peer.register_incoming_video(track.ssrc());
+            }
+        }
+
+        // Говорящий определяется, только когда в комнате появилось аудио
+        if track_type == TrackType::Audio
+            && self.speakers.is_some()
+            && !self.speaker_task_started.swap(true, Ordering::Relaxed)
+        {
+            tokio::spawn(detect_active_speaker(Arc::downgrade(self)).instrument(self.span.clone()));
+        }
+
+        // Обратная связь по битрейту нужна, только когда в комнате появилось видео
+        if track_type != TrackType::Audio
+            && self.config.bitrate_feedback != BitrateAggregation::Off
+            && !self.bitrate_task_started.swap(true, Ordering::Relaxed)
+        {
+            tokio::spawn(send_bitrate_feedback(Arc::downgrade(self)).instrument(self.span.clone()));
+        }
+
+        // Запускаем задачу для чтения и пересылки RTP пакетов
+        let room = self.clone();
+        let from_id = from_peer_id.clone();
+        let span = info_span!(parent: &self.span, "peer", participant_id = %from_peer_id);
+
+        tokio::spawn(
+            async move {
+                if let Err(e) = relay_track(room, from_id, track, track_type).await {
+                    error!("Error relaying track: {}", e);
+                }
+            }
+            .instrument(span),
+        );
+
+        Ok(())
+    }
+
+    /// Снимок числа участников и опубликованных треков
+    pub async fn room_stats(&self) -> RoomStats {
+        let mut rtp = PeerStats::default();
+        for peer in self.get_all_peers().await {
+            rtp.merge(&peer.rtp_stats().await);
+        }
+        let participant_count = self.peer_count().await;
+        let active_tracks = self
+            .published
+            .lock()
+            .unwrap()
+            .values()
+            .map(|sources| sources.ssrcs.len())
+            .sum();
+        RoomStats {
+            room_id: self.id.clone(),
+            participant_count,
+            active_tracks,
+            rtp,
+        }
+    }
+
+    /// Получает статистику комнаты
+    #[allow(dead_code)]
+    pub async fn get_stats(&self) -> String {
+        let peers_guard = self.peers.read().await;
+        let peer_count = peers_guard.len();
+
+        let mut stats = format!("Room {} - {} peers:\n", self.id, peer_count);
+
+        for (_, peer) in peers_guard.iter() {
+            let peer_stats = peer.get_stats().await;
+            stats.push_str(&format!("  {}\n", peer_stats));
+        }
+
+        let events = self.event_log();
+        if !events.is_empty() {
+            stats.push_str("  Events:\n");
+            for event in events {
+                stats.push_str(&format!(
+                    "    +{}ms {:?} {}\n",
+                    event.at_ms, event.kind, event.participant_id
+                ));
+            }
+        }
+
+        stats
+    }
+}
+
+/// Периодически пересчитывает активного говорящего и сообщает о смене всем участникам.
+/// Завершается вместе с комнатой
+async fn detect_active_speaker(room: Weak<Room>) {
+    let mut ticker = tokio::time::interval(ACTIVE_SPEAKER_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let Some(room) = room.upgrade() else {
+            break;
+        };
+        let change = room
+            .speakers
+            .as_ref()
+            .and_then(|speakers| speakers.lock().unwrap().update(Instant::now()));
+        if let Some(participant_id) = change {
+            debug!("Room {}: active speaker {:?}", room.id, participant_id);
+            room.broadcast_message_to_all(ServerMessage::ActiveSpeaker { participant_id })
+                .await;
+        }
+    }
+}
+
+/// Раз в `BITRATE_FEEDBACK_INTERVAL` отправляет каждому издателю видео один REMB
+/// со сводной оценкой его получателей (см. `Room::publisher_bitrate_feedback`).
+/// Завершается вместе с комнатой
+async fn send_bitrate_feedback(room: Weak<Room>) {
+    let mut ticker = tokio::time::interval(BITRATE_FEEDBACK_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let Some(room) = room.upgrade() else {
+            break;
+        };
+        for publisher in room.get_all_peers().await {
+            let Some(bitrate) = room.publisher_bitrate_feedback(&publisher.id).await else {
+                continue;
+            };
+            if let Err(e) = publisher.send_remb(bitrate).await {
+                debug!("Failed to send REMB to peer {}: {}", publisher.id, e);
+            }
+        }
+    }
+}
+
+/// Периодически отключает неактивных участников комнаты (см. `Room::close_idle_peers`).
+/// У каждой комнаты своя задача, чтобы проверка большой комнаты не задерживала другие.
+/// Завершается вместе с комнатой
+async fn reap_idle_peers(room: Weak<Room>, timeout: Duration) {
+    let mut ticker = tokio::time::interval(timeout.min(IDLE_SWEEP_INTERVAL));
+    loop {
+        ticker.tick().await;
+        let Some(room) = room.upgrade() else {
+            break;
+        };
+        if let Err(e) = room.close_idle_peers(timeout, Instant::now()).await {
+            warn!("Room {}: failed to close idle peers: {}", room.id, e);
+        }
+    }
+}
+
+/// Пересылает RTP пакеты от одного участника всем остальным
+async fn relay_track(
+    room: Arc<Room>,
+    from_id: String,
+    track: Arc<TrackRemote>,
+    track_type: TrackType,
+) -> Result<()> {
+    let room_id = room.id.clone();
+    let peers = room.peers.clone();
+    let mut buf = vec![0u8; 1500];
+
+    // Для simulcast треков (с RID) получателю пересылается только выбранный слой
+    let rid = track.rid().to_string();
+    let stream_id = track.stream_id();
+    let codec = track.codec().capability;
+    let mime_type = codec.mime_type;
+    let clock_rate = codec.clock_rate;
+    let frame_marking_id = track
+        .params()
+        .header_extensions
+        .iter()
+        .find(|ext| ext.uri == FRAME_MARKING_URI)
+        .map(|ext| ext.id as u8);
+    let audio_level_id = track
+        .params()
+        .header_extensions
+        .iter()
+        .find(|ext| ext.uri == AUDIO_LEVEL_URI)
+        .map(|ext| ext.id as u8);
+    let mut noise_gate = match (track_type, room.config.noise_gate_threshold_dbov) {
+        (TrackType::Audio, Some(threshold)) if audio_level_id.is_some() => Some(NoiseGate::new(
+            threshold,
+            Duration::from_millis(room.config.noise_gate_hangover_ms),
+        )),
+        _ => None,
+    };
+    // Пакеты тишины распознаются только у Opus и только если комната это разрешает
+    let detect_dtx = track_type == TrackType::Audio
+        && room.config.dtx_suppression
+        && mime_type.eq_ignore_ascii_case(MIME_TYPE_OPUS);
+    let track_id = track.id();
+    // Подпись файла записи: simulcast слои пишутся отдельно
+    let record_label = if rid.is_empty() {
+        format!("{:?}", track_type).to_lowercase()
+    } else {
+        format!("{:?}-{}", track_type, rid).to_lowercase()
+    };
+    let mut recording = room
+        .recorder
+        .as_ref()
+        .map(|recorder| recorder.track(&from_id, &record_label, &mime_type));
+    // Издатель ищется один раз: его состояние пересылки читается из атомиков
+    let source = room.get_peer(&from_id).await;
+    let activity = source.as_ref().map(|peer| peer.activity.clone());
+    let mut flood_detector = room.config.flood_mute_max_pps.map(|max_pps| {
+        FloodDetector::new(
+            max_pps,
+            Duration::from_secs(room.config.flood_mute_sustain_secs),
+        )
+    });
+    let mut prev_marker = true;
+    // Пакет относится к ключевому кадру (учитывается при отбрасывании в очереди)
+    let mut in_keyframe = false;
+    let mut packet_count = 0u64;
+    let mut forwarded_count = 0u64;
+    let mut reorder = room.config.reorder_buffer_depth.map(|depth| {
+        ReorderBuffer::new(depth, Duration::from_millis(room.config.reorder_max_hold_ms))
+    });
+    let mut released = VecDeque::new();
+    // Трек закончился: пересылаются только пакеты, оставшиеся в буфере
+    let mut track_ended = false;
+    // Наибольший полученный sequence number: пакеты с меньшими номерами пришли не по порядку
+    let mut highest_seq: Option<u16> = None;
+
+    let rewriters = (!rid.is_empty()).then(|| room.track_rewriters(&from_id, &track_id));
+
+    if !rid.is_empty() {
+        room.update_simulcast_layer(&from_id, &rid, true);
+    }
+
+    info!(
+        "Starting relay for {:?} track from peer {} in room {} (track_id: {}, kind: {:?})",
+        track_type, from_id, room_id, track.id(), track.kind()
+    );
+
+    loop {
+        let rtp_packet = match released.pop_front() {
+            Some(packet) => packet,
+            None if track_ended => break,
+            None => {
+                // Читаем RTP пакет из входящего трека; пока буфер переупорядочивания
+                // держит пакеты, чтение ждет не дольше их срока
+                let deadline = reorder.as_ref().and_then(ReorderBuffer::deadline);
+                let read = match deadline {
+                    Some(deadline) => {
+                        let deadline = tokio::time::Instant::from_std(deadline);
+                        match tokio::time::timeout_at(deadline, track.read(&mut buf)).await {
+                            Ok(read) => read,
+                            Err(_) => {
+                                if let Some(reorder) = reorder.as_mut() {
+                                    released.extend(reorder.expire(Instant::now()));
+                                }
+                                continue;
+                            }
+                        }
+                    }
+                    None => track.read(&mut buf).await,
+                };
+                let (rtp_packet, _attributes) = match read {
+                    Ok(result) => result,
+                    Err(e) => {
+                        warn!(
+                            "Error reading from track {} in room {}: {}",
+                            track.id(),
+                            room_id,
+                            e
+                        );
+                        track_ended = true;
+                        if let Some(reorder) = reorder.as_mut() {
+                            released.extend(reorder.flush());
+                        }
+                        continue;
+                    }
+                };
+
+                packet_count += 1;
+                if let Some(activity) = &activity {
+                    activity.touch();
+                }
+                let packet_size = rtp_packet.marshal_size() as u64;
+                room.bytes_received.fetch_add(packet_size, Ordering::Relaxed);
+                room.metrics.increment_packets_received(1);
+                room.metrics.increment_bytes_received(packet_size);
+
+                let seq = rtp_packet.header.sequence_number;
+                match highest_seq {
+                    Some(highest) if (seq.wrapping_sub(highest) as i16) < 0 => {
+                        room.metrics.increment_packets_out_of_order();
+                    }
+                    _ => highest_seq = Some(seq),
+                }
+
+                // Логируем первый пакет и каждые 500 пакетов для отладки
+                if packet_count == 1 || packet_count.is_multiple_of(500) {
+                    info!(
+                        "Relay {:?}: received {} packets, forwarded {} from peer {} in room {}",
+                        track_type, packet_count, forwarded_count, from_id, room_id
+                    );
+                }
+
+                // С буфером переупорядочивания пересылаются выданные им пакеты
+                match reorder.as_mut() {
+                    Some(reorder) => {
+                        released.extend(reorder.push(rtp_packet, Instant::now()));
+                        continue;
+                    }
+                    None => rtp_packet,
+                }
+            }
+        };
+        let received_at = Instant::now();
+        let packet_size = rtp_packet.marshal_size() as u64;
+
+        let frame = FrameInfo::detect(&rtp_packet, frame_marking_id, prev_marker, &mime_type);
+        prev_marker = rtp_packet.header.marker;
+        if frame.start_of_frame {
+            in_keyframe = frame.keyframe == Some(true);
+        } else if frame.keyframe == Some(true) {
+            in_keyframe = true;
+        }
+
+        // Защита от флуда: трек с чрезмерной частотой пакетов отключается до решения ведущего
+        let mut server_muted = room.is_track_server_muted(&from_id, &track_id);
+        if let Some(detector) = flood_detector.as_mut() {
+            if detector.record(Instant::now()) && !server_muted {
+                room.server_mute_track(&from_id, &track_id, "excessive packet rate")
+                    .await;
+                server_muted = true;
+            }
+        }
+        if server_muted {
+            continue;
+        }
+
+        // Выключенные микрофон и камера соблюдаются на сервере, а звук, отключенный
+        // ведущим, не пересылается независимо от клиента
+        if !source.as_ref().is_some_and(|peer| peer.forwards(track_type)) {
+            continue;
+        }
+
+        if let (Some(recorder), Some(recording)) = (&room.recorder, recording.as_mut()) {
+            recorder.record(recording, &rtp_packet);
+        }
+
+        if let (Some(speakers), Some(ext_id)) = (&room.speakers, audio_level_id) {
+            if let Some(level) = AudioLevel::from_packet(&rtp_packet, ext_id) {
+                speakers.lock().unwrap().record(&from_id, level, Instant::now());
+            }
+        }
+
+        // До набора минимального числа участников медиа не пересылается
+        if !room.is_forwarding() {
+            continue;
+        }
+
+        // Фоновый шум ниже порога комнаты не пересылается
+        if let (Some(gate), Some(ext_id)) = (noise_gate.as_mut(), audio_level_id) {
+            if !gate.allows(AudioLevel::from_packet(&rtp_packet, ext_id), Instant::now()) {
+                continue;
+            }
+        }
+
+        // Пакет тишины не нужен получателям, отказавшимся от DTX; начало речи
+        // (маркер или флаг голосовой активности) к тишине не относится
+        let dtx = detect_dtx
+            && is_dtx_frame(
+                &rtp_packet,
+                audio_level_id.and_then(|ext_id| AudioLevel::from_packet(&rtp_packet, ext_id)),
+            );
+
+        // Получаем список участников для пересылки
+        let peers_guard = peers.read().await;
+        let peer_count = peers_guard.len();
+
+        for (peer_id, peer) in peers_guard.iter() {
+            // Не отправляем трек обратно отправителю
+            if peer_id == &from_id {
+                continue;
+            }
+
+            // Не отправляем участнику его собственное медиа, даже под чужим id
+            if room.is_own_source(peer_id, rtp_packet.header.ssrc, &stream_id) {
+                continue;
+            }
+
+            if track_type == TrackType::Audio && !room.audio_allowed(peer_id, &from_id) {
+                continue;
+            }
+
+            if dtx && peer.skips_silence() {
+                continue;
+            }
+
+            if !room.is_subscribed(peer_id, &from_id, track_type)
+                || room.is_track_paused(peer_id, &from_id, track_type)
+            {
+                continue;
+            }
+
+            if !rid.is_empty() {
+                // При смене перегрузки получателя новый слой нужен ему сразу, а не
+                // со следующего ключевого кадра. Запросы уходят издателям через канал
+                // получателя, чтобы не ждать RTCP на пути пакета под блокировкой peers
+                if room.update_congestion(peer_id, peer.is_congested()) {
+                    for source in room.simulcast_sources() {
+                        peer.request_keyframe_from(&source);
+                    }
+                }
+                if !room.layer_allows(peer_id, &from_id, &rid, &frame) {
+                    continue;
+                }
+            }
+
+            // Ищем слот (локальный трек), закрепленный за этим источником
+            match peer
+                .local_track_for(&from_id, &track_id, track_type, track.kind(), &mime_type)
+                .await
+            {
+                // Получатель не согласовал кодек источника (предупреждение уже в логе)
+                Err(_) => continue,
+                Ok(Some(local_track)) => {
+                    // Лимит скорости получателя: сверх него видео отбрасывается
+                    if !peer.allow_send(packet_size, track.kind(), in_keyframe) {
+                        continue;
+                    }
+                    // Слои simulcast приходят с разными номерами: получатель видит
+                    // один поток с непрерывными sequence number и timestamp
+                    let mut packet = rtp_packet.clone();
+                    if let Some(rewriters) = &rewriters {
+                        rewriters.rewrite(peer_id, &mut packet, clock_rate, Instant::now());
+                    }
+                    // Ставим RTP пакет в очередь отправки получателя
+                    peer.outbound.push(OutboundPacket {
+                        track: local_track,
+                        packet,
+                        kind: track.kind(),
+                        keyframe: in_keyframe,
+                        received_at,
+                    });
+                    forwarded_count += 1;
+                    room.metrics.increment_packets_sent(1);
+                    room.metrics.increment_bytes_sent(packet_size);
+                }
+                Ok(None) => {
+                    // Свободного слота нет: добавляем новый и согласуем его offer сервера
+                    if peer.can_add_slot(track.kind()).await {
+                        let peer = peer.clone();
+                        tokio::spawn(
+                            async move {
+                                if let Err(e) = peer.add_slot(track_type).await {
+                                    warn!("Failed to add slot for peer {}: {}", peer.id, e);
+                                }
+                            }
+                            .in_current_span(),
+                        );
+                    } else if packet_count == 1 {
+                        warn!(
+                            "No free local track slot for peer {} to receive {:?} from {}",
+                            peer_id, track_type, from_id
+                        );
+                    }
+                }
+            }
+        }
+
+        // Предупреждаем если нет получателей
+        if packet_count == 1 && peer_count <= 1 {
+            info!(
+                "Only {} peer(s) in room, no one to forward {:?} to",
+                peer_count, track_type
+            );
+        }
+    }
+
+    if !rid.is_empty() {
+        room.update_simulcast_layer(&from_id, &rid, false);
+    }
+
+    info!(
+        "Track relay stopped for {:?} from peer {} in room {} (received: {}, forwarded: {})",
+        track_type, from_id, room_id, packet_count, forwarded_count
+    );
+    if let Some(reorder) = reorder.filter(|reorder| reorder.late_packets() > 0) {
+        debug!(
+            "Reorder buffer dropped {} late packets of {:?} from peer {}",
+            reorder.late_packets(),
+            track_type,
+            from_id
+        );
+    }
+
+    Ok(())
+}
+
+/// Менеджер комнат
+pub struct RoomManager {
+    rooms: Arc<RwLock<HashMap<String, Arc<Room>>>>,
+    room_defaults: RoomConfig,
+    /// Каталог с настройками отдельных комнат
+    config_dir: Option<PathBuf>,
+    /// Общие счетчики, передаваемые каждой комнате
+    metrics: MetricsCounter,
+    /// Куда записывать сводку комнаты при закрытии
+    archive_dir: Option<PathBuf>,
+    /// Каталог записей комнат
+    recording_dir: Option<PathBuf>,
+    /// Максимальное количество участников в каждой комнате
+    max_participants: usize,
+    /// Через сколько без активности участник отключается; `None` - не отключать
+    idle_timeout: Option<Duration>,
+}
+
+impl RoomManager {
+    pub fn new() -> Self {
+        Self::with_room_defaults(RoomConfig::default())
+    }
+
+    /// Создает менеджер, применяющий `room_defaults` ко всем новым комнатам
+    pub fn with_room_defaults(room_defaults: RoomConfig) -> Self {
+        Self {
+            rooms: Arc::new(RwLock::new(HashMap::new())),
+            room_defaults,
+            config_dir: None,
+            metrics: MetricsCounter::new(),
+            archive_dir: None,
+            recording_dir: None,
+            max_participants: usize::MAX,
+            idle_timeout: None,
+        }
+    }
+
+    /// Ограничивает число участников в каждой комнате
+    pub fn with_max_participants(mut self, max_participants: usize) -> Self {
+        self.max_participants = max_participants;
+        self
+    }
+
+    /// Счетчики, в которые комнаты записывают принятые и пересланные пакеты
+    pub fn with_metrics(mut self, metrics: MetricsCounter) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Отключать участников, неактивных дольше `timeout`
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Загружать настройки комнаты из `dir/<id>.toml` или `dir/<id>.json` при ее создании
+    pub fn with_room_config_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config_dir = Some(dir.into());
+        self
+    }
+
+    /// Настройки новой комнаты: `room_defaults` с переопределениями из файла комнаты.
+    /// Файл читается один раз при создании комнаты
+    async fn load_room_config(&self, room_id: &str) -> RoomConfig {
+        let Some(dir) = &self.config_dir else {
+            return self.room_defaults.clone();
+        };
+        // id комнаты задает клиент: в путь попадают только безопасные id
+        if room_id.is_empty()
+            || !room_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return self.room_defaults.clone();
+        }
+
+        for (extension, json) in [("toml", false), ("json", true)] {
+            let path = dir.join(format!("{}.{}", room_id, extension));
+            let Ok(content) = tokio::fs::read_to_string(&path).await else {
+                continue;
+            };
+            match self.room_defaults.with_overrides(&content, json) {
+                Ok(config) => {
+                    info!("Loaded config of room {} from {}", room_id, path.display());
+                    return config;
+                }
+                Err(e) => {
+                    warn!("Ignoring room config {}: {:#}", path.display(), e);
+                    break;
+                }
+            }
+        }
+        self.room_defaults.clone()
+    }
+
+    /// Включает запись JSON сводки каждой закрываемой комнаты в `dir`
+    pub fn with_archive_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.archive_dir = Some(dir.into());
+        self
+    }
+
+    /// Записывать треки комнат в подкаталоги `dir`
+    pub fn with_recording_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.recording_dir = Some(dir.into());
+        self
+    }
+
+    /// Получает или создает комнату
+    pub async fn get_or_create_room(&self, room_id: String) -> Arc<Room> {
+        let rooms_guard = self.rooms.read().await;
+
+        if let Some(room) = rooms_guard.get(&room_id) {
+            return room.clone();
+        }
+
+        drop(rooms_guard);
+
+        // Создаем новую комнату; лимит из ее настроек заменяет общий лимит сервера
+        let config = self.load_room_config(&room_id).await;
+        let mut room = Room::with_config(room_id.clone(), config).with_metrics(self.metrics.clone());
+        if room.config.max_participants.is_none() {
+            room = room.with_max_participants(self.max_participants);
+        }
+        match &self.recording_dir {
+            Some(dir) => room = room.with_recording_dir(dir),
+            None if room.config.recording_enabled => {
+                warn!("Room {} has recording enabled but no recording directory is set", room_id)
+            }
+            None => {}
+        }
+
+        // Пока настройки загружались, комнату мог создать другой участник
+        let mut rooms = self.rooms.write().await;
+        if let Some(existing) = rooms.get(&room_id) {
+            return existing.clone();
+        }
+        let room = Arc::new(room);
+        rooms.insert(room_id, room.clone());
+        if let Some(timeout) = self.idle_timeout {
+            tokio::spawn(
+                reap_idle_peers(Arc::downgrade(&room), timeout).instrument(room.span.clone()),
+            );
+        }
+        room
+    }
+
+    /// Получает комнату по ID
+    pub async fn get_room(&self, room_id: &str) -> Option<Arc<Room>> {
+        self.rooms.read().await.get(room_id).cloned()
+    }
+
+    /// Удаляет комнату, если она пуста
+    pub async fn cleanup_empty_room(&self, room_id: &str) -> bool {
+        if let Some(room) = self.get_room(room_id).await {
+            if room.is_empty().await {
+                let removed = self.rooms.write().await.remove(room_id).is_some();
+                info!("Removed empty room: {}", room_id);
+
+                // Сводку пишет только тот, кто действительно удалил комнату
+                if let (true, Some(dir)) = (removed, &self.archive_dir) {
+                    match write_summary(dir, &room.summary()).await {
+                        Ok(path) => {
+                            info!("Wrote summary of room {} to {}", room_id, path.display())
+                        }
+                        Err(e) => warn!("Failed to archive room {}: {}", room_id, e),
+                    }
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Удаляет участника из комнаты (явный выход) и закрывает опустевшую комнату.
+    /// `false`, если такой комнаты или участника нет
+    pub async fn remove_participant(&self, room_id: &str, participant_id: &str) -> Result<bool> {
+        let Some(room) = self.get_room(room_id).await else {
+            return Ok(false);
+        };
+        let removed = room.remove_peer(participant_id).await?;
+        self.cleanup_empty_room(room_id).await;
+        Ok(removed)
+    }
+
+    /// Активные комнаты с id участников, упорядоченные по id комнаты
+    pub async fn list_rooms(&self) -> Vec<RoomListing> {
+        // Блокировка менеджера держится только на время копирования списка комнат
+        let rooms: Vec<Arc<Room>> = self.rooms.read().await.values().cloned().collect();
+
+        let mut listings = Vec::with_capacity(rooms.len());
+        for room in rooms {
+            let mut participants: Vec<String> =
+                room.peers.read().await.keys().cloned().collect();
+            participants.sort();
+            listings.push(RoomListing {
+                room_id: room.id.clone(),
+                participant_count: participants.len(),
+                participants,
+            });
+        }
+        listings.sort_by(|a, b| a.room_id.cmp(&b.room_id));
+        listings
+    }
+
+    /// Подписка на статистику комнаты раз в `every`. Поток завершается, когда
+    /// комната закрыта (в том числе если под тем же id создана новая) или
+    /// подписчик отпустил приемник; `None`, если комнаты нет
+    pub async fn watch_stats(
+        &self,
+        room_id: &str,
+        every: Duration,
+    ) -> Option<mpsc::Receiver<RoomStats>> {
+        let room = self.get_room(room_id).await?;
+        let rooms = self.rooms.clone();
+        let (tx, rx) = mpsc::channel(1);
+        let span = room.span.clone();
+
+        tokio::spawn(
+            async move {
+                let mut ticker = tokio::time::interval(every);
+                loop {
+                    ticker.tick().await;
+                    let current = rooms.read().await.get(&room.id).cloned();
+                    if !current.is_some_and(|current| Arc::ptr_eq(&current, &room)) {
+                        debug!("Room {} closed, ending stats stream", room.id);
+                        break;
+                    }
+                    if tx.send(room.room_stats().await).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            .instrument(span),
+        );
+
+        Some(rx)
+    }
+
+    /// Получает количество комнат
+    pub async fn room_count(&self) -> usize {
+        self.rooms.read().await.len()
+    }
+
+    /// Общее количество участников во всех комнатах
+    pub async fn participant_count(&self) -> usize {
+        let rooms: Vec<Arc<Room>> = self.rooms.read().await.values().cloned().collect();
+        let mut count = 0;
+        for room in rooms {
+            count += room.peer_count().await;
+        }
+        count
+    }
+
+    /// Получает общую статистику
+    #[allow(dead_code)]
+    pub async fn get_stats(&self) -> String {
+        let rooms_guard = self.rooms.read().await;
+        let room_count = rooms_guard.len();
+
+        let mut stats = format!("RoomManager - {} rooms:\n", room_count);
+
+        for (_, room) in rooms_guard.iter() {
+            stats.push_str(&room.get_stats().await);
+        }
+
+        stats
+    }
+
+    /// Останавливает все комнаты при завершении сервера: сообщает участникам об ошибке 503,
+    /// до `grace` ждет отправки уже поставленных в очередь пакетов, затем закрывает
+    /// соединения и удаляет комнаты. Возвращает, сколько участников отключено
+    pub async fn shutdown(&self, grace: Duration) -> usize {
+        let deadline = Instant::now() + grace;
+        let rooms: Vec<Arc<Room>> = self.rooms.read().await.values().cloned().collect();
+
+        let mut peers = Vec::new();
+        for room in &rooms {
+            for peer in room.get_all_peers().await {
+                let _ = peer.send_message(ServerMessage::error(
+                    ErrorCode::Unavailable,
+                    "server shutting down",
+                ));
+                peers.push((room.clone(), peer));
+            }
+        }
+
+        while Instant::now() < deadline && peers.iter().any(|(_, peer)| !peer.outbound.is_empty()) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        // Close всем сразу: уведомления о выходе остальных участников уже не нужны
+        for (_, peer) in &peers {
+            let _ = peer.ws_tx.send(Message::Close(None));
+        }
+        for (room, peer) in &peers {
+            if let Err(e) = room.remove_peer(&peer.id).await {
+                warn!("Error removing peer {} on shutdown: {}", peer.id, e);
+            }
+        }
+        self.cleanup_all_empty_rooms().await;
+        peers.len()
+    }
+
+    /// Очищает все пустые комнаты; возвращает, сколько комнат удалено
+    pub async fn cleanup_all_empty_rooms(&self) -> usize {
+        let room_ids: Vec<String> = self.rooms.read().await.keys().cloned().collect();
+
+        let mut removed = 0;
+        for room_id in room_ids {
+            if self.cleanup_empty_room(&room_id).await {
+                removed += 1;
+            }
+        }
+        removed
+    }
+}
+
+impl Default for RoomManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::PeerBuilder;
+    use crate::signaling::{ws_channel, DEFAULT_WS_SEND_QUEUE_SIZE};
+    use tokio::sync::mpsc;
+    use tokio_tungstenite::tungstenite::Message;
+
+    async fn test_peer(id: &str) -> (Arc<Peer>, mpsc::Receiver<Message>) {
+        let (tx, rx) = ws_channel(DEFAULT_WS_SEND_QUEUE_SIZE);
+        let peer = PeerBuilder::new(id.to_string(), id.to_uppercase(), tx)
+            .with_ice_servers(vec![])
+            .build()
+            .await
+            .unwrap();
+        (Arc::new(peer), rx)
+    }
+
+    fn received(rx: &mut mpsc::Receiver<Message>) -> Vec<ServerMessage> {
+        let mut messages = Vec::new();
+        while let Ok(msg) = rx.try_recv() {
+            messages.push(serde_json::from_str(msg.to_text().unwrap()).unwrap());
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn test_min_participants_gate() {
+        let config = RoomConfig {
+            min_participants_to_start: Some(3),
+            ..Default::default()
+        };
+        let room = Room::with_config("gate".to_string(), config);
+
+        let (a, mut rx_a) = test_peer("a").await;
+        let (b, _rx_b) = test_peer("b").await;
+        room.add_peer(a).await.unwrap();
+        room.add_peer(b).await.unwrap();
+        assert!(!room.is_forwarding());
+
+        let (c, _rx_c) = test_peer("c").await;
+        room.add_peer(c).await.unwrap();
+        assert!(room.is_forwarding());
+        assert!(received(&mut rx_a)
+            .iter()
+            .any(|m| matches!(m, ServerMessage::RoomStarted)));
+
+        // Без pause_below_min_participants пересылка не останавливается
+        room.remove_peer("c").await.unwrap();
+        assert!(room.is_forwarding());
+    }
+
+    #[tokio::test]
+    async fn test_self_echo_suppression() {
+        let room = Room::new("echo".to_string());
+        room.register_source("a", 1111, "stream-a");
+        room.register_source("b", 2222, "stream-b");
+
+        // Собственный SSRC, в т.ч. пришедший под другим id источника
+        assert!(room.is_own_source("a", 1111, ""));
+        // Тот же stream id после renegotiation с новым SSRC
+        assert!(room.is_own_source("a", 3333, "stream-a"));
+        // Чужие источники пересылаются
+        assert!(!room.is_own_source("a", 2222, "stream-b"));
+        assert!(!room.is_own_source("b", 1111, ""));
+
+        room.remove_peer("a").await.unwrap();
+        assert!(!room.is_own_source("a", 1111, "stream-a"));
+
+        let config = RoomConfig {
+            self_echo_suppression: false,
+            ..Default::default()
+        };
+        let room = Room::with_config("no-echo-check".to_string(), config);
+        room.register_source("a", 1111, "stream-a");
+        assert!(!room.is_own_source("a", 1111, "stream-a"));
+    }
+
+    #[test]
+    fn test_audio_subscription_groups() {
+        let config = RoomConfig {
+            audio_groups: HashMap::from([(
+                "panelists".to_string(),
+                vec!["p1".to_string(), "p2".to_string()],
+            )]),
+            default_audio_group: Some("audience".to_string()),
+            audio_subscriptions: HashMap::from([(
+                "audience".to_string(),
+                vec!["panelists".to_string()],
+            )]),
+            ..Default::default()
+        };
+        let room = Room::with_config("panel".to_string(), config);
+
+        // Зритель слышит панелистов, но не других зрителей
+        assert!(room.audio_allowed("a1", "p1"));
+        assert!(!room.audio_allowed("a1", "a2"));
+        // Панелисты без ограничений слышат всех
+        assert!(room.audio_allowed("p1", "p2"));
+        assert!(room.audio_allowed("p1", "a1"));
+    }
+
+    #[tokio::test]
+    async fn test_selective_subscription() {
+        let room = Room::new("tiles".to_string());
+        // Без подписки участник получает всех
+        assert!(room.is_subscribed("viewer", "a", TrackType::Camera));
+
+        room.subscribe("viewer", None, Some(vec!["a".to_string(), "b".to_string()]))
+            .await;
+        assert!(room.is_subscribed("viewer", "c", TrackType::Audio));
+        assert!(room.is_subscribed("viewer", "b", TrackType::Camera));
+        assert!(room.is_subscribed("viewer", "a", TrackType::Screen));
+        assert!(!room.is_subscribed("viewer", "c", TrackType::Camera));
+        // Подписки других получателей не меняются
+        assert!(room.is_subscribed("other", "c", TrackType::Camera));
+
+        room.subscribe("viewer", Some(vec![]), None).await;
+        assert!(!room.is_subscribed("viewer", "a", TrackType::Audio));
+        assert!(room.is_subscribed("viewer", "c", TrackType::Camera));
+
+        // Ушедший участник снова получает всех, если вернется под тем же id
+        room.remove_peer("viewer").await.unwrap();
+        assert!(room.is_subscribed("viewer", "a", TrackType::Audio));
+    }
+
+    #[tokio::test]
+    async fn test_track_pause_and_resume() {
+        let room = Room::new("pause".to_string());
+        let (alice, _rx_a) = test_peer("alice").await;
+        room.add_peer(alice).await.unwrap();
+
+        assert!(room.set_track_paused("bob", "bob", TrackType::Camera, true).await.is_err());
+        assert!(room.set_track_paused("bob", "carol", TrackType::Camera, true).await.is_err());
+
+        room.set_track_paused("bob", "alice", TrackType::Camera, true).await.unwrap();
+        assert!(room.is_track_paused("bob", "alice", TrackType::Camera));
+        // Остальные треки источника и подписка не затронуты
+        assert!(!room.is_track_paused("bob", "alice", TrackType::Audio));
+        assert!(room.is_subscribed("bob", "alice", TrackType::Camera));
+
+        room.set_track_paused("bob", "alice", TrackType::Camera, false).await.unwrap();
+        assert!(!room.is_track_paused("bob", "alice", TrackType::Camera));
+
+        // Пауза снимается, когда источник уходит
+        room.set_track_paused("bob", "alice", TrackType::Screen, true).await.unwrap();
+        room.remove_peer("alice").await.unwrap();
+        assert!(!room.is_track_paused("bob", "alice", TrackType::Screen));
+    }
+
+    #[tokio::test]
+    async fn test_bitrate_feedback_uses_min_subscriber_estimate() {
+        let config = RoomConfig {
+            bitrate_feedback: BitrateAggregation::Min,
+            ..Default::default()
+        };
+        let room = Room::with_config("remb".to_string(), config);
+
+        let (publisher, _rx_p) = test_peer("pub").await;
+        publisher.receive_estimate.store(100_000, Ordering::Relaxed);
+        room.add_peer(publisher).await.unwrap();
+        for (id, estimate) in [("s1", 2_500_000), ("s2", 600_000), ("s3", 1_200_000)] {
+            let (subscriber, _rx) = test_peer(id).await;
+            subscriber.receive_estimate.store(estimate, Ordering::Relaxed);
+            room.add_peer(subscriber).await.unwrap();
+        }
+
+        // Оценка самого издателя не учитывается
+        assert_eq!(room.publisher_bitrate_feedback("pub").await, Some(600_000));
     }
 
-    /// Добавляет участника в комнату
-    pub async fn add_peer(&self, peer: Arc<Peer>) -> Result<()> {
-        let peer_id = peer.id.clone();
-        let peer_name = peer.name.clone();
+    #[tokio::test]
+    async fn test_flood_mutes_track_until_host_restores() {
+        let start = Instant::now();
+        let mut detector = FloodDetector::new(100, Duration::from_secs(2));
+        // 50 пакетов/с - норма
+        let normal = (0..200).any(|i| detector.record(start + Duration::from_millis(i * 20)));
+        assert!(!normal);
 
-        // Уведомляем существующих участников о новом
-        let peers_guard = self.peers.read().await;
-        for (_, existing_peer) in peers_guard.iter() {
-            if let Err(e) = existing_peer.send_message(ServerMessage::ParticipantJoined {
-                id: peer_id.clone(),
-                name: peer_name.clone(),
-            }) {
-                warn!("Failed to notify peer {}: {}", existing_peer.id, e);
-            }
+        // 500 пакетов/с: отключение только после 2 секунд превышения
+        let flood_start = start + Duration::from_secs(4);
+        let muted_at = (0..5000u64)
+            .map(|i| flood_start + Duration::from_millis(i * 2))
+            .find(|now| detector.record(*now))
+            .unwrap();
+        assert!(muted_at.duration_since(flood_start) >= Duration::from_secs(2));
+
+        let room = Room::new("flood".to_string());
+        let (host, mut rx_host) = test_peer("host").await;
+        let (flooder, mut rx_flooder) = test_peer("flooder").await;
+        room.add_peer(host).await.unwrap();
+        room.add_peer(flooder).await.unwrap();
+        received(&mut rx_host);
+
+        room.server_mute_track("flooder", "video-1", "excessive packet rate").await;
+        assert!(room.is_track_server_muted("flooder", "video-1"));
+        for rx in [&mut rx_host, &mut rx_flooder] {
+            assert!(received(rx)
+                .iter()
+                .any(|m| matches!(m, ServerMessage::TrackServerMuted { .. })));
         }
-        drop(peers_guard);
 
-        // Добавляем нового участника
-        self.peers.write().await.insert(peer_id.clone(), peer);
-        info!("Peer {} joined room {}", peer_id, self.id);
+        // Вернуть трек может только ведущий
+        assert!(room.restore_track("flooder", "flooder", "video-1").await.is_err());
+        room.restore_track("host", "flooder", "video-1").await.unwrap();
+        assert!(!room.is_track_server_muted("flooder", "video-1"));
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn test_muted_source_not_forwarded() {
+        let room = Room::new("mute".to_string());
+        let (alice, _rx) = test_peer("alice").await;
+        room.add_peer(alice.clone()).await.unwrap();
+        assert!(room.source_enabled("alice", TrackType::Audio).await);
+
+        // Микрофон и камера выключены: остается только демонстрация экрана
+        alice.update_state(true, false, true).await;
+        assert!(!room.source_enabled("alice", TrackType::Audio).await);
+        assert!(!room.source_enabled("alice", TrackType::Camera).await);
+        assert!(room.source_enabled("alice", TrackType::Screen).await);
+
+        alice.update_state(false, true, false).await;
+        assert!(room.source_enabled("alice", TrackType::Audio).await);
+        assert!(room.source_enabled("alice", TrackType::Camera).await);
+        assert!(!room.source_enabled("unknown", TrackType::Screen).await);
     }
 
-    /// Удаляет участника из комнаты
-    pub async fn remove_peer(&self, peer_id: &str) -> Result<()> {
-        let mut peers_guard = self.peers.write().await;
+    #[tokio::test]
+    async fn test_spotlight_broadcast_and_late_join() {
+        let room = Room::new("spot".to_string());
+        let (host, _rx_host) = test_peer("host").await;
+        let (speaker, mut rx_speaker) = test_peer("speaker").await;
+        room.add_peer(host).await.unwrap();
+        room.add_peer(speaker).await.unwrap();
 
-        if let Some(peer) = peers_guard.remove(peer_id) {
-            info!("Removing peer {} from room {}", peer_id, self.id);
+        assert!(room
+            .set_spotlight("speaker", Some("speaker".to_string()))
+            .await
+            .is_err());
+        room.set_spotlight("host", Some("speaker".to_string()))
+            .await
+            .unwrap();
+        assert!(received(&mut rx_speaker).iter().any(|m| matches!(
+            m,
+            ServerMessage::SpotlightChanged { participant_id: Some(id) } if id == "speaker"
+        )));
 
-            // Закрываем соединение
-            if let Err(e) = peer.close().await {
-                warn!("Error closing peer connection: {}", e);
-            }
-        }
+        // Поздно подключившийся получает текущий выбор в Joined
+        let (late, _rx_late) = test_peer("late").await;
+        room.add_peer(late).await.unwrap();
+        assert_eq!(room.spotlight().as_deref(), Some("speaker"));
 
-        // Уведомляем остальных участников
-        let leave_msg = ServerMessage::ParticipantLeft {
-            participant_id: peer_id.to_string(),
-        };
+        room.remove_peer("speaker").await.unwrap();
+        assert_eq!(room.spotlight(), None);
+    }
 
-        for (_, other_peer) in peers_guard.iter() {
-            if let Err(e) = other_peer.send_message(leave_msg.clone()) {
-                warn!("Failed to notify peer {}: {}", other_peer.id, e);
-            }
-        }
+    #[tokio::test]
+    async fn test_single_screen_share_per_peer() {
+        let room = Room::new("screen".to_string());
+        let (alice, _rx_alice) = test_peer("alice").await;
+        let (bob, mut rx_bob) = test_peer("bob").await;
+        room.add_peer(alice.clone()).await.unwrap();
+        room.add_peer(bob.clone()).await.unwrap();
 
-        Ok(())
+        assert!(room.stop_screen_share(&alice).await.is_err());
+        room.start_screen_share(&alice).await.unwrap();
+        assert!(*alice.screen_sharing.read().await);
+        assert!(room.start_screen_share(&alice).await.is_err());
+        assert!(matches!(
+            received(&mut rx_bob).as_slice(),
+            [ServerMessage::ScreenShareStarted { participant_id }] if participant_id == "alice"
+        ));
+
+        room.stop_screen_share(&alice).await.unwrap();
+        assert!(!*alice.screen_sharing.read().await);
+        assert!(matches!(
+            received(&mut rx_bob).as_slice(),
+            [ServerMessage::ScreenShareStopped { .. }]
+        ));
+        // После остановки можно начать новую демонстрацию
+        room.start_screen_share(&alice).await.unwrap();
     }
 
-    /// Получает участника по ID
-    pub async fn get_peer(&self, peer_id: &str) -> Option<Arc<Peer>> {
-        self.peers.read().await.get(peer_id).cloned()
+    #[tokio::test]
+    async fn test_idle_peers_closed_after_timeout() {
+        let room = Room::new("idle".to_string());
+        let (quiet, mut rx_quiet) = test_peer("quiet").await;
+        let (active, mut rx_active) = test_peer("active").await;
+        room.add_peer(quiet.clone()).await.unwrap();
+        room.add_peer(active.clone()).await.unwrap();
+        received(&mut rx_quiet);
+
+        let now = Instant::now() + Duration::from_secs(60);
+        active.activity.touch_at(now - Duration::from_secs(10));
+        let closed = room.close_idle_peers(Duration::from_secs(30), now).await.unwrap();
+        assert_eq!(closed, vec!["quiet".to_string()]);
+        assert!(room.get_peer("quiet").await.is_none());
+        assert!(room.get_peer("active").await.is_some());
+        assert!(matches!(
+            rx_quiet.try_recv().unwrap().to_text().map(serde_json::from_str::<ServerMessage>),
+            Ok(Ok(ServerMessage::Error { code: Some(408), .. }))
+        ));
+        assert!(matches!(rx_quiet.try_recv(), Ok(Message::Close(_))));
+        assert!(received(&mut rx_active)
+            .iter()
+            .any(|m| matches!(m, ServerMessage::ParticipantLeft { .. })));
     }
 
-    /// Получает всех участников
-    pub async fn get_all_peers(&self) -> Vec<Arc<Peer>> {
-        self.peers.read().await.values().cloned().collect()
+    #[tokio::test]
+    async fn test_cleanup_reclaims_only_empty_rooms() {
+        let manager = RoomManager::new();
+        manager.get_or_create_room("empty-1".to_string()).await;
+        manager.get_or_create_room("empty-2".to_string()).await;
+        let busy = manager.get_or_create_room("busy".to_string()).await;
+        let (a, _rx_a) = test_peer("a").await;
+        busy.add_peer(a).await.unwrap();
+
+        assert_eq!(manager.cleanup_all_empty_rooms().await, 2);
+        assert!(manager.get_room("busy").await.is_some());
+        assert_eq!(manager.cleanup_all_empty_rooms().await, 0);
     }
 
-    /// Возвращает количество участников
-    pub async fn peer_count(&self) -> usize {
-        self.peers.read().await.len()
+    #[tokio::test]
+    async fn test_resumed_session_keeps_identity() {
+        let room = Room::new("resume".to_string());
+        let (stale, mut rx_stale) = test_peer("alice").await;
+        let (bob, mut rx_bob) = test_peer("bob").await;
+        room.add_peer(stale.clone()).await.unwrap();
+        room.add_peer(bob).await.unwrap();
+        stale.update_state(true, false, false).await;
+        received(&mut rx_stale);
+        received(&mut rx_bob);
+
+        let (fresh, _rx_fresh) = test_peer("alice").await;
+        assert!(room.resume_peer(fresh.clone()).await);
+        assert!(Arc::ptr_eq(&room.get_peer("alice").await.unwrap(), &fresh));
+        assert_eq!(room.peer_count().await, 2);
+        assert_eq!(fresh.get_state().await, (true, false, false));
+
+        // Остальные видят переподключение, а не выход и вход
+        let messages = received(&mut rx_bob);
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(
+            &messages[0],
+            ServerMessage::ParticipantReconnected { participant_id } if participant_id == "alice"
+        ));
+        // Вытесненное соединение узнает, что сессию забрали
+        assert!(matches!(
+            rx_stale.try_recv().unwrap().to_text().map(serde_json::from_str::<ServerMessage>),
+            Ok(Ok(ServerMessage::Error { code: Some(409), .. }))
+        ));
+        assert!(matches!(rx_stale.try_recv(), Ok(Message::Close(_))));
+
+        let (unknown, _rx_unknown) = test_peer("carol").await;
+        assert!(!room.resume_peer(unknown).await);
     }
 
-    /// Проверяет, пуста ли комната
-    pub async fn is_empty(&self) -> bool {
-        self.peers.read().await.is_empty()
+    #[tokio::test]
+    async fn test_shutdown_closes_all_peers() {
+        let manager = RoomManager::new();
+        let room = manager.get_or_create_room("call".to_string()).await;
+        let (a, mut rx_a) = test_peer("a").await;
+        let (b, _rx_b) = test_peer("b").await;
+        room.add_peer(a).await.unwrap();
+        room.add_peer(b).await.unwrap();
+        received(&mut rx_a);
+
+        assert_eq!(manager.shutdown(Duration::from_secs(1)).await, 2);
+        assert!(matches!(
+            rx_a.try_recv().unwrap().to_text().map(serde_json::from_str::<ServerMessage>),
+            Ok(Ok(ServerMessage::Error { code: Some(503), .. }))
+        ));
+        assert!(matches!(rx_a.try_recv(), Ok(Message::Close(_))));
+        assert_eq!(manager.room_count().await, 0);
     }
 
-    /// Транслирует сообщение всем участникам кроме отправителя
-    pub async fn broadcast_message(&self, from_id: &str, msg: ServerMessage) {
-        let peers_guard = self.peers.read().await;
+    #[tokio::test]
+    async fn test_room_summary_written_on_close() {
+        let dir = std::env::temp_dir().join(format!("rrtc-archive-{}", std::process::id()));
+        let manager = RoomManager::new().with_archive_dir(&dir);
+        let room = manager.get_or_create_room("call/1".to_string()).await;
 
-        for (id, peer) in peers_guard.iter() {
-            if id != from_id {
-                if let Err(e) = peer.send_message(msg.clone()) {
-                    warn!("Failed to broadcast to peer {}: {}", id, e);
-                }
-            }
+        let (a, _rx_a) = test_peer("a").await;
+        let (b, _rx_b) = test_peer("b").await;
+        room.add_peer(a).await.unwrap();
+        room.add_peer(b).await.unwrap();
+        room.remove_peer("a").await.unwrap();
+        room.remove_peer("b").await.unwrap();
+        assert!(manager.cleanup_empty_room("call/1").await);
+
+        let path = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.file_name().unwrap().to_string_lossy().starts_with("call_1-"))
+            .unwrap();
+        let summary: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(summary["room_id"], "call/1");
+        assert_eq!(summary["peak_participants"], 2);
+        assert_eq!(summary["participants"].as_array().unwrap().len(), 2);
+        assert!(summary["participants"][0]["left_at_ms"].is_u64());
+        for field in ["created_at_ms", "closed_at_ms", "total_bytes", "recording_files"] {
+            assert!(summary.get(field).is_some(), "missing {}", field);
         }
     }
 
-    /// Транслирует сообщение всем участникам включая отправителя
-    pub async fn broadcast_message_to_all(&self, msg: ServerMessage) {
-        let peers_guard = self.peers.read().await;
+    #[tokio::test]
+    async fn test_add_peer_rejects_when_full() {
+        let manager = RoomManager::new().with_max_participants(2);
+        let room = manager.get_or_create_room("full".to_string()).await;
+        assert_eq!(room.max_participants, 2);
 
-        for (_, peer) in peers_guard.iter() {
-            if let Err(e) = peer.send_message(msg.clone()) {
-                warn!("Failed to broadcast to peer {}: {}", peer.id, e);
-            }
-        }
+        let (alice, _alice_rx) = test_peer("alice").await;
+        let (bob, _bob_rx) = test_peer("bob").await;
+        let (carol, _carol_rx) = test_peer("carol").await;
+        room.add_peer(alice).await.unwrap();
+        room.add_peer(bob).await.unwrap();
+
+        let err = room.add_peer(carol).await.unwrap_err();
+        assert!(err.downcast_ref::<RoomFull>().is_some());
+        assert_eq!(room.peer_count().await, 2);
+        assert!(room.get_peer("carol").await.is_none());
     }
 
-    /// Обрабатывает входящий трек от участника и маршрутизирует его другим
-    pub async fn handle_incoming_track(
-        &self,
-        from_peer_id: String,
-        track: Arc<TrackRemote>,
-    ) -> Result<()> {
-        let track_type = TrackType::from_track(&track.id(), track.kind());
+    #[tokio::test]
+    async fn test_participant_left_broadcast_only_for_members() {
+        let room = Room::new("leave".to_string());
+        let (alice, mut alice_rx) = test_peer("alice").await;
+        let (bob, _bob_rx) = test_peer("bob").await;
+        room.add_peer(alice).await.unwrap();
+        room.add_peer(bob).await.unwrap();
+        received(&mut alice_rx);
 
-        info!(
-            "Room {}: Handling incoming {:?} track from peer {} (id: {}, kind: {:?})",
-            self.id,
-            track_type,
-            from_peer_id,
-            track.id(),
-            track.kind()
+        // Участник, не вошедший в комнату, не порождает уведомления
+        room.remove_peer("ghost").await.unwrap();
+        assert!(alice_rx.try_recv().is_err());
+
+        room.remove_peer("bob").await.unwrap();
+        let msg = alice_rx.try_recv().unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(msg.to_text().unwrap()).unwrap(),
+            serde_json::json!({"type": "participant_left", "participant_id": "bob"})
         );
 
-        // Запускаем задачу для чтения и пересылки RTP пакетов
-        let room_id = self.id.clone();
-        let peers = self.peers.clone();
-        let from_id = from_peer_id.clone();
+        // Повторное удаление не дублирует уведомление
+        room.remove_peer("bob").await.unwrap();
+        assert!(alice_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_chat_broadcast_excludes_sender() {
+        let room = Room::new("chat".to_string());
+        let (alice, mut alice_rx) = test_peer("alice").await;
+        let (bob, mut bob_rx) = test_peer("bob").await;
+        room.add_peer(alice.clone()).await.unwrap();
+        room.add_peer(bob).await.unwrap();
+        received(&mut alice_rx);
+        received(&mut bob_rx);
 
-        tokio::spawn(async move {
-            if let Err(e) = relay_track(room_id, peers, from_id, track, track_type).await {
-                error!("Error relaying track: {}", e);
+        room.send_chat(&alice, "hello".to_string()).await.unwrap();
+        assert!(alice_rx.try_recv().is_err());
+        match received(&mut bob_rx).as_slice() {
+            [ServerMessage::Chat {
+                from_id,
+                from_name,
+                text,
+                ts,
+            }] => {
+                assert_eq!(from_id, "alice");
+                assert_eq!(from_name, "ALICE");
+                assert_eq!(text, "hello");
+                assert!(*ts > 0);
             }
-        });
+            other => panic!("unexpected messages: {:?}", other),
+        }
 
-        Ok(())
+        let too_long = "x".repeat(MAX_CHAT_TEXT_BYTES + 1);
+        assert!(room.send_chat(&alice, too_long).await.is_err());
+        assert!(bob_rx.try_recv().is_err());
     }
 
-    /// Получает статистику комнаты
-    pub async fn get_stats(&self) -> String {
-        let peers_guard = self.peers.read().await;
-        let peer_count = peers_guard.len();
-
-        let mut stats = format!("Room {} - {} peers:\n", self.id, peer_count);
+    #[tokio::test]
+    async fn test_rename_broadcast_and_roster() {
+        let room = Room::new("rename".to_string());
+        let (alice, mut alice_rx) = test_peer("alice").await;
+        let (bob, mut bob_rx) = test_peer("bob").await;
+        room.add_peer(alice.clone()).await.unwrap();
+        room.add_peer(bob).await.unwrap();
+        received(&mut alice_rx);
+        received(&mut bob_rx);
 
-        for (_, peer) in peers_guard.iter() {
-            let peer_stats = peer.get_stats().await;
-            stats.push_str(&format!("  {}\n", peer_stats));
+        room.rename(&alice, "  Alice\u{7}\nSmith ").await.unwrap();
+        for rx in [&mut alice_rx, &mut bob_rx] {
+            assert!(matches!(
+                received(rx).as_slice(),
+                [ServerMessage::ParticipantRenamed { participant_id, name }]
+                    if participant_id == "alice" && name == "AliceSmith"
+            ));
         }
+        let roster = room.participants_info("bob").await;
+        assert_eq!(roster[0].name, "AliceSmith");
 
-        stats
+        assert!(room.rename(&alice, " \t").await.is_err());
+        assert!(room.rename(&alice, &"x".repeat(MAX_NAME_CHARS + 1)).await.is_err());
+        assert_eq!(alice.name(), "AliceSmith");
+        assert!(received(&mut bob_rx).is_empty());
     }
-}
 
-/// Пересылает RTP пакеты от одного участника всем остальным
-async fn relay_track(
-    room_id: String,
-    peers: Arc<RwLock<HashMap<String, Arc<Peer>>>>,
-    from_id: String,
-    track: Arc<TrackRemote>,
-    track_type: TrackType,
-) -> Result<()> {
-    let mut buf = vec![0u8; 1500];
-    let mut packet_count = 0u64;
-    let mut forwarded_count = 0u64;
+    #[tokio::test]
+    async fn test_broadcast_removes_disconnected_peer() {
+        let room = Room::new("dead".to_string());
+        let (alice, mut alice_rx) = test_peer("alice").await;
+        let (bob, bob_rx) = test_peer("bob").await;
+        let (carol, mut carol_rx) = test_peer("carol").await;
+        room.add_peer(alice).await.unwrap();
+        room.add_peer(bob).await.unwrap();
+        room.add_peer(carol.clone()).await.unwrap();
+        received(&mut alice_rx);
+        received(&mut carol_rx);
 
-    info!(
-        "Starting relay for {:?} track from peer {} in room {} (track_id: {}, kind: {:?})",
-        track_type, from_id, room_id, track.id(), track.kind()
-    );
+        // WebSocket Боба закрыт: первая же рассылка удаляет его из комнаты
+        drop(bob_rx);
+        room.rename(&carol, "Carol").await.unwrap();
+        assert!(room.get_peer("bob").await.is_none());
+        assert_eq!(room.peer_count().await, 2);
+        for rx in [&mut alice_rx, &mut carol_rx] {
+            assert!(matches!(
+                received(rx).as_slice(),
+                [
+                    ServerMessage::ParticipantRenamed { .. },
+                    ServerMessage::ParticipantLeft { participant_id },
+                ] if participant_id == "bob"
+            ));
+        }
+    }
 
-    loop {
-        // Читаем RTP пакет из входящего трека
-        let (rtp_packet, _attributes) = match track.read(&mut buf).await {
-            Ok(result) => result,
-            Err(e) => {
-                warn!(
-                    "Error reading from track {} in room {}: {}",
-                    track.id(),
-                    room_id,
-                    e
-                );
-                break;
-            }
-        };
+    #[tokio::test]
+    async fn test_reaction_validation_and_rate_limit() {
+        for emoji in ["👍", "❤️", "😂", "👩‍💻", "🇺🇦"] {
+            assert!(is_valid_reaction(emoji), "{} should be accepted", emoji);
+        }
+        for emoji in ["", "ok", "👍 ", "<b>", "👍👍👍👍👍"] {
+            assert!(!is_valid_reaction(emoji), "{:?} should be rejected", emoji);
+        }
 
-        packet_count += 1;
+        let room = Room::new("reactions".to_string());
+        let (alice, _alice_rx) = test_peer("alice").await;
+        let (bob, mut bob_rx) = test_peer("bob").await;
+        room.add_peer(alice.clone()).await.unwrap();
+        room.add_peer(bob).await.unwrap();
+        received(&mut bob_rx);
 
-        // Логируем первый пакет и каждые 500 пакетов для отладки
-        if packet_count == 1 || packet_count % 500 == 0 {
-            info!(
-                "Relay {:?}: received {} packets, forwarded {} from peer {} in room {}",
-                track_type, packet_count, forwarded_count, from_id, room_id
-            );
+        room.send_reaction(&alice, "👍".to_string()).await.unwrap();
+        assert!(matches!(
+            received(&mut bob_rx).as_slice(),
+            [ServerMessage::Reaction { participant_id, emoji, .. }]
+                if participant_id == "alice" && emoji == "👍"
+        ));
+        assert!(room.send_reaction(&alice, "spam".to_string()).await.is_err());
+
+        // Лимит хранится на Peer и действует между сообщениями
+        for _ in 0..crate::peer::REACTION_RATE_LIMIT {
+            assert!(alice.reaction_limiter.check_rate_limit().await);
         }
+        assert!(!alice.reaction_limiter.check_rate_limit().await);
+    }
 
-        // Получаем список участников для пересылки
-        let peers_guard = peers.read().await;
-        let peer_count = peers_guard.len();
+    #[tokio::test]
+    async fn test_kick_requires_host() {
+        let room = Room::new("kick".to_string());
+        let (alice, mut alice_rx) = test_peer("alice").await;
+        let (bob, mut bob_rx) = test_peer("bob").await;
+        let (carol, mut carol_rx) = test_peer("carol").await;
+        room.add_peer(alice).await.unwrap();
+        room.add_peer(bob).await.unwrap();
+        room.add_peer(carol).await.unwrap();
+        received(&mut alice_rx);
+        received(&mut bob_rx);
+        received(&mut carol_rx);
 
-        for (peer_id, peer) in peers_guard.iter() {
-            // Не отправляем трек обратно отправителю
-            if peer_id == &from_id {
-                continue;
-            }
+        // Не ведущий не может удалять участников
+        assert!(room.kick("bob", "carol").await.is_err());
+        assert_eq!(room.peer_count().await, 3);
+        assert!(carol_rx.try_recv().is_err());
 
-            // Ищем соответствующий локальный трек для отправки
-            let local_tracks = peer.local_tracks.read().await;
-            let local_tracks_count = local_tracks.len();
+        room.kick("alice", "carol").await.unwrap();
+        assert_eq!(room.peer_count().await, 2);
+        assert!(matches!(
+            carol_rx.try_recv().unwrap().to_text().map(serde_json::from_str::<ServerMessage>),
+            Ok(Ok(ServerMessage::Kicked { .. }))
+        ));
+        assert!(matches!(carol_rx.try_recv(), Ok(Message::Close(_))));
+        assert!(matches!(
+            received(&mut bob_rx).as_slice(),
+            [ServerMessage::ParticipantLeft { participant_id }] if participant_id == "carol"
+        ));
+    }
 
-            // Логируем при первом пакете
-            if packet_count == 1 {
-                info!(
-                    "Peer {} has {} local tracks, looking for {:?} kind {:?}",
-                    peer_id, local_tracks_count, track_type, track.kind()
-                );
-                for lt in local_tracks.iter() {
-                    info!(
-                        "  - Track type: {:?}, kind: {:?}, id: {}",
-                        lt.track_type, lt.track.kind(), lt.track.id()
-                    );
-                }
-            }
+    #[tokio::test]
+    async fn test_room_lock_requires_host() {
+        let room = Room::new("lock".to_string());
+        let (alice, mut alice_rx) = test_peer("alice").await;
+        let (bob, mut bob_rx) = test_peer("bob").await;
+        room.add_peer(alice).await.unwrap();
+        room.add_peer(bob).await.unwrap();
+        received(&mut alice_rx);
+        received(&mut bob_rx);
 
-            let mut found_track = false;
-            for local_track_info in local_tracks.iter() {
-                // Проверяем, совпадает ли тип трека и codec type
-                if local_track_info.track_type == track_type
-                    && local_track_info.track.kind() == track.kind()
-                {
-                    found_track = true;
-                    // Отправляем RTP пакет
-                    match local_track_info.track.write_rtp(&rtp_packet).await {
-                        Ok(_) => {
-                            forwarded_count += 1;
-                            if packet_count % 100 == 0 {
-                                debug!(
-                                    "Successfully wrote packet {} to peer {} track {:?}",
-                                    packet_count, peer_id, track_type
-                                );
-                            }
-                        }
-                        Err(e) => {
-                            let error_str = e.to_string();
-                            if error_str.contains("InvalidState") {
-                                // Соединение закрыто, это нормально
-                                debug!("Track closed for peer {}", peer_id);
-                            } else {
-                                // Логируем все остальные ошибки подробно
-                                error!(
-                                    "Error writing RTP packet {} to peer {} track {:?} (kind {:?}): {}",
-                                    packet_count, peer_id, track_type, track.kind(), error_str
-                                );
-                                // Логируем каждую 10-ю ошибку чтобы не спамить
-                                if packet_count % 10 == 0 {
-                                    error!("RTP write error details: peer_id={}, track_type={:?}, track_id={}, error={}",
-                                        peer_id, track_type, local_track_info.track.id(), error_str);
-                                }
-                            }
-                        }
-                    }
-                    break;
-                }
-            }
+        // Не ведущий не может закрыть комнату
+        assert!(room.set_locked("bob", true).await.is_err());
+        assert!(!room.is_locked());
+        assert!(received(&mut alice_rx).is_empty());
 
-            if !found_track && packet_count == 1 {
-                warn!(
-                    "No matching local track found for peer {} to receive {:?} from {}",
-                    peer_id, track_type, from_id
-                );
-            }
+        room.set_locked("alice", true).await.unwrap();
+        assert!(room.is_locked());
+        for rx in [&mut alice_rx, &mut bob_rx] {
+            assert!(matches!(
+                received(rx).as_slice(),
+                [ServerMessage::RoomLocked { locked: true }]
+            ));
         }
 
-        // Предупреждаем если нет получателей
-        if packet_count == 1 && peer_count <= 1 {
-            info!(
-                "Only {} peer(s) in room, no one to forward {:?} to",
-                peer_count, track_type
-            );
-        }
+        // Повторное закрытие ничего не рассылает; открыть может только ведущий
+        room.set_locked("alice", true).await.unwrap();
+        assert!(received(&mut bob_rx).is_empty());
+        assert!(room.set_locked("bob", false).await.is_err());
+        room.set_locked("alice", false).await.unwrap();
+        assert!(!room.is_locked());
+        assert!(matches!(
+            received(&mut bob_rx).as_slice(),
+            [ServerMessage::RoomLocked { locked: false }]
+        ));
     }
 
-    info!(
-        "Track relay stopped for {:?} from peer {} in room {} (received: {}, forwarded: {})",
-        track_type, from_id, room_id, packet_count, forwarded_count
-    );
+    #[tokio::test]
+    async fn test_locked_room_refuses_join_with_existing_id() {
+        let room = Room::new("lock".to_string());
+        let (alice, _alice_rx) = test_peer("alice").await;
+        room.add_peer(alice).await.unwrap();
+        room.set_locked("alice", true).await.unwrap();
 
-    Ok(())
-}
+        // Занятый id не дает пройти в закрытую комнату, токен переподключения дает
+        assert!(room.get_peer("alice").await.is_some());
+        assert!(!room.accepts_join(false));
+        assert!(room.accepts_join(true));
 
-/// Менеджер комнат
-pub struct RoomManager {
-    rooms: Arc<RwLock<HashMap<String, Arc<Room>>>>,
-}
+        room.set_locked("alice", false).await.unwrap();
+        assert!(room.accepts_join(false));
+    }
 
-impl RoomManager {
-    pub fn new() -> Self {
-        Self {
-            rooms: Arc::new(RwLock::new(HashMap::new())),
-        }
+    #[tokio::test]
+    async fn test_manual_admission_by_host() {
+        let room = Room::new("admission".to_string());
+        // В пустой комнате спросить некого: первый участник входит сразу
+        assert!(room.request_admission("alice", "Alice").await.is_none());
+
+        let (alice, mut alice_rx) = test_peer("alice").await;
+        let (bob, mut bob_rx) = test_peer("bob").await;
+        room.add_peer(alice).await.unwrap();
+        room.add_peer(bob).await.unwrap();
+        received(&mut alice_rx);
+        received(&mut bob_rx);
+
+        let carol = room.request_admission("carol", "Carol").await.unwrap();
+        let mut dave = room.request_admission("dave", "Dave").await.unwrap();
+        assert!(matches!(
+            received(&mut alice_rx).as_slice(),
+            [
+                ServerMessage::JoinRequest { participant_id: c, .. },
+                ServerMessage::JoinRequest { participant_id: d, .. },
+            ] if c == "carol" && d == "dave"
+        ));
+        assert!(received(&mut bob_rx).is_empty());
+        // Ожидающие участники не входят в комнату
+        assert_eq!(room.peer_count().await, 2);
+
+        assert!(room.admit("bob", "carol", true).is_err());
+        room.admit("alice", "carol", true).unwrap();
+        assert_eq!(carol.await, Ok(true));
+        assert!(room.admit("alice", "carol", true).is_err());
+
+        // Ведущий ушел: заявка dave переходит к новому ведущему
+        room.remove_peer("alice").await.unwrap();
+        assert!(received(&mut bob_rx).iter().any(|m| matches!(
+            m,
+            ServerMessage::JoinRequest { participant_id, name }
+                if participant_id == "dave" && name == "Dave"
+        )));
+        room.admit("bob", "dave", false).unwrap();
+        assert_eq!(dave.try_recv(), Ok(false));
+
+        // Участник перестал ждать: решение по нему больше не принимается
+        let eve = room.request_admission("eve", "Eve").await.unwrap();
+        drop(eve);
+        room.cancel_admission("eve");
+        assert!(room.admit("bob", "eve", true).is_err());
     }
 
-    /// Получает или создает комнату
-    pub async fn get_or_create_room(&self, room_id: String) -> Arc<Room> {
-        let rooms_guard = self.rooms.read().await;
+    #[tokio::test]
+    async fn test_event_log_is_bounded() {
+        let config = RoomConfig {
+            event_log_size: 4,
+            ..Default::default()
+        };
+        let room = Room::with_config("events".to_string(), config);
+        let (alice, _alice_rx) = test_peer("alice").await;
+        let (bob, _bob_rx) = test_peer("bob").await;
+        room.add_peer(alice.clone()).await.unwrap();
+        room.add_peer(bob).await.unwrap();
+        room.start_screen_share(&alice).await.unwrap();
+        room.kick("alice", "bob").await.unwrap();
 
-        if let Some(room) = rooms_guard.get(&room_id) {
-            return room.clone();
+        // Журнал ограничен: самое старое событие (вход alice) вытеснено
+        let events: Vec<_> = room
+            .event_log()
+            .into_iter()
+            .map(|event| (event.kind, event.participant_id))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (RoomEventKind::Joined, "bob".to_string()),
+                (RoomEventKind::ScreenShareStarted, "alice".to_string()),
+                (RoomEventKind::Kicked, "bob".to_string()),
+                (RoomEventKind::Left, "bob".to_string()),
+            ]
+        );
+        assert!(room.get_stats().await.contains("Kicked bob"));
+
+        // Журнал доступен только ведущему
+        assert!(room.event_log_for("alice").is_ok());
+        assert!(room.event_log_for("bob").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_set_layer_per_subscriber() {
+        let room = Room::new("simulcast".to_string());
+        for id in ["alice", "bob", "carol"] {
+            room.add_peer(test_peer(id).await.0).await.unwrap();
         }
+        for rid in ["f", "q", "h"] {
+            room.update_simulcast_layer("alice", rid, true);
+        }
+        let keyframe = FrameInfo {
+            start_of_frame: true,
+            end_of_frame: true,
+            keyframe: Some(true),
+        };
 
-        drop(rooms_guard);
+        // По умолчанию пересылается самый высокий слой
+        assert!(!room.layer_allows("bob", "alice", "q", &keyframe));
+        assert!(room.layer_allows("bob", "alice", "f", &keyframe));
 
-        // Создаем новую комнату
-        let room = Arc::new(Room::new(room_id.clone()));
-        self.rooms.write().await.insert(room_id, room.clone());
+        room.set_layer("carol", "alice", SimulcastLayer::Low).await.unwrap();
+        assert!(room.layer_allows("carol", "alice", "q", &keyframe));
+        assert!(!room.layer_allows("carol", "alice", "f", &keyframe));
 
-        room
+        // Выбор одного получателя не влияет на другого; переключение на границе кадра
+        room.set_layer("bob", "alice", SimulcastLayer::Mid).await.unwrap();
+        assert!(room.layer_allows("bob", "alice", "h", &keyframe));
+        assert!(room.layer_allows("carol", "alice", "q", &keyframe));
+
+        // Перегруженный по TWCC получатель переходит на нижний слой
+        assert!(room.update_congestion("bob", true));
+        assert!(!room.update_congestion("bob", true));
+        assert!(room.layer_allows("bob", "alice", "q", &keyframe));
+        assert!(!room.layer_allows("bob", "alice", "h", &keyframe));
+
+        assert!(room.set_layer("bob", "ghost", SimulcastLayer::Low).await.is_err());
+        assert!(room.set_layer("bob", "bob", SimulcastLayer::Low).await.is_err());
     }
 
-    /// Получает комнату по ID
-    pub async fn get_room(&self, room_id: &str) -> Option<Arc<Room>> {
-        self.rooms.read().await.get(room_id).cloned()
+    #[tokio::test]
+    async fn test_relay_data_caps_message_size() {
+        let room = Room::new("data".to_string());
+        let (alice, _alice_rx) = test_peer("alice").await;
+        let (bob, _bob_rx) = test_peer("bob").await;
+        room.add_peer(alice).await.unwrap();
+        room.add_peer(bob).await.unwrap();
+
+        // У Боба нет data channels: доставить некуда, но это не ошибка
+        let cursor = Bytes::from_static(br#"{"x":10,"y":20}"#);
+        assert_eq!(room.relay_data("alice", "cursors", cursor, true).await.unwrap(), 0);
+
+        let oversized = Bytes::from(vec![0u8; MAX_DATA_MESSAGE_BYTES + 1]);
+        assert!(room.relay_data("alice", "whiteboard", oversized, false).await.is_err());
     }
 
-    /// Удаляет комнату, если она пуста
-    pub async fn cleanup_empty_room(&self, room_id: &str) -> bool {
-        if let Some(room) = self.get_room(room_id).await {
-            if room.is_empty().await {
-                self.rooms.write().await.remove(room_id);
-                info!("Removed empty room: {}", room_id);
-                return true;
-            }
+    #[tokio::test]
+    async fn test_force_mute_requires_host() {
+        let room = Room::new("mute".to_string());
+        let (alice, mut alice_rx) = test_peer("alice").await;
+        let (bob, mut bob_rx) = test_peer("bob").await;
+        room.add_peer(alice).await.unwrap();
+        room.add_peer(bob.clone()).await.unwrap();
+        received(&mut alice_rx);
+        received(&mut bob_rx);
+
+        assert!(room.force_mute("bob", "alice", true).await.is_err());
+        assert!(!room.is_force_muted("alice"));
+
+        room.force_mute("alice", "bob", true).await.unwrap();
+        assert!(room.is_force_muted("bob"));
+        assert!(*bob.muted.read().await);
+        for rx in [&mut alice_rx, &mut bob_rx] {
+            assert!(matches!(
+                received(rx).as_slice(),
+                [ServerMessage::StateUpdate { participant_id, muted: true, .. }]
+                    if participant_id == "bob"
+            ));
         }
-        false
+
+        room.force_mute("alice", "bob", false).await.unwrap();
+        assert!(!room.is_force_muted("bob"));
     }
 
-    /// Получает количество комнат
-    pub async fn room_count(&self) -> usize {
-        self.rooms.read().await.len()
+    #[tokio::test]
+    async fn test_lifting_force_mute_keeps_own_mute() {
+        let room = Room::new("mute".to_string());
+        let (alice, mut alice_rx) = test_peer("alice").await;
+        let (bob, _bob_rx) = test_peer("bob").await;
+        room.add_peer(alice).await.unwrap();
+        room.add_peer(bob.clone()).await.unwrap();
+        bob.update_state(true, false, false).await;
+        received(&mut alice_rx);
+
+        room.force_mute("alice", "bob", true).await.unwrap();
+        room.force_mute("alice", "bob", false).await.unwrap();
+        assert!(*bob.muted.read().await);
+        assert!(!room.source_enabled("bob", TrackType::Audio).await);
+        assert!(matches!(
+            received(&mut alice_rx).as_slice(),
+            [
+                ServerMessage::StateUpdate { muted: true, .. },
+                ServerMessage::StateUpdate { muted: true, .. },
+            ]
+        ));
     }
 
-    /// Получает общую статистику
-    pub async fn get_stats(&self) -> String {
-        let rooms_guard = self.rooms.read().await;
-        let room_count = rooms_guard.len();
+    #[tokio::test]
+    async fn test_room_config_from_dir() {
+        let dir = std::env::temp_dir().join(format!("rrtc-rooms-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("webinar.toml"),
+            "max_participants = 50\nscreen_sharing_enabled = false\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("broken.json"), "{not json").unwrap();
 
-        let mut stats = format!("RoomManager - {} rooms:\n", room_count);
+        let defaults = RoomConfig {
+            recording_enabled: true,
+            ..RoomConfig::default()
+        };
+        let manager = RoomManager::with_room_defaults(defaults)
+            .with_max_participants(10)
+            .with_room_config_dir(&dir);
 
-        for (_, room) in rooms_guard.iter() {
-            stats.push_str(&room.get_stats().await);
+        let webinar = manager.get_or_create_room("webinar".to_string()).await;
+        assert_eq!(webinar.max_participants, 50);
+        assert!(!webinar.config.screen_sharing_enabled);
+        // Поля, которых нет в файле комнаты, берутся из room_defaults
+        assert!(webinar.config.recording_enabled);
+
+        for id in ["other", "broken", "../webinar"] {
+            let room = manager.get_or_create_room(id.to_string()).await;
+            assert_eq!(room.max_participants, 10);
+            assert!(room.config.screen_sharing_enabled);
         }
 
-        stats
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
-    /// Очищает все пустые комнаты
-    pub async fn cleanup_all_empty_rooms(&self) {
-        let room_ids: Vec<String> = self.rooms.read().await.keys().cloned().collect();
+    #[test]
+    fn test_room_password() {
+        let config = RoomConfig {
+            password: Some("secret".to_string()),
+            ..RoomConfig::default()
+        };
+        let room = Room::with_config("locked".to_string(), config);
+        assert!(room.authorize(Some("secret")));
+        assert!(!room.authorize(Some("wrong")));
+        assert!(!room.authorize(None));
 
-        for room_id in room_ids {
-            self.cleanup_empty_room(&room_id).await;
+        // Без пароля в конфигурации его задает первый вошедший
+        let room = Room::new("open".to_string());
+        assert!(room.authorize(Some("first")));
+        assert!(room.authorize(Some("first")));
+        assert!(!room.authorize(Some("second")));
+        assert!(!room.authorize(None));
+
+        // Комната, в которую уже вошли без пароля, остается открытой
+        let room = Room::new("public".to_string());
+        assert!(room.authorize(None));
+        assert!(room.authorize(Some("late")));
+        assert!(room.authorize(None));
+
+        // Проверка до допуска пароль не закрепляет
+        let room = Room::new("pending".to_string());
+        assert!(room.check_password(Some("refused")));
+        assert!(room.check_password(Some("admitted")));
+        assert!(room.authorize(Some("admitted")));
+        assert!(!room.check_password(Some("refused")));
+        assert!(!room.check_password(None));
+    }
+
+    #[tokio::test]
+    async fn test_keyframe_request_routed_to_publisher() {
+        let room = Room::new("feedback".to_string());
+        let (alice, _alice_rx) = test_peer("alice").await;
+        let (bob, _bob_rx) = test_peer("bob").await;
+        alice.register_incoming_video(1111);
+        room.add_peer(alice.clone()).await.unwrap();
+        room.add_peer(bob.clone()).await.unwrap();
+        assert!(bob.take_keyframe_requests().is_none());
+
+        // Вход Боба уже запросил ключевой кадр у Алисы; ждем окончания интервала
+        tokio::time::sleep(crate::peer::KEYFRAME_REQUEST_INTERVAL).await;
+        bob.request_keyframe_from("alice");
+        bob.request_keyframe_from("ghost");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Запрос дошел до Алисы: следующий в пределах интервала пропускается
+        assert!(!alice.request_keyframe().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_participants_info_for_joined() {
+        let room = Room::new("roster".to_string());
+        let (alice, _alice_rx) = test_peer("alice").await;
+        let (bob, _bob_rx) = test_peer("bob").await;
+        alice.update_state(true, false, true).await;
+        alice.update_hand(true).await;
+        room.add_peer(alice).await.unwrap();
+        room.add_peer(bob).await.unwrap();
+
+        let roster = room.participants_info("bob").await;
+        assert_eq!(roster.len(), 1);
+        assert_eq!(roster[0].id, "alice");
+        assert_eq!(roster[0].name, "ALICE");
+        assert!(roster[0].muted);
+        assert!(!roster[0].video_on);
+        assert!(roster[0].screen_sharing);
+        assert!(roster[0].hand_raised);
+    }
+
+    #[tokio::test]
+    async fn test_remove_participant() {
+        let manager = RoomManager::new();
+        let room = manager.get_or_create_room("leave".to_string()).await;
+        let (alice, _alice_rx) = test_peer("alice").await;
+        room.add_peer(alice).await.unwrap();
+
+        assert!(manager.remove_participant("leave", "alice").await.unwrap());
+        assert_eq!(manager.room_count().await, 0);
+        assert!(!manager.remove_participant("leave", "alice").await.unwrap());
+        assert!(!manager.remove_participant("missing", "bob").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_list_rooms() {
+        let manager = RoomManager::new();
+        let first = manager.get_or_create_room("first".to_string()).await;
+        let second = manager.get_or_create_room("second".to_string()).await;
+        for id in ["bob", "alice"] {
+            first.add_peer(test_peer(id).await.0).await.unwrap();
         }
+        second.add_peer(test_peer("carol").await.0).await.unwrap();
+
+        let rooms = manager.list_rooms().await;
+        assert_eq!(
+            rooms,
+            vec![
+                RoomListing {
+                    room_id: "first".to_string(),
+                    participant_count: 2,
+                    participants: vec!["alice".to_string(), "bob".to_string()],
+                },
+                RoomListing {
+                    room_id: "second".to_string(),
+                    participant_count: 1,
+                    participants: vec!["carol".to_string()],
+                },
+            ]
+        );
     }
-}
 
-impl Default for RoomManager {
-    fn default() -> Self {
-        Self::new()
+    #[tokio::test]
+    async fn test_watch_stats_ends_when_room_closes() {
+        let manager = RoomManager::new();
+        let room = manager.get_or_create_room("room1".to_string()).await;
+        room.add_peer(test_peer("alice").await.0).await.unwrap();
+        room.register_source("alice", 1111, "stream-a");
+
+        let every = Duration::from_millis(10);
+        assert!(manager.watch_stats("missing", every).await.is_none());
+        let mut stats = manager.watch_stats("room1", every).await.unwrap();
+        assert_eq!(
+            stats.recv().await,
+            Some(RoomStats {
+                room_id: "room1".to_string(),
+                participant_count: 1,
+                active_tracks: 1,
+                rtp: PeerStats::default(),
+            })
+        );
+
+        manager.remove_participant("room1", "alice").await.unwrap();
+        // После закрытия комнаты поток завершается без ошибки
+        while stats.recv().await.is_some() {}
+        assert!(manager.get_room("room1").await.is_none());
     }
 }
@@ -0,0 +1,129 @@
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, watch};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::utils::MetricsCounter;
+
+/// Размер очереди сообщений клиенту по умолчанию (в сообщениях)
+pub const DEFAULT_WS_SEND_QUEUE_SIZE: usize = 256;
+
+/// Что делать с сообщением, если очередь клиента заполнена
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendPriority {
+    /// Состояние, которое клиент получит следующим обновлением: сообщение отбрасывается
+    Droppable,
+    /// Без сообщения сессия ломается (answer, кандидаты, ошибки):
+    /// клиент отключается как не отвечающий
+    Critical,
+}
+
+/// Отправка сообщений клиенту через ограниченную очередь WebSocket.
+/// Медленный клиент не копит сообщения в памяти сервера: при заполненной очереди
+/// второстепенные сообщения отбрасываются, а невозможность отправить критичное
+/// помечает клиента не отвечающим (см. `unresponsive`)
+#[derive(Clone)]
+pub struct WsSender {
+    tx: mpsc::Sender<Message>,
+    unresponsive: Arc<watch::Sender<bool>>,
+    metrics: Option<MetricsCounter>,
+}
+
+/// Очередь сообщений клиенту на `capacity` сообщений
+pub fn ws_channel(capacity: usize) -> (WsSender, mpsc::Receiver<Message>) {
+    let (tx, rx) = mpsc::channel(capacity.max(1));
+    let sender = WsSender {
+        tx,
+        unresponsive: Arc::new(watch::channel(false).0),
+        metrics: None,
+    };
+    (sender, rx)
+}
+
+impl WsSender {
+    /// Учитывать отброшенные сообщения и отключения в метриках сервера
+    pub fn with_metrics(mut self, metrics: MetricsCounter) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Ставит в очередь критичное сообщение
+    pub fn send(&self, msg: Message) -> Result<()> {
+        self.send_with(msg, SendPriority::Critical)
+    }
+
+    /// Ставит сообщение в очередь; при заполненной очереди поступает по `priority`
+    pub fn send_with(&self, msg: Message, priority: SendPriority) -> Result<()> {
+        match self.tx.try_send(msg) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Closed(_)) => anyhow::bail!("WebSocket connection closed"),
+            Err(TrySendError::Full(_)) if priority == SendPriority::Droppable => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.increment_ws_messages_dropped();
+                }
+                Ok(())
+            }
+            Err(TrySendError::Full(_)) => {
+                if !self.unresponsive.send_replace(true) {
+                    if let Some(metrics) = &self.metrics {
+                        metrics.increment_ws_unresponsive_disconnects();
+                    }
+                }
+                anyhow::bail!("WebSocket send queue is full")
+            }
+        }
+    }
+
+    /// Клиент отключился: сообщения больше некому читать
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    #[cfg(test)]
+    pub fn is_unresponsive(&self) -> bool {
+        *self.unresponsive.borrow()
+    }
+
+    /// Завершается, когда критичное сообщение не поместилось в очередь
+    pub async fn unresponsive(&self) {
+        let mut rx = self.unresponsive.subscribe();
+        let _ = rx.wait_for(|unresponsive| *unresponsive).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_full_queue_drops_state_and_flags_unresponsive() {
+        let metrics = MetricsCounter::new();
+        // Клиент не читает сообщения: очередь не освобождается
+        let (tx, mut rx) = ws_channel(2);
+        let tx = tx.with_metrics(metrics.clone());
+
+        tx.send(Message::text("answer")).unwrap();
+        tx.send_with(Message::text("state 1"), SendPriority::Droppable).unwrap();
+        for n in 2..10 {
+            let state = Message::text(format!("state {}", n));
+            assert!(tx.send_with(state, SendPriority::Droppable).is_ok());
+        }
+        assert_eq!(metrics.get_ws_messages_dropped(), 8);
+        assert!(!tx.is_unresponsive());
+
+        // Критичное сообщение не помещается: клиент отключается
+        assert!(tx.send(Message::text("error")).is_err());
+        assert!(tx.is_unresponsive());
+        tokio::time::timeout(Duration::from_secs(1), tx.unresponsive())
+            .await
+            .unwrap();
+        assert!(tx.send(Message::Close(None)).is_err());
+        assert_eq!(metrics.get_ws_unresponsive_disconnects(), 1);
+
+        assert_eq!(rx.recv().await, Some(Message::text("answer")));
+        assert_eq!(rx.recv().await, Some(Message::text("state 1")));
+        assert!(rx.try_recv().is_err());
+    }
+}
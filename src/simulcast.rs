@@ -0,0 +1,417 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use webrtc::rtp::packet::Packet;
+
+/// URI RTP расширения frame-marking (draft-ietf-avtext-framemarking)
+pub const FRAME_MARKING_URI: &str = "urn:ietf:params:rtp-hdrext:framemarking";
+
+/// Границы кадра, которым принадлежит RTP пакет
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameInfo {
+    pub start_of_frame: bool,
+    pub end_of_frame: bool,
+    /// `None`, если тип кадра определить не удалось
+    pub keyframe: Option<bool>,
+}
+
+impl FrameInfo {
+    /// Разбирает расширение frame-marking: `|S|E|I|D|B| TID |`
+    pub fn from_frame_marking(data: &[u8]) -> Option<Self> {
+        let flags = *data.first()?;
+        Some(Self {
+            start_of_frame: flags & 0x80 != 0,
+            end_of_frame: flags & 0x40 != 0,
+            keyframe: Some(flags & 0x20 != 0),
+        })
+    }
+
+    /// Определяет границы кадра VP8 по payload descriptor (RFC 7741)
+    pub fn from_vp8(packet: &Packet) -> Option<Self> {
+        let payload = &packet.payload;
+        let first = *payload.first()?;
+        let mut offset = 1;
+
+        if first & 0x80 != 0 {
+            let ext = *payload.get(offset)?;
+            offset += 1;
+            if ext & 0x80 != 0 {
+                // PictureID: 7 или 15 бит
+                let pid = *payload.get(offset)?;
+                offset += if pid & 0x80 != 0 { 2 } else { 1 };
+            }
+            if ext & 0x40 != 0 {
+                offset += 1; // TL0PICIDX
+            }
+            if ext & 0x30 != 0 {
+                offset += 1; // TID/KEYIDX
+            }
+        }
+
+        let start = first & 0x10 != 0 && first & 0x07 == 0;
+        let keyframe = if start {
+            Some(payload.get(offset)? & 0x01 == 0)
+        } else {
+            Some(false)
+        };
+
+        Some(Self {
+            start_of_frame: start,
+            end_of_frame: packet.header.marker,
+            keyframe,
+        })
+    }
+
+    /// Определяет границы кадра VP9 по payload descriptor
+    pub fn from_vp9(packet: &Packet) -> Option<Self> {
+        let first = *packet.payload.first()?;
+        let start = first & 0x08 != 0;
+        Some(Self {
+            start_of_frame: start,
+            end_of_frame: first & 0x04 != 0 || packet.header.marker,
+            keyframe: Some(start && first & 0x40 == 0),
+        })
+    }
+
+    /// Определяет границы кадра H264 по типу NAL (RFC 6184)
+    pub fn from_h264(packet: &Packet, prev_marker: bool) -> Option<Self> {
+        let payload = &packet.payload;
+        let nal_type = payload.first()? & 0x1F;
+
+        let (start, keyframe) = match nal_type {
+            // FU-A: начало кадра только у первого фрагмента
+            28 => {
+                let fu_header = *payload.get(1)?;
+                let start = fu_header & 0x80 != 0 && prev_marker;
+                (start, matches!(fu_header & 0x1F, 5 | 7))
+            }
+            // STAP-A: SPS внутри агрегата означает ключевой кадр
+            24 => (prev_marker, payload.get(3).map(|b| b & 0x1F) == Some(7)),
+            5 | 7 | 8 => (prev_marker, true),
+            _ => (prev_marker, false),
+        };
+
+        Some(Self {
+            start_of_frame: start,
+            end_of_frame: packet.header.marker,
+            keyframe: Some(keyframe),
+        })
+    }
+
+    /// Определяет границы кадра: по frame-marking, если оно согласовано,
+    /// иначе по payload descriptor кодека, иначе по marker bit
+    pub fn detect(
+        packet: &Packet,
+        frame_marking_id: Option<u8>,
+        prev_marker: bool,
+        mime_type: &str,
+    ) -> Self {
+        let from_extension = frame_marking_id
+            .and_then(|id| packet.header.get_extension(id))
+            .and_then(|data| Self::from_frame_marking(&data));
+
+        let detected = from_extension.or_else(|| {
+            if mime_type.eq_ignore_ascii_case("video/vp8") {
+                Self::from_vp8(packet)
+            } else if mime_type.eq_ignore_ascii_case("video/vp9") {
+                Self::from_vp9(packet)
+            } else if mime_type.eq_ignore_ascii_case("video/h264") {
+                Self::from_h264(packet, prev_marker)
+            } else {
+                None
+            }
+        });
+
+        detected.unwrap_or(Self {
+            start_of_frame: prev_marker,
+            end_of_frame: packet.header.marker,
+            keyframe: None,
+        })
+    }
+}
+
+/// Качество simulcast слоя, которое получатель выбирает для источника
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SimulcastLayer {
+    Low,
+    Mid,
+    #[default]
+    High,
+}
+
+impl SimulcastLayer {
+    /// RID слоя среди опубликованных (упорядоченных `order_layers`)
+    pub fn pick(self, rids: &[String]) -> Option<&str> {
+        let last = rids.len().checked_sub(1)?;
+        let index = match self {
+            Self::Low => 0,
+            Self::Mid => last / 2,
+            Self::High => last,
+        };
+        Some(&rids[index])
+    }
+}
+
+/// Упорядочивает RID от низкого качества к высокому. Распознаются обычные
+/// обозначения ("q/h/f", "l/m/h", "low/mid/high", "0/1/2"), неизвестные RID
+/// считаются средним слоем.
+pub fn order_layers(rids: &mut [String]) {
+    let has_full = rids.iter().any(|rid| rid.eq_ignore_ascii_case("f"));
+    rids.sort_by_cached_key(|rid| {
+        let rank = match rid.to_ascii_lowercase().as_str() {
+            "q" | "l" | "lo" | "low" | "0" => 0,
+            // В схеме q/h/f "h" - половинное разрешение
+            "h" if has_full => 1,
+            "m" | "mid" | "medium" | "1" => 1,
+            "h" | "hi" | "high" | "f" | "full" | "2" => 2,
+            _ => 1,
+        };
+        (rank, rid.clone())
+    });
+}
+
+/// Состояние выбора simulcast слоя для пары (получатель, источник).
+/// Переключение на целевой слой происходит только на границе кадра:
+/// текущий кадр старого слоя досылается целиком, новый слой начинается
+/// с начала кадра (и, при `keyframe_only`, с ключевого кадра).
+#[derive(Debug, Clone)]
+pub struct LayerSwitch {
+    current: Option<String>,
+    target: Option<String>,
+    frame_open: bool,
+    keyframe_only: bool,
+}
+
+impl LayerSwitch {
+    pub fn new(keyframe_only: bool) -> Self {
+        Self {
+            current: None,
+            target: None,
+            frame_open: false,
+            keyframe_only,
+        }
+    }
+
+    /// Слой, который пересылается сейчас
+    #[cfg(test)]
+    pub fn current(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    pub fn set_target(&mut self, layer: &str) {
+        self.target = Some(layer.to_string());
+    }
+
+    /// Решает, пересылать ли пакет слоя `layer`, и при необходимости
+    /// выполняет отложенное переключение
+    pub fn should_forward(&mut self, layer: &str, frame: &FrameInfo) -> bool {
+        if self.target.is_none() {
+            self.target = Some(layer.to_string());
+        }
+
+        let is_target = self.target.as_deref() == Some(layer);
+        let is_current = self.current.as_deref() == Some(layer);
+        let switch_point = frame.start_of_frame
+            && (!self.keyframe_only || frame.keyframe != Some(false));
+
+        if is_target && !is_current && !self.frame_open && switch_point {
+            self.current = Some(layer.to_string());
+        } else if !is_current {
+            return false;
+        }
+
+        if frame.start_of_frame {
+            self.frame_open = true;
+        }
+        if frame.end_of_frame {
+            self.frame_open = false;
+        }
+        true
+    }
+}
+
+/// Последний пакет, отправленный получателю после перезаписи
+#[derive(Debug, Clone, Copy)]
+struct SentPacket {
+    sequence_number: u16,
+    timestamp: u32,
+    at: Instant,
+}
+
+/// Перезапись sequence number и timestamp simulcast трека для одного получателя:
+/// слои - разные RTP потоки со своими номерами, а получатель должен видеть один
+/// непрерывный поток. При смене слоя (SSRC) смещения выбираются так, чтобы номер
+/// шел сразу за последним отправленным, а timestamp вырос на прошедшее время.
+/// Слой меняется только на границе кадра (см. `LayerSwitch`), поэтому маркер
+/// последнего пакета старого слоя закрывает кадр
+#[derive(Debug, Clone, Default)]
+pub struct RtpRewriter {
+    /// SSRC слоя, пакеты которого пересылаются сейчас
+    source_ssrc: Option<u32>,
+    sequence_offset: u16,
+    timestamp_offset: u32,
+    last_sent: Option<SentPacket>,
+}
+
+impl RtpRewriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Переписывает номер и timestamp пакета; `clock_rate` - частота RTP часов кодека
+    pub fn rewrite(&mut self, packet: &mut Packet, clock_rate: u32, now: Instant) {
+        let header = &mut packet.header;
+        if self.source_ssrc != Some(header.ssrc) {
+            if let Some(last) = self.last_sent {
+                let elapsed = now.duration_since(last.at).as_secs_f64();
+                let ticks = ((elapsed * f64::from(clock_rate)) as u32).max(1);
+                self.sequence_offset = last
+                    .sequence_number
+                    .wrapping_add(1)
+                    .wrapping_sub(header.sequence_number);
+                self.timestamp_offset = last
+                    .timestamp
+                    .wrapping_add(ticks)
+                    .wrapping_sub(header.timestamp);
+            }
+            self.source_ssrc = Some(header.ssrc);
+        }
+
+        header.sequence_number = header.sequence_number.wrapping_add(self.sequence_offset);
+        header.timestamp = header.timestamp.wrapping_add(self.timestamp_offset);
+
+        // Переставленные пакеты не сдвигают точку отсчета для следующего переключения
+        let newer = self.last_sent.is_none_or(|last| {
+            (header.sequence_number.wrapping_sub(last.sequence_number) as i16) > 0
+        });
+        if newer {
+            self.last_sent = Some(SentPacket {
+                sequence_number: header.sequence_number,
+                timestamp: header.timestamp,
+                at: now,
+            });
+        }
+    }
+}
+
+/// Перезапись номеров одного simulcast трека для каждого получателя. Слои трека
+/// пересылают разные задачи, поэтому состояние у них общее: новый слой продолжает
+/// поток, начатый другим
+#[derive(Debug, Default)]
+pub struct TrackRewriters {
+    subscribers: Mutex<HashMap<String, RtpRewriter>>,
+}
+
+impl TrackRewriters {
+    /// Переписывает пакет для получателя `subscriber_id` (см. `RtpRewriter::rewrite`)
+    pub fn rewrite(
+        &self,
+        subscriber_id: &str,
+        packet: &mut Packet,
+        clock_rate: u32,
+        now: Instant,
+    ) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if !subscribers.contains_key(subscriber_id) {
+            subscribers.insert(subscriber_id.to_string(), RtpRewriter::new());
+        }
+        if let Some(rewriter) = subscribers.get_mut(subscriber_id) {
+            rewriter.rewrite(packet, clock_rate, now);
+        }
+    }
+
+    /// Забывает состояние получателя (он ушел или переподключился)
+    pub fn forget(&self, subscriber_id: &str) {
+        self.subscribers.lock().unwrap().remove(subscriber_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(start: bool, end: bool) -> FrameInfo {
+        FrameInfo {
+            start_of_frame: start,
+            end_of_frame: end,
+            keyframe: Some(start),
+        }
+    }
+
+    #[test]
+    fn test_parse_frame_marking() {
+        let info = FrameInfo::from_frame_marking(&[0b1010_0000]).unwrap();
+        assert!(info.start_of_frame);
+        assert!(!info.end_of_frame);
+        assert_eq!(info.keyframe, Some(true));
+        assert!(FrameInfo::from_frame_marking(&[]).is_none());
+    }
+
+    #[test]
+    fn test_order_and_pick_layers() {
+        let mut rids: Vec<String> = ["f", "q", "h"].map(String::from).to_vec();
+        order_layers(&mut rids);
+        assert_eq!(rids, ["q", "h", "f"]);
+        assert_eq!(SimulcastLayer::Mid.pick(&rids), Some("h"));
+
+        let mut rids: Vec<String> = ["high", "low"].map(String::from).to_vec();
+        order_layers(&mut rids);
+        assert_eq!(SimulcastLayer::default().pick(&rids), Some("high"));
+        assert_eq!(SimulcastLayer::Mid.pick(&rids), Some("low"));
+        assert_eq!(SimulcastLayer::Low.pick(&[]), None);
+    }
+
+    #[test]
+    fn test_layer_switch_waits_for_frame_boundary() {
+        let mut switch = LayerSwitch::new(false);
+        assert!(switch.should_forward("low", &frame(true, true)));
+        assert_eq!(switch.current(), Some("low"));
+
+        // Кадр нижнего слоя начат, переключение запрошено посреди кадра
+        assert!(switch.should_forward("low", &frame(true, false)));
+        switch.set_target("high");
+        assert!(!switch.should_forward("high", &frame(true, false)));
+        assert!(switch.should_forward("low", &frame(false, true)));
+
+        // Середина кадра верхнего слоя не годится для переключения
+        assert!(!switch.should_forward("high", &frame(false, true)));
+        assert!(switch.should_forward("high", &frame(true, false)));
+        assert_eq!(switch.current(), Some("high"));
+        assert!(!switch.should_forward("low", &frame(true, true)));
+    }
+
+    #[test]
+    fn test_rewriter_keeps_stream_continuous_across_layers() {
+        use std::time::Duration;
+
+        let packet = |ssrc: u32, sequence_number: u16, timestamp: u32| {
+            let mut packet = Packet::default();
+            packet.header.ssrc = ssrc;
+            packet.header.sequence_number = sequence_number;
+            packet.header.timestamp = timestamp;
+            packet
+        };
+        let start = Instant::now();
+        let mut rewriter = RtpRewriter::new();
+        let mut sent = |mut packet: Packet, ms: u64| {
+            rewriter.rewrite(&mut packet, 90_000, start + Duration::from_millis(ms));
+            (packet.header.sequence_number, packet.header.timestamp)
+        };
+
+        // Первый слой пересылается без изменений, в том числе переставленный пакет
+        assert_eq!(sent(packet(1, 65534, 1000), 0), (65534, 1000));
+        assert_eq!(sent(packet(1, 65535, 1000), 1), (65535, 1000));
+        assert_eq!(sent(packet(1, 1, 4000), 33), (1, 4000));
+        assert_eq!(sent(packet(1, 0, 4000), 34), (0, 4000));
+
+        // Переключение на другой слой через 33 мс: номера продолжаются,
+        // timestamp растет на 33 мс в 90 кГц
+        assert_eq!(sent(packet(2, 500, 77_000), 66), (2, 6970));
+        assert_eq!(sent(packet(2, 501, 80_000), 99), (3, 9970));
+
+        // Возврат на первый слой тоже без разрыва
+        assert_eq!(sent(packet(1, 10, 13_000), 132), (4, 12_940));
+    }
+}
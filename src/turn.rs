@@ -0,0 +1,63 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Временные учетные данные TURN по схеме TURN REST API
+/// (draft-uberti-behave-turn-rest, `use-auth-secret` в coturn)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TurnCredentials {
+    /// `<unix время истечения>:<id сессии>`
+    pub username: String,
+    /// `base64(HMAC-SHA1(secret, username))`
+    pub credential: String,
+}
+
+impl TurnCredentials {
+    /// Учетные данные для сессии `session_id`, действующие `ttl` от `now`.
+    /// TURN сервер с тем же секретом проверяет подпись и отклоняет истекшие имена
+    pub fn generate(secret: &str, session_id: &str, ttl: Duration, now: SystemTime) -> Self {
+        let expires_at = now
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .saturating_add(ttl)
+            .as_secs();
+        let username = format!("{}:{}", expires_at, session_id);
+
+        // HMAC принимает ключ любой длины
+        let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key");
+        mac.update(username.as_bytes());
+        let credential = STANDARD.encode(mac.finalize().into_bytes());
+
+        Self {
+            username,
+            credential,
+        }
+    }
+}
+
+/// URL относится к TURN серверу (`turn:` или `turns:`), а не к STUN
+pub fn is_turn_url(url: &str) -> bool {
+    let scheme = url.split(':').next().unwrap_or_default();
+    scheme.eq_ignore_ascii_case("turn") || scheme.eq_ignore_ascii_case("turns")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credentials_match_coturn() {
+        // Эталон: echo -n "1433895918:alice" | openssl dgst -sha1 -hmac north -binary | base64
+        let now = UNIX_EPOCH + Duration::from_secs(1_433_809_518);
+        let credentials =
+            TurnCredentials::generate("north", "alice", Duration::from_secs(86_400), now);
+        assert_eq!(credentials.username, "1433895918:alice");
+        assert_eq!(credentials.credential, "RqMvcGPYTJMGThVLSi4amT4zFtI=");
+
+        assert!(is_turn_url("turn:coturn:3478?transport=udp"));
+        assert!(is_turn_url("turns:turn.example.com:5349"));
+        assert!(!is_turn_url("stun:stun.l.google.com:19302"));
+    }
+}
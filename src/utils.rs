@@ -1,9 +1,84 @@
 use log::{debug, info};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
+/// Линейных поддиапазонов на каждую степень двойки: погрешность квантиля не больше 25%
+const HISTOGRAM_SUB_BUCKETS: u32 = 4;
+/// Корзины до 2^40 мкс (~12 суток), все большее попадает в последнюю
+const HISTOGRAM_BUCKETS: usize = 40 * HISTOGRAM_SUB_BUCKETS as usize;
+
+/// Гистограмма длительностей без блокировок: логарифмические корзины
+/// (по 4 на степень двойки микросекунд, как в HDR гистограмме), запись - одно
+/// атомарное сложение, поэтому ее можно вести на каждый пересылаемый пакет
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    buckets: Box<[AtomicU64]>,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: (0..HISTOGRAM_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    fn bucket(micros: u64) -> usize {
+        let sub = u64::from(HISTOGRAM_SUB_BUCKETS);
+        if micros < sub {
+            return micros as usize;
+        }
+        let msb = 63 - micros.leading_zeros();
+        let offset = (micros >> (msb - 2)) & (sub - 1);
+        (((msb - 1) * HISTOGRAM_SUB_BUCKETS) as usize + offset as usize).min(HISTOGRAM_BUCKETS - 1)
+    }
+
+    /// Верхняя граница корзины (мкс)
+    fn upper_bound(bucket: usize) -> u64 {
+        let sub = HISTOGRAM_SUB_BUCKETS as usize;
+        if bucket < sub {
+            return bucket as u64;
+        }
+        let msb = bucket / sub + 1;
+        let lower = ((sub + bucket % sub) as u64) << (msb - 2);
+        lower + (1u64 << (msb - 2)) - 1
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let micros = duration.as_micros().min(u128::from(u64::MAX)) as u64;
+        self.buckets[Self::bucket(micros)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).sum()
+    }
+
+    /// Квантиль `q` (0..=1) по верхней границе корзины; `None`, если наблюдений нет
+    pub fn percentile(&self, q: f64) -> Option<Duration> {
+        let counts: Vec<u64> = self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        let bucket = counts.iter().position(|&count| {
+            seen += count;
+            seen >= rank
+        })?;
+        Some(Duration::from_micros(Self::upper_bound(bucket)))
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Счетчик для метрик
 #[derive(Debug, Clone)]
 pub struct MetricsCounter {
@@ -13,6 +88,26 @@ pub struct MetricsCounter {
     bytes_sent: Arc<AtomicU64>,
     connections_total: Arc<AtomicU64>,
     connections_active: Arc<AtomicU64>,
+    /// Второстепенные сообщения, отброшенные из-за заполненной очереди клиента
+    ws_messages_dropped: Arc<AtomicU64>,
+    /// Клиенты, отключенные из-за заполненной очереди сообщений
+    ws_unresponsive_disconnects: Arc<AtomicU64>,
+    /// Сообщения клиентов, которые не удалось разобрать
+    malformed_messages: Arc<AtomicU64>,
+    /// Время от получения RTP пакета до его записи в трек получателя
+    forward_latency: Arc<LatencyHistogram>,
+    /// Пакеты, которые не удалось записать в трек получателя (кроме закрытого соединения)
+    packets_dropped: Arc<AtomicU64>,
+    /// Пакеты издателей, пришедшие после пакетов с большими номерами
+    packets_out_of_order: Arc<AtomicU64>,
+    /// Отправленные издателям запросы ключевого кадра (PLI)
+    keyframes_requested: Arc<AtomicU64>,
+    /// Пакеты, отброшенные при переполнении очередей отправки получателям
+    outbound_audio_dropped: Arc<AtomicU64>,
+    outbound_video_delta_dropped: Arc<AtomicU64>,
+    outbound_video_keyframe_dropped: Arc<AtomicU64>,
+    /// Участники, ожидающие слота DTLS handshake
+    handshakes_queued: Arc<AtomicU64>,
     start_time: Instant,
 }
 
@@ -25,6 +120,17 @@ impl MetricsCounter {
             bytes_sent: Arc::new(AtomicU64::new(0)),
             connections_total: Arc::new(AtomicU64::new(0)),
             connections_active: Arc::new(AtomicU64::new(0)),
+            ws_messages_dropped: Arc::new(AtomicU64::new(0)),
+            ws_unresponsive_disconnects: Arc::new(AtomicU64::new(0)),
+            malformed_messages: Arc::new(AtomicU64::new(0)),
+            forward_latency: Arc::new(LatencyHistogram::new()),
+            packets_dropped: Arc::new(AtomicU64::new(0)),
+            packets_out_of_order: Arc::new(AtomicU64::new(0)),
+            keyframes_requested: Arc::new(AtomicU64::new(0)),
+            outbound_audio_dropped: Arc::new(AtomicU64::new(0)),
+            outbound_video_delta_dropped: Arc::new(AtomicU64::new(0)),
+            outbound_video_keyframe_dropped: Arc::new(AtomicU64::new(0)),
+            handshakes_queued: Arc::new(AtomicU64::new(0)),
             start_time: Instant::now(),
         }
     }
@@ -54,6 +160,54 @@ impl MetricsCounter {
         self.connections_active.fetch_sub(1, Ordering::Relaxed);
     }
 
+    pub fn increment_ws_messages_dropped(&self) {
+        self.ws_messages_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_ws_unresponsive_disconnects(&self) {
+        self.ws_unresponsive_disconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_malformed_messages(&self) {
+        self.malformed_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn observe_forward_latency(&self, latency: Duration) {
+        self.forward_latency.observe(latency);
+    }
+
+    pub fn increment_packets_dropped(&self) {
+        self.packets_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_packets_out_of_order(&self) {
+        self.packets_out_of_order.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_keyframes_requested(&self) {
+        self.keyframes_requested.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_handshakes_queued(&self) {
+        self.handshakes_queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn decrement_handshakes_queued(&self) {
+        self.handshakes_queued.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_outbound_audio_dropped(&self) {
+        self.outbound_audio_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_outbound_video_delta_dropped(&self) {
+        self.outbound_video_delta_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_outbound_video_keyframe_dropped(&self) {
+        self.outbound_video_keyframe_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn get_packets_received(&self) -> u64 {
         self.packets_received.load(Ordering::Relaxed)
     }
@@ -78,6 +232,46 @@ impl MetricsCounter {
         self.connections_active.load(Ordering::Relaxed)
     }
 
+    pub fn get_ws_messages_dropped(&self) -> u64 {
+        self.ws_messages_dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn get_ws_unresponsive_disconnects(&self) -> u64 {
+        self.ws_unresponsive_disconnects.load(Ordering::Relaxed)
+    }
+
+    pub fn get_malformed_messages(&self) -> u64 {
+        self.malformed_messages.load(Ordering::Relaxed)
+    }
+
+    pub fn get_packets_dropped(&self) -> u64 {
+        self.packets_dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn get_packets_out_of_order(&self) -> u64 {
+        self.packets_out_of_order.load(Ordering::Relaxed)
+    }
+
+    pub fn get_keyframes_requested(&self) -> u64 {
+        self.keyframes_requested.load(Ordering::Relaxed)
+    }
+
+    pub fn get_handshakes_queued(&self) -> u64 {
+        self.handshakes_queued.load(Ordering::Relaxed)
+    }
+
+    pub fn get_outbound_audio_dropped(&self) -> u64 {
+        self.outbound_audio_dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn get_outbound_video_delta_dropped(&self) -> u64 {
+        self.outbound_video_delta_dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn get_outbound_video_keyframe_dropped(&self) -> u64 {
+        self.outbound_video_keyframe_dropped.load(Ordering::Relaxed)
+    }
+
     pub fn get_uptime(&self) -> Duration {
         self.start_time.elapsed()
     }
@@ -87,6 +281,11 @@ impl MetricsCounter {
         let hours = uptime.as_secs() / 3600;
         let minutes = (uptime.as_secs() % 3600) / 60;
         let seconds = uptime.as_secs() % 60;
+        let latency = |q: f64| {
+            self.forward_latency
+                .percentile(q)
+                .map_or_else(|| "n/a".to_string(), |d| format!("{:.1}ms", d.as_secs_f64() * 1000.0))
+        };
 
         format!(
             "Metrics:\n\
@@ -95,8 +294,14 @@ impl MetricsCounter {
              - Total Connections: {}\n\
              - Packets Received: {}\n\
              - Packets Sent: {}\n\
+             - Packets Dropped: {}\n\
+             - Packets Out of Order: {}\n\
+             - Keyframes Requested: {}\n\
              - Bytes Received: {} MB\n\
-             - Bytes Sent: {} MB",
+             - Bytes Sent: {} MB\n\
+             - WebSocket Messages Dropped: {} ({} clients disconnected)\n\
+             - Malformed Client Messages: {}\n\
+             - Forward Latency p50/p95/p99: {}/{}/{}",
             hours,
             minutes,
             seconds,
@@ -104,11 +309,150 @@ impl MetricsCounter {
             self.get_connections_total(),
             self.get_packets_received(),
             self.get_packets_sent(),
+            self.get_packets_dropped(),
+            self.get_packets_out_of_order(),
+            self.get_keyframes_requested(),
             self.get_bytes_received() / 1_000_000,
-            self.get_bytes_sent() / 1_000_000
+            self.get_bytes_sent() / 1_000_000,
+            self.get_ws_messages_dropped(),
+            self.get_ws_unresponsive_disconnects(),
+            self.get_malformed_messages(),
+            latency(0.5),
+            latency(0.95),
+            latency(0.99)
         )
     }
 
+    /// Метрики в текстовом формате Prometheus
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            out.push_str(&format!(
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+            ));
+        };
+
+        metric(
+            "rrtc_packets_received_total",
+            "counter",
+            "RTP packets received from publishers",
+            self.get_packets_received().to_string(),
+        );
+        metric(
+            "rrtc_packets_sent_total",
+            "counter",
+            "RTP packets forwarded to subscribers",
+            self.get_packets_sent().to_string(),
+        );
+        metric(
+            "rrtc_packets_dropped_total",
+            "counter",
+            "RTP packets that failed to be written to a subscriber",
+            self.get_packets_dropped().to_string(),
+        );
+        metric(
+            "rrtc_packets_out_of_order_total",
+            "counter",
+            "RTP packets received after packets with higher sequence numbers",
+            self.get_packets_out_of_order().to_string(),
+        );
+        metric(
+            "rrtc_keyframes_requested_total",
+            "counter",
+            "Keyframe requests (PLI) sent to publishers",
+            self.get_keyframes_requested().to_string(),
+        );
+        metric(
+            "rrtc_outbound_audio_dropped_total",
+            "counter",
+            "Audio packets dropped because a subscriber send queue was full",
+            self.get_outbound_audio_dropped().to_string(),
+        );
+        metric(
+            "rrtc_outbound_video_delta_dropped_total",
+            "counter",
+            "Video delta frame packets dropped because a subscriber send queue was full",
+            self.get_outbound_video_delta_dropped().to_string(),
+        );
+        metric(
+            "rrtc_outbound_video_keyframe_dropped_total",
+            "counter",
+            "Video keyframe packets dropped because a subscriber send queue was full",
+            self.get_outbound_video_keyframe_dropped().to_string(),
+        );
+        metric(
+            "rrtc_bytes_received_total",
+            "counter",
+            "RTP bytes received from publishers",
+            self.get_bytes_received().to_string(),
+        );
+        metric(
+            "rrtc_bytes_sent_total",
+            "counter",
+            "RTP bytes forwarded to subscribers",
+            self.get_bytes_sent().to_string(),
+        );
+        metric(
+            "rrtc_connections_total",
+            "counter",
+            "Signaling connections accepted",
+            self.get_connections_total().to_string(),
+        );
+        metric(
+            "rrtc_connections_active",
+            "gauge",
+            "Open signaling connections",
+            self.get_connections_active().to_string(),
+        );
+        metric(
+            "rrtc_handshakes_queued",
+            "gauge",
+            "Joins waiting for a DTLS handshake slot",
+            self.get_handshakes_queued().to_string(),
+        );
+        metric(
+            "rrtc_ws_messages_dropped_total",
+            "counter",
+            "Signaling messages dropped because the client send queue was full",
+            self.get_ws_messages_dropped().to_string(),
+        );
+        metric(
+            "rrtc_ws_unresponsive_disconnects_total",
+            "counter",
+            "Clients disconnected because a critical message did not fit the send queue",
+            self.get_ws_unresponsive_disconnects().to_string(),
+        );
+        metric(
+            "rrtc_malformed_messages_total",
+            "counter",
+            "Client signaling messages that could not be parsed",
+            self.get_malformed_messages().to_string(),
+        );
+        metric(
+            "rrtc_uptime_seconds",
+            "gauge",
+            "Seconds since the server started",
+            format!("{:.3}", self.get_uptime().as_secs_f64()),
+        );
+
+        let name = "rrtc_forward_latency_seconds";
+        out.push_str(&format!(
+            "# HELP {name} Time from receiving an RTP packet to writing it to a subscriber\n\
+             # TYPE {name} summary\n"
+        ));
+        for q in [0.5, 0.95, 0.99] {
+            if let Some(latency) = self.forward_latency.percentile(q) {
+                out.push_str(&format!(
+                    "{name}{{quantile=\"{q}\"}} {:.6}\n",
+                    latency.as_secs_f64()
+                ));
+            }
+        }
+        out.push_str(&format!("{name}_count {}\n", self.forward_latency.count()));
+        out
+    }
+
+    #[allow(dead_code)]
     pub fn log_stats(&self) {
         info!("{}", self.format_stats());
     }
@@ -121,11 +465,13 @@ impl Default for MetricsCounter {
 }
 
 /// Генератор уникальных ID
+#[allow(dead_code)]
 pub struct IdGenerator {
     counter: Arc<AtomicU64>,
     prefix: String,
 }
 
+#[allow(dead_code)]
 impl IdGenerator {
     pub fn new(prefix: &str) -> Self {
         Self {
@@ -150,11 +496,13 @@ impl IdGenerator {
 }
 
 /// Таймер для измерения длительности операций
+#[allow(dead_code)]
 pub struct Timer {
     start: Instant,
     name: String,
 }
 
+#[allow(dead_code)]
 impl Timer {
     pub fn new(name: &str) -> Self {
         debug!("Timer '{}' started", name);
@@ -184,63 +532,124 @@ impl Drop for Timer {
     }
 }
 
-/// Кеш для хранения временных данных
+/// Записи кеша и порядок их использования (от давних к недавним)
+#[allow(dead_code)]
+struct CacheData<T> {
+    entries: HashMap<String, (T, Instant, u64)>,
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+#[allow(dead_code)]
+impl<T> CacheData<T> {
+    /// Отмечает запись использованной последней
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        if let Some((_, _, used)) = self.entries.get_mut(key) {
+            self.recency.remove(used);
+            *used = self.tick;
+            self.recency.insert(self.tick, key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<T> {
+        let (value, _, used) = self.entries.remove(key)?;
+        self.recency.remove(&used);
+        Some(value)
+    }
+}
+
+/// Кеш для хранения временных данных. Записи истекают через `ttl`; кеш с
+/// ограниченной емкостью при переполнении вытесняет давно не использованную запись
 #[derive(Clone)]
+#[allow(dead_code)]
 pub struct Cache<T: Clone> {
-    data: Arc<RwLock<std::collections::HashMap<String, (T, Instant)>>>,
+    data: Arc<RwLock<CacheData<T>>>,
     ttl: Duration,
+    capacity: Option<usize>,
 }
 
+#[allow(dead_code)]
 impl<T: Clone> Cache<T> {
+    /// Кеш без ограничения числа записей
     pub fn new(ttl: Duration) -> Self {
         Self {
-            data: Arc::new(RwLock::new(std::collections::HashMap::new())),
+            data: Arc::new(RwLock::new(CacheData {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+            })),
             ttl,
+            capacity: None,
+        }
+    }
+
+    /// Кеш не больше чем на `max_entries` записей (LRU)
+    pub fn with_capacity(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            capacity: Some(max_entries.max(1)),
+            ..Self::new(ttl)
         }
     }
 
     pub async fn insert(&self, key: String, value: T) {
         let mut data = self.data.write().await;
-        data.insert(key, (value, Instant::now()));
-    }
+        data.remove(&key);
+        data.entries.insert(key.clone(), (value, Instant::now(), 0));
+        data.touch(&key);
 
-    pub async fn get(&self, key: &str) -> Option<T> {
-        let data = self.data.read().await;
-        if let Some((value, inserted_at)) = data.get(key) {
-            if inserted_at.elapsed() < self.ttl {
-                return Some(value.clone());
+        if let Some(capacity) = self.capacity {
+            while data.entries.len() > capacity {
+                let Some((_, oldest)) = data.recency.pop_first() else {
+                    break;
+                };
+                data.entries.remove(&oldest);
             }
         }
-        None
+    }
+
+    pub async fn get(&self, key: &str) -> Option<T> {
+        let mut data = self.data.write().await;
+        let value = match data.entries.get(key) {
+            Some((value, inserted_at, _)) if inserted_at.elapsed() < self.ttl => value.clone(),
+            _ => return None,
+        };
+        data.touch(key);
+        Some(value)
     }
 
     pub async fn remove(&self, key: &str) -> Option<T> {
         let mut data = self.data.write().await;
-        data.remove(key).map(|(value, _)| value)
+        data.remove(key)
     }
 
     pub async fn cleanup_expired(&self) {
         let mut data = self.data.write().await;
-        data.retain(|_, (_, inserted_at)| inserted_at.elapsed() < self.ttl);
+        let ttl = self.ttl;
+        let CacheData { entries, recency, .. } = &mut *data;
+        entries.retain(|_, (_, inserted_at, _)| inserted_at.elapsed() < ttl);
+        recency.retain(|_, key| entries.contains_key(key));
     }
 
     pub async fn clear(&self) {
         let mut data = self.data.write().await;
-        data.clear();
+        data.entries.clear();
+        data.recency.clear();
     }
 
     pub async fn len(&self) -> usize {
         let data = self.data.read().await;
-        data.len()
+        data.entries.len()
     }
 
     pub async fn is_empty(&self) -> bool {
         let data = self.data.read().await;
-        data.is_empty()
+        data.entries.is_empty()
     }
 }
 
 /// Форматирование байтов в человекочитаемый вид
+#[allow(dead_code)]
 pub fn format_bytes(bytes: u64) -> String {
     const UNITS: [&str; 6] = ["B", "KB", "MB", "GB", "TB", "PB"];
     let mut size = bytes as f64;
@@ -255,6 +664,7 @@ pub fn format_bytes(bytes: u64) -> String {
 }
 
 /// Форматирование длительности в человекочитаемый вид
+#[allow(dead_code)]
 pub fn format_duration(duration: Duration) -> String {
     let total_secs = duration.as_secs();
     let days = total_secs / 86400;
@@ -304,12 +714,207 @@ impl RateLimiter {
         }
     }
 
+    #[allow(dead_code)]
     pub async fn reset(&self) {
         let mut requests = self.requests.write().await;
         requests.clear();
     }
 }
 
+/// Token bucket без блокировок: до `capacity` токенов, пополнение `refill_per_sec`
+/// токенов в секунду. Хранится одно время - когда корзина станет полной (GCRA),
+/// поэтому проверка - O(1) и один CAS вместо списка запросов как у `RateLimiter`
+#[derive(Debug)]
+pub struct TokenBucket {
+    start: Instant,
+    capacity: u64,
+    nanos_per_token: u64,
+    /// Момент (нс от `start`), к которому корзина снова заполнится
+    full_at: AtomicU64,
+}
+
+impl TokenBucket {
+    /// Полная корзина на `capacity` токенов
+    pub fn new(capacity: u64, refill_per_sec: f64) -> Self {
+        Self {
+            start: Instant::now(),
+            capacity: capacity.max(1),
+            nanos_per_token: (1e9 / refill_per_sec.max(f64::MIN_POSITIVE)).max(1.0) as u64,
+            full_at: AtomicU64::new(0),
+        }
+    }
+
+    fn nanos_since_start(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.start).as_nanos() as u64
+    }
+
+    /// Берет `n` токенов, если они есть
+    pub fn try_acquire(&self, n: u64) -> bool {
+        self.try_acquire_at(n, Instant::now())
+    }
+
+    pub fn try_acquire_at(&self, n: u64, now: Instant) -> bool {
+        let now = self.nanos_since_start(now);
+        let window = self.capacity.saturating_mul(self.nanos_per_token);
+        let cost = n.saturating_mul(self.nanos_per_token);
+        let mut full_at = self.full_at.load(Ordering::Relaxed);
+        loop {
+            // Корзина не переполняется: пустое время не копит больше `capacity` токенов
+            let next = full_at.max(now).saturating_add(cost);
+            if next - now > window {
+                return false;
+            }
+            match self.full_at.compare_exchange_weak(
+                full_at,
+                next,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => full_at = current,
+            }
+        }
+    }
+
+    /// Сколько токенов доступно сейчас
+    pub fn available(&self) -> u64 {
+        self.available_at(Instant::now())
+    }
+
+    pub fn available_at(&self, now: Instant) -> u64 {
+        let now = self.nanos_since_start(now);
+        let missing = self.full_at.load(Ordering::Relaxed).saturating_sub(now);
+        self.capacity - missing.div_ceil(self.nanos_per_token).min(self.capacity)
+    }
+
+    /// Ждет, пока наберутся `n` токенов, и берет их; `false`, если `n` больше емкости
+    #[allow(dead_code)]
+    pub async fn acquire(&self, n: u64) -> bool {
+        if n > self.capacity {
+            return false;
+        }
+        while !self.try_acquire(n) {
+            let missing = n.saturating_sub(self.available());
+            let wait = Duration::from_nanos(missing.max(1) * self.nanos_per_token);
+            tokio::time::sleep(wait).await;
+        }
+        true
+    }
+}
+
+/// Ограничение частоты новых соединений с одного IP: у каждого адреса свой
+/// `TokenBucket`. Корзины простаивающих адресов удаляются `prune`
+pub struct IpRateLimiter {
+    capacity: u64,
+    refill_per_sec: f64,
+    buckets: std::sync::Mutex<HashMap<IpAddr, TokenBucket>>,
+}
+
+impl IpRateLimiter {
+    /// Не больше `max` соединений с адреса за `window`
+    pub fn new(max: u64, window: Duration) -> Self {
+        Self {
+            capacity: max,
+            refill_per_sec: max as f64 / window.as_secs_f64().max(f64::MIN_POSITIVE),
+            buckets: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Учитывает новое соединение с `ip`; `false`, если лимит адреса исчерпан
+    pub fn allow(&self, ip: IpAddr) -> bool {
+        self.buckets
+            .lock()
+            .unwrap()
+            .entry(ip)
+            .or_insert_with(|| TokenBucket::new(self.capacity, self.refill_per_sec))
+            .try_acquire(1)
+    }
+
+    /// Удаляет корзины, которые успели заполниться: адрес не подключался за окно
+    /// и его пустая корзина не отличается от новой. Возвращает, сколько осталось
+    pub fn prune(&self) -> usize {
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets.retain(|_, bucket| bucket.available() < self.capacity);
+        buckets.len()
+    }
+}
+
+/// Измеритель частоты событий по последовательным окнам фиксированной длины
+#[derive(Debug, Clone)]
+pub struct RateMeter {
+    window: Duration,
+    window_start: Option<Instant>,
+    count: u64,
+    last_rate: f64,
+}
+
+impl RateMeter {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            window_start: None,
+            count: 0,
+            last_rate: 0.0,
+        }
+    }
+
+    /// Учитывает событие. Когда окно завершается, возвращает частоту за него (в секунду)
+    pub fn record(&mut self, now: Instant) -> Option<f64> {
+        let start = *self.window_start.get_or_insert(now);
+        let elapsed = now.duration_since(start);
+
+        if elapsed < self.window {
+            self.count += 1;
+            return None;
+        }
+
+        self.last_rate = self.count as f64 / elapsed.as_secs_f64();
+        self.window_start = Some(now);
+        self.count = 1;
+        Some(self.last_rate)
+    }
+}
+
+/// Время последней активности без блокировок: отметка - одна атомарная запись,
+/// поэтому ее можно делать на каждый RTP пакет
+#[derive(Debug)]
+pub struct ActivityClock {
+    start: Instant,
+    /// Последняя активность, мс от `start`
+    last_ms: AtomicU64,
+}
+
+impl ActivityClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            last_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Отмечает активность в момент `Instant::now()`
+    pub fn touch(&self) {
+        self.touch_at(Instant::now());
+    }
+
+    pub fn touch_at(&self, now: Instant) {
+        let ms = now.saturating_duration_since(self.start).as_millis() as u64;
+        self.last_ms.fetch_max(ms, Ordering::Relaxed);
+    }
+
+    /// Сколько прошло с последней активности к моменту `now`
+    pub fn idle_for(&self, now: Instant) -> Duration {
+        let last = self.start + Duration::from_millis(self.last_ms.load(Ordering::Relaxed));
+        now.saturating_duration_since(last)
+    }
+}
+
+impl Default for ActivityClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -323,6 +928,26 @@ mod tests {
         assert_eq!(counter.get_packets_sent(), 5);
     }
 
+    #[test]
+    fn test_forward_latency_percentiles() {
+        let counter = MetricsCounter::new();
+        assert!(counter.format_stats().contains("p50/p95/p99: n/a/n/a/n/a"));
+
+        // 90 быстрых пакетов по 100 мкс и 10 медленных по 20 мс
+        for _ in 0..90 {
+            counter.observe_forward_latency(Duration::from_micros(100));
+        }
+        for _ in 0..10 {
+            counter.observe_forward_latency(Duration::from_millis(20));
+        }
+        let p50 = counter.forward_latency.percentile(0.5).unwrap();
+        let p99 = counter.forward_latency.percentile(0.99).unwrap();
+        // Граница корзины не дальше 25% от значения
+        assert!(p50 >= Duration::from_micros(100) && p50 <= Duration::from_micros(125));
+        assert!(p99 >= Duration::from_millis(20) && p99 <= Duration::from_millis(25));
+        assert!(counter.to_prometheus().contains("rrtc_forward_latency_seconds_count 100"));
+    }
+
     #[test]
     fn test_id_generator() {
         let gen = IdGenerator::new("peer");
@@ -356,6 +981,69 @@ mod tests {
         assert_eq!(cache.get("key1").await, None);
     }
 
+    #[tokio::test]
+    async fn test_token_bucket_refills_at_rate() {
+        let bucket = TokenBucket::new(10, 100.0);
+        let start = Instant::now();
+        assert_eq!((0..15).filter(|_| bucket.try_acquire_at(1, start)).count(), 10);
+        assert!(!bucket.try_acquire_at(1, start));
+
+        // 100 токенов/с: за 50 мс набирается 5, но не больше емкости за долгую паузу
+        assert_eq!(bucket.available_at(start + Duration::from_millis(50)), 5);
+        assert_eq!(bucket.available_at(start + Duration::from_secs(10)), 10);
+
+        // В реальном времени: опустошаем, ждем и проверяем пополнение с допуском
+        let bucket = TokenBucket::new(100, 200.0);
+        let drained = Instant::now();
+        assert!(bucket.try_acquire(100));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let refilled = bucket.available();
+        let expected = (drained.elapsed().as_secs_f64() * 200.0) as u64;
+        assert!(refilled.abs_diff(expected) <= 2, "refilled {} of {}", refilled, expected);
+        assert!(refilled >= 20);
+
+        // Асинхронный вариант ждет недостающие токены
+        let waited = Instant::now();
+        assert!(bucket.acquire(refilled + 10).await);
+        assert!(waited.elapsed() >= Duration::from_millis(40));
+        assert!(!bucket.acquire(101).await);
+    }
+
+    #[test]
+    fn test_ip_rate_limiter_per_address() {
+        let limiter = IpRateLimiter::new(3, Duration::from_secs(10));
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.2".parse().unwrap();
+
+        assert_eq!((0..5).filter(|_| limiter.allow(a)).count(), 3);
+        // Лимит одного адреса не мешает другим
+        assert!(limiter.allow(b));
+        // Корзины с недавними соединениями не удаляются
+        assert_eq!(limiter.prune(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cache_evicts_least_recently_used() {
+        let cache = Cache::with_capacity(Duration::from_secs(60), 2);
+        cache.insert("oldest".to_string(), 1).await;
+        cache.insert("used".to_string(), 2).await;
+        // Чтение делает запись недавней: вытесняется не она
+        assert_eq!(cache.get("oldest").await, Some(1));
+        cache.insert("newest".to_string(), 3).await;
+
+        assert_eq!(cache.len().await, 2);
+        assert_eq!(cache.get("used").await, None);
+        assert_eq!(cache.get("oldest").await, Some(1));
+        assert_eq!(cache.get("newest").await, Some(3));
+
+        // Повторная вставка того же ключа не занимает второе место
+        cache.insert("newest".to_string(), 4).await;
+        cache.insert("another".to_string(), 5).await;
+        assert_eq!(cache.get("oldest").await, None);
+        assert_eq!(cache.get("newest").await, Some(4));
+        assert_eq!(cache.get("another").await, Some(5));
+    }
+
     #[tokio::test]
     async fn test_rate_limiter() {
         let limiter = RateLimiter::new(2, Duration::from_secs(1));